@@ -1,6 +1,8 @@
 //! Map types
 
-use geo::{coord, point, BoundingRect, Coord, CoordNum, LineString, Point, Polygon, Rect};
+use geo::{
+    coord, point, BoundingRect, Coord, CoordNum, LineString, MultiLineString, MultiPolygon, Point, Polygon, Rect,
+};
 use rstar::{Envelope, PointDistance, RTreeObject, AABB};
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
@@ -10,15 +12,22 @@ pub const DBS_FOLDER: &str = "dbs";
 // 18 is quite far, no need more than that
 pub const ZOOM_LEVELS: u32 = 18;
 
+/// OSM `admin_level` of country borders
+pub const COUNTRY_ADMIN_LEVEL: u8 = 2;
+
+/// Latitude limit of the Web Mercator projection, the projected world is a square within it
+pub const MERCATOR_MAX_LAT: f64 = 85.051_128_779_806_59;
+
+/// Tile grid extent, latitudes match the range [crate::tiles::lat_lon_to_world] projects
 pub fn get_world_boundary() -> Rect {
     Rect::new(
         Coord {
             x: -180.0,
-            y: 89.0,
+            y: MERCATOR_MAX_LAT,
         },
         Coord {
             x: 180.0,
-            y: -75.0,
+            y: -MERCATOR_MAX_LAT,
         },
     )
 }
@@ -39,7 +48,11 @@ pub struct MapPointInfo {
 pub enum MapGeometry<T: CoordNum = f64> {
     Line(LineString<T>),
     Poly(Polygon<T>),
-    Coord(Coord<T>)
+    Coord(Coord<T>),
+    /// Polygons of one feature, e.g. a multipolygon relation with several outer rings
+    MultiPoly(MultiPolygon<T>),
+    /// Lines of one feature, e.g. parts of a line clipped by a tile
+    MultiLine(MultiLineString<T>),
 }
 
 impl<T> BoundingRect<T> for MapGeometry<T>
@@ -53,6 +66,8 @@ where
             MapGeometry::Line(line) => line.bounding_rect(),
             MapGeometry::Poly(poly) => poly.bounding_rect(),
             MapGeometry::Coord(coord) => Some(coord.bounding_rect()),
+            MapGeometry::MultiPoly(multi_poly) => multi_poly.bounding_rect(),
+            MapGeometry::MultiLine(multi_line) => multi_line.bounding_rect(),
         }
     }
 }
@@ -94,7 +109,15 @@ impl RTreeObject for MapGeometry {
         match self {
             MapGeometry::Line(line) => line.envelope(),
             MapGeometry::Poly(poly) => poly.envelope(),
-            MapGeometry::Coord(coord) => point!(x: coord.x, y: coord.y).envelope()
+            MapGeometry::Coord(coord) => point!(x: coord.x, y: coord.y).envelope(),
+            MapGeometry::MultiPoly(multi_poly) => match multi_poly.bounding_rect() {
+                Some(rect) => AABB::from_corners(rect.min().into(), rect.max().into()),
+                None => AABB::from_point(point!(x: 0.0, y: 0.0)),
+            },
+            MapGeometry::MultiLine(multi_line) => match multi_line.bounding_rect() {
+                Some(rect) => AABB::from_corners(rect.min().into(), rect.max().into()),
+                None => AABB::from_point(point!(x: 0.0, y: 0.0)),
+            },
         }
     }
 }
@@ -105,6 +128,14 @@ impl PointDistance for MapGeometry {
             MapGeometry::Line(line) => line.distance_2(point),
             MapGeometry::Poly(poly) => poly.exterior().distance_2(point),
             MapGeometry::Coord(coord) => {coord.distance_2(&coord! {x: point.x(), y: point.y()}) }
+            MapGeometry::MultiPoly(multi_poly) => multi_poly
+                .iter()
+                .map(|poly| poly.exterior().distance_2(point))
+                .fold(f64::INFINITY, f64::min),
+            MapGeometry::MultiLine(multi_line) => multi_line
+                .iter()
+                .map(|line| line.distance_2(point))
+                .fold(f64::INFINITY, f64::min),
         }
     }
 }
@@ -115,7 +146,48 @@ pub enum MapPointObjectKind {
     TrafficLight,
     Toilet,
     Parking,
-    TrainStation(bool),
+    TrainStation(StationKind),
+    /// POI without a dedicated kind
+    Generic,
+}
+
+/// Transport served by a `railway=station`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Ord, Eq, Hash, PartialOrd)]
+pub enum StationKind {
+    /// Heavy rail
+    Train,
+    Subway,
+    LightRail,
+    Tram,
+    /// Station without transport tags
+    Other,
+}
+
+impl StationKind {
+    /// Station kind from its `train`, `subway`, `light_rail`, `tram` and `station` tags.
+    /// Interchange stations take the first kind in the declaration order, heavy rail wins
+    pub fn from_tags<'a>(tags: impl IntoIterator<Item = (&'a str, &'a str)>) -> Self {
+        tags.into_iter()
+            .filter_map(|tag| match tag {
+                ("train", "yes") | ("station", "train") => Some(StationKind::Train),
+                ("subway", "yes") | ("station", "subway") => Some(StationKind::Subway),
+                ("light_rail", "yes") | ("station", "light_rail") => Some(StationKind::LightRail),
+                ("tram", "yes") | ("station", "tram") => Some(StationKind::Tram),
+                _ => None,
+            })
+            .min()
+            .unwrap_or(StationKind::Other)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StationKind::Train => "train",
+            StationKind::Subway => "subway",
+            StationKind::LightRail => "light_rail",
+            StationKind::Tram => "tram",
+            StationKind::Other => "other",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Hash, Eq)]
@@ -127,15 +199,18 @@ pub struct PopAreaInfo {
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Ord, Eq, Hash, PartialOrd)]
 pub enum MapGeomObjectKind {
     Nature(NatureKind),
-    Building(u16),
+    Building(BuildingInfo),
     Way(WayInfo),
-    AdminLine,
-    Poi(MapPointInfo)
+    /// Administrative boundary with OSM `admin_level`
+    AdminLine(u8),
+    Poi(MapPointInfo),
+    /// Tile boundary rectangle, emitted only for debugging clipping and seams
+    TileBounds,
 }
 
 impl MapGeomObjectKind {
     pub fn from_tag(k: &str, v: &str, way_info: Option<WayInfo>, name_en: Option<String>,
-                    levels: Option<u16>, is_train: bool) -> MapGeomObjectKind {
+                    building: Option<BuildingInfo>, station: StationKind) -> MapGeomObjectKind {
         match k {
             "highway" => {
                 if v == "traffic_signals" {
@@ -166,7 +241,7 @@ impl MapGeomObjectKind {
                 if v == "station" {
                     MapGeomObjectKind::Poi(MapPointInfo {
                         text: name_en.unwrap_or("".to_string()),
-                        kind: MapPointObjectKind::TrainStation(is_train),
+                        kind: MapPointObjectKind::TrainStation(station),
                     })
                 } else {
                     MapGeomObjectKind::Way(way_info.unwrap())
@@ -174,7 +249,7 @@ impl MapGeomObjectKind {
             },
             "water" => MapGeomObjectKind::Nature(NatureKind::Water),
             "leisure" => MapGeomObjectKind::Nature(NatureKind::Park),
-            "building" => MapGeomObjectKind::Building(levels.unwrap_or(0)),
+            "building" => MapGeomObjectKind::Building(building.unwrap_or_default()),
             "natural" | "landuse" => {
                 if v == "water" || v == "bay" {
                     MapGeomObjectKind::Nature(NatureKind::Water)
@@ -208,6 +283,57 @@ pub struct WayInfo {
     pub name_en: Option<String>
 }
 
+#[derive(Derivative, Debug, Clone, Default, Serialize, Deserialize)]
+#[derivative(PartialEq, Hash, Eq)]
+pub struct BuildingInfo {
+    pub levels: u16,
+    /// Meters, `levels * 3` if the height isn't tagged
+    #[derivative(PartialEq="ignore")]
+    #[derivative(Hash="ignore")]
+    pub height: f32,
+    pub housenumber: Option<String>,
+    pub street: Option<String>,
+}
+
+impl BuildingInfo {
+    pub const LEVEL_HEIGHT: f32 = 3.0;
+
+    pub fn new(levels: u16, height: Option<f32>, housenumber: Option<String>, street: Option<String>) -> Self {
+        BuildingInfo {
+            levels,
+            height: height.unwrap_or(levels as f32 * Self::LEVEL_HEIGHT),
+            housenumber,
+            street,
+        }
+    }
+
+    /// Parses OSM `height` value in meters, an optional `m` unit suffix is allowed
+    pub fn parse_height(value: &str) -> Option<f32> {
+        let value = value.trim();
+        let value = value.strip_suffix('m').unwrap_or(value).trim_end();
+        value
+            .parse::<f32>()
+            .ok()
+            .filter(|height| height.is_finite() && *height >= 0.0)
+    }
+}
+
+impl Ord for BuildingInfo {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.levels, &self.housenumber, &self.street).cmp(&(
+            other.levels,
+            &other.housenumber,
+            &other.street,
+        ))
+    }
+}
+
+impl PartialOrd for BuildingInfo {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl Ord for MapGeomObject {
     fn cmp(&self, other: &Self) -> Ordering {
         self.kind.cmp(&other.kind)
@@ -370,13 +496,44 @@ impl HighwayKind {
 pub enum RailwayKind {
     #[default]
     Rail,
+    Subway,
+    Tram,
+    LightRail,
+    Monorail,
 }
 
 impl RailwayKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Rail => "rail",
+            Self::Subway => "subway",
+            Self::Tram => "tram",
+            Self::LightRail => "light_rail",
+            Self::Monorail => "monorail",
+        }
+    }
+
+    pub fn from_descr(val: &str) -> Option<Self> {
+        match val {
+            "rail" => Some(Self::Rail),
+            "subway" => Some(Self::Subway),
+            "tram" => Some(Self::Tram),
+            "light_rail" => Some(Self::LightRail),
+            "monorail" => Some(Self::Monorail),
+            _ => None,
+        }
+    }
+
     pub fn get_layer(&self) -> u16 {
         match self {
             // should be above all roads withing a layer
             RailwayKind::Rail => 17,
+            RailwayKind::LightRail => 18,
+            RailwayKind::Subway => 19,
+            // trams run on the street, so they have to be drawn over the rest of railways
+            RailwayKind::Tram => 20,
+            // monorails are almost always elevated
+            RailwayKind::Monorail => 21,
         }
     }
 }
@@ -387,6 +544,13 @@ pub enum LineKind {
 }
 
 impl LineKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Highway { kind } => kind.as_str(),
+            Self::Railway { kind } => kind.as_str(),
+        }
+    }
+
     pub fn get_layer(&self) -> u16 {
         match self {
             Self::Highway { kind } => kind.get_layer(),
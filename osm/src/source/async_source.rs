@@ -0,0 +1,68 @@
+use crate::source::{TileSource, TileSourceFetchError};
+use error_stack::{Report, ResultExt};
+use std::future::Future;
+use std::sync::Arc;
+
+pub trait AsyncTileSource: Send + Sync + 'static {
+    fn fetch_async(
+        &self,
+        x: i32,
+        y: i32,
+        z: i32,
+    ) -> impl Future<Output = Result<Vec<u8>, Report<TileSourceFetchError>>> + Send;
+}
+
+/// Runs the blocking [TileSource::fetch] on the tokio blocking pool.
+/// Dropping the future doesn't interrupt the fetch, it runs to completion on the pool,
+/// so locks held by the source are always released normally.
+impl<S: TileSource + ?Sized> AsyncTileSource for Arc<S> {
+    async fn fetch_async(
+        &self,
+        x: i32,
+        y: i32,
+        z: i32,
+    ) -> Result<Vec<u8>, Report<TileSourceFetchError>> {
+        let source = self.clone();
+        tokio::task::spawn_blocking(move || source.fetch(x, y, z))
+            .await
+            .change_context(TileSourceFetchError::Internal)?
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::source::async_source::AsyncTileSource;
+    use crate::source::tiles_sqlite_store::TilesSQLiteStore;
+    use crate::source::TileSource;
+    use rusqlite::Connection;
+    use std::fs;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_fetch_async() {
+        let path = std::env::temp_dir().join(format!("osm_tiles_async_{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE tiles (x INTEGER NOT NULL, y INTEGER NOT NULL, z INTEGER NOT NULL, data BLOB);
+             INSERT INTO tiles VALUES (3, 5, 10, x'0102');",
+        )
+        .unwrap();
+        drop(conn);
+
+        let source: Arc<dyn TileSource> = Arc::new(TilesSQLiteStore::new(&path));
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            assert_eq!(source.fetch_async(3, 5, 10).await.unwrap(), vec![1, 2]);
+            assert!(source.fetch_async(3, 5, 11).await.is_err());
+            // a dropped request doesn't break following ones
+            drop(source.fetch_async(3, 5, 10));
+            assert_eq!(source.fetch_async(3, 5, 10).await.unwrap(), vec![1, 2]);
+        });
+
+        drop(source);
+        fs::remove_file(path).unwrap();
+    }
+}
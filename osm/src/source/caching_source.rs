@@ -0,0 +1,156 @@
+use crate::source::{TileSource, TileSourceFetchError};
+use crate::tiles::{TileGrid, TileKey};
+use error_stack::Report;
+use rustc_hash::FxHashMap;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// Keeps up to `capacity` fetched tiles of the inner source in memory,
+/// the least recently used tile is evicted first. Failed fetches are not cached.
+pub struct CachingTileSource<S: TileSource> {
+    inner: S,
+    capacity: usize,
+    cache: Mutex<LruCache>,
+}
+
+#[derive(Default)]
+struct LruCache {
+    tick: u64,
+    entries: FxHashMap<TileKey, (u64, Vec<u8>)>,
+    // last access tick to key, the first entry is the least recently used one
+    usage: BTreeMap<u64, TileKey>,
+}
+
+impl LruCache {
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn get(&mut self, key: &TileKey) -> Option<Vec<u8>> {
+        let tick = self.next_tick();
+        let (last_tick, data) = self.entries.get_mut(key)?;
+        self.usage.remove(last_tick);
+        self.usage.insert(tick, *key);
+        *last_tick = tick;
+        Some(data.clone())
+    }
+
+    fn insert(&mut self, key: TileKey, data: Vec<u8>, capacity: usize) {
+        let tick = self.next_tick();
+        if let Some((last_tick, _)) = self.entries.insert(key, (tick, data)) {
+            self.usage.remove(&last_tick);
+        }
+        self.usage.insert(tick, key);
+        while self.entries.len() > capacity {
+            let Some((_, evicted)) = self.usage.pop_first() else {
+                break;
+            };
+            self.entries.remove(&evicted);
+        }
+    }
+}
+
+impl<S: TileSource> CachingTileSource<S> {
+    pub fn new(inner: S, capacity: usize) -> Self {
+        Self {
+            inner,
+            capacity,
+            cache: Mutex::new(LruCache::default()),
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S: TileSource> TileSource for CachingTileSource<S> {
+    fn fetch(&self, x: i32, y: i32, z: i32) -> Result<Vec<u8>, Report<TileSourceFetchError>> {
+        let key = TileKey::new(x, y, z);
+        if let Some(data) = self.cache.lock().expect("Expect lock").get(&key) {
+            return Ok(data);
+        }
+        // the lock isn't held while fetching, concurrent misses of the same tile
+        // may fetch it twice
+        let data = self.inner.fetch(x, y, z)?;
+        if self.capacity > 0 {
+            self.cache
+                .lock()
+                .expect("Expect lock")
+                .insert(key, data.clone(), self.capacity);
+        }
+        Ok(data)
+    }
+
+    fn contains(&self, x: i32, y: i32, z: i32) -> bool {
+        self.cache
+            .lock()
+            .expect("Expect lock")
+            .entries
+            .contains_key(&TileKey::new(x, y, z))
+            || self.inner.contains(x, y, z)
+    }
+
+    fn list_zooms(&self) -> Vec<i32> {
+        self.inner.list_zooms()
+    }
+
+    fn grid(&self) -> TileGrid {
+        self.inner.grid()
+    }
+
+    fn tile_count(&self) -> Result<Option<u64>, Report<TileSourceFetchError>> {
+        self.inner.tile_count()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::source::caching_source::CachingTileSource;
+    use crate::source::{TileSource, TileSourceFetchError};
+    use error_stack::Report;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct CountingTileSource {
+        fetches: AtomicUsize,
+    }
+
+    impl TileSource for CountingTileSource {
+        fn fetch(&self, x: i32, y: i32, z: i32) -> Result<Vec<u8>, Report<TileSourceFetchError>> {
+            self.fetches.fetch_add(1, Ordering::SeqCst);
+            if z < 0 {
+                return Err(TileSourceFetchError::Internal.into());
+            }
+            Ok(vec![x as u8, y as u8, z as u8])
+        }
+    }
+
+    #[test]
+    fn test_caching_tile_source() {
+        let source = CachingTileSource::new(CountingTileSource::default(), 2);
+        let fetches = |source: &CachingTileSource<CountingTileSource>| {
+            source.inner().fetches.load(Ordering::SeqCst)
+        };
+
+        assert_eq!(source.fetch(1, 2, 3).unwrap(), vec![1, 2, 3]);
+        assert_eq!(source.fetch(1, 2, 3).unwrap(), vec![1, 2, 3]);
+        assert_eq!(fetches(&source), 1);
+
+        // (1, 2, 3) is used more recently than (4, 5, 6) and survives the eviction
+        source.fetch(4, 5, 6).unwrap();
+        source.fetch(1, 2, 3).unwrap();
+        source.fetch(7, 8, 9).unwrap();
+        assert_eq!(fetches(&source), 3);
+        source.fetch(1, 2, 3).unwrap();
+        assert_eq!(fetches(&source), 3);
+        source.fetch(4, 5, 6).unwrap();
+        assert_eq!(fetches(&source), 4);
+
+        // errors aren't cached
+        assert!(source.fetch(0, 0, -1).is_err());
+        assert!(source.fetch(0, 0, -1).is_err());
+        assert_eq!(fetches(&source), 6);
+    }
+}
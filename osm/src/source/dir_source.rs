@@ -0,0 +1,101 @@
+use crate::source::{TileSource, TileSourceFetchError};
+use error_stack::Report;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// Reads tiles from `{root}/{z}/{x}/{y}` files, optionally with an extension like `{y}.pbf`
+pub struct DirTileSource {
+    root: PathBuf,
+    extension: Option<String>,
+}
+
+impl DirTileSource {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            extension: None,
+        }
+    }
+
+    pub fn with_extension(mut self, extension: &str) -> Self {
+        self.extension = Some(extension.trim_start_matches('.').to_string());
+        self
+    }
+
+    /// Only non-negative coordinates map to a file, so a path never leaves `root`
+    fn tile_path(&self, x: i32, y: i32, z: i32) -> Option<PathBuf> {
+        if x < 0 || y < 0 || z < 0 {
+            return None;
+        }
+        let file_name = match &self.extension {
+            Some(extension) => format!("{y}.{extension}"),
+            None => y.to_string(),
+        };
+        Some(self.root.join(z.to_string()).join(x.to_string()).join(file_name))
+    }
+}
+
+impl TileSource for DirTileSource {
+    fn fetch(&self, x: i32, y: i32, z: i32) -> Result<Vec<u8>, Report<TileSourceFetchError>> {
+        let path = self
+            .tile_path(x, y, z)
+            .ok_or(TileSourceFetchError::MissingData)?;
+        std::fs::read(&path).map_err(|err| {
+            let context = if err.kind() == ErrorKind::NotFound {
+                TileSourceFetchError::MissingData
+            } else {
+                TileSourceFetchError::Internal
+            };
+            Report::new(err)
+                .change_context(context)
+                .attach_printable(path.display().to_string())
+        })
+    }
+
+    fn contains(&self, x: i32, y: i32, z: i32) -> bool {
+        self.tile_path(x, y, z).is_some_and(|path| path.is_file())
+    }
+
+    fn list_zooms(&self) -> Vec<i32> {
+        let Ok(entries) = std::fs::read_dir(&self.root) else {
+            return vec![];
+        };
+        let mut zooms = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| entry.file_name().to_str()?.parse::<i32>().ok())
+            .filter(|zoom| *zoom >= 0)
+            .collect::<Vec<_>>();
+        zooms.sort();
+        zooms
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::source::dir_source::DirTileSource;
+    use crate::source::{TileSource, TileSourceFetchError};
+    use std::fs;
+
+    #[test]
+    fn test_dir_tile_source() {
+        let root = std::env::temp_dir().join(format!("osm_tiles_dir_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("10").join("3")).unwrap();
+        fs::write(root.join("10").join("3").join("5.pbf"), [1, 2, 3]).unwrap();
+
+        let source = DirTileSource::new(&root).with_extension("pbf");
+        assert_eq!(source.fetch(3, 5, 10).unwrap(), vec![1, 2, 3]);
+        assert!(source.contains(3, 5, 10));
+        assert_eq!(source.list_zooms(), vec![10]);
+
+        for (x, y, z) in [(3, 6, 10), (-3, 5, 10), (3, 5, -10), (i32::MAX, 5, 10)] {
+            let err = source.fetch(x, y, z).unwrap_err();
+            assert!(matches!(err.current_context(), TileSourceFetchError::MissingData));
+            assert!(!source.contains(x, y, z));
+        }
+        assert!(!DirTileSource::new(&root).contains(3, 5, 10));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,85 @@
+use crate::source::{TileSource, TileSourceFetchError};
+use crate::tiles::TileGrid;
+use error_stack::Report;
+use itertools::Itertools;
+
+/// Fetches from `fallback` when `primary` fails, e.g. a partial local db with the rest
+/// proxied from the upstream server. When both fail the primary error is returned.
+pub struct FallbackTileSource<P: TileSource, F: TileSource> {
+    pub primary: P,
+    pub fallback: F,
+}
+
+impl<P: TileSource, F: TileSource> FallbackTileSource<P, F> {
+    pub fn new(primary: P, fallback: F) -> Self {
+        Self { primary, fallback }
+    }
+}
+
+impl<P: TileSource, F: TileSource> TileSource for FallbackTileSource<P, F> {
+    fn fetch(&self, x: i32, y: i32, z: i32) -> Result<Vec<u8>, Report<TileSourceFetchError>> {
+        self.primary
+            .fetch(x, y, z)
+            .or_else(|err| self.fallback.fetch(x, y, z).map_err(|_| err))
+    }
+
+    fn contains(&self, x: i32, y: i32, z: i32) -> bool {
+        self.primary.contains(x, y, z) || self.fallback.contains(x, y, z)
+    }
+
+    fn list_zooms(&self) -> Vec<i32> {
+        self.primary
+            .list_zooms()
+            .into_iter()
+            .chain(self.fallback.list_zooms())
+            .sorted()
+            .dedup()
+            .collect()
+    }
+
+    /// Both sources are expected to share the tile grid
+    fn grid(&self) -> TileGrid {
+        self.primary.grid()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::source::fallback_source::FallbackTileSource;
+    use crate::source::{TileSource, TileSourceFetchError};
+    use error_stack::Report;
+
+    struct FixedTileSource(Option<Vec<u8>>, &'static str);
+
+    impl TileSource for FixedTileSource {
+        fn fetch(&self, _: i32, _: i32, _: i32) -> Result<Vec<u8>, Report<TileSourceFetchError>> {
+            self.0
+                .clone()
+                .ok_or_else(|| Report::new(TileSourceFetchError::Internal).attach_printable(self.1))
+        }
+    }
+
+    #[test]
+    fn test_fallback_tile_source() {
+        let source = FallbackTileSource::new(
+            FixedTileSource(None, "primary"),
+            FixedTileSource(Some(vec![1]), "fallback"),
+        );
+        assert_eq!(source.fetch(1, 2, 3).unwrap(), vec![1]);
+        assert!(source.contains(1, 2, 3));
+
+        let source = FallbackTileSource::new(
+            FixedTileSource(Some(vec![2]), "primary"),
+            FixedTileSource(Some(vec![1]), "fallback"),
+        );
+        assert_eq!(source.fetch(1, 2, 3).unwrap(), vec![2]);
+
+        let source = FallbackTileSource::new(
+            FixedTileSource(None, "primary"),
+            FixedTileSource(None, "fallback"),
+        );
+        let err = format!("{:?}", source.fetch(1, 2, 3).unwrap_err());
+        assert!(err.contains("primary"));
+        assert!(!err.contains("fallback"));
+    }
+}
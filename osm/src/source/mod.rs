@@ -1,24 +1,79 @@
+#[cfg(feature = "async")]
+pub mod async_source;
+pub mod caching_source;
+pub mod dir_source;
+pub mod fallback_source;
 pub mod reqwest_source;
 pub mod tiles_sqlite_store;
 
 use crate::source::reqwest_source::ReqwestSource;
-use crate::source::tiles_sqlite_store::TilesSQLiteStore;
+use crate::source::tiles_sqlite_store::{TilesSQLiteStore, TilesSQLiteStoreError};
+use crate::tiles::TileGrid;
 use error_stack::{Report, ResultExt};
 use thiserror::Error;
 
 pub trait TileSource: Send + Sync + 'static {
     fn fetch(&self, x: i32, y: i32, z: i32) -> Result<Vec<u8>, Report<TileSourceFetchError>>;
+
+    /// Whether the tile exists, the default implementation fetches it
+    fn contains(&self, x: i32, y: i32, z: i32) -> bool {
+        self.fetch(x, y, z).is_ok()
+    }
+
+    /// Zoom levels having at least one tile, sources that can't enumerate tiles
+    /// report every zoom level of the default [TileGrid]
+    fn list_zooms(&self) -> Vec<i32> {
+        TileGrid::default()
+            .zoom_levels()
+            .map(|zoom_level| zoom_level as i32)
+            .collect()
+    }
+
+    /// Tile grid of the source tiles, sources without metadata use the default [TileGrid]
+    fn grid(&self) -> TileGrid {
+        TileGrid::default()
+    }
+
+    /// Amount of stored tiles, `None` for sources that can't count their tiles
+    fn tile_count(&self) -> Result<Option<u64>, Report<TileSourceFetchError>> {
+        Ok(None)
+    }
 }
 
 #[derive(Debug, Error)]
 pub enum TileSourceFetchError {
     #[error("Internal")]
     Internal,
+    #[error("MissingData")]
+    MissingData,
 }
 
 impl TileSource for TilesSQLiteStore {
     fn fetch(&self, x: i32, y: i32, z: i32) -> Result<Vec<u8>, Report<TileSourceFetchError>> {
-        self.get_tile(x, y, z)
+        self.get_tile(x, y, z).map_err(|err| {
+            let context = match err.current_context() {
+                TilesSQLiteStoreError::MissingData => TileSourceFetchError::MissingData,
+                _ => TileSourceFetchError::Internal,
+            };
+            err.change_context(context)
+        })
+    }
+
+    fn contains(&self, x: i32, y: i32, z: i32) -> bool {
+        self.has_tile(x, y, z).unwrap_or(false)
+    }
+
+    fn list_zooms(&self) -> Vec<i32> {
+        self.zoom_levels().unwrap_or_default()
+    }
+
+    fn grid(&self) -> TileGrid {
+        TilesSQLiteStore::grid(self)
+    }
+
+    fn tile_count(&self) -> Result<Option<u64>, Report<TileSourceFetchError>> {
+        self.count_tiles()
+            .map(Some)
             .change_context(TileSourceFetchError::Internal)
     }
 }
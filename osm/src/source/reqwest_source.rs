@@ -1,30 +1,134 @@
 use crate::styles::style_loader::StylesFetchError;
 use crate::styles::Style;
+use crate::tiles::codec::{gzip_blob, raw_blob};
 use error_stack::{Report, ResultExt};
 use log::error;
-use std::time::SystemTime;
+use reqwest::Url;
+use reqwest::blocking::Response;
+use reqwest::{header, StatusCode};
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+
+const DEFAULT_BASE_URL: &str = "http://ec2-3-107-91-243.ap-southeast-2.compute.amazonaws.com:3000";
+/// Overrides the default tiles server base URL for [ReqwestSource::new]
+const BASE_URL_ENV: &str = "SHASHLIK_TILES_URL";
+const DEFAULT_MAX_RETRIES: u32 = 2;
+/// Delay before the first retry, doubled for every next one
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(100);
 
 pub struct ReqwestSource {
     client: reqwest::blocking::Client,
+    base_url: String,
+    max_retries: u32,
+}
+
+#[derive(Debug, Error)]
+pub enum ReqwestSourceError {
+    #[error("Invalid base URL {0}")]
+    BaseUrl(String),
 }
 
 impl ReqwestSource {
     pub fn new() -> ReqwestSource {
-        ReqwestSource {
+        std::env::var(BASE_URL_ENV)
+            .ok()
+            .and_then(|base_url| {
+                Self::new_with_base(base_url)
+                    .inspect_err(|err| error!("Ignoring {}: {:?}", BASE_URL_ENV, err))
+                    .ok()
+            })
+            .unwrap_or_else(|| {
+                Self::new_with_base(DEFAULT_BASE_URL.to_string()).expect("Valid default URL")
+            })
+    }
+
+    /// `base_url` is an http(s) URL the `/tile/{x}/{y}/{z}` and `/styles_v0.json` paths are appended to
+    pub fn new_with_base(base_url: String) -> Result<ReqwestSource, Report<ReqwestSourceError>> {
+        Self::new_with_retry(base_url, DEFAULT_MAX_RETRIES)
+    }
+
+    /// Requests failing with connection, timeout or 5xx gateway errors are repeated
+    /// up to `max_retries` times with exponential backoff
+    pub fn new_with_retry(
+        base_url: String,
+        max_retries: u32,
+    ) -> Result<ReqwestSource, Report<ReqwestSourceError>> {
+        let url = Url::parse(&base_url).change_context(ReqwestSourceError::BaseUrl(base_url.clone()))?;
+        if !matches!(url.scheme(), "http" | "https") || !url.has_host() {
+            return Err(ReqwestSourceError::BaseUrl(base_url).into());
+        }
+        Ok(ReqwestSource {
             client: reqwest::blocking::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            max_retries,
+        })
+    }
+
+    fn is_transient(err: &reqwest::Error) -> bool {
+        err.is_connect()
+            || err.is_timeout()
+            || err.is_request()
+            || err.status().is_some_and(|status| {
+                matches!(
+                    status,
+                    StatusCode::BAD_GATEWAY
+                        | StatusCode::SERVICE_UNAVAILABLE
+                        | StatusCode::GATEWAY_TIMEOUT
+                )
+            })
+    }
+
+    fn send_with_retry(
+        &self,
+        url: &str,
+        accept_encoding: Option<&str>,
+    ) -> Result<Response, Report<reqwest::Error>> {
+        let mut delay = INITIAL_RETRY_DELAY;
+        let mut attempt = 0;
+        loop {
+            let mut request = self.client.get(url);
+            if let Some(accept_encoding) = accept_encoding {
+                request = request.header(header::ACCEPT_ENCODING, accept_encoding);
+            }
+            match request
+                .send()
+                .and_then(|response| response.error_for_status())
+            {
+                Ok(response) => return Ok(response),
+                Err(err) if attempt < self.max_retries && Self::is_transient(&err) => {
+                    error!("Request {} failed, retrying in {:?}: {}", url, delay, err);
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                    attempt += 1;
+                }
+                Err(err) => {
+                    return Err(Report::new(err)
+                        .attach_printable(format!("{} failed after {} attempts", url, attempt + 1)))
+                }
+            }
         }
     }
+
+    pub fn tile_url(&self, x: i32, y: i32, z: i32) -> String {
+        format!("{}/tile/{x}/{y}/{z}", self.base_url)
+    }
+
+    pub fn styles_url(&self) -> String {
+        format!("{}/styles_v0.json", self.base_url)
+    }
+
+    /// Tile blob as stored in tiles dbs. The server sends the bare gzip stream
+    /// with `Content-Encoding: gzip` or decompressed data, both are wrapped into a blob header
     pub fn get_tile(&self, x: i32, y: i32, z: i32) -> Result<Vec<u8>, Report<reqwest::Error>> {
         let t1 = SystemTime::now();
-        // TODO Configurable URL
-        let response = self
-            .client
-            .get(format!(
-            "http://ec2-3-107-91-243.ap-southeast-2.compute.amazonaws.com:3000/tile/{x}/{y}/{z}"
-        ))
-            .send();
+        let response = self.send_with_retry(&self.tile_url(x, y, z), Some("gzip"))?;
+        let gzip = response
+            .headers()
+            .get(header::CONTENT_ENCODING)
+            .is_some_and(|value| value.as_bytes().eq_ignore_ascii_case(b"gzip"));
         let td = SystemTime::now();
-        let bytes = response.and_then(|response| response.bytes())?.to_vec();
+        let body = response.bytes()?;
+        let bytes = if gzip { gzip_blob(&body) } else { raw_blob(&body) };
         let t2 = SystemTime::now();
         error!(
             "get_tile, x = {}, y = {}, z = {}, total_time = {:?}, download_time = {:?}, len = {}",
@@ -39,7 +143,7 @@ impl ReqwestSource {
     }
 
     pub fn styles(&self) -> Result<Vec<Style>, Report<StylesFetchError>> {
-        let response = self.client.get("http://ec2-3-107-91-243.ap-southeast-2.compute.amazonaws.com:3000/styles_v0.json".to_string()).send();
+        let response = self.send_with_retry(&self.styles_url(), None);
         response
             .change_context(StylesFetchError::Internal)
             .and_then(|response| {
@@ -53,3 +157,75 @@ impl ReqwestSource {
             })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::source::reqwest_source::ReqwestSource;
+    use crate::tiles::codec::{decompress_tile, gzip_stream, TileCodec};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_base_url() {
+        let source = ReqwestSource::new_with_base("https://tiles.example.com:8080/".to_string()).unwrap();
+        assert_eq!(source.tile_url(1, 2, 3), "https://tiles.example.com:8080/tile/1/2/3");
+        assert_eq!(source.styles_url(), "https://tiles.example.com:8080/styles_v0.json");
+
+        assert!(ReqwestSource::new_with_base("tiles.example.com".to_string()).is_err());
+        assert!(ReqwestSource::new_with_base("ftp://tiles.example.com".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_retry() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            for attempt in 0..3 {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request).unwrap();
+                // first attempts are dropped without a response
+                if attempt == 2 {
+                    stream
+                        .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 3\r\nConnection: close\r\n\r\nabc")
+                        .unwrap();
+                }
+            }
+        });
+
+        let source = ReqwestSource::new_with_retry(base_url.clone(), 2).unwrap();
+        assert_eq!(decompress_tile(&source.get_tile(1, 2, 3).unwrap()).unwrap(), b"abc".to_vec());
+        server.join().unwrap();
+
+        let source = ReqwestSource::new_with_retry(base_url, 1).unwrap();
+        let err = format!("{:?}", source.get_tile(1, 2, 3).unwrap_err());
+        assert!(err.contains("failed after 2 attempts"));
+    }
+
+    #[test]
+    fn test_gzip_tile() {
+        let blob = TileCodec::Gzip.compress(b"tile data").unwrap();
+        let stream = gzip_stream(&blob).unwrap().to_vec();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream_conn, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let len = stream_conn.read(&mut request).unwrap();
+            let request = String::from_utf8_lossy(&request[..len]).to_lowercase();
+            assert!(request.contains("accept-encoding: gzip"));
+            let head = format!(
+                "HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                stream.len()
+            );
+            stream_conn.write_all(head.as_bytes()).unwrap();
+            stream_conn.write_all(&stream).unwrap();
+        });
+
+        let source = ReqwestSource::new_with_retry(base_url, 0).unwrap();
+        let tile = source.get_tile(1, 2, 3).unwrap();
+        server.join().unwrap();
+        assert_eq!(tile, blob);
+        assert_eq!(decompress_tile(&tile).unwrap(), b"tile data".to_vec());
+    }
+}
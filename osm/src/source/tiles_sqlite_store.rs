@@ -1,11 +1,30 @@
 use error_stack::{Report, ResultExt};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use geo::{coord, Rect};
 use rusqlite::{named_params, Connection, OpenFlags};
+use crate::tiles::codec::{decompress_tile, join_features, TileCodec, GEOMETRY_STORE_DEDUP};
+use crate::tiles::{world_bounds_metadata, TileGrid, TileKey, TileScheme};
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
 use std::path::Path;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
 use thiserror::Error;
 
 pub struct TilesSQLiteStore {
-    db_conn: Mutex<Connection>,
+    db_conns: Vec<Mutex<Connection>>,
+    next_conn: AtomicUsize,
+    scheme: TileScheme,
+    grid: TileGrid,
+    /// Tile blobs hold `geom` ids and are assembled and compressed with `codec` on read
+    dedup_geometries: bool,
+    codec: TileCodec,
+    /// Tiles are gzip Mapbox Vector Tiles instead of bincode collections
+    mvt_tiles: bool,
+    /// Missing tiles inside the covered rects at the covered zoom levels are empty
+    covered_rects: Vec<Rect>,
+    covered_zooms: RangeInclusive<i32>,
 }
 #[derive(Debug, Error)]
 pub enum TilesSQLiteStoreError {
@@ -13,18 +32,106 @@ pub enum TilesSQLiteStoreError {
     SqliteError,
     #[error("MissingData")]
     MissingData,
+    #[error("MalformedData")]
+    MalformedData,
+    #[error("Tiles db was built for another world boundary, rebuild it")]
+    WorldBoundary,
 }
 
 impl TilesSQLiteStore {
+    pub const DEFAULT_DB_PATH: &'static str = "./dbs/tiles.db";
     const TILE_QUERY: &'static str = "SELECT data FROM tiles WHERE x=:x AND y=:y AND z=:z;";
+    const METADATA_QUERY: &'static str = "SELECT name, value FROM metadata;";
+    const GEOM_QUERY: &'static str = "SELECT data FROM geom WHERE id=?1;";
+    const HAS_TILE_QUERY: &'static str = "SELECT 1 FROM tiles WHERE x=:x AND y=:y AND z=:z LIMIT 1;";
+    const ZOOM_LEVELS_QUERY: &'static str = "SELECT DISTINCT z FROM tiles ORDER BY z;";
+    const TILE_COUNT_QUERY: &'static str = "SELECT count(*) FROM tiles;";
     pub fn new<P: AsRef<Path>>(path: P) -> TilesSQLiteStore {
-        Self {
-            db_conn: Mutex::new(Self::create_tiles_db_connection(path)),
+        Self::new_pooled(path, 1)
+    }
+
+    /// Opens `size` read-only connections so concurrent requests don't wait for each other,
+    /// panics for incompatible dbs, see [TilesSQLiteStore::open_pooled]
+    pub fn new_pooled<P: AsRef<Path>>(path: P, size: usize) -> TilesSQLiteStore {
+        Self::open_pooled(path, size).expect("Incompatible tiles db")
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<TilesSQLiteStore, Report<TilesSQLiteStoreError>> {
+        Self::open_pooled(path, 1)
+    }
+
+    /// Same as [TilesSQLiteStore::new_pooled], fails for dbs built for another world boundary
+    pub fn open_pooled<P: AsRef<Path>>(
+        path: P,
+        size: usize,
+    ) -> Result<TilesSQLiteStore, Report<TilesSQLiteStoreError>> {
+        let mut store = Self {
+            db_conns: (0..size.max(1))
+                .map(|_| Mutex::new(Self::create_tiles_db_connection(&path)))
+                .collect(),
+            next_conn: AtomicUsize::new(0),
+            scheme: TileScheme::default(),
+            grid: TileGrid::default(),
+            dedup_geometries: false,
+            codec: TileCodec::default(),
+            mvt_tiles: false,
+            covered_rects: vec![],
+            covered_zooms: RangeInclusive::new(1, 0),
+        };
+        // dbs without metadata are always in the internal tiles grid with inline geometries
+        let metadata = store.metadata().unwrap_or_default();
+        // built dbs from before the world boundary was recorded have another one
+        if metadata.contains_key("generated_at")
+            && metadata.get("world_bounds") != Some(&world_bounds_metadata())
+        {
+            return Err(Report::new(TilesSQLiteStoreError::WorldBoundary));
+        }
+        store.scheme = metadata
+            .get("scheme")
+            .and_then(|name| TileScheme::from_name(name))
+            .unwrap_or_default();
+        if let Some(tiles_count) = metadata.get("tiles_count").and_then(|value| value.parse().ok()) {
+            store.grid.tiles_count = tiles_count;
+        }
+        store.dedup_geometries =
+            metadata.get("geometry_store").map(String::as_str) == Some(GEOMETRY_STORE_DEDUP);
+        store.codec = metadata
+            .get("compression")
+            .and_then(|name| TileCodec::from_name(name))
+            .unwrap_or_default();
+        store.mvt_tiles = metadata.get("format").map(String::as_str) == Some("pbf");
+        if let Some((min_zoom, max_zoom)) = metadata
+            .get("coverage_zooms")
+            .and_then(|zooms| zooms.split_once(','))
+            .and_then(|(min, max)| Some((min.parse().ok()?, max.parse().ok()?)))
+        {
+            store.covered_rects = store.coverage().unwrap_or_default();
+            store.covered_zooms = min_zoom..=max_zoom;
         }
+        Ok(store)
+    }
+
+    /// Row addressing of the stored tiles, `get_tile` expects rows in this scheme
+    pub fn scheme(&self) -> TileScheme {
+        self.scheme
+    }
+
+    /// Tile grid the db was built with, tile keys and boundaries have to be calculated with it
+    pub fn grid(&self) -> TileGrid {
+        self.grid
     }
 
     pub fn new_default_db() -> TilesSQLiteStore {
-        Self::new("./dbs/tiles.db")
+        Self::new(Self::DEFAULT_DB_PATH)
+    }
+
+    /// A free connection if there is one, otherwise waits for the next one in round-robin order
+    fn db_conn(&self) -> MutexGuard<'_, Connection> {
+        let start = self.next_conn.fetch_add(1, Ordering::Relaxed);
+        let len = self.db_conns.len();
+        (0..len)
+            .find_map(|i| self.db_conns[(start + i) % len].try_lock().ok())
+            .unwrap_or_else(|| self.db_conns[start % len].lock().expect("Expect lock"))
     }
 
     fn create_tiles_db_connection<P: AsRef<Path>>(path: P) -> Connection {
@@ -46,11 +153,155 @@ impl TilesSQLiteStore {
         let tile_data = self
             .get_tile_internal(x, y, z)
             .change_context(TilesSQLiteStoreError::SqliteError)?;
-        tile_data.ok_or(TilesSQLiteStoreError::MissingData.into())
+        let Some(tile_data) = tile_data else {
+            return if self.is_covered(x, y, z) {
+                self.empty_tile()
+            } else {
+                Err(Report::new(TilesSQLiteStoreError::MissingData))
+            };
+        };
+        if self.dedup_geometries {
+            self.join_geometries(&tile_data)
+        } else {
+            Ok(tile_data)
+        }
+    }
+
+    /// Whether the row key is inside the [TilesSQLiteStore::coverage] at a covered zoom level
+    fn is_covered(&self, x: i32, y: i32, z: i32) -> bool {
+        if !self.covered_zooms.contains(&z) {
+            return false;
+        }
+        let key = self.grid.flip_y(&TileKey::new(x, y, z), self.scheme);
+        let (Ok(tile_x), Ok(tile_y)) = (u32::try_from(key.tile_x), u32::try_from(key.tile_y)) else {
+            return false;
+        };
+        self.covered_rects.iter().any(|rect| {
+            let ranges = self.grid.tile_ranges(z, rect);
+            (ranges.min_x..=ranges.max_x).contains(&tile_x)
+                && (ranges.min_y..=ranges.max_y).contains(&tile_y)
+        })
+    }
+
+    /// Blob of a covered tile without features, covered tiles aren't stored
+    fn empty_tile(&self) -> Result<Vec<u8>, Report<TilesSQLiteStoreError>> {
+        if self.mvt_tiles {
+            GzEncoder::new(Vec::new(), Compression::default()).finish()
+        } else {
+            self.codec.compress(&join_features(&[]))
+        }
+        .change_context(TilesSQLiteStoreError::MalformedData)
+    }
+
+    /// Replaces `geom` ids of the tile blob with the geometries
+    fn join_geometries(&self, tile_data: &[u8]) -> Result<Vec<u8>, Report<TilesSQLiteStoreError>> {
+        let ids: Vec<i64> = decompress_tile(tile_data)
+            .change_context(TilesSQLiteStoreError::MalformedData)
+            .and_then(|data| {
+                bincode::deserialize(&data).change_context(TilesSQLiteStoreError::MalformedData)
+            })?;
+        let features = self
+            .geometries_internal(&ids)
+            .change_context(TilesSQLiteStoreError::SqliteError)?;
+        self.codec
+            .compress(&join_features(&features))
+            .change_context(TilesSQLiteStoreError::MalformedData)
+    }
+
+    fn geometries_internal(&self, ids: &[i64]) -> rusqlite::Result<Vec<Vec<u8>>> {
+        let conn = self.db_conn();
+        let mut stmt = conn.prepare_cached(Self::GEOM_QUERY)?;
+        ids.iter()
+            .map(|id| stmt.query_row([id], |row| row.get(0)))
+            .collect()
+    }
+
+    /// Whether the tile is stored or is an empty tile inside the coverage
+    pub fn has_tile(&self, x: i32, y: i32, z: i32) -> Result<bool, Report<TilesSQLiteStoreError>> {
+        if self.is_covered(x, y, z) {
+            return Ok(true);
+        }
+        self.has_tile_internal(x, y, z)
+            .change_context(TilesSQLiteStoreError::SqliteError)
+    }
+
+    /// Distinct zoom levels of stored tiles in ascending order
+    pub fn zoom_levels(&self) -> Result<Vec<i32>, Report<TilesSQLiteStoreError>> {
+        self.zoom_levels_internal()
+            .change_context(TilesSQLiteStoreError::SqliteError)
+    }
+
+    /// Amount of stored tiles, scans the whole tiles table
+    pub fn count_tiles(&self) -> Result<u64, Report<TilesSQLiteStoreError>> {
+        self.count_tiles_internal()
+            .change_context(TilesSQLiteStoreError::SqliteError)
+    }
+
+    fn count_tiles_internal(&self) -> rusqlite::Result<u64> {
+        self.db_conn()
+            .prepare(Self::TILE_COUNT_QUERY)?
+            .query_row((), |row| row.get(0))
+    }
+
+    fn has_tile_internal(&self, x: i32, y: i32, z: i32) -> rusqlite::Result<bool> {
+        self.db_conn()
+            .prepare(Self::HAS_TILE_QUERY)?
+            .exists(named_params! {":x": x, ":y": y, ":z": z})
+    }
+
+    fn zoom_levels_internal(&self) -> rusqlite::Result<Vec<i32>> {
+        self.db_conn()
+            .prepare(Self::ZOOM_LEVELS_QUERY)?
+            .query_map((), |row| row.get(0))?
+            .collect()
+    }
+
+    /// Name/value pairs like `minzoom`, `maxzoom`, `bounds` and `format` written with tiles
+    pub fn metadata(&self) -> Result<HashMap<String, String>, Report<TilesSQLiteStoreError>> {
+        self.metadata_internal()
+            .change_context(TilesSQLiteStoreError::SqliteError)
+    }
+
+    /// Areas the db was built for, tiles inside them without features aren't stored
+    /// and are read as empty tiles. Empty for dbs built without coverage
+    pub fn coverage(&self) -> Result<Vec<Rect>, Report<TilesSQLiteStoreError>> {
+        let metadata = self.metadata()?;
+        let Some(coverage) = metadata.get("coverage") else {
+            return Ok(vec![]);
+        };
+        coverage
+            .split(';')
+            .map(|rect| {
+                let values = rect
+                    .split(',')
+                    .map(str::parse::<f64>)
+                    .collect::<Result<Vec<_>, _>>()
+                    .change_context(TilesSQLiteStoreError::MalformedData)?;
+                match values[..] {
+                    [left, bottom, right, top] => Ok(Rect::new(
+                        coord! {x: left, y: bottom},
+                        coord! {x: right, y: top},
+                    )),
+                    _ => Err(Report::new(TilesSQLiteStoreError::MalformedData)),
+                }
+            })
+            .collect()
+    }
+
+    /// Zoom levels the [TilesSQLiteStore::coverage] applies to, empty for dbs built without coverage
+    pub fn covered_zooms(&self) -> RangeInclusive<i32> {
+        self.covered_zooms.clone()
+    }
+
+    fn metadata_internal(&self) -> rusqlite::Result<HashMap<String, String>> {
+        self.db_conn()
+            .prepare(Self::METADATA_QUERY)?
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect()
     }
 
     fn get_tile_internal(&self, x: i32, y: i32, z: i32) -> rusqlite::Result<Option<Vec<u8>>> {
-        self.db_conn.lock().expect("Expect lock").prepare(Self::TILE_QUERY)?.query_and_then(
+        self.db_conn().prepare(Self::TILE_QUERY)?.query_and_then(
             named_params! {
                     ":x": x.to_string().as_str(),
                     ":y": y.to_string().as_str(),
@@ -62,3 +313,125 @@ impl TilesSQLiteStore {
             .map_or(Ok(None), |data| data.map(|data| Some(data)))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::source::tiles_sqlite_store::{TilesSQLiteStore, TilesSQLiteStoreError};
+    use crate::source::TileSource;
+    use crate::tiles::{world_bounds_metadata, TileKey, TileScheme};
+    use rusqlite::Connection;
+    use std::fs;
+
+    #[test]
+    fn test_contains() {
+        let path = std::env::temp_dir().join(format!("osm_tiles_contains_{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE tiles (x INTEGER NOT NULL, y INTEGER NOT NULL, z INTEGER NOT NULL, data BLOB);
+             INSERT INTO tiles VALUES (3, 5, 10, x'00');
+             INSERT INTO tiles VALUES (1, 2, 0, x'01');
+             INSERT INTO tiles VALUES (4, 5, 10, x'02');",
+        )
+        .unwrap();
+        drop(conn);
+
+        let store = TilesSQLiteStore::new(&path);
+        assert!(store.contains(3, 5, 10));
+        assert!(store.contains(1, 2, 0));
+        assert!(!store.contains(5, 3, 10));
+        assert!(!store.contains(3, 5, 11));
+        assert_eq!(store.list_zooms(), vec![0, 10]);
+
+        drop(store);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_grid_from_metadata() {
+        let path = std::env::temp_dir().join(format!("osm_tiles_grid_{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE tiles (x INTEGER NOT NULL, y INTEGER NOT NULL, z INTEGER NOT NULL, data BLOB);
+             CREATE TABLE metadata (name TEXT NOT NULL, value TEXT NOT NULL);
+             INSERT INTO metadata VALUES ('tiles_count', '64'), ('scheme', 'xyz');",
+        )
+        .unwrap();
+        drop(conn);
+
+        let store = TilesSQLiteStore::new(&path);
+        assert_eq!(store.grid().tiles_count, 64);
+        assert_eq!(store.scheme(), TileScheme::Xyz);
+        assert_eq!(
+            store.grid().flip_y(&TileKey::new(0, 0, 0), store.scheme()),
+            TileKey::new(0, 63, 0)
+        );
+
+        drop(store);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_world_bounds_metadata() {
+        let path = std::env::temp_dir().join(format!("osm_tiles_world_{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE tiles (x INTEGER NOT NULL, y INTEGER NOT NULL, z INTEGER NOT NULL, data BLOB);
+             CREATE TABLE metadata (name TEXT NOT NULL, value TEXT NOT NULL);
+             INSERT INTO metadata VALUES ('generated_at', '0');",
+        )
+        .unwrap();
+
+        let err = TilesSQLiteStore::open(&path).err().unwrap();
+        assert!(matches!(err.current_context(), TilesSQLiteStoreError::WorldBoundary));
+
+        conn.execute("INSERT INTO metadata VALUES ('world_bounds', '0,0,1,1')", [])
+            .unwrap();
+        assert!(TilesSQLiteStore::open(&path).is_err());
+
+        conn.execute(
+            "UPDATE metadata SET value = ?1 WHERE name = 'world_bounds'",
+            [world_bounds_metadata()],
+        )
+        .unwrap();
+        assert!(TilesSQLiteStore::open(&path).is_ok());
+
+        drop(conn);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_pooled_concurrent_get_tile() {
+        let path = std::env::temp_dir().join(format!("osm_tiles_pool_{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE tiles (x INTEGER NOT NULL, y INTEGER NOT NULL, z INTEGER NOT NULL, data BLOB);",
+        )
+        .unwrap();
+        for x in 0..16 {
+            conn.execute("INSERT INTO tiles VALUES (?1, 0, 0, ?2)", (x, vec![x as u8]))
+                .unwrap();
+        }
+        drop(conn);
+
+        for size in [1, 4] {
+            let store = TilesSQLiteStore::new_pooled(&path, size);
+            std::thread::scope(|scope| {
+                for thread in 0..8 {
+                    let store = &store;
+                    scope.spawn(move || {
+                        for i in 0..50 {
+                            let x = (thread + i) % 16;
+                            assert_eq!(store.get_tile(x, 0, 0).unwrap(), vec![x as u8]);
+                        }
+                    });
+                }
+            });
+        }
+
+        fs::remove_file(path).unwrap();
+    }
+}
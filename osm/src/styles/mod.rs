@@ -1,26 +1,110 @@
 pub mod style_loader;
 
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
 #[derive(Serialize, Deserialize)]
 pub struct Style {
     pub id: String,
+    /// Used for zoom levels below the first override
     pub render_style: RenderStyle,
+    /// (zoom, style) pairs, the style applies from its zoom level up to the next override
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub zoom_overrides: Vec<(u8, RenderStyle)>,
 }
 
-#[derive(Serialize, Deserialize)]
+impl Style {
+    /// The override with the highest zoom at or below `zoom`, or the base style
+    pub fn resolve(&self, zoom: u8) -> &RenderStyle {
+        self.zoom_overrides
+            .iter()
+            .filter(|(override_zoom, _)| *override_zoom <= zoom)
+            .max_by_key(|(override_zoom, _)| *override_zoom)
+            .map_or(&self.render_style, |(_, render_style)| render_style)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum RenderStyle {
     Fill(RenderStyleColor),
     Border(RenderStyleColor, f32),
-    Dashed(RenderStyleColor, RenderStyleColor, DashStyle),
+    /// Also accepts the legacy `[color, gap_color, dash_style]` form with [DEFAULT_DASH_ARRAY]
+    #[serde(deserialize_with = "deserialize_dashed")]
+    Dashed {
+        color: RenderStyleColor,
+        gap_color: RenderStyleColor,
+        /// Alternating dash and gap lengths
+        dash_array: Vec<f32>,
+        dash_style: DashStyle,
+    },
+    Line {
+        color: RenderStyleColor,
+        /// Width 0.0 is a hairline, drawn one pixel wide at any zoom
+        width: f32,
+        #[serde(default)]
+        cap: LineCap,
+        #[serde(default)]
+        join: LineJoin,
+    },
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub enum DashStyle {
+    #[default]
     Solid,
     Circles
 }
 
-#[derive(Serialize, Deserialize)]
+pub const DEFAULT_DASH_ARRAY: [f32; 2] = [4.0, 2.0];
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DashedRepr {
+    Legacy(RenderStyleColor, RenderStyleColor, DashStyle),
+    Current {
+        color: RenderStyleColor,
+        gap_color: RenderStyleColor,
+        dash_array: Vec<f32>,
+        #[serde(default)]
+        dash_style: DashStyle,
+    },
+}
+
+fn deserialize_dashed<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<(RenderStyleColor, RenderStyleColor, Vec<f32>, DashStyle), D::Error> {
+    Ok(match DashedRepr::deserialize(deserializer)? {
+        DashedRepr::Legacy(color, gap_color, dash_style) => {
+            (color, gap_color, DEFAULT_DASH_ARRAY.to_vec(), dash_style)
+        }
+        DashedRepr::Current {
+            color,
+            gap_color,
+            dash_array,
+            dash_style,
+        } => (color, gap_color, dash_array, dash_style),
+    })
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LineCap {
+    #[default]
+    Butt,
+    Round,
+    Square,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LineJoin {
+    #[default]
+    Miter,
+    Round,
+    Bevel,
+}
+
+/// Deserialized from either `{"r", "g", "b", "a"}` floats or a hex string, see [RenderStyleColor::from_hex]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "RenderStyleColorRepr")]
 pub struct RenderStyleColor {
     r: f32,
     g: f32,
@@ -28,8 +112,192 @@ pub struct RenderStyleColor {
     a: f32,
 }
 
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RenderStyleColorRepr {
+    Hex(String),
+    Channels { r: f32, g: f32, b: f32, a: f32 },
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ColorError {
+    #[error("Hex color {0} must start with #")]
+    MissingHash(String),
+    #[error("Hex color {0} must have 3, 6 or 8 digits")]
+    Length(String),
+    #[error("Hex color {0} has invalid digits")]
+    Digits(String),
+}
+
+impl TryFrom<RenderStyleColorRepr> for RenderStyleColor {
+    type Error = ColorError;
+
+    fn try_from(repr: RenderStyleColorRepr) -> Result<Self, Self::Error> {
+        match repr {
+            RenderStyleColorRepr::Hex(hex) => Self::from_hex(&hex),
+            RenderStyleColorRepr::Channels { r, g, b, a } => Ok(Self { r, g, b, a }),
+        }
+    }
+}
+
 impl RenderStyleColor {
+    /// Parses `#rgb`, `#rrggbb` and `#rrggbbaa` into 0.0..=1.0 channels, alpha defaults to 1.0
+    pub fn from_hex(hex: &str) -> Result<Self, ColorError> {
+        let digits = hex
+            .strip_prefix('#')
+            .ok_or_else(|| ColorError::MissingHash(hex.to_string()))?;
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(ColorError::Digits(hex.to_string()));
+        }
+        let channel = |i: usize, width: usize| {
+            let value = u8::from_str_radix(&digits[i * width..(i + 1) * width], 16)
+                .expect("Hex digits");
+            // #rgb is a shorthand for #rrggbb
+            let value = if width == 1 { value * 0x11 } else { value };
+            value as f32 / 255.0
+        };
+        let (width, has_alpha) = match digits.len() {
+            3 => (1, false),
+            6 => (2, false),
+            8 => (2, true),
+            _ => return Err(ColorError::Length(hex.to_string())),
+        };
+        Ok(Self {
+            r: channel(0, width),
+            g: channel(1, width),
+            b: channel(2, width),
+            a: if has_alpha { channel(3, width) } else { 1.0 },
+        })
+    }
+
     pub fn as_array(&self) -> [f32; 4] {
         [self.r, self.g, self.b, self.a]
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::styles::{
+        ColorError, DashStyle, LineCap, LineJoin, RenderStyle, RenderStyleColor, Style,
+        DEFAULT_DASH_ARRAY,
+    };
+
+    #[test]
+    fn test_from_hex() {
+        assert_eq!(RenderStyleColor::from_hex("#ff0000").unwrap().as_array(), [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(RenderStyleColor::from_hex("#0f0").unwrap().as_array(), [0.0, 1.0, 0.0, 1.0]);
+        assert_eq!(
+            RenderStyleColor::from_hex("#0000FF33").unwrap().as_array(),
+            [0.0, 0.0, 1.0, 0.2]
+        );
+        assert_eq!(
+            RenderStyleColor::from_hex("ff0000").err(),
+            Some(ColorError::MissingHash("ff0000".to_string()))
+        );
+        assert_eq!(
+            RenderStyleColor::from_hex("#ff00").err(),
+            Some(ColorError::Length("#ff00".to_string()))
+        );
+        assert_eq!(
+            RenderStyleColor::from_hex("#gg0000").err(),
+            Some(ColorError::Digits("#gg0000".to_string()))
+        );
+
+        let styles: Vec<Style> = serde_json::from_str(
+            r##"[
+                {"id": "hex", "render_style": {"Fill": "#1a2b3cff"}},
+                {"id": "floats", "render_style": {"Fill": {"r": 0.5, "g": 0.5, "b": 0.5, "a": 1.0}}}
+            ]"##,
+        )
+        .unwrap();
+        assert_eq!(styles.len(), 2);
+        assert!(serde_json::from_str::<Vec<Style>>(
+            r##"[{"id": "hex", "render_style": {"Fill": "#1a2"}}]"##
+        )
+        .is_ok());
+        assert!(serde_json::from_str::<Vec<Style>>(
+            r##"[{"id": "hex", "render_style": {"Fill": "1a2b3c"}}]"##
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_resolve_zoom_overrides() {
+        let style: Style = serde_json::from_str(
+            r##"{
+                "id": "road",
+                "render_style": {"Border": ["#ffffff", 1.0]},
+                "zoom_overrides": [[14, {"Border": ["#ffffff", 4.0]}], [10, {"Border": ["#ffffff", 2.0]}]]
+            }"##,
+        )
+        .unwrap();
+        let width = |zoom: u8| match style.resolve(zoom) {
+            RenderStyle::Border(_, width) => *width,
+            _ => panic!("Expected Border style"),
+        };
+        assert_eq!(width(9), 1.0);
+        assert_eq!(width(10), 2.0);
+        assert_eq!(width(12), 2.0);
+        assert_eq!(width(15), 4.0);
+
+        let style: Style =
+            serde_json::from_str(r##"{"id": "ground", "render_style": {"Fill": "#000"}}"##).unwrap();
+        assert!(style.zoom_overrides.is_empty());
+        assert!(matches!(style.resolve(20), RenderStyle::Fill(..)));
+    }
+
+    #[test]
+    fn test_line_style_round_trip() {
+        let line = RenderStyle::Line {
+            color: RenderStyleColor::from_hex("#336699").unwrap(),
+            width: 2.5,
+            cap: LineCap::Round,
+            join: LineJoin::Bevel,
+        };
+        let json = serde_json::to_string(&line).unwrap();
+        assert_eq!(serde_json::from_str::<RenderStyle>(&json).unwrap(), line);
+
+        let hairline: RenderStyle =
+            serde_json::from_str(r##"{"Line": {"color": "#000", "width": 0.0}}"##).unwrap();
+        assert_eq!(
+            hairline,
+            RenderStyle::Line {
+                color: RenderStyleColor::from_hex("#000").unwrap(),
+                width: 0.0,
+                cap: LineCap::Butt,
+                join: LineJoin::Miter,
+            }
+        );
+    }
+
+    #[test]
+    fn test_dashed_legacy_form() {
+        let legacy: RenderStyle =
+            serde_json::from_str(r##"{"Dashed": ["#fff", "#333", "Circles"]}"##).unwrap();
+        assert_eq!(
+            legacy,
+            RenderStyle::Dashed {
+                color: RenderStyleColor::from_hex("#fff").unwrap(),
+                gap_color: RenderStyleColor::from_hex("#333").unwrap(),
+                dash_array: DEFAULT_DASH_ARRAY.to_vec(),
+                dash_style: DashStyle::Circles,
+            }
+        );
+
+        let dashed: RenderStyle = serde_json::from_str(
+            r##"{"Dashed": {"color": "#fff", "gap_color": "#333", "dash_array": [1.0, 3.0]}}"##,
+        )
+        .unwrap();
+        assert_eq!(
+            dashed,
+            RenderStyle::Dashed {
+                color: RenderStyleColor::from_hex("#fff").unwrap(),
+                gap_color: RenderStyleColor::from_hex("#333").unwrap(),
+                dash_array: vec![1.0, 3.0],
+                dash_style: DashStyle::Solid,
+            }
+        );
+        let json = serde_json::to_string(&dashed).unwrap();
+        assert_eq!(serde_json::from_str::<RenderStyle>(&json).unwrap(), dashed);
+    }
+}
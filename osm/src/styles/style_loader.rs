@@ -1,6 +1,8 @@
 use crate::source::reqwest_source::ReqwestSource;
 use crate::styles::Style;
+use error_stack::{Report, ResultExt};
 use log::error;
+use std::path::Path;
 use thiserror::Error;
 
 pub struct StyleLoader;
@@ -9,6 +11,10 @@ pub struct StyleLoader;
 pub enum StylesFetchError {
     #[error("Internal")]
     Internal,
+    #[error("Failed to read styles file")]
+    Read,
+    #[error("Malformed styles")]
+    Parse,
 }
 
 impl StyleLoader {
@@ -19,4 +25,50 @@ impl StyleLoader {
         }
         styles.unwrap_or_default()
     }
+
+    /// Loads styles from a local file in the `styles_v0.json` format served by the tiles server
+    pub fn load_from_path<P: AsRef<Path>>(path: P) -> Result<Vec<Style>, Report<StylesFetchError>> {
+        let path = path.as_ref();
+        let data = std::fs::read(path)
+            .change_context(StylesFetchError::Read)
+            .attach_printable_lazy(|| path.display().to_string())?;
+        serde_json::from_slice(&data)
+            .change_context(StylesFetchError::Parse)
+            .attach_printable_lazy(|| path.display().to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::styles::style_loader::{StyleLoader, StylesFetchError};
+    use crate::styles::RenderStyle;
+    use std::fs;
+
+    #[test]
+    fn test_load_from_path() {
+        let path = std::env::temp_dir().join(format!("osm_styles_{}.json", std::process::id()));
+        fs::write(
+            &path,
+            r#"[{"id": "road", "render_style": {"Border": [{"r": 1.0, "g": 0.5, "b": 0.0, "a": 1.0}, 2.0]}}]"#,
+        )
+        .unwrap();
+        let styles = StyleLoader::load_from_path(&path).unwrap();
+        assert_eq!(styles.len(), 1);
+        assert_eq!(styles[0].id, "road");
+        match &styles[0].render_style {
+            RenderStyle::Border(color, width) => {
+                assert_eq!(color.as_array(), [1.0, 0.5, 0.0, 1.0]);
+                assert_eq!(*width, 2.0);
+            }
+            _ => panic!("Expected Border style"),
+        }
+
+        fs::write(&path, r#"[{"id": "road""#).unwrap();
+        let err = StyleLoader::load_from_path(&path).err().unwrap();
+        assert!(matches!(err.current_context(), StylesFetchError::Parse));
+
+        fs::remove_file(&path).unwrap();
+        let err = StyleLoader::load_from_path(&path).err().unwrap();
+        assert!(matches!(err.current_context(), StylesFetchError::Read));
+    }
 }
@@ -1,2 +1,3 @@
 pub mod tile_writer;
-pub mod sutherland_hodgman;
\ No newline at end of file
+pub mod sutherland_hodgman;
+pub mod mvt;
\ No newline at end of file
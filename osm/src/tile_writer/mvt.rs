@@ -0,0 +1,470 @@
+//! Mapbox Vector Tile encoding, see https://github.com/mapbox/vector-tile-spec/tree/master/2.1
+
+use crate::map::{MapGeomObject, MapGeomObjectKind, MapGeometry, MapPointObjectKind, NatureKind};
+use geo::{Coord, LineString, Polygon};
+use prost::Message;
+use rustc_hash::FxHashMap;
+
+pub const EXTENT: u32 = 4096;
+const VERSION: u32 = 2;
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Tile {
+    #[prost(message, repeated, tag = "3")]
+    pub layers: ::prost::alloc::vec::Vec<Layer>,
+}
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Layer {
+    #[prost(uint32, required, tag = "15")]
+    pub version: u32,
+    #[prost(string, required, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "2")]
+    pub features: ::prost::alloc::vec::Vec<Feature>,
+    #[prost(string, repeated, tag = "3")]
+    pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(message, repeated, tag = "4")]
+    pub values: ::prost::alloc::vec::Vec<Value>,
+    #[prost(uint32, optional, tag = "5", default = "4096")]
+    pub extent: ::core::option::Option<u32>,
+}
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Feature {
+    #[prost(uint64, optional, tag = "1", default = "0")]
+    pub id: ::core::option::Option<u64>,
+    /// Pairs of key/value indexes in the layer keys/values tables
+    #[prost(uint32, repeated, packed = "true", tag = "2")]
+    pub tags: ::prost::alloc::vec::Vec<u32>,
+    #[prost(enumeration = "GeomType", optional, tag = "3", default = "Unknown")]
+    pub r#type: ::core::option::Option<i32>,
+    /// Encoded command integers and zigzag parameters
+    #[prost(uint32, repeated, packed = "true", tag = "4")]
+    pub geometry: ::prost::alloc::vec::Vec<u32>,
+}
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Value {
+    #[prost(string, optional, tag = "1")]
+    pub string_value: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(float, optional, tag = "2")]
+    pub float_value: ::core::option::Option<f32>,
+    #[prost(double, optional, tag = "3")]
+    pub double_value: ::core::option::Option<f64>,
+    #[prost(int64, optional, tag = "4")]
+    pub int_value: ::core::option::Option<i64>,
+    #[prost(uint64, optional, tag = "5")]
+    pub uint_value: ::core::option::Option<u64>,
+    #[prost(sint64, optional, tag = "6")]
+    pub sint_value: ::core::option::Option<i64>,
+    #[prost(bool, optional, tag = "7")]
+    pub bool_value: ::core::option::Option<bool>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum GeomType {
+    Unknown = 0,
+    Point = 1,
+    Linestring = 2,
+    Polygon = 3,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum PropertyValue {
+    String(String),
+    Int(i64),
+    /// f64 bits, so values can be deduplicated by hash
+    Double(u64),
+}
+
+impl From<&PropertyValue> for Value {
+    fn from(value: &PropertyValue) -> Self {
+        match value {
+            PropertyValue::String(value) => Value {
+                string_value: Some(value.clone()),
+                ..Default::default()
+            },
+            PropertyValue::Int(value) => Value {
+                int_value: Some(*value),
+                ..Default::default()
+            },
+            PropertyValue::Double(bits) => Value {
+                double_value: Some(f64::from_bits(*bits)),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+struct LayerBuilder {
+    layer: Layer,
+    keys: FxHashMap<&'static str, u32>,
+    values: FxHashMap<PropertyValue, u32>,
+}
+
+impl LayerBuilder {
+    fn new(name: &str) -> Self {
+        LayerBuilder {
+            layer: Layer {
+                version: VERSION,
+                name: name.to_string(),
+                extent: Some(EXTENT),
+                ..Default::default()
+            },
+            keys: FxHashMap::default(),
+            values: FxHashMap::default(),
+        }
+    }
+
+    fn add_feature(
+        &mut self,
+        id: i64,
+        properties: Vec<(&'static str, PropertyValue)>,
+        geom_type: GeomType,
+        geometry: Vec<u32>,
+    ) {
+        let mut tags = Vec::with_capacity(properties.len() * 2);
+        for (key, value) in properties {
+            let keys_len = self.keys.len() as u32;
+            let key_index = *self.keys.entry(key).or_insert_with(|| {
+                self.layer.keys.push(key.to_string());
+                keys_len
+            });
+            let values_len = self.values.len() as u32;
+            let value_index = *self.values.entry(value).or_insert_with_key(|value| {
+                self.layer.values.push(value.into());
+                values_len
+            });
+            tags.push(key_index);
+            tags.push(value_index);
+        }
+        self.layer.features.push(Feature {
+            // synthetic objects(merged polygons, shapes) have negative ids
+            id: u64::try_from(id).ok(),
+            tags,
+            r#type: Some(geom_type as i32),
+            geometry,
+        });
+    }
+}
+
+#[derive(Default)]
+struct GeometryEncoder {
+    commands: Vec<u32>,
+    cursor: (i32, i32),
+}
+
+impl GeometryEncoder {
+    const MOVE_TO: u32 = 1;
+    const LINE_TO: u32 = 2;
+    const CLOSE_PATH: u32 = 7;
+
+    fn command(id: u32, count: usize) -> u32 {
+        (id & 0x7) | ((count as u32) << 3)
+    }
+
+    fn zigzag(value: i32) -> u32 {
+        ((value << 1) ^ (value >> 31)) as u32
+    }
+
+    fn push_points(&mut self, points: &[(i32, i32)]) {
+        for point in points {
+            self.commands.push(Self::zigzag(point.0 - self.cursor.0));
+            self.commands.push(Self::zigzag(point.1 - self.cursor.1));
+            self.cursor = *point;
+        }
+    }
+
+    fn add_point(&mut self, point: (i32, i32)) {
+        self.commands.push(Self::command(Self::MOVE_TO, 1));
+        self.push_points(&[point]);
+    }
+
+    fn add_line(&mut self, points: &[(i32, i32)]) {
+        self.commands.push(Self::command(Self::MOVE_TO, 1));
+        self.push_points(&points[..1]);
+        self.commands
+            .push(Self::command(Self::LINE_TO, points.len() - 1));
+        self.push_points(&points[1..]);
+    }
+
+    /// `points` should not repeat the first point at the end
+    fn add_ring(&mut self, points: &[(i32, i32)]) {
+        self.add_line(points);
+        self.commands.push(Self::command(Self::CLOSE_PATH, 1));
+    }
+}
+
+fn to_tile_coord(coord: &Coord, tile_max: &Coord) -> (i32, i32) {
+    // local coordinates have the origin in the bottom left corner and y goes up (negative),
+    // MVT has the origin in the top left corner
+    (
+        (coord.x / tile_max.x * EXTENT as f64).round() as i32,
+        ((1.0 - coord.y / tile_max.y) * EXTENT as f64).round() as i32,
+    )
+}
+
+fn to_tile_line(line: &LineString, tile_max: &Coord) -> Vec<(i32, i32)> {
+    let mut points = line
+        .coords()
+        .map(|coord| to_tile_coord(coord, tile_max))
+        .collect::<Vec<_>>();
+    points.dedup();
+    points
+}
+
+fn to_tile_ring(line: &LineString, tile_max: &Coord, exterior: bool) -> Option<Vec<(i32, i32)>> {
+    let mut points = to_tile_line(line, tile_max);
+    if points.len() > 1 && points.first() == points.last() {
+        points.pop();
+    }
+    if points.len() < 3 {
+        return None;
+    }
+    // surveyor's formula, exterior rings should have positive area in tile coordinates
+    let area: i64 = points
+        .iter()
+        .zip(points.iter().cycle().skip(1))
+        .map(|(a, b)| a.0 as i64 * b.1 as i64 - b.0 as i64 * a.1 as i64)
+        .sum();
+    if area == 0 {
+        return None;
+    }
+    if (area > 0) != exterior {
+        points.reverse();
+    }
+    Some(points)
+}
+
+/// Exterior ring followed by the interior rings, `None` if the exterior collapses
+fn add_polygon(encoder: &mut GeometryEncoder, poly: &Polygon, tile_max: &Coord) -> Option<()> {
+    encoder.add_ring(&to_tile_ring(poly.exterior(), tile_max, true)?);
+    for interior in poly.interiors() {
+        if let Some(points) = to_tile_ring(interior, tile_max, false) {
+            encoder.add_ring(&points);
+        }
+    }
+    Some(())
+}
+
+fn encode_geometry(geometry: &MapGeometry, tile_max: &Coord) -> Option<(GeomType, Vec<u32>)> {
+    let mut encoder = GeometryEncoder::default();
+    let geom_type = match geometry {
+        MapGeometry::Coord(coord) => {
+            encoder.add_point(to_tile_coord(coord, tile_max));
+            GeomType::Point
+        }
+        MapGeometry::Line(line) => {
+            let points = to_tile_line(line, tile_max);
+            if points.len() < 2 {
+                return None;
+            }
+            encoder.add_line(&points);
+            GeomType::Linestring
+        }
+        MapGeometry::Poly(poly) => {
+            add_polygon(&mut encoder, poly, tile_max)?;
+            GeomType::Polygon
+        }
+        MapGeometry::MultiPoly(multi_poly) => {
+            // polygons collapsed to nothing in tile coordinates are dropped, not the whole feature
+            let added = multi_poly
+                .iter()
+                .filter_map(|poly| add_polygon(&mut encoder, poly, tile_max))
+                .count();
+            if added == 0 {
+                return None;
+            }
+            GeomType::Polygon
+        }
+        MapGeometry::MultiLine(multi_line) => {
+            // same as polygons, lines collapsed to a point are dropped
+            let mut added = 0;
+            for line in multi_line {
+                let points = to_tile_line(line, tile_max);
+                if points.len() >= 2 {
+                    encoder.add_line(&points);
+                    added += 1;
+                }
+            }
+            if added == 0 {
+                return None;
+            }
+            GeomType::Linestring
+        }
+    };
+    Some((geom_type, encoder.commands))
+}
+
+fn layer_and_properties(
+    object: &MapGeomObject,
+) -> (&'static str, Vec<(&'static str, PropertyValue)>) {
+    match &object.kind {
+        MapGeomObjectKind::Way(info) => {
+            let mut properties = vec![
+                ("kind", PropertyValue::String(info.line_kind.as_str().to_string())),
+                ("layer", PropertyValue::Int(info.layer as i64)),
+            ];
+            if let Some(name) = &info.name_en {
+                properties.push(("name", PropertyValue::String(name.clone())));
+            }
+            ("roads", properties)
+        }
+        MapGeomObjectKind::Building(info) => {
+            let mut properties = vec![
+                ("levels", PropertyValue::Int(info.levels as i64)),
+                ("height", PropertyValue::Double((info.height as f64).to_bits())),
+            ];
+            if let Some(housenumber) = &info.housenumber {
+                properties.push(("housenumber", PropertyValue::String(housenumber.clone())));
+            }
+            if let Some(street) = &info.street {
+                properties.push(("street", PropertyValue::String(street.clone())));
+            }
+            ("buildings", properties)
+        }
+        MapGeomObjectKind::Nature(NatureKind::Water) => ("water", vec![]),
+        MapGeomObjectKind::Nature(kind) => {
+            let kind = match kind {
+                NatureKind::Ground => "ground",
+                NatureKind::Park => "park",
+                NatureKind::Forest => "forest",
+                NatureKind::Water => "water",
+            };
+            ("landuse", vec![("kind", PropertyValue::String(kind.to_string()))])
+        }
+        MapGeomObjectKind::AdminLine(level) => (
+            "boundaries",
+            vec![("admin_level", PropertyValue::Int(*level as i64))],
+        ),
+        MapGeomObjectKind::Poi(info) => {
+            let kind = match info.kind {
+                MapPointObjectKind::PopArea(_) => "pop_area",
+                MapPointObjectKind::TrafficLight => "traffic_light",
+                MapPointObjectKind::Toilet => "toilet",
+                MapPointObjectKind::Parking => "parking",
+                MapPointObjectKind::TrainStation(_) => "train_station",
+                MapPointObjectKind::Generic => "poi",
+            };
+            let mut properties = vec![("kind", PropertyValue::String(kind.to_string()))];
+            if let MapPointObjectKind::TrainStation(station) = info.kind {
+                properties.push(("station", PropertyValue::String(station.as_str().to_string())));
+            }
+            if !info.text.is_empty() {
+                properties.push(("name", PropertyValue::String(info.text.clone())));
+            }
+            ("poi", properties)
+        }
+        MapGeomObjectKind::TileBounds => ("debug", vec![]),
+    }
+}
+
+/// Encodes tile data to MVT protobuf.
+/// Geometry should be in tile local world coordinates with the origin in the bottom left
+/// tile corner, `tile_max` is the top right tile corner in the same coordinates.
+pub fn encode_tile(data: &[(MapGeomObject, MapGeometry)], tile_max: Coord) -> Vec<u8> {
+    let mut layers: Vec<LayerBuilder> = Vec::new();
+    let mut layer_indexes: FxHashMap<&'static str, usize> = FxHashMap::default();
+    for (object, geometry) in data {
+        let Some((geom_type, commands)) = encode_geometry(geometry, &tile_max) else {
+            continue;
+        };
+        let (layer_name, properties) = layer_and_properties(object);
+        let index = *layer_indexes.entry(layer_name).or_insert_with(|| {
+            layers.push(LayerBuilder::new(layer_name));
+            layers.len() - 1
+        });
+        layers[index].add_feature(object.id, properties, geom_type, commands);
+    }
+    Tile {
+        layers: layers.into_iter().map(|builder| builder.layer).collect(),
+    }
+    .encode_to_vec()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{encode_tile, GeomType, Tile, EXTENT};
+    use crate::map::{
+        BuildingInfo, HighwayKind, LayerKind, LineKind, MapGeomObject, MapGeomObjectKind, MapGeometry,
+        MapPointInfo, MapPointObjectKind, WayInfo,
+    };
+    use geo::{coord, line_string, polygon};
+    use prost::Message;
+
+    #[test]
+    fn test_encode_tile() {
+        let road = MapGeomObject {
+            id: 1,
+            kind: MapGeomObjectKind::Way(WayInfo {
+                line_kind: LineKind::Highway {
+                    kind: HighwayKind::Primary,
+                },
+                layer: 0,
+                layer_kind: LayerKind::None,
+                name_en: Some("Main st".to_string()),
+            }),
+        };
+        let building = MapGeomObject {
+            id: 2,
+            kind: MapGeomObjectKind::Building(BuildingInfo::new(3, None, None, None)),
+        };
+        let poi = MapGeomObject {
+            id: -1,
+            kind: MapGeomObjectKind::Poi(MapPointInfo {
+                text: "".to_string(),
+                kind: MapPointObjectKind::Toilet,
+            }),
+        };
+        let data = vec![
+            (
+                road.clone(),
+                MapGeometry::Line(line_string![(x: 0.0, y: 0.0), (x: 50.0, y: -50.0)]),
+            ),
+            (
+                road,
+                MapGeometry::Line(line_string![(x: 10.0, y: 0.0), (x: 10.0, y: -100.0)]),
+            ),
+            (
+                building,
+                MapGeometry::Poly(polygon![
+                    (x: 10.0, y: -10.0),
+                    (x: 20.0, y: -10.0),
+                    (x: 20.0, y: -20.0),
+                    (x: 10.0, y: -20.0),
+                ]),
+            ),
+            (poi, MapGeometry::Coord(coord! {x: 50.0, y: -50.0})),
+        ];
+
+        let tile = Tile::decode(encode_tile(&data, coord! {x: 100.0, y: -100.0}).as_slice())
+            .unwrap();
+
+        let layers = tile
+            .layers
+            .iter()
+            .map(|layer| (layer.name.as_str(), layer.features.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(layers, vec![("roads", 2), ("buildings", 1), ("poi", 1)]);
+
+        let road_layer = &tile.layers[0];
+        assert_eq!(road_layer.keys, vec!["kind", "layer", "name"]);
+        assert_eq!(road_layer.values.len(), 3, "Expect deduplicated values");
+        assert_eq!(road_layer.features[0].r#type, Some(GeomType::Linestring as i32));
+        // MoveTo(1) to (0, 4096), LineTo(1) by (2048, -2048)
+        assert_eq!(
+            road_layer.features[0].geometry,
+            vec![9, 0, EXTENT * 2, 10, 4096, 4095]
+        );
+
+        let poi_layer = &tile.layers[2];
+        assert_eq!(poi_layer.features[0].id, None);
+        assert_eq!(poi_layer.features[0].geometry, vec![9, 4096, 4096]);
+    }
+}
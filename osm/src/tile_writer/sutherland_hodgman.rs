@@ -1,30 +1,55 @@
-use geo::{coord, Coord, LineString, Rect};
+use geo::{coord, Area, Coord, LineString, MultiLineString, Polygon, Rect};
+/// Intersection of the segment with the rect line of the `side` bit.
+/// Segments parallel to the line and unknown sides give the endpoint `b` clamped to the rect.
 fn intersect_edge(a: &Coord, b: &Coord, side: i8, rect: &Rect) -> Coord {
+    let clamped_b = coord! {
+        x: b.x.clamp(rect.min().x, rect.max().x),
+        y: b.y.clamp(rect.min().y, rect.max().y),
+    };
+    let dx = b.x - a.x;
+    let dy = b.y - a.y;
+
     if side & 8 != 0 {
-        let cx = a.x + (b.x - a.x) * (rect.max().y - a.y) / (b.y - a.y);
+        if dy == 0.0 {
+            return clamped_b;
+        }
+        let cx = a.x + dx * (rect.max().y - a.y) / dy;
         let cy = rect.max().y;
         return coord! { x: cx, y: cy };
     }
 
     if side & 4 != 0 {
-        let cx = a.x + (b.x - a.x) * (rect.min().y - a.y) / (b.y - a.y);
+        if dy == 0.0 {
+            return clamped_b;
+        }
+        let cx = a.x + dx * (rect.min().y - a.y) / dy;
         let cy = rect.min().y;
         return coord! { x: cx, y: cy };
     }
 
     if side & 2 != 0 {
+        if dx == 0.0 {
+            return clamped_b;
+        }
         let cx = rect.max().x;
-        let cy = a.y + (b.y - a.y) * (rect.max().x - a.x) / (b.x - a.x);
+        let cy = a.y + dy * (rect.max().x - a.x) / dx;
         return coord! { x: cx, y: cy };
     }
 
     if side & 1 != 0 {
+        if dx == 0.0 {
+            return clamped_b;
+        }
         let cx = rect.min().x;
-        let cy = a.y + (b.y - a.y) * (rect.min().x - a.x) / (b.x - a.x);
+        let cy = a.y + dy * (rect.min().x - a.x) / dx;
         return coord! { x: cx, y: cy };
     }
 
-    panic!("Should never happen")
+    clamped_b
+}
+
+fn is_nan(coord: &Coord) -> bool {
+    coord.x.is_nan() || coord.y.is_nan()
 }
 
 fn get_side(coord: &Coord, rect: &Rect) -> i8 {
@@ -42,7 +67,17 @@ fn get_side(coord: &Coord, rect: &Rect) -> i8 {
     code
 }
 
+/// Clips a closed ring to the rect. Returns the ring unchanged when it's fully inside,
+/// and `None` when nothing or only a degenerate ring without area is left.
+/// The result is always closed.
 pub fn sutherland_hodgman_clip(subject_polygon: &LineString, rect: &Rect) -> Option<LineString> {
+    if subject_polygon.0.is_empty() {
+        return None;
+    }
+    if subject_polygon.0.iter().all(|coord| get_side(coord, rect) == 0) {
+        return Some(subject_polygon.clone());
+    }
+
     let mut points = subject_polygon.0.clone();
     let mut edge = 1;
     while edge <= 8 {
@@ -54,7 +89,10 @@ pub fn sutherland_hodgman_clip(subject_polygon: &LineString, rect: &Rect) -> Opt
             let inside = (get_side(&p, rect) & edge) == 0;
 
             if inside != prev_inside {
-                result.push(intersect_edge(&prev, &p, edge, rect));
+                let intersection = intersect_edge(&prev, &p, edge, rect);
+                if !is_nan(&intersection) {
+                    result.push(intersection);
+                }
             }
             if inside {
                 result.push(p);
@@ -69,9 +107,204 @@ pub fn sutherland_hodgman_clip(subject_polygon: &LineString, rect: &Rect) -> Opt
         edge *= 2;
     }
 
-    if points.len() >= 2 {
-        Some(LineString(points))
-    } else {
-        None
+    // clipping along the rect edges produces repeated points
+    points.dedup();
+    while points.len() > 1 && points.first() == points.last() {
+        points.pop();
+    }
+    if points.len() < 3 {
+        return None;
+    }
+    points.push(points[0]);
+
+    let ring = LineString(points);
+    if Polygon::new(ring.clone(), vec![]).unsigned_area() == 0.0 {
+        return None;
+    }
+    Some(ring)
+}
+
+/// Cohen-Sutherland clip of the segment, `None` when it's outside of the rect.
+/// The second value is whether the start was moved to the rect boundary.
+fn clip_segment(mut a: Coord, mut b: Coord, rect: &Rect) -> Option<(Coord, Coord, bool)> {
+    let mut side_a = get_side(&a, rect);
+    let mut side_b = get_side(&b, rect);
+    let mut start_clipped = false;
+    // every step moves an endpoint onto one of the rect lines, the rest is float error
+    for _ in 0..8 {
+        if side_a | side_b == 0 {
+            return Some((a, b, start_clipped));
+        }
+        if side_a & side_b != 0 {
+            return None;
+        }
+        if side_a != 0 {
+            a = intersect_edge(&a, &b, side_a, rect);
+            side_a = get_side(&a, rect);
+            start_clipped = true;
+        } else {
+            b = intersect_edge(&a, &b, side_b, rect);
+            side_b = get_side(&b, rect);
+        }
+        if is_nan(&a) || is_nan(&b) {
+            return None;
+        }
+    }
+    None
+}
+
+/// Trims the line to the rect, every part of the line inside the rect becomes a separate line
+pub fn cohen_sutherland_clip(line: &LineString, rect: &Rect) -> MultiLineString {
+    let mut lines = Vec::new();
+    let mut current: Vec<Coord> = Vec::new();
+    for segment in line.lines() {
+        match clip_segment(segment.start, segment.end, rect) {
+            Some((start, end, start_clipped)) => {
+                if start_clipped || current.last() != Some(&start) {
+                    if current.len() > 1 {
+                        lines.push(LineString(current));
+                    }
+                    current = vec![start];
+                }
+                if current.last() != Some(&end) {
+                    current.push(end);
+                }
+            }
+            None => {
+                if current.len() > 1 {
+                    lines.push(LineString(std::mem::take(&mut current)));
+                }
+                current.clear();
+            }
+        }
+    }
+    if current.len() > 1 {
+        lines.push(LineString(current));
+    }
+    MultiLineString(lines)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tile_writer::sutherland_hodgman::{
+        cohen_sutherland_clip, intersect_edge, sutherland_hodgman_clip,
+    };
+    use geo::{coord, line_string, Area, Polygon, Rect};
+
+    #[test]
+    fn test_clip_l_shape() {
+        let l_shape = line_string![
+            (x: 0.0, y: 0.0),
+            (x: 4.0, y: 0.0),
+            (x: 4.0, y: 2.0),
+            (x: 2.0, y: 2.0),
+            (x: 2.0, y: 4.0),
+            (x: 0.0, y: 4.0),
+            (x: 0.0, y: 0.0),
+        ];
+
+        // the rect bisects the notch at (2, 2)
+        let rect = Rect::new(coord! {x: 1.0, y: 1.0}, coord! {x: 3.0, y: 3.0});
+        let clipped = sutherland_hodgman_clip(&l_shape, &rect).unwrap();
+        assert!(clipped.is_closed());
+        assert_eq!(clipped.0.len(), 7);
+        assert_eq!(Polygon::new(clipped.clone(), vec![]).unsigned_area(), 3.0);
+        assert!(clipped.coords().all(|coord| rect.min().x <= coord.x
+            && coord.x <= rect.max().x
+            && rect.min().y <= coord.y
+            && coord.y <= rect.max().y));
+
+        let inside_rect = Rect::new(coord! {x: -1.0, y: -1.0}, coord! {x: 5.0, y: 5.0});
+        assert_eq!(sutherland_hodgman_clip(&l_shape, &inside_rect), Some(l_shape.clone()));
+
+        let outside_rect = Rect::new(coord! {x: 10.0, y: 10.0}, coord! {x: 12.0, y: 12.0});
+        assert_eq!(sutherland_hodgman_clip(&l_shape, &outside_rect), None);
+
+        // only the edge x = 4 touches the rect
+        let touching_rect = Rect::new(coord! {x: 4.0, y: 0.0}, coord! {x: 6.0, y: 2.0});
+        assert_eq!(sutherland_hodgman_clip(&l_shape, &touching_rect), None);
+    }
+
+    #[test]
+    fn test_clip_line() {
+        let rect = Rect::new(coord! {x: 1.0, y: 1.0}, coord! {x: 2.0, y: 2.0});
+        let on_boundary = |coord: &geo::Coord| {
+            (coord.x == rect.min().x || coord.x == rect.max().x) && (1.0..=2.0).contains(&coord.y)
+                || (coord.y == rect.min().y || coord.y == rect.max().y)
+                    && (1.0..=2.0).contains(&coord.x)
+        };
+
+        let diagonal = line_string![(x: 0.0, y: 0.5), (x: 4.0, y: 2.5)];
+        let clipped = cohen_sutherland_clip(&diagonal, &rect);
+        assert_eq!(clipped.0.len(), 1);
+        assert_eq!(clipped.0[0], line_string![(x: 1.0, y: 1.0), (x: 2.0, y: 1.5)]);
+        assert!(clipped.0[0].coords().all(on_boundary));
+
+        // enters and leaves through the top edge
+        let zigzag = line_string![
+            (x: 1.2, y: 3.0),
+            (x: 1.5, y: 1.5),
+            (x: 1.8, y: 3.0),
+            (x: 1.8, y: 4.0),
+        ];
+        let clipped = cohen_sutherland_clip(&zigzag, &rect);
+        assert_eq!(clipped.0.len(), 1);
+        let line = &clipped.0[0];
+        assert_eq!(line.0.len(), 3);
+        assert!(on_boundary(&line.0[0]) && line.0[0].y == 2.0);
+        assert_eq!(line.0[1], coord! {x: 1.5, y: 1.5});
+        assert!(on_boundary(&line.0[2]) && line.0[2].y == 2.0);
+
+        // leaves and enters again
+        let u_turn = line_string![
+            (x: 1.2, y: 1.5),
+            (x: 3.0, y: 1.5),
+            (x: 3.0, y: 1.8),
+            (x: 1.2, y: 1.8),
+        ];
+        let clipped = cohen_sutherland_clip(&u_turn, &rect);
+        assert_eq!(
+            clipped.0,
+            vec![
+                line_string![(x: 1.2, y: 1.5), (x: 2.0, y: 1.5)],
+                line_string![(x: 2.0, y: 1.8), (x: 1.2, y: 1.8)],
+            ]
+        );
+
+        let outside = line_string![(x: 3.0, y: 0.0), (x: 3.0, y: 3.0)];
+        assert!(cohen_sutherland_clip(&outside, &rect).0.is_empty());
+    }
+
+    #[test]
+    fn test_degenerate_segments() {
+        let rect = Rect::new(coord! {x: 1.0, y: 1.0}, coord! {x: 3.0, y: 3.0});
+        let outside = coord! {x: 5.0, y: 2.0};
+        for side in [0, 1, 2, 4, 8] {
+            assert_eq!(
+                intersect_edge(&outside, &outside, side, &rect),
+                coord! {x: 3.0, y: 2.0}
+            );
+        }
+
+        // duplicated vertices on both sides of the rect edge
+        let ring = line_string![
+            (x: 0.0, y: 0.0),
+            (x: 2.0, y: 0.0),
+            (x: 2.0, y: 0.0),
+            (x: 2.0, y: 2.0),
+            (x: 2.0, y: 2.0),
+            (x: 0.0, y: 2.0),
+            (x: 0.0, y: 0.0),
+        ];
+        let clipped = sutherland_hodgman_clip(&ring, &rect).unwrap();
+        assert!(clipped.coords().all(|coord| !coord.x.is_nan() && !coord.y.is_nan()));
+        assert!(clipped.is_closed());
+        assert_eq!(Polygon::new(clipped, vec![]).unsigned_area(), 1.0);
+
+        let line = line_string![(x: 0.0, y: 2.0), (x: 0.0, y: 2.0), (x: 2.0, y: 2.0)];
+        assert_eq!(
+            cohen_sutherland_clip(&line, &rect).0,
+            vec![line_string![(x: 1.0, y: 2.0), (x: 2.0, y: 2.0)]]
+        );
     }
 }
@@ -1,24 +1,31 @@
-use crate::map::{
-    MapGeomObject, MapGeometry, MapGeometryCollection, DBS_FOLDER,
-};
-use crate::tile_writer::sutherland_hodgman::sutherland_hodgman_clip;
+use crate::map::{MapGeomObject, MapGeomObjectKind, MapGeometry, MapGeometryCollection};
+use crate::source::tiles_sqlite_store::TilesSQLiteStore;
+use crate::tile_writer::mvt;
+use crate::tile_writer::sutherland_hodgman::{cohen_sutherland_clip, sutherland_hodgman_clip};
+use crate::tiles::codec::{concat_features, decompress_tile, TileCodec, GEOMETRY_STORE_DEDUP};
 use crate::tiles::{
-    calc_tile_ranges, create_tiles_db_connection, TileKey, TileRanges, TILES_COUNT,
+    create_tiles_db_connection, lat_lon_to_world, world_bounds_metadata, TileGrid, TileKey,
+    TileScheme,
 };
 use flate2::write::GzEncoder;
 use flate2::Compression;
-use geo::line_intersection::line_intersection;
 use geo::{
-    coord, BoundingRect, Contains, Coord, Intersects, Line, LineIntersection, LineString,
-    MapCoords, MapCoordsInPlace, MultiLineString, Polygon, Rect,
+    coord, BooleanOps, BoundingRect, Contains, Coord, CoordFloat, Intersects, LineString, MapCoords,
+    MapCoordsInPlace, MultiLineString, MultiPolygon, Polygon, Rect,
 };
-use googleprojection::Mercator;
 use itertools::Itertools;
-use rusqlite::{Connection, Transaction};
-use rustc_hash::{FxHashMap, FxHashSet};
+use rusqlite::{Connection, OpenFlags, OptionalExtension, Transaction};
+use rustc_hash::{FxHashMap, FxHashSet, FxHasher};
+use serde::{Deserialize, Serialize};
 use std::io::Write;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::{channel, Receiver, Sender};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::{fs, io};
 use threadpool::ThreadPool;
 
@@ -28,6 +35,186 @@ pub struct TileWriter {
     receiver: Receiver<(TileKey, MapGeomObject, MapGeometry)>,
     tile_db_map: FxHashMap<TileKey, MapGeometryCollection>,
     tile_keys_cache: Arc<FxHashSet<TileKey>>,
+    config: TileWriterConfig,
+    /// Areas the tiles are built for, see [TileWriter::add_coverage]
+    coverage: Vec<Rect>,
+    output: Option<TilesOutput>,
+}
+
+/// Tiles db which already holds the written or the checkpointed areas,
+/// it's completed by [TileWriter::save_to_path]
+struct TilesOutput {
+    path: PathBuf,
+    conn: Connection,
+    /// The db is in WAL mode, see [TileWriterConfig::durable_output]
+    durable: bool,
+    /// Keys of the written tiles, they are needed for the metadata
+    tile_keys: Vec<TileKey>,
+    /// `geom` table ids of the written features by [feature_hash],
+    /// see [TileWriterConfig::dedup_geometries]
+    geom_ids: FxHashMap<u128, i64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TileWriterConfig {
+    /// Geometry for zoom levels outside of the range is silently skipped
+    pub zoom_range: RangeInclusive<u32>,
+    /// Adds the tile boundary rectangle to every tile as [MapGeomObjectKind::TileBounds]
+    pub emit_tile_bounds_debug: bool,
+    pub tile_format: TileFormat,
+    /// Compression of [TileFormat::Internal] blobs, MVT tiles are always plain gzip
+    pub tile_codec: TileCodec,
+    /// Stores every distinct [TileFormat::Internal] feature once in the `geom` table,
+    /// tile blobs hold the list of feature ids instead
+    pub dedup_geometries: bool,
+    /// Runs VACUUM on the finished db, it's expensive for big dbs
+    pub vacuum_after_build: bool,
+    /// Writes the db in WAL mode with synced commits, so committed tiles survive a killed process.
+    /// It's slower, the finished db is switched back to the rollback journal
+    pub durable_output: bool,
+    /// All geometry is clipped to the polygon before tiling, features outside are dropped
+    pub clip_polygon: Option<Arc<Polygon>>,
+    /// Row addressing of stored tiles
+    pub tile_scheme: TileScheme,
+    /// Tiles count and max zoom level, must match the grid the geometry was processed for
+    pub grid: TileGrid,
+    /// Scale of the tile bbox geometry is clipped to. Slightly bigger bbox reduces
+    /// border artefacts, it's a tradeoff against over tessellation/drawing. 1.0 disables it
+    pub tile_padding: f64,
+    /// Tiles db written by [TileWriter::save_to_file]
+    pub db_path: PathBuf,
+}
+
+pub const DEFAULT_TILE_PADDING: f64 = 1.01;
+
+/// Key of a serialized feature in [TilesOutput::geom_ids], so the features aren't kept in memory.
+/// Two independent 64 bit hashes make collisions of distinct features negligible
+fn feature_hash(feature: &[u8]) -> u128 {
+    let mut fx_hasher = FxHasher::default();
+    feature.hash(&mut fx_hasher);
+    let mut sip_hasher = DefaultHasher::new();
+    feature.hash(&mut sip_hasher);
+    (fx_hasher.finish() as u128) << 64 | sip_hasher.finish() as u128
+}
+
+/// Compressed tile blob or serialized features to be stored in the `geom` table
+enum TileData {
+    Compressed(Vec<u8>),
+    Features(Vec<Vec<u8>>),
+}
+
+impl TilesOutput {
+    /// Creates an empty tiles db at `path`, an existing db is replaced
+    fn create(path: &Path, durable: bool) -> Self {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).expect("Could not create tiles db dir");
+        }
+        let _ = fs::remove_file(path);
+        // WAL files left by an interrupted durable build mustn't be applied to the new db
+        for suffix in ["-wal", "-shm"] {
+            let mut file = path.as_os_str().to_owned();
+            file.push(suffix);
+            let _ = fs::remove_file(file);
+        }
+        TilesOutput {
+            path: path.to_path_buf(),
+            conn: TileWriter::create_internal_tiles_db_connection(path, durable),
+            durable,
+            tile_keys: Vec::new(),
+            geom_ids: FxHashMap::default(),
+        }
+    }
+
+    /// Continues the db of an interrupted build if it has checkpoints, otherwise creates a new one.
+    /// Checkpoints are useless in a db corrupted by the crash, so it's always durable
+    fn open(path: &Path, config: &TileWriterConfig) -> Self {
+        if TileWriter::completed_areas(path).is_empty() {
+            return Self::create(path, true);
+        }
+        let conn = TileWriter::open_internal_tiles_db_connection(path, true);
+        let tile_keys = conn
+            .prepare("SELECT x, y, z FROM tiles")
+            .and_then(|mut stmt| {
+                stmt.query_map((), |row| Ok(TileKey::new(row.get(0)?, row.get(1)?, row.get(2)?)))?
+                    .collect::<rusqlite::Result<Vec<_>>>()
+            })
+            .unwrap()
+            .iter()
+            .map(|key| config.grid.flip_y(key, config.tile_scheme))
+            .collect();
+        let geom_ids = conn
+            .prepare("SELECT data, id FROM geom")
+            .and_then(|mut stmt| {
+                stmt.query_map((), |row| Ok((feature_hash(&row.get::<_, Vec<u8>>(0)?), row.get(1)?)))?
+                    .collect::<rusqlite::Result<FxHashMap<_, _>>>()
+            })
+            .unwrap();
+        TilesOutput {
+            path: path.to_path_buf(),
+            conn,
+            durable: true,
+            tile_keys,
+            geom_ids,
+        }
+    }
+}
+
+impl TileWriterConfig {
+    /// Value of `geometry_store` in the tiles db metadata
+    pub fn geometry_store(&self) -> &'static str {
+        if self.dedup_geometries && self.tile_format == TileFormat::Internal {
+            GEOMETRY_STORE_DEDUP
+        } else {
+            "inline"
+        }
+    }
+}
+
+/// Format of the tile blob, both are gzip compressed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TileFormat {
+    /// bincode serialized [MapGeometryCollection]
+    #[default]
+    Internal,
+    /// Mapbox Vector Tile, see [mvt]
+    Mvt,
+}
+
+impl TileFormat {
+    /// Value of `format` in the tiles db metadata
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Internal => "bincode",
+            Self::Mvt => "pbf",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bincode" => Some(Self::Internal),
+            "pbf" => Some(Self::Mvt),
+            _ => None,
+        }
+    }
+}
+
+impl Default for TileWriterConfig {
+    fn default() -> Self {
+        TileWriterConfig {
+            zoom_range: TileGrid::default().zoom_levels(),
+            emit_tile_bounds_debug: false,
+            tile_format: TileFormat::default(),
+            tile_codec: TileCodec::default(),
+            dedup_geometries: false,
+            vacuum_after_build: false,
+            durable_output: false,
+            clip_polygon: None,
+            tile_scheme: TileScheme::default(),
+            grid: TileGrid::default(),
+            tile_padding: DEFAULT_TILE_PADDING,
+            db_path: PathBuf::from(TilesSQLiteStore::DEFAULT_DB_PATH),
+        }
+    }
 }
 
 impl Default for TileWriter {
@@ -38,7 +225,12 @@ impl Default for TileWriter {
 
 impl TileWriter {
     const MIN_ZOOM_FOR_PLANET_TILES: u32 = 10;
+    const COMPRESSION_CHUNK_SIZE: usize = 64;
     pub fn new() -> Self {
+        Self::with_config(TileWriterConfig::default())
+    }
+
+    pub fn with_config(config: TileWriterConfig) -> Self {
         let (tx, rx) = channel::<(TileKey, MapGeomObject, MapGeometry)>();
         TileWriter {
             thread_pool: ThreadPool::new(3),
@@ -46,9 +238,19 @@ impl TileWriter {
             receiver: rx,
             tile_db_map: FxHashMap::default(),
             tile_keys_cache: Arc::new(FxHashSet::default()),
+            config,
+            coverage: Vec::new(),
+            output: None,
         }
     }
 
+    /// Marks the area as built. It's written as `coverage` metadata instead of empty tiles,
+    /// readers treat missing tiles inside it as empty, so clients can tell "no data"
+    /// from a tile outside of the built areas
+    pub fn add_coverage(&mut self, rect: Rect) {
+        self.coverage.push(rect);
+    }
+
     pub fn add_to_tiles(
         &mut self,
         zoom_level: u32,
@@ -56,6 +258,30 @@ impl TileWriter {
         map_geometry: MapGeometry,
         can_create_new_tiles: bool,
     ) {
+        let padding = self.config.tile_padding;
+        self.add_to_tiles_with_padding(
+            zoom_level,
+            map_geom_object,
+            map_geometry,
+            can_create_new_tiles,
+            padding,
+        );
+    }
+
+    /// Same as [TileWriter::add_to_tiles] with the tile padding of the geometry,
+    /// see [TileWriterConfig::tile_padding]
+    pub fn add_to_tiles_with_padding(
+        &mut self,
+        zoom_level: u32,
+        map_geom_object: MapGeomObject,
+        map_geometry: MapGeometry,
+        can_create_new_tiles: bool,
+        padding: f64,
+    ) {
+        if !self.config.zoom_range.contains(&zoom_level) {
+            return;
+        }
+
         // if !can_create_new_tiles && zoom_level >= Self::MIN_ZOOM_FOR_GEOM_INDEX_DB {
         //     let key = MapIndexKey::new(zoom_level);
         //     let geom_index = self.geom_db_map.entry(key).or_default();
@@ -64,39 +290,194 @@ impl TileWriter {
 
         if !can_create_new_tiles && self.tile_keys_cache.is_empty() {
             let mut temp_map = FxHashSet::default();
-            temp_map.extend(self.tile_db_map.keys().copied());
+            temp_map.extend(self.tile_keys().copied());
             self.tile_keys_cache = Arc::new(temp_map);
         }
 
         let sender = self.sender.clone().unwrap();
         let tile_keys_cache = Arc::clone(&self.tile_keys_cache);
+        let clip_polygon = self.config.clip_polygon.clone();
+        let grid = self.config.grid;
 
         self.thread_pool.execute(move || {
-            let geom_rect = &map_geometry.bounding_rect().unwrap();
-            let ranges = calc_tile_ranges(TILES_COUNT, zoom_level as i32, geom_rect);
-            Self::fill_map(
-                &tile_keys_cache,
-                sender,
-                zoom_level as i32,
-                &map_geom_object,
-                map_geometry,
-                geom_rect,
-                ranges,
-                can_create_new_tiles,
-            );
+            let map_geometries = Self::split_at_antimeridian(map_geometry);
+            let map_geometries = match clip_polygon {
+                Some(clip_polygon) => map_geometries
+                    .into_iter()
+                    .flat_map(|map_geometry| Self::clip_to_polygon(map_geometry, &clip_polygon))
+                    .collect(),
+                None => map_geometries,
+            };
+            for map_geometry in map_geometries {
+                Self::fill_map(
+                    &tile_keys_cache,
+                    sender.clone(),
+                    zoom_level as i32,
+                    &map_geom_object,
+                    map_geometry,
+                    &grid,
+                    padding,
+                    can_create_new_tiles,
+                );
+            }
         });
     }
 
+    /// Geometry with a jump of more than half of the world between consecutive coords crosses
+    /// the antimeridian, it's split into the eastern and the western parts.
+    /// Geometry spanning most of the world without such jumps is left as is.
+    fn split_at_antimeridian(map_geometry: MapGeometry) -> Vec<MapGeometry> {
+        let crosses = |line: &LineString| {
+            line.lines()
+                .any(|segment| (segment.end.x - segment.start.x).abs() > 180.0)
+        };
+        let poly_crosses =
+            |poly: &Polygon| crosses(poly.exterior()) || poly.interiors().iter().any(crosses);
+        let crossing = match &map_geometry {
+            MapGeometry::Line(line) => crosses(line),
+            MapGeometry::Poly(poly) => poly_crosses(poly),
+            MapGeometry::Coord(_) => false,
+            MapGeometry::MultiPoly(multi_poly) => multi_poly.iter().any(poly_crosses),
+            MapGeometry::MultiLine(multi_line) => multi_line.iter().any(crosses),
+        };
+        if !crossing {
+            return vec![map_geometry];
+        }
+
+        // western longitudes continue the eastern ones beyond 180
+        let unwrap = |coord: Coord| {
+            if coord.x < 0.0 {
+                coord! {x: coord.x + 360.0, y: coord.y}
+            } else {
+                coord
+            }
+        };
+        let rewrap = |coord: Coord| coord! {x: coord.x - 360.0, y: coord.y};
+        let east = Rect::new(coord! {x: 0.0, y: -90.0}, coord! {x: 180.0, y: 90.0});
+        let west = Rect::new(coord! {x: 180.0, y: -90.0}, coord! {x: 360.0, y: 90.0});
+        match map_geometry {
+            MapGeometry::Line(line) => {
+                let line = line.map_coords(unwrap);
+                cohen_sutherland_clip(&line, &east)
+                    .into_iter()
+                    .map(MapGeometry::Line)
+                    .chain(
+                        cohen_sutherland_clip(&line, &west)
+                            .into_iter()
+                            .map(|line| MapGeometry::Line(line.map_coords(rewrap))),
+                    )
+                    .collect()
+            }
+            MapGeometry::Poly(poly) => {
+                let poly = poly.map_coords(unwrap);
+                east.to_polygon()
+                    .intersection(&poly)
+                    .into_iter()
+                    .map(MapGeometry::Poly)
+                    .chain(
+                        west.to_polygon()
+                            .intersection(&poly)
+                            .into_iter()
+                            .map(|poly| MapGeometry::Poly(poly.map_coords(rewrap))),
+                    )
+                    .collect()
+            }
+            MapGeometry::Coord(_) => vec![map_geometry],
+            MapGeometry::MultiPoly(multi_poly) => {
+                let multi_poly = multi_poly.map_coords(unwrap);
+                [
+                    east.to_polygon().intersection(&multi_poly),
+                    west.to_polygon().intersection(&multi_poly).map_coords(rewrap),
+                ]
+                .into_iter()
+                .filter(|part| !part.0.is_empty())
+                .map(MapGeometry::MultiPoly)
+                .collect()
+            }
+            MapGeometry::MultiLine(multi_line) => {
+                let multi_line = multi_line.map_coords(unwrap);
+                let clip = |rect: &Rect| {
+                    multi_line
+                        .iter()
+                        .flat_map(|line| cohen_sutherland_clip(line, rect))
+                        .collect_vec()
+                };
+                let west_lines = clip(&west).into_iter().map(|line| line.map_coords(rewrap)).collect();
+                Self::lines_geometry(clip(&east))
+                    .into_iter()
+                    .chain(Self::lines_geometry(west_lines))
+                    .collect()
+            }
+        }
+    }
+
+    /// Parts of one line stay one feature, a single part is kept as a plain line
+    fn lines_geometry(mut lines: Vec<LineString>) -> Option<MapGeometry> {
+        match lines.len() {
+            0 => None,
+            1 => lines.pop().map(MapGeometry::Line),
+            _ => Some(MapGeometry::MultiLine(MultiLineString::new(lines))),
+        }
+    }
+
+    fn clip_to_polygon(map_geometry: MapGeometry, clip_polygon: &Polygon) -> Vec<MapGeometry> {
+        let (Some(geom_rect), Some(clip_rect)) =
+            (map_geometry.bounding_rect(), clip_polygon.bounding_rect())
+        else {
+            return vec![];
+        };
+        if !geom_rect.intersects(&clip_rect) {
+            return vec![];
+        }
+        if clip_polygon.contains(&geom_rect) {
+            return vec![map_geometry];
+        }
+        match map_geometry {
+            MapGeometry::Line(line) => {
+                Self::lines_geometry(clip_polygon.clip(&MultiLineString(vec![line]), false).0)
+                    .into_iter()
+                    .collect()
+            }
+            MapGeometry::Poly(poly) => clip_polygon
+                .intersection(&poly)
+                .into_iter()
+                .map(MapGeometry::Poly)
+                .collect(),
+            MapGeometry::Coord(coord) => {
+                if clip_polygon.contains(&coord) {
+                    vec![MapGeometry::Coord(coord)]
+                } else {
+                    vec![]
+                }
+            }
+            MapGeometry::MultiPoly(multi_poly) => {
+                let clipped = clip_polygon.intersection(&multi_poly);
+                if clipped.0.is_empty() {
+                    vec![]
+                } else {
+                    vec![MapGeometry::MultiPoly(clipped)]
+                }
+            }
+            MapGeometry::MultiLine(multi_line) => {
+                Self::lines_geometry(clip_polygon.clip(&multi_line, false).0)
+                    .into_iter()
+                    .collect()
+            }
+        }
+    }
+
     fn fill_map(
         keys_cache: &Arc<FxHashSet<TileKey>>,
         sender: Sender<(TileKey, MapGeomObject, MapGeometry)>,
         zoom_level: i32,
         map_geom_object: &MapGeomObject,
         map_geometry: MapGeometry,
-        geom_rect: &Rect,
-        tile_ranges: TileRanges,
+        grid: &TileGrid,
+        padding: f64,
         force: bool,
     ) {
+        let geom_rect = &map_geometry.bounding_rect().unwrap();
+        let tile_ranges = grid.tile_ranges(zoom_level, geom_rect);
         for i in tile_ranges.min_x..tile_ranges.max_x + 1 {
             for j in tile_ranges.min_y..tile_ranges.max_y + 1 {
                 let key = TileKey::new(i as i32, j as i32, zoom_level);
@@ -104,11 +485,8 @@ impl TileWriter {
                     || zoom_level >= Self::MIN_ZOOM_FOR_PLANET_TILES as i32
                     || keys_cache.contains(&key)
                 {
-                    // increase a bit bbox for tile to reduce borders artefacts.
-                    // it's a good tradeoff between amount artefacts and over tesselation/drawing
                     // note: caching this calculation isn't helpful
-
-                    let tile_rect = key.calc_tile_boundary(1.01);
+                    let tile_rect = grid.tile_boundary(&key, padding);
 
                     for item in Self::intersection(&map_geometry, &tile_rect, geom_rect) {
                         sender.send((key, map_geom_object.clone(), item)).unwrap();
@@ -132,67 +510,124 @@ impl TileWriter {
             return vec![];
         }
         match map_geometry {
-            MapGeometry::Line(line) => {
-                let mut multi_line = MultiLineString(Vec::new());
-                let mut new_line = Vec::new();
-                let coords = line.coords().collect_vec();
-                for (index, &coord) in coords.iter().enumerate() {
-                    if index > 0 {
-                        let line = Line::new(*coords[index - 1], *coord);
-                        if !tile_rect.contains(&line.start)
-                            && !tile_rect.contains(&line.end)
-                            && Self::rect_intersection(tile_rect, &line).is_none()
-                        {
-                            if new_line.len() > 1 {
-                                multi_line.0.push(LineString(new_line));
-                            }
-                            new_line = Vec::new();
-                        }
-                    }
-                    new_line.push(*coord)
-                }
-                if new_line.len() > 1 {
-                    multi_line.0.push(LineString(new_line));
-                }
-                multi_line.0.into_iter().map(MapGeometry::Line).collect()
-            }
+            MapGeometry::Line(line) => Self::lines_geometry(cohen_sutherland_clip(line, tile_rect).0)
+                .into_iter()
+                .collect(),
             MapGeometry::Poly(poly) => {
-                let exterior = poly.exterior();
-
-                if let Some(intersected) = sutherland_hodgman_clip(exterior, tile_rect) {
-                    let intersected_inters = poly
-                        .interiors()
-                        .iter()
-                        .flat_map(|interior| sutherland_hodgman_clip(interior, tile_rect))
-                        .collect_vec();
-                    vec![MapGeometry::Poly(Polygon::new(
-                        intersected,
-                        intersected_inters,
-                    ))]
-                } else {
-                    vec![]
-                }
+                Self::clip_polygon_to_rect(poly, tile_rect)
+                    .map(MapGeometry::Poly)
+                    .into_iter()
+                    .collect()
 
                 // TODO make boolean intersection available with config a bit later
                 // let intersected = poly.clone().intersection(&rect.to_polygon());
                 // intersected.0.iter().map(|item| MapGeometry::Poly(item.clone())).collect()
             }
             MapGeometry::Coord(_) => vec![map_geometry.clone()],
+            MapGeometry::MultiPoly(multi_poly) => {
+                let clipped = multi_poly
+                    .iter()
+                    .filter_map(|poly| Self::clip_polygon_to_rect(poly, tile_rect))
+                    .collect_vec();
+                if clipped.is_empty() {
+                    vec![]
+                } else {
+                    vec![MapGeometry::MultiPoly(MultiPolygon::new(clipped))]
+                }
+            }
+            MapGeometry::MultiLine(multi_line) => {
+                let clipped = multi_line
+                    .iter()
+                    .flat_map(|line| cohen_sutherland_clip(line, tile_rect))
+                    .collect_vec();
+                Self::lines_geometry(clipped).into_iter().collect()
+            }
         }
     }
 
-    fn rect_intersection(rect: &Rect, line: &Line) -> Option<Coord> {
-        for rect_line in rect.to_lines() {
-            if let Some(intersection) = line_intersection(rect_line, *line) {
-                match intersection {
-                    LineIntersection::SinglePoint { intersection, .. } => {
-                        return Some(intersection);
-                    }
-                    LineIntersection::Collinear { .. } => {}
-                }
-            }
+    fn clip_polygon_to_rect(poly: &Polygon, tile_rect: &Rect) -> Option<Polygon> {
+        let intersected = sutherland_hodgman_clip(poly.exterior(), tile_rect)?;
+        let intersected_inters = poly
+            .interiors()
+            .iter()
+            .flat_map(|interior| sutherland_hodgman_clip(interior, tile_rect))
+            .collect_vec();
+        Some(Polygon::new(intersected, intersected_inters))
+    }
+
+    pub fn config(&self) -> &TileWriterConfig {
+        &self.config
+    }
+
+    /// Keys of the tiles collected so far, tiles being processed are included after [TileWriter::flush_to_collections].
+    /// Tiles already written to the db are included as well
+    pub fn tile_keys(&self) -> impl Iterator<Item = &TileKey> {
+        let written = self.output.iter().flat_map(|output| &output.tile_keys);
+        self.tile_db_map.keys().chain(written)
+    }
+
+    /// Writes the tiles collected so far to [TileWriterConfig::db_path] and drops them from memory,
+    /// so only the tiles of the area in progress are kept.
+    /// Features of a tile already in the db are added to it, [TileFormat::Mvt] tiles are replaced.
+    /// The db is completed by [TileWriter::save_to_path] with the same path
+    pub fn write_collected_tiles(&mut self) {
+        self.flush_to_collections(true);
+        let output = self
+            .output
+            .get_or_insert_with(|| TilesOutput::create(&self.config.db_path, self.config.durable_output));
+        Self::write_to_output(output, &mut self.tile_db_map, &self.config, None);
+        println!("\nCollected tiles written");
+    }
+
+    /// Same as [TileWriter::write_collected_tiles] recording the area as completed in the same transaction.
+    /// A db with checkpoints is continued instead of being replaced, so a build interrupted
+    /// after the checkpoint can skip the [TileWriter::completed_areas].
+    /// The db is written in WAL mode regardless of [TileWriterConfig::durable_output]
+    pub fn save_checkpoint(&mut self, area: &str) {
+        self.flush_to_collections(true);
+        let output = self
+            .output
+            .get_or_insert_with(|| TilesOutput::open(&self.config.db_path, &self.config));
+        Self::write_to_output(output, &mut self.tile_db_map, &self.config, Some(area));
+        println!("\nArea {area} saved");
+    }
+
+    /// Continues the db of an interrupted build at [TileWriterConfig::db_path], so it's extended
+    /// instead of being replaced even if no further checkpoint is saved
+    pub fn resume(&mut self) {
+        if self.output.is_none() {
+            self.output = Some(TilesOutput::open(&self.config.db_path, &self.config));
         }
-        None
+    }
+
+    fn write_to_output(
+        output: &mut TilesOutput,
+        tile_db_map: &mut FxHashMap<TileKey, MapGeometryCollection>,
+        config: &TileWriterConfig,
+        checkpoint: Option<&str>,
+    ) {
+        let append = !output.tile_keys.is_empty();
+        let tx = output.conn.transaction().unwrap();
+        Self::perform_queries(&tx, tile_db_map, config, &mut output.geom_ids, append);
+        if let Some(area) = checkpoint {
+            tx.execute("CREATE TABLE IF NOT EXISTS checkpoint (area TEXT NOT NULL)", ())
+                .unwrap();
+            tx.execute("INSERT INTO checkpoint (area) VALUES (?1)", (area,))
+                .unwrap();
+        }
+        tx.commit().unwrap();
+        output.tile_keys.extend(tile_db_map.drain().map(|(key, _)| key));
+    }
+
+    /// Areas recorded by [TileWriter::save_checkpoint] in the db at `path`,
+    /// empty if there is no db or it has no checkpoints
+    pub fn completed_areas(path: &Path) -> Vec<String> {
+        let Ok(conn) = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY) else {
+            return vec![];
+        };
+        conn.prepare("SELECT area FROM checkpoint")
+            .and_then(|mut stmt| stmt.query_map((), |row| row.get(0))?.collect())
+            .unwrap_or_default()
     }
 
     pub fn flush_to_collections(&mut self, recreate_channel: bool) {
@@ -211,79 +646,407 @@ impl TileWriter {
         }
     }
 
+    /// Writes the tiles db to [TileWriterConfig::db_path]
     pub fn save_to_file(&mut self) {
-        println!("Saving all DBs");
-        match fs::remove_dir_all(DBS_FOLDER) {
-            Ok(_) => {}
-            Err(_) => {
-                println!("Failed to remove DBs");
+        let db_path = self.config.db_path.clone();
+        self.save_to_path(&db_path);
+    }
+
+    /// Writes the tiles db to `path`, an existing db is replaced
+    pub fn save_to_path(&mut self, path: &Path) {
+        println!("Saving DB {:?}", path);
+        let mut output = match self.output.take() {
+            Some(output) => {
+                assert_eq!(output.path, path, "Written tiles are in another db");
+                output
             }
-        }
-        fs::create_dir_all(DBS_FOLDER).expect("Could not create dir dbs");
+            None => TilesOutput::create(path, self.config.durable_output),
+        };
 
         self.flush_to_collections(false);
         let tile_db_map_len = self.tile_db_map.len();
         println!("tile_db_map len = {:?}", tile_db_map_len);
 
-        let mut conn = Self::create_internal_tiles_db_connection();
-        let tx = conn.transaction().unwrap();
+        let append = !output.tile_keys.is_empty();
+        let tx = output.conn.transaction().unwrap();
 
-        Self::perform_queries(&tx, &mut self.tile_db_map);
+        Self::perform_queries(&tx, &mut self.tile_db_map, &self.config, &mut output.geom_ids, append);
+        // a resumed db has the metadata of the interrupted build
+        tx.execute("DELETE FROM metadata", ()).unwrap();
+        Self::write_metadata(
+            &tx,
+            self.tile_db_map.keys().chain(&output.tile_keys),
+            &self.config,
+        );
+        Self::write_coverage(&tx, &self.coverage, &self.config.zoom_range);
+        // the finished db isn't resumed anymore
+        tx.execute("DROP TABLE IF EXISTS checkpoint", ()).unwrap();
 
         tx.commit().unwrap();
+
+        Self::optimize_tiles_db(&output.conn, self.config.vacuum_after_build);
+        if output.durable {
+            // the finished db doesn't need the -wal and -shm files next to it,
+            // it's still valid in WAL mode if another connection keeps it open
+            if let Err(err) = output.conn.pragma_update(None, "journal_mode", "delete") {
+                println!("Tiles db is left in WAL mode: {err}");
+            }
+        }
     }
 
+    /// Writes the covered areas as `coverage` metadata, `left,bottom,right,top` rects separated by `;`,
+    /// and the zoom levels they are covered at as `coverage_zooms`, `min,max`
+    pub fn write_coverage(tx: &Transaction, coverage: &[Rect], zoom_range: &RangeInclusive<u32>) {
+        if coverage.is_empty() || zoom_range.is_empty() {
+            return;
+        }
+        let value = coverage
+            .iter()
+            .map(|rect| {
+                format!(
+                    "{},{},{},{}",
+                    rect.min().x,
+                    rect.min().y,
+                    rect.max().x,
+                    rect.max().y
+                )
+            })
+            .join(";");
+        tx.execute(
+            "INSERT INTO metadata (name, value) VALUES ('coverage', ?1), ('coverage_zooms', ?2)",
+            (value, format!("{},{}", zoom_range.start(), zoom_range.end())),
+        )
+        .unwrap();
+    }
+
+    /// Gathers statistics for the query planner so exact tile lookups use `tiles_index`
+    fn optimize_tiles_db(conn: &Connection, vacuum: bool) {
+        println!("\nOptimizing DB");
+        conn.execute("ANALYZE;", ()).unwrap();
+        if vacuum {
+            conn.execute("VACUUM;", ()).unwrap();
+        }
+    }
+
+    pub fn write_metadata<'a>(
+        tx: &Transaction,
+        tile_keys: impl Iterator<Item = &'a TileKey>,
+        config: &TileWriterConfig,
+    ) {
+        let mut zoom_range: Option<(i32, i32)> = None;
+        let mut bounds: Option<Rect> = None;
+        for key in tile_keys {
+            zoom_range = Some(zoom_range.map_or((key.zoom_level, key.zoom_level), |(min, max)| {
+                (min.min(key.zoom_level), max.max(key.zoom_level))
+            }));
+            let tile_rect = config.grid.tile_boundary(key, 1.0);
+            bounds = Some(bounds.map_or(tile_rect, |bounds| {
+                Rect::new(
+                    coord! {x: bounds.min().x.min(tile_rect.min().x), y: bounds.min().y.min(tile_rect.min().y)},
+                    coord! {x: bounds.max().x.max(tile_rect.max().x), y: bounds.max().y.max(tile_rect.max().y)},
+                )
+            }));
+        }
+
+        // SOURCE_DATE_EPOCH makes the metadata reproducible as well
+        let generated_at = std::env::var("SOURCE_DATE_EPOCH")
+            .ok()
+            .and_then(|epoch| epoch.parse::<u64>().ok())
+            .unwrap_or_else(|| {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs()
+            });
+        let mut metadata = vec![
+            ("format", config.tile_format.as_str().to_string()),
+            ("compression", config.tile_codec.as_str().to_string()),
+            ("geometry_store", config.geometry_store().to_string()),
+            ("scheme", config.tile_scheme.as_str().to_string()),
+            ("tiles_count", config.grid.tiles_count.to_string()),
+            ("world_bounds", world_bounds_metadata()),
+            ("generated_at", generated_at.to_string()),
+        ];
+        if let Some((min_zoom, max_zoom)) = zoom_range {
+            metadata.push(("minzoom", min_zoom.to_string()));
+            metadata.push(("maxzoom", max_zoom.to_string()));
+        }
+        if let Some(bounds) = bounds {
+            metadata.push((
+                "bounds",
+                format!(
+                    "{},{},{},{}",
+                    bounds.min().x,
+                    bounds.min().y,
+                    bounds.max().x,
+                    bounds.max().y
+                ),
+            ));
+        }
+
+        let mut stmt = tx
+            .prepare("INSERT INTO metadata (name, value) VALUES (?1, ?2)")
+            .unwrap();
+        for (name, value) in metadata {
+            stmt.execute((name, value)).unwrap();
+        }
+    }
+
+    /// `geom_ids` are the features already in the `geom` table.
+    /// Tiles are added to the tiles already in the db if `append`, see [TileWriter::save_checkpoint]
     fn perform_queries(
         tx: &Transaction,
         tile_db_map: &mut FxHashMap<TileKey, MapGeometryCollection>,
+        config: &TileWriterConfig,
+        geom_ids: &mut FxHashMap<u128, i64>,
+        append: bool,
+    ) {
+        let threads = std::thread::available_parallelism().map_or(1, |count| count.get());
+        Self::perform_queries_parallel(tx, tile_db_map, config, threads, geom_ids, append);
+    }
+
+    /// Tiles are compressed on `threads` workers in chunks, every compressed chunk is
+    /// inserted as a batch from the calling thread which owns the transaction.
+    /// Tiles are inserted in the key order regardless of the threads timing, so the same
+    /// tiles produce the same db.
+    fn perform_queries_parallel(
+        tx: &Transaction,
+        tile_db_map: &mut FxHashMap<TileKey, MapGeometryCollection>,
+        config: &TileWriterConfig,
+        threads: usize,
+        geom_ids: &mut FxHashMap<u128, i64>,
+        append: bool,
     ) {
         let mut stmt = tx
-            .prepare("INSERT INTO tiles (x, y, z, data) VALUES (?1, ?2, ?3, ?4)")
+            .prepare("INSERT OR REPLACE INTO tiles (x, y, z, data) VALUES (?1, ?2, ?3, ?4)")
+            .unwrap();
+        let mut existing_stmt = tx
+            .prepare("SELECT data FROM tiles WHERE x = ?1 AND y = ?2 AND z = ?3")
             .unwrap();
 
         let len = tile_db_map.len();
+        let mut entries = tile_db_map.iter_mut().collect::<Vec<_>>();
+        entries.sort_unstable_by_key(|(key, _)| (key.zoom_level, key.tile_x, key.tile_y));
+        let chunks = Mutex::new(entries.chunks_mut(Self::COMPRESSION_CHUNK_SIZE).enumerate());
+        let (sender, receiver) = channel::<(usize, Vec<(TileKey, TileData)>)>();
+        let mut geom_stmt = tx
+            .prepare("INSERT INTO geom (id, data) VALUES (?1, ?2)")
+            .unwrap();
         print!("Compressing: 0%");
-        tile_db_map
+        std::thread::scope(|scope| {
+            for _ in 0..threads.max(1) {
+                let sender = sender.clone();
+                let chunks = &chunks;
+                scope.spawn(move || loop {
+                    let Some((chunk_index, chunk)) = chunks.lock().unwrap().next() else {
+                        break;
+                    };
+                    let batch = chunk
+                        .iter_mut()
+                        .map(|(key, data)| (**key, Self::compress_tile(key, data, config)))
+                        .collect();
+                    sender.send((chunk_index, batch)).unwrap();
+                });
+            }
+            drop(sender);
+
+            let mut index = 0;
+            // batches finished ahead of the next one in order
+            let mut pending = BTreeMap::new();
+            let mut next_chunk = 0;
+            for (chunk_index, batch) in receiver {
+                pending.insert(chunk_index, batch);
+                while let Some(batch) = pending.remove(&next_chunk) {
+                    next_chunk += 1;
+                    for (key, tile_data) in batch {
+                        let compressed_data = match tile_data {
+                            TileData::Compressed(compressed_data) => compressed_data,
+                            TileData::Features(features) => {
+                                let ids = features
+                                    .into_iter()
+                                    .map(|feature| {
+                                        let next_id = geom_ids.len() as i64;
+                                        *geom_ids.entry(feature_hash(&feature)).or_insert_with(|| {
+                                            geom_stmt.execute((next_id, feature)).unwrap();
+                                            next_id
+                                        })
+                                    })
+                                    .collect::<Vec<_>>();
+                                config
+                                    .tile_codec
+                                    .compress(&bincode::serialize(&ids).unwrap())
+                                    .unwrap()
+                            }
+                        };
+                        let stored_key = config.grid.flip_y(&key, config.tile_scheme);
+                        let existing: Option<Vec<u8>> = if append {
+                            existing_stmt
+                                .query_row(
+                                    (stored_key.tile_x, stored_key.tile_y, stored_key.zoom_level),
+                                    |row| row.get(0),
+                                )
+                                .optional()
+                                .unwrap()
+                        } else {
+                            None
+                        };
+                        let compressed_data = match existing {
+                            Some(existing) if config.tile_format == TileFormat::Internal => {
+                                Self::append_tile(&existing, &compressed_data, config.tile_codec)
+                            }
+                            _ => compressed_data,
+                        };
+                        stmt.execute((
+                            stored_key.tile_x,
+                            stored_key.tile_y,
+                            stored_key.zoom_level,
+                            compressed_data,
+                        ))
+                        .unwrap();
+                        index += 1;
+                    }
+                }
+
+                let percent = ((index as f32 / len as f32) * 100.0).round() as i32;
+                print!("\rCompressing: {}%", percent);
+                io::stdout().flush().unwrap();
+            }
+        });
+    }
+
+    /// Blob with the features of both blobs, dedup blobs are concatenated the same way
+    /// since they are lists of `geom` ids
+    fn append_tile(existing: &[u8], blob: &[u8], codec: TileCodec) -> Vec<u8> {
+        let data = concat_features(
+            &decompress_tile(existing).unwrap(),
+            &decompress_tile(blob).unwrap(),
+        )
+        .unwrap();
+        codec.compress(&data).unwrap()
+    }
+
+    fn compress_tile(
+        key: &TileKey,
+        data: &mut MapGeometryCollection,
+        config: &TileWriterConfig,
+    ) -> TileData {
+        let tile_rect = config.grid.tile_boundary(key, 1.0);
+        if config.emit_tile_bounds_debug {
+            data.0.push((
+                MapGeomObject {
+                    id: -1,
+                    kind: MapGeomObjectKind::TileBounds,
+                },
+                MapGeometry::Poly(tile_rect.to_polygon()),
+            ));
+        }
+
+        data.0.sort_by(Self::feature_cmp);
+
+        let tile_rect_origin = lat_lon_to_world(&tile_rect.min());
+        data.0
             .iter_mut()
-            .enumerate()
-            .for_each(|(index, (key, data))| {
-                data.0.sort_by(|(a, _), (b, _)| a.cmp(b));
+            .for_each(|(_, geometry)| Self::convert_coords(geometry, tile_rect_origin));
 
-                let tile_rect = key.calc_tile_boundary(1.0);
-                let tile_rect_origin = Self::lat_lon_to_world(&tile_rect.min());
+        match config.tile_format {
+            TileFormat::Internal if config.dedup_geometries => TileData::Features(
                 data.0
-                    .iter_mut()
-                    .for_each(|(_, geometry)| Self::convert_coords(geometry, tile_rect_origin));
-
+                    .iter()
+                    .map(|(obj, geometry)| {
+                        bincode::serialize(&(obj, Self::convert_data(geometry))).unwrap()
+                    })
+                    .collect(),
+            ),
+            TileFormat::Internal => {
                 let data = MapGeometryCollection::<f32>(
                     data.0
                         .iter()
                         .map(|(obj, geometry)| (obj.clone(), Self::convert_data(geometry)))
                         .collect(),
                 );
-
-                let serialized = bincode::serialize(&data).unwrap();
+                TileData::Compressed(
+                    config
+                        .tile_codec
+                        .compress(&bincode::serialize(&data).unwrap())
+                        .unwrap(),
+                )
+            }
+            TileFormat::Mvt => {
+                let tile_max = lat_lon_to_world(&tile_rect.max()) - tile_rect_origin;
                 let mut encoder = GzEncoder::new(Vec::new(), Compression::new(1));
-                encoder.write_all(&serialized).unwrap();
-                let compressed_data = encoder.finish().unwrap();
-                stmt.execute((key.tile_x, key.tile_y, key.zoom_level, compressed_data))
-                    .unwrap();
+                encoder.write_all(&mvt::encode_tile(&data.0, tile_max)).unwrap();
+                TileData::Compressed(encoder.finish().unwrap())
+            }
+        }
+    }
 
-                let percent = ((index as f32 / len as f32) * 100.0).round() as i32;
-                print!("\rCompressing: {}%", percent);
-                io::stdout().flush().unwrap();
-            });
+    /// Total order of tile features so the tile blob doesn't depend on the order they were added in.
+    /// Features are ordered by kind first, the rest only breaks ties.
+    pub fn feature_cmp<T: CoordFloat + Serialize>(
+        a: &(MapGeomObject, MapGeometry<T>),
+        b: &(MapGeomObject, MapGeometry<T>),
+    ) -> Ordering {
+        a.0.cmp(&b.0)
+            .then(a.0.id.cmp(&b.0.id))
+            .then_with(|| Self::geometry_cmp(&a.1, &b.1))
+            // fields ignored by the kind order, e.g. names
+            .then_with(|| bincode::serialize(a).unwrap().cmp(&bincode::serialize(b).unwrap()))
+    }
+
+    fn geometry_cmp<T: CoordFloat>(a: &MapGeometry<T>, b: &MapGeometry<T>) -> Ordering {
+        let rank = |geometry: &MapGeometry<T>| match geometry {
+            MapGeometry::Coord(_) => 0,
+            MapGeometry::Line(_) => 1,
+            MapGeometry::Poly(_) => 2,
+            MapGeometry::MultiPoly(_) => 3,
+            MapGeometry::MultiLine(_) => 4,
+        };
+        let coords = |geometry: &MapGeometry<T>| -> Vec<Coord<T>> {
+            match geometry {
+                MapGeometry::Coord(coord) => vec![*coord],
+                MapGeometry::Line(line) => line.0.clone(),
+                MapGeometry::Poly(poly) => std::iter::once(poly.exterior())
+                    .chain(poly.interiors())
+                    .flat_map(|line| line.0.iter().copied())
+                    .collect(),
+                MapGeometry::MultiPoly(multi_poly) => multi_poly
+                    .iter()
+                    .flat_map(|poly| std::iter::once(poly.exterior()).chain(poly.interiors()))
+                    .flat_map(|line| line.0.iter().copied())
+                    .collect(),
+                MapGeometry::MultiLine(multi_line) => {
+                    multi_line.iter().flat_map(|line| line.0.iter().copied()).collect()
+                }
+            }
+        };
+        rank(a).cmp(&rank(b)).then_with(|| {
+            let (a, b) = (coords(a), coords(b));
+            // both f32 and f64 coords are ordered as f64, the conversion is lossless
+            let total_cmp = |a: T, b: T| {
+                let (a, b) = (a.to_f64().unwrap_or(f64::NAN), b.to_f64().unwrap_or(f64::NAN));
+                a.total_cmp(&b)
+            };
+            a.iter()
+                .zip(&b)
+                .map(|(a, b)| total_cmp(a.x, b.x).then(total_cmp(a.y, b.y)))
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or(a.len().cmp(&b.len()))
+        })
     }
 
     fn convert_coords(geometry: &mut MapGeometry, tile_rect_origin: geo::Coord) {
         match geometry {
             MapGeometry::Line(line) => line.coords_mut().for_each(|coord| {
-                *coord = Self::lat_lon_to_world(&coord) - tile_rect_origin;
+                *coord = lat_lon_to_world(&coord) - tile_rect_origin;
             }),
             MapGeometry::Poly(poly) => {
-                poly.map_coords_in_place(|coord| Self::lat_lon_to_world(&coord) - tile_rect_origin)
+                poly.map_coords_in_place(|coord| lat_lon_to_world(&coord) - tile_rect_origin)
             }
-            MapGeometry::Coord(coord) => *coord = Self::lat_lon_to_world(&coord) - tile_rect_origin,
+            MapGeometry::Coord(coord) => *coord = lat_lon_to_world(&coord) - tile_rect_origin,
+            MapGeometry::MultiPoly(multi_poly) => multi_poly
+                .map_coords_in_place(|coord| lat_lon_to_world(&coord) - tile_rect_origin),
+            MapGeometry::MultiLine(multi_line) => multi_line
+                .map_coords_in_place(|coord| lat_lon_to_world(&coord) - tile_rect_origin),
         }
     }
 
@@ -298,28 +1061,45 @@ impl TileWriter {
             MapGeometry::Coord(coord) => {
                 MapGeometry::Coord(coord! {x: coord.x as f32, y: coord.y as f32})
             }
+            MapGeometry::MultiPoly(multi_poly) => MapGeometry::MultiPoly(multi_poly.map_coords(|coord| {
+                coord! {x: coord.x as f32, y: coord.y as f32}
+            })),
+            MapGeometry::MultiLine(multi_line) => MapGeometry::MultiLine(multi_line.map_coords(|coord| {
+                coord! {x: coord.x as f32, y: coord.y as f32}
+            })),
         }
     }
 
-    fn lat_lon_to_world(lat_lon: &Coord<f64>) -> Coord<f64> {
-        let lat_lon: (f64, f64) = (*lat_lon).into();
-        Mercator::with_size(1)
-            .from_ll_to_subpixel(&lat_lon, 22)
-            .unwrap()
-            .into()
+    fn create_internal_tiles_db_connection(path: &Path, durable: bool) -> Connection {
+        let conn = Self::open_internal_tiles_db_connection(path, durable);
+
+        conn.execute("VACUUM;", ()).unwrap();
+
+        Self::create_tables(&conn);
+
+        conn
     }
 
-    fn create_internal_tiles_db_connection() -> Connection {
-        let conn = create_tiles_db_connection();
+    /// Fast connection without a journal, a killed process leaves a corrupt db unless `durable`
+    fn open_internal_tiles_db_connection(path: &Path, durable: bool) -> Connection {
+        let conn = create_tiles_db_connection(path);
 
-        conn.execute("PRAGMA synchronous = OFF;", ()).unwrap();
+        if durable {
+            conn.execute("PRAGMA synchronous = NORMAL;", ()).unwrap();
+        } else {
+            conn.execute("PRAGMA synchronous = OFF;", ()).unwrap();
+        }
 
         conn.execute("PRAGMA page_size = 65536;", ()).unwrap();
 
-        conn.pragma_update(None, "journal_mode", "off").unwrap();
+        let journal_mode = if durable { "wal" } else { "off" };
+        conn.pragma_update(None, "journal_mode", journal_mode).unwrap();
 
-        conn.execute("VACUUM;", ()).unwrap();
+        conn
+    }
 
+    /// Creates empty `tiles`, `geom` and `metadata` tables, existing ones are dropped
+    pub fn create_tables(conn: &Connection) {
         conn.execute("DROP TABLE IF EXISTS tiles;", ()).unwrap();
 
         conn.execute(
@@ -336,6 +1116,686 @@ impl TileWriter {
         conn.execute("CREATE UNIQUE INDEX tiles_index ON tiles(x, y, z);", ())
             .unwrap();
 
-        conn
+        conn.execute("DROP TABLE IF EXISTS geom;", ()).unwrap();
+
+        conn.execute(
+            "CREATE TABLE geom (
+                     id  INTEGER PRIMARY KEY,
+                     data  BLOB NOT NULL
+                   )",
+            (),
+        )
+        .unwrap();
+
+        conn.execute("DROP TABLE IF EXISTS metadata;", ()).unwrap();
+
+        conn.execute(
+            "CREATE TABLE metadata (
+                     name  TEXT NOT NULL,
+                     value  TEXT
+                   )",
+            (),
+        )
+        .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::map::{
+        MapGeomObject, MapGeomObjectKind, MapGeometry, MapGeometryCollection, COUNTRY_ADMIN_LEVEL,
+    };
+    use crate::source::tiles_sqlite_store::TilesSQLiteStore;
+    use crate::tile_writer::tile_writer::{TileWriter, TileWriterConfig};
+    use crate::tiles::{TileGrid, TileKey, TileScheme, TileStore};
+    use geo::{coord, line_string, point, polygon, BoundingRect, Contains, Intersects, Rect};
+    use rusqlite::Connection;
+    use rustc_hash::FxHashMap;
+    use std::fs;
+
+    #[test]
+    fn test_metadata() {
+        let path = std::env::temp_dir().join(format!("osm_tiles_metadata_{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut conn = Connection::open(&path).unwrap();
+        TileWriter::create_tables(&conn);
+
+        let mut tile_db_map = FxHashMap::default();
+        for key in [TileKey::new(100, 200, 2), TileKey::new(10, 20, 6)] {
+            let rect = key.calc_tile_boundary(1.0);
+            tile_db_map.insert(
+                key,
+                MapGeometryCollection(vec![(
+                    MapGeomObject {
+                        id: 1,
+                        kind: MapGeomObjectKind::AdminLine(COUNTRY_ADMIN_LEVEL),
+                    },
+                    MapGeometry::Coord(coord! {x: rect.center().x, y: rect.center().y}),
+                )]),
+            );
+        }
+        let config = TileWriterConfig::default();
+        let tx = conn.transaction().unwrap();
+        TileWriter::perform_queries(&tx, &mut tile_db_map, &config, &mut FxHashMap::default(), false);
+        TileWriter::write_metadata(&tx, tile_db_map.keys(), &config);
+        tx.commit().unwrap();
+        drop(conn);
+
+        let metadata = TilesSQLiteStore::new(&path).metadata().unwrap();
+        assert_eq!(metadata["minzoom"], "2");
+        assert_eq!(metadata["maxzoom"], "6");
+        assert_eq!(metadata["format"], "bincode");
+        assert!(metadata.contains_key("bounds"));
+        assert!(metadata.contains_key("generated_at"));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_tile_scheme() {
+        let path = std::env::temp_dir().join(format!("osm_tiles_scheme_{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut conn = Connection::open(&path).unwrap();
+        TileWriter::create_tables(&conn);
+
+        let key = TileKey::new(3, 5, 10);
+        let mut tile_db_map = FxHashMap::default();
+        tile_db_map.insert(key, MapGeometryCollection(vec![]));
+        let config = TileWriterConfig {
+            tile_scheme: TileScheme::Xyz,
+            ..Default::default()
+        };
+        let tx = conn.transaction().unwrap();
+        TileWriter::perform_queries(&tx, &mut tile_db_map, &config, &mut FxHashMap::default(), false);
+        TileWriter::write_metadata(&tx, tile_db_map.keys(), &config);
+        tx.commit().unwrap();
+        drop(conn);
+
+        let store = TilesSQLiteStore::new(&path);
+        assert_eq!(store.scheme(), TileScheme::Xyz);
+        // 32 rows at zoom level 10
+        assert!(store.get_tile(3, 26, 10).is_ok());
+        assert!(store.get_tile(3, 5, 10).is_err());
+        // TMS row is the internal one
+        assert_eq!(TileKey::new(3, 26, 10).flip_y(TileScheme::Xyz), key);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_parallel_queries() {
+        let tile_db_map = || {
+            let mut tile_db_map = FxHashMap::default();
+            for x in 0..200 {
+                let key = TileKey::new(1000 + x, 20000, 0);
+                let rect = key.calc_tile_boundary(1.0);
+                tile_db_map.insert(
+                    key,
+                    MapGeometryCollection(vec![
+                        (
+                            MapGeomObject {
+                                id: x as i64,
+                                kind: MapGeomObjectKind::AdminLine(COUNTRY_ADMIN_LEVEL),
+                            },
+                            MapGeometry::Line(line_string![
+                                (x: rect.min().x, y: rect.min().y),
+                                (x: rect.max().x, y: rect.max().y),
+                            ]),
+                        ),
+                        (
+                            MapGeomObject {
+                                id: 1,
+                                kind: MapGeomObjectKind::TileBounds,
+                            },
+                            MapGeometry::Coord(coord! {x: rect.center().x, y: rect.center().y}),
+                        ),
+                    ]),
+                );
+            }
+            tile_db_map
+        };
+        let rows = |threads: usize| {
+            let mut conn = Connection::open_in_memory().unwrap();
+            TileWriter::create_tables(&conn);
+            let tx = conn.transaction().unwrap();
+            TileWriter::perform_queries_parallel(
+                &tx,
+                &mut tile_db_map(),
+                &TileWriterConfig::default(),
+                threads,
+                &mut FxHashMap::default(),
+                false,
+            );
+            tx.commit().unwrap();
+            let mut stmt = conn
+                .prepare("SELECT x, y, z, data FROM tiles ORDER BY z, x, y")
+                .unwrap();
+            let rows = stmt
+                .query_map((), |row| {
+                    Ok((
+                        row.get::<_, i32>(0)?,
+                        row.get::<_, i32>(1)?,
+                        row.get::<_, i32>(2)?,
+                        row.get::<_, Vec<u8>>(3)?,
+                    ))
+                })
+                .unwrap()
+                .map(|row| row.unwrap())
+                .collect::<Vec<_>>();
+            rows
+        };
+
+        let serial = rows(1);
+        assert_eq!(serial.len(), 200);
+        assert_eq!(rows(4), serial);
+    }
+
+    #[test]
+    fn test_deterministic_output() {
+        let features = |key: &TileKey| {
+            let rect = key.calc_tile_boundary(1.0);
+            // same kind and id, only the geometry tells them apart
+            (0..3)
+                .map(|i| {
+                    (
+                        MapGeomObject {
+                            id: 7,
+                            kind: MapGeomObjectKind::AdminLine(COUNTRY_ADMIN_LEVEL),
+                        },
+                        MapGeometry::Coord(coord! {
+                            x: rect.min().x + rect.width() * (i + 1) as f64 / 4.0,
+                            y: rect.center().y,
+                        }),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let rows = |threads: usize, reversed: bool| {
+            let mut keys = (0..300)
+                .map(|x| TileKey::new(1000 + x, 20000 + x % 3, 0))
+                .collect::<Vec<_>>();
+            if reversed {
+                keys.reverse();
+            }
+            let mut tile_db_map = FxHashMap::default();
+            for key in keys {
+                let mut features = features(&key);
+                if reversed {
+                    features.reverse();
+                }
+                tile_db_map.insert(key, MapGeometryCollection(features));
+            }
+
+            let mut conn = Connection::open_in_memory().unwrap();
+            TileWriter::create_tables(&conn);
+            let config = TileWriterConfig {
+                dedup_geometries: true,
+                ..Default::default()
+            };
+            let tx = conn.transaction().unwrap();
+            TileWriter::perform_queries_parallel(&tx, &mut tile_db_map, &config, threads, &mut FxHashMap::default(), false);
+            tx.commit().unwrap();
+            let mut stmt = conn
+                .prepare("SELECT x, y, z, data FROM tiles ORDER BY rowid")
+                .unwrap();
+            let tiles = stmt
+                .query_map((), |row| {
+                    Ok((
+                        row.get::<_, i32>(0)?,
+                        row.get::<_, i32>(1)?,
+                        row.get::<_, i32>(2)?,
+                        row.get::<_, Vec<u8>>(3)?,
+                    ))
+                })
+                .unwrap()
+                .map(|row| row.unwrap())
+                .collect::<Vec<_>>();
+            let mut stmt = conn.prepare("SELECT data FROM geom ORDER BY id").unwrap();
+            let geom = stmt
+                .query_map((), |row| row.get::<_, Vec<u8>>(0))
+                .unwrap()
+                .map(|row| row.unwrap())
+                .collect::<Vec<_>>();
+            (tiles, geom)
+        };
+
+        let serial = rows(1, false);
+        assert_eq!(serial.0.len(), 300);
+        assert_eq!(rows(4, false), serial);
+        assert_eq!(rows(4, true), serial);
+    }
+
+    #[test]
+    fn test_dedup_geometries() {
+        let path = std::env::temp_dir().join(format!("osm_tiles_dedup_{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut conn = Connection::open(&path).unwrap();
+        TileWriter::create_tables(&conn);
+
+        // both tiles have the same min corner, so the shared feature has the same tile coords
+        let small_key = TileKey::new(2000, 2000, 0);
+        let large_key = TileKey::new(1000, 1000, 1);
+        let rect = small_key.calc_tile_boundary(1.0);
+        let feature = |id: i64, x: f64| {
+            (
+                MapGeomObject {
+                    id,
+                    kind: MapGeomObjectKind::AdminLine(COUNTRY_ADMIN_LEVEL),
+                },
+                MapGeometry::Coord(coord! {x: x, y: rect.center().y}),
+            )
+        };
+        let shared = feature(1, rect.center().x);
+        let unique = feature(2, rect.min().x + rect.width() / 4.0);
+        let mut tile_db_map = FxHashMap::default();
+        tile_db_map.insert(small_key, MapGeometryCollection(vec![shared.clone()]));
+        tile_db_map.insert(large_key, MapGeometryCollection(vec![shared.clone(), unique.clone()]));
+
+        let config = TileWriterConfig {
+            dedup_geometries: true,
+            ..Default::default()
+        };
+        let tx = conn.transaction().unwrap();
+        TileWriter::perform_queries(&tx, &mut tile_db_map, &config, &mut FxHashMap::default(), false);
+        TileWriter::write_metadata(&tx, tile_db_map.keys(), &config);
+        tx.commit().unwrap();
+        let geom_count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM geom", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(geom_count, 2);
+        drop(conn);
+
+        let tile_store = TileStore::new(TilesSQLiteStore::new(&path));
+        let ids = |key: &TileKey| {
+            tile_store
+                .load_geometries(key)
+                .iter()
+                .map(|(obj, _)| obj.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(&small_key), vec![1]);
+        assert_eq!(ids(&large_key), vec![1, 2]);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_split_at_antimeridian() {
+        let grid = TileGrid::default();
+        let zoom_level = 10;
+        let poly = polygon![
+            (x: 170.0, y: 10.0),
+            (x: -170.0, y: 10.0),
+            (x: -170.0, y: 20.0),
+            (x: 170.0, y: 20.0),
+        ];
+        let ranges = grid.tile_ranges(zoom_level, &poly.bounding_rect().unwrap());
+        assert_eq!(ranges.max_x - ranges.min_x + 1, 32);
+
+        let parts = TileWriter::split_at_antimeridian(MapGeometry::Poly(poly));
+        assert_eq!(parts.len(), 2);
+        let rects = parts
+            .iter()
+            .map(|part| part.bounding_rect().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!((rects[0].min().x, rects[0].max().x), (170.0, 180.0));
+        assert_eq!((rects[1].min().x, rects[1].max().x), (-180.0, -170.0));
+        for rect in rects {
+            let ranges = grid.tile_ranges(zoom_level, &rect);
+            assert_eq!(ranges.max_x, ranges.min_x);
+        }
+
+        let line = line_string![(x: 179.0, y: 0.0), (x: -179.0, y: 2.0)];
+        let parts = TileWriter::split_at_antimeridian(MapGeometry::Line(line));
+        assert_eq!(
+            parts,
+            vec![
+                MapGeometry::Line(line_string![(x: 179.0, y: 0.0), (x: 180.0, y: 1.0)]),
+                MapGeometry::Line(line_string![(x: -180.0, y: 1.0), (x: -179.0, y: 2.0)]),
+            ]
+        );
+
+        // spans most of the world without crossing the antimeridian
+        let line = MapGeometry::Line(line_string![(x: -170.0, y: 0.0), (x: 0.0, y: 0.0), (x: 170.0, y: 0.0)]);
+        assert_eq!(TileWriter::split_at_antimeridian(line.clone()), vec![line]);
+    }
+
+    #[test]
+    fn test_tile_padding() {
+        let grid = TileGrid::default();
+        let key = TileKey::new(1000, 20000, 0);
+        let left_key = TileKey::new(999, 20000, 0);
+        let rect = grid.tile_boundary(&key, 1.0);
+        // ends exactly on the left edge of the tile
+        let line = MapGeometry::Line(line_string![
+            (x: rect.min().x - rect.width() / 2.0, y: rect.center().y),
+            (x: rect.min().x, y: rect.center().y),
+        ]);
+
+        for (padding, included) in [(1.0, false), (1.01, true)] {
+            let mut tile_writer = TileWriter::with_config(TileWriterConfig {
+                tile_padding: padding,
+                ..Default::default()
+            });
+            tile_writer.add_to_tiles(
+                0,
+                MapGeomObject {
+                    id: 1,
+                    kind: MapGeomObjectKind::AdminLine(COUNTRY_ADMIN_LEVEL),
+                },
+                line.clone(),
+                true,
+            );
+            tile_writer.flush_to_collections(false);
+            assert!(tile_writer.tile_db_map.contains_key(&left_key));
+            assert_eq!(tile_writer.tile_db_map.contains_key(&key), included);
+        }
+    }
+
+    #[test]
+    fn test_save_to_db_path() {
+        let dir = std::env::temp_dir().join(format!("osm_tiles_db_path_{}", std::process::id()));
+        let path = dir.join("nested").join("city.db");
+        let mut tile_writer = TileWriter::with_config(TileWriterConfig {
+            db_path: path.clone(),
+            ..Default::default()
+        });
+        let grid = TileGrid::default();
+        let key = TileKey::new(1000, 20000, 0);
+        tile_writer.add_to_tiles(
+            0,
+            MapGeomObject {
+                id: 7,
+                kind: MapGeomObjectKind::AdminLine(COUNTRY_ADMIN_LEVEL),
+            },
+            MapGeometry::Coord(grid.tile_boundary(&key, 1.0).center()),
+            true,
+        );
+        tile_writer.save_to_file();
+
+        let tile_store = TileStore::new(TilesSQLiteStore::new(&path));
+        let geometries = tile_store.load_geometries(&key);
+        assert_eq!(geometries.len(), 1);
+        assert_eq!(geometries[0].0.id, 7);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_empty_covered_tiles() {
+        let path = std::env::temp_dir().join(format!("osm_tiles_coverage_{}.db", std::process::id()));
+        let mut tile_writer = TileWriter::with_config(TileWriterConfig {
+            db_path: path.clone(),
+            zoom_range: 0..=0,
+            ..Default::default()
+        });
+        let grid = TileGrid::default();
+        let key = TileKey::new(1000, 20000, 0);
+        let empty_key = TileKey::new(1001, 20000, 0);
+        let coverage = Rect::new(
+            grid.tile_boundary(&key, 1.0).center(),
+            grid.tile_boundary(&empty_key, 1.0).center(),
+        );
+        tile_writer.add_coverage(coverage);
+        tile_writer.add_to_tiles(
+            0,
+            MapGeomObject {
+                id: 7,
+                kind: MapGeomObjectKind::AdminLine(COUNTRY_ADMIN_LEVEL),
+            },
+            MapGeometry::Coord(grid.tile_boundary(&key, 1.0).center()),
+            true,
+        );
+        tile_writer.save_to_file();
+
+        let sqlite_store = TilesSQLiteStore::new(&path);
+        assert_eq!(sqlite_store.coverage().unwrap(), vec![coverage]);
+        // only the tile with features is stored
+        assert_eq!(sqlite_store.count_tiles().unwrap(), 1);
+        assert!(sqlite_store.has_tile(1001, 20000, 0).unwrap());
+        assert!(!sqlite_store.has_tile(1001, 20000, 1).unwrap());
+        let tile_store = TileStore::new(sqlite_store);
+        assert_eq!(tile_store.try_load_geometries(&key).unwrap().len(), 1);
+        assert!(tile_store.try_load_geometries(&empty_key).unwrap().is_empty());
+        assert!(tile_store
+            .try_load_geometries(&TileKey::new(1002, 20000, 0))
+            .is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_clip_to_polygon() {
+        let clip_polygon = polygon![(x: 0.0, y: 0.0), (x: 10.0, y: 0.0), (x: 0.0, y: 10.0)];
+
+        let inside = MapGeometry::Coord(coord! {x: 1.0, y: 1.0});
+        assert_eq!(TileWriter::clip_to_polygon(inside.clone(), &clip_polygon), vec![inside]);
+        let outside = MapGeometry::Coord(coord! {x: 8.0, y: 8.0});
+        assert!(TileWriter::clip_to_polygon(outside, &clip_polygon).is_empty());
+
+        let line = MapGeometry::Line(line_string![(x: 1.0, y: 1.0), (x: 9.0, y: 9.0)]);
+        let clipped = TileWriter::clip_to_polygon(line, &clip_polygon);
+        assert_eq!(clipped.len(), 1);
+        let MapGeometry::Line(clipped_line) = &clipped[0] else {
+            panic!("Expect line");
+        };
+        assert!(clipped_line.coords().all(|coord| coord.x <= 5.0 + 1e-9));
+
+        let poly = MapGeometry::Poly(polygon![
+            (x: 4.0, y: 4.0),
+            (x: 20.0, y: 4.0),
+            (x: 20.0, y: 20.0),
+            (x: 4.0, y: 20.0),
+        ]);
+        let clipped = TileWriter::clip_to_polygon(poly, &clip_polygon);
+        assert_eq!(clipped.len(), 1);
+        let MapGeometry::Poly(clipped_poly) = &clipped[0] else {
+            panic!("Expect polygon");
+        };
+        assert!(clipped_poly.intersects(&point!(x: 4.5, y: 4.5)));
+        assert!(!clipped_poly.intersects(&point!(x: 6.0, y: 6.0)));
+    }
+
+    #[test]
+    fn test_clip_line_to_multiline() {
+        let tile_rect = Rect::new(coord! {x: 0.0, y: 0.0}, coord! {x: 10.0, y: 10.0});
+        // the line leaves the tile and comes back
+        let line = MapGeometry::Line(line_string![
+            (x: 1.0, y: 5.0),
+            (x: 5.0, y: 5.0),
+            (x: 5.0, y: 15.0),
+            (x: 8.0, y: 15.0),
+            (x: 8.0, y: 5.0),
+        ]);
+        let geom_rect = line.bounding_rect().unwrap();
+
+        let clipped = TileWriter::intersection(&line, &tile_rect, &geom_rect);
+        assert_eq!(clipped.len(), 1);
+        let MapGeometry::MultiLine(multi_line) = &clipped[0] else {
+            panic!("Expect multiline, got {:?}", clipped[0]);
+        };
+        assert_eq!(multi_line.0.len(), 2);
+        assert!(tile_rect.contains(&multi_line.bounding_rect().unwrap()));
+
+        let clipped = TileWriter::intersection(&clipped[0], &tile_rect, &geom_rect);
+        assert_eq!(clipped.len(), 1);
+        assert!(matches!(clipped[0], MapGeometry::MultiLine(_)));
+
+        // a single part stays a line
+        let tile_rect = Rect::new(coord! {x: 3.0, y: 0.0}, coord! {x: 20.0, y: 20.0});
+        let clipped = TileWriter::intersection(&line, &tile_rect, &geom_rect);
+        assert!(matches!(clipped[..], [MapGeometry::Line(_)]));
+    }
+
+    #[test]
+    fn test_write_collected_tiles() {
+        let path = std::env::temp_dir().join(format!("osm_tiles_incremental_{}.db", std::process::id()));
+        let mut tile_writer = TileWriter::with_config(TileWriterConfig {
+            db_path: path.clone(),
+            zoom_range: 0..=3,
+            dedup_geometries: true,
+            ..Default::default()
+        });
+        let grid = TileGrid::default();
+        // every area has 8 neighbouring tiles at zoom level 0, the first two areas
+        // share a tile at zoom level 3
+        let area_coords = |area: i32| {
+            (0..8)
+                .map(|i| grid.tile_boundary(&TileKey::new(1004 + area * 8 + i, 20000, 0), 1.0).center())
+                .collect::<Vec<_>>()
+        };
+
+        let mut peak_len = 0;
+        for area in 0..4 {
+            for (i, coord) in area_coords(area).iter().enumerate() {
+                for zoom_level in 0..=3 {
+                    tile_writer.add_to_tiles(
+                        zoom_level,
+                        MapGeomObject {
+                            id: (area * 8) as i64 + i as i64,
+                            kind: MapGeomObjectKind::AdminLine(COUNTRY_ADMIN_LEVEL),
+                        },
+                        MapGeometry::Coord(*coord),
+                        true,
+                    );
+                }
+            }
+            tile_writer.flush_to_collections(true);
+            peak_len = peak_len.max(tile_writer.tile_db_map.len());
+            tile_writer.write_collected_tiles();
+            assert!(tile_writer.tile_db_map.is_empty());
+        }
+        tile_writer.save_to_file();
+
+        // tiles of one area at a time: 8 + 4 + 2 + 2
+        assert_eq!(peak_len, 16);
+        let sqlite_store = TilesSQLiteStore::new(&path);
+        assert_eq!(sqlite_store.count_tiles().unwrap(), 32 + 16 + 8 + 5);
+        let tile_store = TileStore::new(sqlite_store);
+        let mut ids = tile_store
+            .try_load_geometries(&TileKey::new(126, 2500, 3))
+            .unwrap()
+            .into_iter()
+            .map(|(obj, _)| obj.id)
+            .collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(ids, (4..12).collect::<Vec<_>>());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_durable_output_interrupted() {
+        let path = std::env::temp_dir().join(format!("osm_tiles_durable_{}.db", std::process::id()));
+        let wal_path = path.with_extension("db-wal");
+        let config = TileWriterConfig {
+            db_path: path.clone(),
+            zoom_range: 0..=1,
+            durable_output: true,
+            ..Default::default()
+        };
+        let grid = TileGrid::default();
+        let key = TileKey::new(1000, 20000, 0);
+        let add_feature = |tile_writer: &mut TileWriter, zoom_level| {
+            tile_writer.add_to_tiles(
+                zoom_level,
+                MapGeomObject {
+                    id: 7,
+                    kind: MapGeomObjectKind::AdminLine(COUNTRY_ADMIN_LEVEL),
+                },
+                MapGeometry::Coord(grid.tile_boundary(&key, 1.0).center()),
+                true,
+            );
+        };
+
+        let mut tile_writer = TileWriter::with_config(config.clone());
+        add_feature(&mut tile_writer, 0);
+        add_feature(&mut tile_writer, 1);
+        tile_writer.write_collected_tiles();
+        // the process is killed before the db is completed, nothing is closed
+        std::mem::forget(tile_writer);
+        assert!(wal_path.exists());
+
+        let tile_store = TileStore::new(TilesSQLiteStore::new(&path));
+        let geometries = tile_store.try_load_geometries(&key).unwrap();
+        assert_eq!(geometries.len(), 1);
+        assert_eq!(geometries[0].0.id, 7);
+        drop(tile_store);
+
+        let mut tile_writer = TileWriter::with_config(config);
+        add_feature(&mut tile_writer, 0);
+        tile_writer.save_to_file();
+        assert!(!wal_path.exists());
+        let tile_store = TileStore::new(TilesSQLiteStore::new(&path));
+        assert_eq!(tile_store.try_load_geometries(&key).unwrap().len(), 1);
+
+        assert!(!path.with_extension("db-shm").exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_resume_from_checkpoint() {
+        let path = std::env::temp_dir().join(format!("osm_tiles_checkpoint_{}.db", std::process::id()));
+        // checkpoints are durable without durable_output
+        let config = TileWriterConfig {
+            db_path: path.clone(),
+            zoom_range: 0..=1,
+            ..Default::default()
+        };
+        let grid = TileGrid::default();
+        let first_key = TileKey::new(1000, 20000, 0);
+        let second_key = TileKey::new(1001, 20000, 0);
+        // both areas have a feature in the same tile at zoom level 1
+        let shared_key = TileKey::new(500, 10000, 1);
+        let add_area = |tile_writer: &mut TileWriter, id, key: &TileKey| {
+            for zoom_level in 0..=1 {
+                tile_writer.add_to_tiles(
+                    zoom_level,
+                    MapGeomObject {
+                        id,
+                        kind: MapGeomObjectKind::AdminLine(COUNTRY_ADMIN_LEVEL),
+                    },
+                    MapGeometry::Coord(grid.tile_boundary(key, 1.0).center()),
+                    true,
+                );
+            }
+        };
+
+        let mut tile_writer = TileWriter::with_config(config.clone());
+        add_area(&mut tile_writer, 1, &first_key);
+        tile_writer.save_checkpoint("first");
+        // the process is killed while the second area is processed
+        add_area(&mut tile_writer, 2, &second_key);
+        std::mem::forget(tile_writer);
+
+        let journal_mode: String = Connection::open(&path)
+            .unwrap()
+            .pragma_query_value(None, "journal_mode", |row| row.get(0))
+            .unwrap();
+        assert_eq!(journal_mode, "wal");
+        assert_eq!(TileWriter::completed_areas(&path), vec!["first".to_string()]);
+        let mut tile_writer = TileWriter::with_config(config);
+        add_area(&mut tile_writer, 2, &second_key);
+        tile_writer.save_checkpoint("second");
+        assert_eq!(TileWriter::completed_areas(&path), vec!["first", "second"]);
+        tile_writer.save_to_file();
+        assert!(TileWriter::completed_areas(&path).is_empty());
+
+        let sqlite_store = TilesSQLiteStore::new(&path);
+        assert_eq!(sqlite_store.count_tiles().unwrap(), 3);
+        assert_eq!(sqlite_store.metadata().unwrap()["minzoom"], "0");
+        let tile_store = TileStore::new(sqlite_store);
+        let ids = |key: &TileKey| {
+            let mut ids = tile_store
+                .try_load_geometries(key)
+                .unwrap()
+                .into_iter()
+                .map(|(obj, _)| obj.id)
+                .collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+        assert_eq!(ids(&first_key), vec![1]);
+        assert_eq!(ids(&second_key), vec![2]);
+        assert_eq!(ids(&shared_key), vec![1, 2]);
+        fs::remove_file(&path).unwrap();
     }
 }
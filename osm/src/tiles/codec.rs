@@ -0,0 +1,224 @@
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::io::{Read, Write};
+
+const GZIP_TAG: u8 = 0;
+const ZSTD_TAG: u8 = 1;
+/// Uncompressed data, only for tiles received decompressed from the tiles server
+const RAW_TAG: u8 = 2;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_LEVEL: i32 = 3;
+/// Leading bytes of tile blobs with a format header
+const TILE_MAGIC: [u8; 3] = *b"SHT";
+/// Version of the tile blob format, it's bumped with every change of the serialized map types.
+/// Blobs without the header are version 0, they were written with map types before
+/// building info, admin levels and station kinds and can't be decoded anymore.
+/// Version 1 has [crate::map::MapGeomObjectKind::Building] info, admin levels,
+/// station kinds and multi geometries
+pub const TILE_FORMAT_VERSION: u8 = 1;
+/// Value of `geometry_store` metadata for dbs with tile blobs holding `geom` table ids
+pub const GEOMETRY_STORE_DEDUP: &str = "dedup";
+
+/// Compression of internal tile blobs. Blobs start with a header of magic bytes,
+/// the format version and a one byte codec tag. Version 0 blobs have no magic and version,
+/// they are either codec tagged or plain gzip streams written before the tag was introduced.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TileCodec {
+    #[default]
+    Gzip,
+    Zstd,
+}
+
+impl TileCodec {
+    fn tag(&self) -> u8 {
+        match self {
+            Self::Gzip => GZIP_TAG,
+            Self::Zstd => ZSTD_TAG,
+        }
+    }
+
+    /// Value of `compression` in the tiles db metadata
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "gzip" => Some(Self::Gzip),
+            "zstd" => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Compressed data prefixed with the blob header
+    pub fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut blob = TILE_MAGIC.to_vec();
+        blob.extend_from_slice(&[TILE_FORMAT_VERSION, self.tag()]);
+        match self {
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(blob, Compression::new(1));
+                encoder.write_all(data)?;
+                blob = encoder.finish()?;
+            }
+            Self::Zstd => zstd::stream::copy_encode(data, &mut blob, ZSTD_LEVEL)?,
+        }
+        Ok(blob)
+    }
+}
+
+fn tagged_blob(tag: u8, data: &[u8]) -> Vec<u8> {
+    let mut blob = TILE_MAGIC.to_vec();
+    blob.extend_from_slice(&[TILE_FORMAT_VERSION, tag]);
+    blob.extend_from_slice(data);
+    blob
+}
+
+/// Blob of a gzip stream of the current format version, e.g. a tile
+/// the tiles server sent with `Content-Encoding: gzip`
+pub fn gzip_blob(stream: &[u8]) -> Vec<u8> {
+    tagged_blob(GZIP_TAG, stream)
+}
+
+/// Blob of uncompressed data of the current format version, e.g. a tile
+/// the tiles server sent without `Content-Encoding`
+pub fn raw_blob(data: &[u8]) -> Vec<u8> {
+    tagged_blob(RAW_TAG, data)
+}
+
+/// bincode [crate::map::MapGeometryCollection] from individually serialized features,
+/// a sequence is serialized as its u64 length followed by the elements
+pub fn join_features(features: &[Vec<u8>]) -> Vec<u8> {
+    let mut data = (features.len() as u64).to_le_bytes().to_vec();
+    features.iter().for_each(|feature| data.extend_from_slice(feature));
+    data
+}
+
+/// Concatenates two bincode [crate::map::MapGeometryCollection]s without deserializing features,
+/// `None` if a collection is too short to hold the length prefix
+pub fn concat_features(first: &[u8], second: &[u8]) -> Option<Vec<u8>> {
+    let len = |data: &[u8]| Some(u64::from_le_bytes(data.get(..8)?.try_into().ok()?));
+    let mut data = (len(first)? + len(second)?).to_le_bytes().to_vec();
+    data.extend_from_slice(&first[8..]);
+    data.extend_from_slice(&second[8..]);
+    Some(data)
+}
+
+/// Format version of the blob and the codec tagged data following the header
+pub fn split_header(blob: &[u8]) -> (u8, &[u8]) {
+    match blob.strip_prefix(&TILE_MAGIC) {
+        Some([version, data @ ..]) => (*version, data),
+        _ => (0, blob),
+    }
+}
+
+/// Gzip stream of a tagged gzip or a legacy untagged blob, `None` for other codecs
+/// and unsupported versions
+pub fn gzip_stream(blob: &[u8]) -> Option<&[u8]> {
+    let (version, blob) = split_header(blob);
+    if version > TILE_FORMAT_VERSION {
+        return None;
+    }
+    match blob {
+        [GZIP_TAG, compressed @ ..] => Some(compressed),
+        _ if blob.starts_with(&GZIP_MAGIC) => Some(blob),
+        _ => None,
+    }
+}
+
+/// Decompresses a blob of any supported version
+pub fn decompress_tile(blob: &[u8]) -> io::Result<Vec<u8>> {
+    let (version, blob) = split_header(blob);
+    if version > TILE_FORMAT_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unsupported tile version {version}"),
+        ));
+    }
+    let mut data = Vec::new();
+    match blob {
+        [GZIP_TAG, compressed @ ..] => {
+            GzDecoder::new(compressed).read_to_end(&mut data)?;
+        }
+        [ZSTD_TAG, compressed @ ..] => {
+            zstd::stream::copy_decode(compressed, &mut data)?;
+        }
+        [RAW_TAG, raw @ ..] => data.extend_from_slice(raw),
+        _ if blob.starts_with(&GZIP_MAGIC) => {
+            GzDecoder::new(blob).read_to_end(&mut data)?;
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Unknown tile codec",
+            ))
+        }
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::map::{
+        MapGeomObject, MapGeomObjectKind, MapGeometry, MapGeometryCollection, COUNTRY_ADMIN_LEVEL,
+    };
+    use crate::tiles::codec::{
+        decompress_tile, gzip_blob, gzip_stream, join_features, raw_blob, TileCodec,
+    };
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use geo::{coord, line_string};
+    use std::io::Write;
+
+    #[test]
+    fn test_codec_round_trip() {
+        let collection = MapGeometryCollection::<f32>(vec![
+            (
+                MapGeomObject {
+                    id: 1,
+                    kind: MapGeomObjectKind::AdminLine(COUNTRY_ADMIN_LEVEL),
+                },
+                MapGeometry::Line(line_string![(x: 0.0, y: 0.0), (x: 10.0, y: 5.0)]),
+            ),
+            (
+                MapGeomObject {
+                    id: 2,
+                    kind: MapGeomObjectKind::TileBounds,
+                },
+                MapGeometry::Coord(coord! {x: 3.0, y: 4.0}),
+            ),
+        ]);
+        let serialized = bincode::serialize(&collection).unwrap();
+
+        for codec in [TileCodec::Gzip, TileCodec::Zstd] {
+            let blob = codec.compress(&serialized).unwrap();
+            let decoded: MapGeometryCollection<f32> =
+                bincode::deserialize(&decompress_tile(&blob).unwrap()).unwrap();
+            assert_eq!(decoded.0, collection.0);
+        }
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::new(1));
+        encoder.write_all(&serialized).unwrap();
+        let legacy = encoder.finish().unwrap();
+        assert_eq!(decompress_tile(&legacy).unwrap(), serialized);
+
+        assert!(decompress_tile(&[7, 1, 2]).is_err());
+
+        let gzip = TileCodec::Gzip.compress(&serialized).unwrap();
+        assert_eq!(gzip_blob(gzip_stream(&gzip).unwrap()), gzip);
+        assert_eq!(decompress_tile(&raw_blob(&serialized)).unwrap(), serialized);
+        assert!(gzip_stream(&raw_blob(&serialized)).is_none());
+
+        let features = collection
+            .0
+            .iter()
+            .map(|feature| bincode::serialize(feature).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(join_features(&features), serialized);
+    }
+}
@@ -1,12 +1,41 @@
-use crate::map::{get_world_boundary, MapGeomObject, MapGeometry, MapGeometryCollection};
-use crate::source::TileSource;
-use flate2::read::GzDecoder;
-use geo::{coord, Rect, Scale};
+pub mod codec;
+
+use crate::map::{
+    get_world_boundary, MapGeomObject, MapGeometry, MapGeometryCollection, MERCATOR_MAX_LAT,
+    ZOOM_LEVELS,
+};
+use crate::source::{TileSource, TileSourceFetchError};
+use crate::tiles::codec::{decompress_tile, split_header, TILE_FORMAT_VERSION};
+use error_stack::{Report, ResultExt};
+use geo::{coord, Coord, LineString, Rect, Scale};
+use googleprojection::Mercator;
 use rusqlite::Connection;
 use serde::{Deserialize, Serialize};
-use std::io::Read;
+use serde_json::{json, Value};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use thiserror::Error;
 
 pub const TILES_COUNT: i32 = 32768;
+const WORLD_PROJECTION_ZOOM: usize = 22;
+
+/// Projects lat/lon to the world coordinates that tile geometry is stored in.
+/// Latitudes beyond the Mercator range are clamped to the world edge.
+pub fn lat_lon_to_world(lat_lon: &Coord<f64>) -> Coord<f64> {
+    let lat_lon = (lat_lon.x, lat_lon.y.clamp(-MERCATOR_MAX_LAT, MERCATOR_MAX_LAT));
+    Mercator::with_size(1)
+        .from_ll_to_subpixel(&lat_lon, WORLD_PROJECTION_ZOOM)
+        .unwrap()
+        .into()
+}
+
+pub fn world_to_lat_lon(world: &Coord<f64>) -> Coord<f64> {
+    let world: (f64, f64) = (*world).into();
+    Mercator::with_size(1)
+        .from_pixel_to_ll(&world, WORLD_PROJECTION_ZOOM)
+        .unwrap()
+        .into()
+}
 
 #[derive(Hash, PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct TileKey {
@@ -15,18 +44,132 @@ pub struct TileKey {
     pub zoom_level: i32,
 }
 
-impl TileKey {
-    pub fn calc_tile_boundary(&self, scale_factor: f64) -> Rect {
+/// Amount of tiles per axis for the zoom level.
+/// Zoom levels that would overflow the divider collapse to a single tile.
+pub fn tiles_count_for_zoom(total_tiles: i32, zoom_level: i32) -> i32 {
+    2i32.checked_pow(zoom_level.max(0) as u32)
+        .map_or(1, |divider| (total_tiles / divider).max(1))
+}
+
+/// Row addressing of stored and requested tiles.
+/// The internal tile grid counts rows from the south edge of the world boundary like TMS does.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TileScheme {
+    #[default]
+    Tms,
+    /// Rows are counted from the north edge
+    Xyz,
+}
+
+impl TileScheme {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Tms => "tms",
+            Self::Xyz => "xyz",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "tms" => Some(Self::Tms),
+            "xyz" => Some(Self::Xyz),
+            _ => None,
+        }
+    }
+}
+
+/// Tile grid over the world boundary. Zoom level 0 is the most detailed one
+/// with `tiles_count` tiles per axis, every next zoom level halves it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TileGrid {
+    pub tiles_count: i32,
+    /// The least detailed zoom level, inclusive
+    pub max_zoom: u32,
+}
+
+impl Default for TileGrid {
+    fn default() -> Self {
+        TileGrid {
+            tiles_count: TILES_COUNT,
+            max_zoom: ZOOM_LEVELS - 1,
+        }
+    }
+}
+
+impl TileGrid {
+    pub fn zoom_levels(&self) -> RangeInclusive<u32> {
+        0..=self.max_zoom
+    }
+
+    pub fn tiles_count_for_zoom(&self, zoom_level: i32) -> i32 {
+        tiles_count_for_zoom(self.tiles_count, zoom_level)
+    }
+
+    pub fn tile_ranges(&self, zoom_level: i32, rect: &Rect) -> TileRanges {
+        calc_tile_ranges(self.tiles_count, zoom_level, rect)
+    }
+
+    pub fn tile_boundary(&self, key: &TileKey, scale_factor: f64) -> Rect {
         let world_rect = get_world_boundary();
 
-        let tiles_count = (TILES_COUNT / 2i32.pow(self.zoom_level as u32)).max(1);
+        let tiles_count = self.tiles_count_for_zoom(key.zoom_level);
         let tile_width = world_rect.width() / tiles_count as f64;
         let tile_height = world_rect.height() / tiles_count as f64;
-        let p1 = coord!(x: tile_width * self.tile_x as f64 + world_rect.min().x,
-            y: tile_height * self.tile_y as f64 + world_rect.min().y);
+        let p1 = coord!(x: tile_width * key.tile_x as f64 + world_rect.min().x,
+            y: tile_height * key.tile_y as f64 + world_rect.min().y);
         let p2 = coord!(x: p1.x + tile_width, y: p1.y + tile_height);
         Rect::new(p1, p2).scale(scale_factor)
     }
+
+    /// Same as [TileGrid::tile_boundary] with the world split into equal squares of the projected
+    /// world coordinates geometry is stored in, see [lat_lon_to_world]. Tiles are taller in lat/lon
+    /// toward the poles instead of being stretched in the projection
+    pub fn tile_boundary_mercator(&self, key: &TileKey, scale_factor: f64) -> Rect {
+        let world_rect = get_world_boundary();
+        // projected y grows southward
+        let south_west = lat_lon_to_world(&world_rect.min());
+        let north_east = lat_lon_to_world(&world_rect.max());
+
+        let tiles_count = self.tiles_count_for_zoom(key.zoom_level) as f64;
+        let tile_width = (north_east.x - south_west.x) / tiles_count;
+        let tile_height = (north_east.y - south_west.y) / tiles_count;
+        let p1 = coord!(x: south_west.x + tile_width * key.tile_x as f64,
+            y: south_west.y + tile_height * key.tile_y as f64);
+        let p2 = coord!(x: p1.x + tile_width, y: p1.y + tile_height);
+        let world_tile = Rect::new(p1, p2).scale(scale_factor);
+        Rect::new(
+            world_to_lat_lon(&world_tile.min()),
+            world_to_lat_lon(&world_tile.max()),
+        )
+    }
+
+    /// Converts the key between the grid and the scheme, converting twice gives
+    /// the same key back
+    pub fn flip_y(&self, key: &TileKey, scheme: TileScheme) -> TileKey {
+        match scheme {
+            TileScheme::Tms => *key,
+            TileScheme::Xyz => TileKey {
+                tile_y: self.tiles_count_for_zoom(key.zoom_level) - 1 - key.tile_y,
+                ..*key
+            },
+        }
+    }
+}
+
+/// Shortcuts for the default [TileGrid]
+impl TileKey {
+    pub fn flip_y(&self, scheme: TileScheme) -> TileKey {
+        TileGrid::default().flip_y(self, scheme)
+    }
+
+    pub fn calc_tile_boundary(&self, scale_factor: f64) -> Rect {
+        TileGrid::default().tile_boundary(self, scale_factor)
+    }
+
+    pub fn calc_tile_boundary_mercator(&self, scale_factor: f64) -> Rect {
+        TileGrid::default().tile_boundary_mercator(self, scale_factor)
+    }
 }
 
 #[derive(Clone)]
@@ -37,28 +180,30 @@ pub struct TileRanges {
     pub max_y: u32,
 }
 
+/// Value of `world_bounds` in the tiles db metadata, `left,bottom,right,top`.
+/// Tile addresses depend on the world boundary, dbs built for another one can't be read
+pub fn world_bounds_metadata() -> String {
+    let world = get_world_boundary();
+    format!("{},{},{},{}", world.min().x, world.min().y, world.max().x, world.max().y)
+}
+
 pub fn calc_tile_ranges(total_tiles: i32, zoom_level: i32, rect: &Rect) -> TileRanges {
     let world_rect = get_world_boundary();
 
-    let tiles_count = (total_tiles / 2i32.pow(zoom_level as u32)).max(1);
+    let tiles_count = tiles_count_for_zoom(total_tiles, zoom_level);
     let tiles_count_f64 = tiles_count as f64;
 
-    let tile_min_x = ((tiles_count_f64
-        * ((rect.min().x - world_rect.min().x)
-        / world_rect.width())) as i32)
-        .clamp(0, tiles_count - 1);
-    let tile_max_x = ((tiles_count_f64
-        * ((rect.max().x - world_rect.min().x)
-        / world_rect.width())) as i32)
-        .clamp(0, tiles_count - 1);
-    let tile_min_y = ((tiles_count_f64
-        * ((rect.min().y - world_rect.min().y)
-        / world_rect.height())) as i32)
-        .clamp(0, tiles_count - 1);
-    let tile_max_y = ((tiles_count_f64
-        * ((rect.max().y - world_rect.min().y)
-        / world_rect.height())) as i32)
-        .clamp(0, tiles_count - 1);
+    // floor instead of truncation toward zero, edges just outside the world boundary are negative
+    let tile_index = |value: f64, world_min: f64, world_size: f64| {
+        ((tiles_count_f64 * ((value - world_min) / world_size)).floor() as i32)
+            .clamp(0, tiles_count - 1)
+    };
+    let x1 = tile_index(rect.min().x, world_rect.min().x, world_rect.width());
+    let x2 = tile_index(rect.max().x, world_rect.min().x, world_rect.width());
+    let y1 = tile_index(rect.min().y, world_rect.min().y, world_rect.height());
+    let y2 = tile_index(rect.max().y, world_rect.min().y, world_rect.height());
+    let (tile_min_x, tile_max_x) = (x1.min(x2), x1.max(x2));
+    let (tile_min_y, tile_max_y) = (y1.min(y2), y1.max(y2));
 
     TileRanges {
         min_x: tile_min_x as u32,
@@ -68,8 +213,8 @@ pub fn calc_tile_ranges(total_tiles: i32, zoom_level: i32, rect: &Rect) -> TileR
     }
 }
 
-pub fn create_tiles_db_connection() -> Connection {
-    Connection::open("dbs/tiles.db").unwrap()
+pub fn create_tiles_db_connection<P: AsRef<Path>>(path: P) -> Connection {
+    Connection::open(path).unwrap()
 }
 
 impl TileKey {
@@ -85,6 +230,30 @@ impl TileKey {
     }
 }
 
+#[derive(Debug, Error)]
+pub enum TileDecodeError {
+    #[error("Decompress")]
+    Decompress,
+    #[error("Deserialize")]
+    Deserialize,
+    #[error("Unsupported tile version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Tile was written with legacy map types, rebuild the tiles db")]
+    LegacyVersion,
+}
+
+/// Geometries of a stored tile blob, in the tile local world coordinates.
+/// Blobs of other versions than [TILE_FORMAT_VERSION] have a different layout of the map types
+pub fn decode_tile(bytes: &[u8]) -> Result<MapGeometryCollection<f32>, Report<TileDecodeError>> {
+    match split_header(bytes).0 {
+        TILE_FORMAT_VERSION => {}
+        0 => return Err(Report::new(TileDecodeError::LegacyVersion)),
+        version => return Err(Report::new(TileDecodeError::UnsupportedVersion(version))),
+    }
+    let data = decompress_tile(bytes).change_context(TileDecodeError::Decompress)?;
+    bincode::deserialize(&data).change_context(TileDecodeError::Deserialize)
+}
+
 pub struct TileStore<S: TileSource> {
     tile_source: S,
 }
@@ -94,25 +263,371 @@ impl<S: TileSource> TileStore<S> {
         Self { tile_source }
     }
 
-    // TODO Report
+    /// Geometries of the tile, any failure is logged and results in no geometries
     pub fn load_geometries(&self, tile_key: &TileKey) -> Vec<(MapGeomObject, MapGeometry<f32>)> {
+        self.try_load_geometries(tile_key).unwrap_or_else(|err| {
+            println!("Failed to load tile key {tile_key:?}. Error: {err:?}");
+            vec![]
+        })
+    }
+
+    /// Geometries of the tile. A tile stored empty is an empty vec,
+    /// while a tile which wasn't built is [TileSourceFetchError::MissingData]
+    pub fn try_load_geometries(
+        &self,
+        tile_key: &TileKey,
+    ) -> Result<Vec<(MapGeomObject, MapGeometry<f32>)>, Report<TileSourceFetchError>> {
         let data = self
             .tile_source
-            .fetch(tile_key.tile_x, tile_key.tile_y, tile_key.zoom_level)
-            .unwrap_or_else(|err| {
-                println!("Failed to fetch tile key {tile_key:?}. Error: {err}");
-                vec![]
-            });
-        let mut decoder = GzDecoder::new(&data[..]);
-        let mut decompressed_data = Vec::new();
-        decoder.read_to_end(&mut decompressed_data).unwrap_or_else(|err| {
-            println!("Failed to decompress tile key {tile_key:?}. Error: {err}");
-            0
-        });
-        let collection: MapGeometryCollection<f32> = bincode::deserialize(&decompressed_data).unwrap_or_else(|err| {
-            println!("Failed to deserialize tile key {tile_key:?}, Error: {err}");
-            MapGeometryCollection::<f32>(vec![])
-        });
-        collection.0
+            .fetch(tile_key.tile_x, tile_key.tile_y, tile_key.zoom_level)?;
+        let collection = decode_tile(&data).change_context(TileSourceFetchError::Internal)?;
+        Ok(collection.0)
+    }
+
+    /// Debug representation of the tile as GeoJSON FeatureCollection in lat/lon
+    pub fn tile_to_geojson(&self, tile_key: &TileKey) -> String {
+        let grid = self.tile_source.grid();
+        tile_geometries_to_geojson(&grid, tile_key, &self.load_geometries(tile_key)).to_string()
+    }
+}
+
+/// Reverses the tile local world coordinates transform of [TileKey] tile geometry,
+/// `tile_key` is in the internal addressing of `grid`
+pub fn tile_geometries_to_geojson(
+    grid: &TileGrid,
+    tile_key: &TileKey,
+    geometries: &[(MapGeomObject, MapGeometry<f32>)],
+) -> Value {
+    let origin = lat_lon_to_world(&grid.tile_boundary(tile_key, 1.0).min());
+    let to_position = |coord: &Coord<f32>| {
+        let lat_lon = world_to_lat_lon(&(coord! {x: coord.x as f64, y: coord.y as f64} + origin));
+        json!([lat_lon.x, lat_lon.y])
+    };
+    let to_positions =
+        |line: &LineString<f32>| line.coords().map(to_position).collect::<Vec<_>>();
+
+    let features = geometries
+        .iter()
+        .map(|(obj, geometry)| {
+            let geometry = match geometry {
+                MapGeometry::Line(line) => json!({
+                    "type": "LineString",
+                    "coordinates": to_positions(line),
+                }),
+                MapGeometry::Poly(poly) => json!({
+                    "type": "Polygon",
+                    "coordinates": std::iter::once(poly.exterior())
+                        .chain(poly.interiors())
+                        .map(to_positions)
+                        .collect::<Vec<_>>(),
+                }),
+                MapGeometry::Coord(coord) => json!({
+                    "type": "Point",
+                    "coordinates": to_position(coord),
+                }),
+                MapGeometry::MultiPoly(multi_poly) => json!({
+                    "type": "MultiPolygon",
+                    "coordinates": multi_poly
+                        .iter()
+                        .map(|poly| {
+                            std::iter::once(poly.exterior())
+                                .chain(poly.interiors())
+                                .map(to_positions)
+                                .collect::<Vec<_>>()
+                        })
+                        .collect::<Vec<_>>(),
+                }),
+                MapGeometry::MultiLine(multi_line) => json!({
+                    "type": "MultiLineString",
+                    "coordinates": multi_line.iter().map(to_positions).collect::<Vec<_>>(),
+                }),
+            };
+            json!({
+                "type": "Feature",
+                "geometry": geometry,
+                "properties": {
+                    "id": obj.id,
+                    "kind": obj.kind,
+                },
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "type": "FeatureCollection",
+        "features": features,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::map::{
+        BuildingInfo, MapGeomObject, MapGeomObjectKind, MapGeometry, MapGeometryCollection,
+        MapPointInfo, MapPointObjectKind, NatureKind, StationKind, COUNTRY_ADMIN_LEVEL,
+        MERCATOR_MAX_LAT,
+    };
+    use crate::source::{TileSource, TileSourceFetchError};
+    use crate::tiles::codec::{decompress_tile, gzip_stream, split_header, TileCodec, TILE_FORMAT_VERSION};
+    use crate::tiles::{decode_tile, lat_lon_to_world, TileDecodeError, TileGrid, TileKey, TileStore};
+    use error_stack::Report;
+    use geo::{coord, Coord, LineString, MapCoords, Polygon, Rect};
+    use serde_json::Value;
+
+    struct StaticTileSource(Vec<u8>);
+
+    impl TileSource for StaticTileSource {
+        fn fetch(&self, _: i32, _: i32, _: i32) -> Result<Vec<u8>, Report<TileSourceFetchError>> {
+            Ok(self.0.clone())
+        }
+    }
+
+    #[test]
+    fn test_small_grid_tile_ranges() {
+        // 4 tiles per axis at zoom level 1, tiles are 90 degrees wide and half of the max latitude high
+        let grid = TileGrid {
+            tiles_count: 8,
+            max_zoom: 3,
+        };
+        let rect = Rect::new(coord! {x: -100.0, y: -70.0}, coord! {x: 10.0, y: 10.0});
+        let ranges = grid.tile_ranges(1, &rect);
+        assert_eq!(
+            (ranges.min_x, ranges.max_x, ranges.min_y, ranges.max_y),
+            (0, 2, 0, 2)
+        );
+
+        let ranges = grid.tile_ranges(3, &rect);
+        assert_eq!(
+            (ranges.min_x, ranges.max_x, ranges.min_y, ranges.max_y),
+            (0, 0, 0, 0)
+        );
+
+        let boundary = grid.tile_boundary(&TileKey::new(2, 1, 1), 1.0);
+        assert_eq!(boundary.min(), coord! {x: 0.0, y: -MERCATOR_MAX_LAT / 2.0});
+        assert_eq!(boundary.max(), coord! {x: 90.0, y: 0.0});
+        assert_eq!(grid.zoom_levels().count(), 4);
+    }
+
+    #[test]
+    fn test_tile_ranges_at_tile_edge() {
+        // 4 tiles per axis at zoom level 1, tiles are 90 degrees wide and half of the max latitude high
+        let grid = TileGrid {
+            tiles_count: 8,
+            max_zoom: 3,
+        };
+        let ranges = |rect: Rect| {
+            let ranges = grid.tile_ranges(1, &rect);
+            (ranges.min_x, ranges.max_x, ranges.min_y, ranges.max_y)
+        };
+
+        // straddles the x = 0 and y = 0 tile edges
+        let rect = Rect::new(coord! {x: -0.001, y: -0.001}, coord! {x: 0.001, y: 0.001});
+        assert_eq!(ranges(rect), (1, 2, 1, 2));
+
+        // just outside the west and south world boundary
+        let rect = Rect::new(coord! {x: -180.5, y: -85.5}, coord! {x: -179.5, y: -84.5});
+        assert_eq!(ranges(rect), (0, 0, 0, 0));
+
+        // exactly on a tile boundary, the min edge belongs to the tile starting there
+        let rect = Rect::new(coord! {x: 0.0, y: 0.0}, coord! {x: 45.0, y: 20.0});
+        assert_eq!(ranges(rect), (2, 2, 2, 2));
+        let rect = Rect::new(coord! {x: -45.0, y: -20.0}, coord! {x: 0.0, y: 0.0});
+        assert_eq!(ranges(rect), (1, 2, 1, 2));
+    }
+
+    #[test]
+    fn test_project_far_north() {
+        let grid = TileGrid::default();
+        let point = coord! {x: 12.0, y: 88.0};
+        let ranges = grid.tile_ranges(0, &Rect::new(point, point));
+        let key = TileKey::new(ranges.min_x as i32, ranges.max_y as i32, 0);
+        assert_eq!(key.tile_y, grid.tiles_count_for_zoom(0) - 1);
+
+        let tile_rect = grid.tile_boundary(&key, 1.0);
+        let (tile_min, tile_max) = (
+            lat_lon_to_world(&tile_rect.min()),
+            lat_lon_to_world(&tile_rect.max()),
+        );
+        let world = lat_lon_to_world(&point);
+        // the world edge projects to 0 up to rounding
+        let (min_y, max_y) = (tile_min.y.min(tile_max.y) - 1e-6, tile_min.y.max(tile_max.y));
+        assert!((tile_min.x..=tile_max.x).contains(&world.x));
+        assert!((min_y..=max_y).contains(&world.y));
+        assert_eq!(world, lat_lon_to_world(&coord! {x: 12.0, y: MERCATOR_MAX_LAT}));
+    }
+
+    #[test]
+    fn test_mercator_tile_boundary() {
+        let grid = TileGrid::default();
+        let projected_size = |rect: Rect| {
+            let (min, max) = (lat_lon_to_world(&rect.min()), lat_lon_to_world(&rect.max()));
+            ((max.x - min.x).abs(), (max.y - min.y).abs())
+        };
+        // around 45 degrees north
+        let key = TileKey::new(512, 655, 5);
+
+        let tile_rect = grid.tile_boundary_mercator(&key, 1.0);
+        assert!((44.0..46.0).contains(&tile_rect.min().y));
+        let (width, height) = projected_size(tile_rect);
+        assert!((width / height - 1.0).abs() < 1e-9);
+        assert_eq!(tile_rect, key.calc_tile_boundary_mercator(1.0));
+
+        // linear lat/lon tiles are stretched in the projection
+        let (width, height) = projected_size(grid.tile_boundary(&key, 1.0));
+        assert!((width / height - 1.0).abs() > 0.1);
+
+        let south_west = grid.tile_boundary_mercator(&TileKey::new(0, 0, 5), 1.0).min();
+        assert!((south_west.x + 180.0).abs() < 1e-9);
+        assert!((south_west.y + MERCATOR_MAX_LAT).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_decode_tile() {
+        let geometries = vec![(
+            MapGeomObject {
+                id: 1,
+                kind: MapGeomObjectKind::Nature(NatureKind::Water),
+            },
+            MapGeometry::Coord(coord! {x: 1.0, y: 2.0}),
+        )];
+        let blob = TileCodec::Gzip
+            .compress(&bincode::serialize(&MapGeometryCollection::<f32>(geometries.clone())).unwrap())
+            .unwrap();
+        assert_eq!(decode_tile(&blob).unwrap().0, geometries);
+
+        let err = decode_tile(&blob[..blob.len() / 2]).unwrap_err();
+        assert!(matches!(err.current_context(), TileDecodeError::Decompress));
+
+        let blob = TileCodec::Gzip.compress(&[1, 0, 0, 0, 0, 0, 0, 0, 7]).unwrap();
+        let err = decode_tile(&blob).unwrap_err();
+        assert!(matches!(err.current_context(), TileDecodeError::Deserialize));
+    }
+
+    #[test]
+    fn test_decode_tile_versions() {
+        let collection = MapGeometryCollection::<f32>(vec![(
+            MapGeomObject {
+                id: 1,
+                kind: MapGeomObjectKind::Nature(NatureKind::Water),
+            },
+            MapGeometry::Coord(coord! {x: 1.0, y: 2.0}),
+        )]);
+        let serialized = bincode::serialize(&collection).unwrap();
+
+        let blob = TileCodec::Zstd.compress(&serialized).unwrap();
+        assert_eq!(split_header(&blob).0, TILE_FORMAT_VERSION);
+        assert_eq!(decode_tile(&blob).unwrap().0, collection.0);
+
+        // version 0, codec tagged blob without the header
+        let (_, tagged) = split_header(&blob);
+        assert_eq!(split_header(tagged).0, 0);
+        assert!(matches!(
+            decode_tile(tagged).unwrap_err().current_context(),
+            TileDecodeError::LegacyVersion
+        ));
+
+        let mut future = blob.clone();
+        future[3] = TILE_FORMAT_VERSION + 1;
+        let err = decode_tile(&future).unwrap_err();
+        assert!(matches!(
+            err.current_context(),
+            TileDecodeError::UnsupportedVersion(version) if *version == TILE_FORMAT_VERSION + 1
+        ));
+        assert!(decompress_tile(&future).is_err());
+        assert!(gzip_stream(&future).is_none());
+    }
+
+    #[test]
+    fn test_decode_legacy_tile() {
+        let feature = |kind| {
+            (
+                MapGeomObject { id: 1, kind },
+                MapGeometry::Coord(coord! {x: 1.0, y: 2.0}),
+            )
+        };
+        // kinds whose serialized layout changed before the blob header was introduced
+        let collection = MapGeometryCollection::<f32>(vec![
+            feature(MapGeomObjectKind::Building(BuildingInfo::default())),
+            feature(MapGeomObjectKind::AdminLine(COUNTRY_ADMIN_LEVEL)),
+            feature(MapGeomObjectKind::Poi(MapPointInfo {
+                text: "".to_string(),
+                kind: MapPointObjectKind::TrainStation(StationKind::Subway),
+            })),
+        ]);
+        let serialized = bincode::serialize(&collection).unwrap();
+        let blob = TileCodec::Gzip.compress(&serialized).unwrap();
+        assert_eq!(decode_tile(&blob).unwrap().0, collection.0);
+
+        // codec tagged and plain gzip blobs without the header
+        let (_, tagged) = split_header(&blob);
+        let plain = gzip_stream(&blob).unwrap();
+        for legacy in [tagged, plain] {
+            assert_eq!(split_header(legacy).0, 0);
+            assert_eq!(decompress_tile(legacy).unwrap(), serialized);
+            let err = decode_tile(legacy).unwrap_err();
+            assert!(matches!(err.current_context(), TileDecodeError::LegacyVersion));
+            assert!(err.current_context().to_string().contains("rebuild the tiles db"));
+        }
+    }
+
+    #[test]
+    fn test_tile_to_geojson() {
+        let tile_key = TileKey::new(600, 400, 5);
+        let rect = tile_key.calc_tile_boundary(1.0);
+        let origin = lat_lon_to_world(&rect.min());
+        let to_tile = |coord: Coord| {
+            let world = lat_lon_to_world(&coord) - origin;
+            coord! {x: world.x as f32, y: world.y as f32}
+        };
+        let center = rect.center();
+        let poly = Polygon::new(
+            LineString::from(vec![rect.min(), center, coord! {x: rect.max().x, y: rect.min().y}]),
+            vec![],
+        );
+        let geometries = vec![
+            (
+                MapGeomObject {
+                    id: 1,
+                    kind: MapGeomObjectKind::Poi(MapPointInfo {
+                        text: "".to_string(),
+                        kind: MapPointObjectKind::Toilet,
+                    }),
+                },
+                MapGeometry::Coord(to_tile(center)),
+            ),
+            (
+                MapGeomObject {
+                    id: 2,
+                    kind: MapGeomObjectKind::AdminLine(COUNTRY_ADMIN_LEVEL),
+                },
+                MapGeometry::Line(LineString::from(vec![to_tile(rect.min()), to_tile(center)])),
+            ),
+            (
+                MapGeomObject {
+                    id: 3,
+                    kind: MapGeomObjectKind::Nature(NatureKind::Water),
+                },
+                MapGeometry::Poly(poly.map_coords(to_tile)),
+            ),
+        ];
+        let blob = TileCodec::Gzip
+            .compress(&bincode::serialize(&MapGeometryCollection::<f32>(geometries)).unwrap())
+            .unwrap();
+        let tile_store = TileStore::new(StaticTileSource(blob));
+
+        let geojson: Value = serde_json::from_str(&tile_store.tile_to_geojson(&tile_key)).unwrap();
+
+        assert_eq!(geojson["type"], "FeatureCollection");
+        let features = geojson["features"].as_array().unwrap();
+        let types = features
+            .iter()
+            .map(|feature| feature["geometry"]["type"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(types, vec!["Point", "LineString", "Polygon"]);
+        assert_eq!(features[0]["properties"]["id"], 1);
+
+        let point = &features[0]["geometry"]["coordinates"];
+        assert!((point[0].as_f64().unwrap() - center.x).abs() < 0.0001);
+        assert!((point[1].as_f64().unwrap() - center.y).abs() < 0.0001);
+        assert_eq!(features[2]["geometry"]["coordinates"][0].as_array().unwrap().len(), 4);
     }
 }
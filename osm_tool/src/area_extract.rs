@@ -0,0 +1,292 @@
+use crate::config::ShashlikConfig;
+use crate::pbf_processor::PbfProcessor;
+use crate::reader;
+use crate::tile_processor::TileProcessor;
+use osm::tile_writer::tile_writer::TileWriter;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+const STDIN_PATH: &str = "-";
+
+/// OsmReader needs Seek to pre-extract ways from relations, so stdin is buffered
+/// into a temporary file once and the same copy is reused by all areas reading from stdin.
+/// Gzip compressed data is decompressed, see [reader::open_decompressed]
+pub fn open_osm_file(path: &str, stdin_buffer: &mut Option<PathBuf>) -> File {
+    if path != STDIN_PATH {
+        return reader::open_decompressed(Path::new(path)).expect("Could not open OSM file");
+    }
+    let buffer_path = stdin_buffer.get_or_insert_with(|| {
+        let buffer_path = std::env::temp_dir()
+            .join(format!("osm_tool_stdin_{}.osm.pbf", std::process::id()));
+        let mut buffer_file =
+            File::create(&buffer_path).expect("Could not create stdin buffer file");
+        let size = io::copy(&mut io::stdin().lock(), &mut buffer_file)
+            .expect("Could not read OSM data from stdin");
+        println!("Buffered {} bytes from stdin into {:?}", size, buffer_path);
+        buffer_path
+    });
+    reader::open_decompressed(buffer_path).expect("Could not open stdin buffer file")
+}
+
+/// Blob offsets index is kept next to the OSM file, there is nothing to keep for stdin
+pub fn offsets_index_path(path: &str) -> Option<PathBuf> {
+    (path != STDIN_PATH).then(|| PathBuf::from(format!("{}.offsets", path)))
+}
+
+/// Extracts the enabled areas of the config into the tiles of `tile_processor`.
+/// With `resume` every extracted area is saved as a checkpoint and the areas completed
+/// by an interrupted build of [ShashlikConfig::tiles_db_path] are skipped,
+/// the checkpoint writes the tiles like [ShashlikConfig::incremental_output].
+/// Returns the names of the extracted areas
+pub fn extract_areas(
+    config: &ShashlikConfig,
+    tile_processor: &mut TileProcessor,
+    resume: bool,
+) -> Vec<String> {
+    let tag_mapping = config.tag_mapping();
+    let area_dbs_dir = config.area_dbs_dir();
+    let completed_areas = if resume {
+        TileWriter::completed_areas(&config.tiles_db_path())
+    } else {
+        vec![]
+    };
+    // the checkpointed tiles are kept even if all areas are already completed
+    if !completed_areas.is_empty() {
+        tile_processor.tile_writer.resume();
+    }
+    let mut extracted_areas = vec![];
+    let mut stdin_buffer = None;
+    for area in &config.areas {
+        if !area.enabled {
+            println!("Area {} disabled", area.name);
+            continue;
+        }
+        let mut osm_file = open_osm_file(&area.path, &mut stdin_buffer);
+        if completed_areas.contains(&area.name) {
+            println!("Area {} already extracted", area.name);
+            tile_processor.tile_writer.add_coverage(area.resolve_boundary(&mut osm_file));
+            continue;
+        }
+        println!("Extracting OSM data for {}", area.name);
+        let boundary = area.resolve_boundary(&mut osm_file);
+        let mut pbf_processor = PbfProcessor::new(tag_mapping.clone())
+            .with_node_spill_threshold(config.node_spill_threshold)
+            .with_clip_to_bounds(config.clip_to_bounds);
+        pbf_processor.process_pbf(boundary, osm_file, tile_processor,
+                                  config.merge_polygons,
+                                  config.preserve_road_topology,
+                                  offsets_index_path(&area.path));
+        tile_processor.tile_writer.add_coverage(boundary);
+        if config.per_area_output {
+            tile_processor.save_to_path(&area_dbs_dir.join(area.db_file_name()));
+        } else if resume {
+            tile_processor.save_checkpoint(&area.name);
+        } else if config.incremental_output {
+            tile_processor.write_collected_tiles();
+        }
+        extracted_areas.push(area.name.clone());
+    }
+    if let Some(stdin_buffer) = stdin_buffer {
+        let _ = fs::remove_file(stdin_buffer);
+    }
+    extracted_areas
+}
+
+#[cfg(test)]
+mod test {
+    use crate::area_extract::extract_areas;
+    use crate::config::{Area, ConfigError, ShashlikConfig};
+    use crate::proto::{DenseNodes, PrimitiveBlock, PrimitiveGroup, StringTable, Way};
+    use crate::reader::test_pbf;
+    use crate::simplification::SimplificationConfig;
+    use crate::tile_processor::TileProcessor;
+    use osm::source::tiles_sqlite_store::TilesSQLiteStore;
+    use osm::tile_writer::tile_writer::{TileFormat, TileWriter};
+    use osm::tiles::{TileKey, TileStore};
+    use rusqlite::Connection;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::path::Path;
+
+    /// A road near 0.01/0.01 with id 10 and one near 1.01/1.01 with id 11
+    fn roads_pbf() -> Vec<u8> {
+        let string_table = StringTable {
+            s: ["", "highway", "primary"].map(|s| s.as_bytes().to_vec()).to_vec(),
+        };
+        let block = |group| PrimitiveBlock {
+            stringtable: string_table.clone(),
+            primitivegroup: vec![group],
+            granularity: None,
+            lat_offset: None,
+            lon_offset: None,
+            date_granularity: None,
+        };
+        let nodes = block(PrimitiveGroup {
+            dense: Some(DenseNodes {
+                id: vec![1, 1, 1, 1],
+                denseinfo: None,
+                lat: vec![100_000, 0, 10_000_000, 0],
+                lon: vec![100_000, 40_000, 9_960_000, 40_000],
+                keys_vals: vec![0, 0, 0, 0],
+            }),
+            ..Default::default()
+        });
+        let way = |id, refs| Way {
+            id,
+            keys: vec![1],
+            vals: vec![2],
+            info: None,
+            refs,
+            lat: vec![],
+            lon: vec![],
+        };
+        let ways = block(PrimitiveGroup {
+            ways: vec![way(10, vec![1, 1]), way(11, vec![3, 1])],
+            ..Default::default()
+        });
+        test_pbf(vec![nodes, ways])
+    }
+
+    fn area(name: &str, path: &Path, min: f64) -> Area {
+        Area {
+            name: name.to_string(),
+            enabled: true,
+            path: path.to_str().unwrap().to_string(),
+            left: Some(min),
+            top: Some(min + 0.02),
+            right: Some(min + 0.02),
+            bottom: Some(min),
+        }
+    }
+
+    /// Ids of the features in all tiles of the db
+    fn feature_ids(db_path: &Path) -> Vec<i64> {
+        let keys = Connection::open(db_path)
+            .unwrap()
+            .prepare("SELECT x, y, z FROM tiles")
+            .unwrap()
+            .query_map((), |row| Ok(TileKey::new(row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let tile_store = TileStore::new(TilesSQLiteStore::new(db_path));
+        let mut ids = keys
+            .iter()
+            .flat_map(|key| tile_store.load_geometries(key))
+            .map(|(obj, _)| obj.id)
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids.dedup();
+        ids
+    }
+
+    #[test]
+    fn test_resume_extract() {
+        let pbf_path = std::env::temp_dir().join(format!("osm_tool_resume_{}.osm.pbf", std::process::id()));
+        std::fs::write(&pbf_path, roads_pbf()).unwrap();
+        let db_path = std::env::temp_dir().join(format!("osm_tool_resume_{}.db", std::process::id()));
+
+        let config = |second_path: &Path| ShashlikConfig {
+            areas: vec![area("first", &pbf_path, 0.0), area("second", second_path, 1.0)],
+            tiles_db_path: Some(db_path.to_str().unwrap().to_string()),
+            max_zoom: Some(0),
+            ..Default::default()
+        };
+        let tile_processor = || {
+            TileProcessor::new(config(&pbf_path).tile_writer_config(), SimplificationConfig::default())
+        };
+
+        // the build is interrupted while the second area is extracted
+        let mut interrupted = tile_processor();
+        let missing_path = pbf_path.with_extension("missing");
+        assert!(catch_unwind(AssertUnwindSafe(|| {
+            extract_areas(&config(&missing_path), &mut interrupted, true)
+        }))
+        .is_err());
+        std::mem::forget(interrupted);
+        assert_eq!(TileWriter::completed_areas(&db_path), vec!["first".to_string()]);
+
+        let mut tile_processor = tile_processor();
+        let extracted = extract_areas(&config(&pbf_path), &mut tile_processor, true);
+        assert_eq!(extracted, vec!["second".to_string()]);
+        tile_processor.save_to_disk();
+
+        assert_eq!(TilesSQLiteStore::new(&db_path).coverage().unwrap().len(), 2);
+        assert_eq!(feature_ids(&db_path), vec![10, 11]);
+
+        std::fs::remove_file(&pbf_path).unwrap();
+        std::fs::remove_file(&db_path).unwrap();
+        // left by the connection of the interrupted build
+        for suffix in ["-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", db_path.display()));
+        }
+    }
+
+    #[test]
+    fn test_resume_after_all_areas() {
+        let pbf_path = std::env::temp_dir().join(format!("osm_tool_resume_all_{}.osm.pbf", std::process::id()));
+        std::fs::write(&pbf_path, roads_pbf()).unwrap();
+        let db_path = std::env::temp_dir().join(format!("osm_tool_resume_all_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&db_path);
+        let config = ShashlikConfig {
+            areas: vec![area("first", &pbf_path, 0.0), area("second", &pbf_path, 1.0)],
+            tiles_db_path: Some(db_path.to_str().unwrap().to_string()),
+            max_zoom: Some(0),
+            ..Default::default()
+        };
+        assert!(config.validate_resume().is_ok());
+        let tile_processor =
+            || TileProcessor::new(config.tile_writer_config(), SimplificationConfig::default());
+
+        // the build is interrupted after the last checkpoint, e.g. while planet data is extracted
+        let mut interrupted = tile_processor();
+        extract_areas(&config, &mut interrupted, true);
+        std::mem::forget(interrupted);
+        assert_eq!(TileWriter::completed_areas(&db_path), vec!["first", "second"]);
+
+        let mut tile_processor = tile_processor();
+        assert!(extract_areas(&config, &mut tile_processor, true).is_empty());
+        tile_processor.save_to_disk();
+
+        assert_eq!(feature_ids(&db_path), vec![10, 11]);
+        assert!(TileWriter::completed_areas(&db_path).is_empty());
+
+        std::fs::remove_file(&pbf_path).unwrap();
+        std::fs::remove_file(&db_path).unwrap();
+        for suffix in ["-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", db_path.display()));
+        }
+    }
+
+    #[test]
+    fn test_incremental_extract() {
+        let pbf_path = std::env::temp_dir().join(format!("osm_tool_incremental_{}.osm.pbf", std::process::id()));
+        std::fs::write(&pbf_path, roads_pbf()).unwrap();
+        let db_path = std::env::temp_dir().join(format!("osm_tool_incremental_{}.db", std::process::id()));
+        let config = ShashlikConfig {
+            areas: vec![area("first", &pbf_path, 0.0), area("second", &pbf_path, 1.0)],
+            tiles_db_path: Some(db_path.to_str().unwrap().to_string()),
+            incremental_output: true,
+            max_zoom: Some(0),
+            ..Default::default()
+        };
+        let mut tile_processor =
+            TileProcessor::new(config.tile_writer_config(), SimplificationConfig::default());
+
+        extract_areas(&config, &mut tile_processor, false);
+        // the tiles are in the db before it's completed
+        assert_eq!(feature_ids(&db_path), vec![10, 11]);
+        tile_processor.save_to_disk();
+        assert_eq!(feature_ids(&db_path), vec![10, 11]);
+
+        let mvt_config = ShashlikConfig {
+            tile_format: TileFormat::Mvt,
+            ..config
+        };
+        assert!(matches!(mvt_config.validate(), Err(ConfigError::IncrementalOutput)));
+        assert!(matches!(mvt_config.validate_resume(), Err(ConfigError::Resume)));
+
+        std::fs::remove_file(&pbf_path).unwrap();
+        std::fs::remove_file(&db_path).unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+use geo::{unary_union, Intersects, Polygon};
+use itertools::Itertools;
+use osm::map::BuildingInfo;
+use rstar::primitives::GeomWithData;
+use rstar::{RTree, RTreeObject};
+use rustc_hash::FxHashMap;
+use std::collections::BTreeMap;
+
+/// Small building footprints of less detailed zoom levels, touching footprints are merged
+/// into one building per zoom level
+pub struct BuildingStore {
+    items: FxHashMap<u32, Vec<(i64, Polygon, BuildingInfo)>>,
+}
+
+impl BuildingStore {
+    pub fn new() -> Self {
+        BuildingStore {
+            items: FxHashMap::default(),
+        }
+    }
+
+    pub fn add_building(&mut self, zoom_level: u32, id: i64, polygon: Polygon, info: BuildingInfo) {
+        self.items.entry(zoom_level).or_default().push((id, polygon, info));
+    }
+
+    /// Merges and removes all collected buildings. Merged buildings take the smallest id,
+    /// the max levels and height of their parts, addresses of the parts are dropped
+    pub fn drain_merged(&mut self) -> Vec<(u32, i64, Polygon, BuildingInfo)> {
+        self.items
+            .drain()
+            .sorted_by_key(|(zoom_level, _)| *zoom_level)
+            .flat_map(|(zoom_level, buildings)| {
+                Self::merge_buildings(buildings)
+                    .into_iter()
+                    .map(move |(id, polygon, info)| (zoom_level, id, polygon, info))
+            })
+            .collect()
+    }
+
+    fn merge_buildings(
+        buildings: Vec<(i64, Polygon, BuildingInfo)>,
+    ) -> Vec<(i64, Polygon, BuildingInfo)> {
+        let rtree = RTree::bulk_load(
+            buildings
+                .iter()
+                .enumerate()
+                .map(|(index, (_, polygon, _))| GeomWithData::new(polygon.clone(), index))
+                .collect(),
+        );
+
+        // union-find of touching buildings
+        let mut parents = (0..buildings.len()).collect_vec();
+        fn root(parents: &mut [usize], index: usize) -> usize {
+            let mut root = index;
+            while parents[root] != root {
+                root = parents[root];
+            }
+            parents[index] = root;
+            root
+        }
+        for (index, (_, polygon, _)) in buildings.iter().enumerate() {
+            for other in rtree.locate_in_envelope_intersecting(&polygon.envelope()) {
+                if other.data > index && polygon.intersects(other.geom()) {
+                    let (a, b) = (root(&mut parents, index), root(&mut parents, other.data));
+                    parents[a.max(b)] = a.min(b);
+                }
+            }
+        }
+
+        let mut groups: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for index in 0..buildings.len() {
+            groups.entry(root(&mut parents, index)).or_default().push(index);
+        }
+        groups
+            .into_values()
+            .flat_map(|group| {
+                if let [index] = group[..] {
+                    return vec![buildings[index].clone()];
+                }
+                let id = group.iter().map(|index| buildings[*index].0).min().unwrap();
+                let info = group
+                    .iter()
+                    .map(|index| &buildings[*index].2)
+                    .fold(BuildingInfo::default(), |merged, info| BuildingInfo {
+                        levels: merged.levels.max(info.levels),
+                        height: merged.height.max(info.height),
+                        ..merged
+                    });
+                unary_union(group.iter().map(|index| &buildings[*index].1))
+                    .0
+                    .into_iter()
+                    .map(|polygon| (id, polygon, info.clone()))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::building_store::BuildingStore;
+    use geo::{polygon, Area};
+    use osm::map::BuildingInfo;
+
+    #[test]
+    fn test_merge_touching_buildings() {
+        let mut building_store = BuildingStore::new();
+        building_store.add_building(
+            1,
+            7,
+            polygon![(x: 0.0, y: 0.0), (x: 1.0, y: 0.0), (x: 1.0, y: 1.0), (x: 0.0, y: 1.0)],
+            BuildingInfo::new(2, None, Some("1".to_string()), None),
+        );
+        building_store.add_building(
+            1,
+            3,
+            polygon![(x: 1.0, y: 0.0), (x: 2.0, y: 0.0), (x: 2.0, y: 1.0), (x: 1.0, y: 1.0)],
+            BuildingInfo::new(5, Some(20.0), None, None),
+        );
+        building_store.add_building(
+            1,
+            5,
+            polygon![(x: 5.0, y: 5.0), (x: 6.0, y: 5.0), (x: 6.0, y: 6.0), (x: 5.0, y: 6.0)],
+            BuildingInfo::new(1, None, None, None),
+        );
+
+        let merged = building_store.drain_merged();
+        assert_eq!(merged.len(), 2);
+        let (zoom_level, id, polygon, info) = &merged[0];
+        assert_eq!(*zoom_level, 1);
+        assert_eq!(*id, 3);
+        assert!((polygon.unsigned_area() - 2.0).abs() < 1e-9);
+        assert_eq!(info.levels, 5);
+        assert_eq!(info.height, 20.0);
+        assert_eq!(info.housenumber, None);
+        assert_eq!(merged[1].1, 5);
+        assert_eq!(merged[1].3.levels, 1);
+        assert!(building_store.drain_merged().is_empty());
+    }
+}
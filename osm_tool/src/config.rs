@@ -1,28 +1,450 @@
+use crate::reader::OsmReader;
+use crate::shape_processor::PlanetDataPaths;
+use crate::simplification::{SimplificationConfig, SimplificationPreset};
+use crate::tag_mapping::TagMapping;
+use crate::tile_processor::{PopulationThreshold, ZoomPolicy};
+use geo::{Coord, Polygon, Rect};
+use osm::map::get_world_boundary;
+use osm::source::tiles_sqlite_store::TilesSQLiteStore;
+use osm::tiles::codec::TileCodec;
+use osm::tiles::{TileGrid, TileScheme};
+use osm::tile_writer::tile_writer::{TileFormat, TileWriterConfig, DEFAULT_TILE_PADDING};
 use serde::Deserialize;
 use serde_derive::Serialize;
+use serde_json::Value;
+use std::io::{Read, Seek};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use thiserror::Error;
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ShashlikConfig {
-    #[serde(rename = "land_path")]
-    pub land_path: String,
     #[serde(rename = "planet_data")]
     pub planet_data: bool,
+    #[serde(rename = "planet_data_paths", default)]
+    pub planet_data_paths: PlanetDataPaths,
+    /// Populated places aren't filtered by population if empty
+    #[serde(rename = "population_thresholds", default)]
+    pub population_thresholds: Vec<PopulationThreshold>,
+    /// Zoom levels feature kinds are written to
+    #[serde(rename = "zoom_policy", default)]
+    pub zoom_policy: ZoomPolicy,
+    /// Output tiles db, `dbs/tiles.db` if not set
+    #[serde(rename = "tiles_db_path")]
+    pub tiles_db_path: Option<String>,
+    /// Every area is saved to its own db named after the area next to the shared tiles db,
+    /// planet data is saved to the shared tiles db
+    #[serde(rename = "per_area_output", default)]
+    pub per_area_output: bool,
+    /// Tiles of every area are written to the tiles db once the area is extracted instead of
+    /// being kept in memory until the end,
+    /// see [osm::tile_writer::tile_writer::TileWriter::write_collected_tiles]
+    #[serde(rename = "incremental_output", default)]
+    pub incremental_output: bool,
+    /// Geometry of every area is clipped to the area bounds, otherwise ways and polygons
+    /// crossing the bounds are cut at their last node inside
+    #[serde(rename = "clip_to_bounds", default)]
+    pub clip_to_bounds: bool,
     #[serde(rename = "merge_polygons")]
     pub merge_polygons: bool,
     #[serde(rename = "preserve_road_topology")]
     pub preserve_road_topology: bool,
+    #[serde(rename = "min_zoom")]
+    pub min_zoom: Option<u32>,
+    #[serde(rename = "max_zoom")]
+    pub max_zoom: Option<u32>,
+    #[serde(rename = "emit_tile_bounds_debug", default)]
+    pub emit_tile_bounds_debug: bool,
+    #[serde(rename = "tile_format", default)]
+    pub tile_format: TileFormat,
+    #[serde(rename = "tile_codec", default)]
+    pub tile_codec: TileCodec,
+    /// Scale of the tile bbox geometry is clipped to, see [TileWriterConfig::tile_padding]
+    #[serde(rename = "tile_padding")]
+    pub tile_padding: Option<f64>,
+    #[serde(rename = "dedup_geometries", default)]
+    pub dedup_geometries: bool,
+    #[serde(rename = "tile_scheme", default)]
+    pub tile_scheme: TileScheme,
+    #[serde(rename = "tile_grid", default)]
+    pub tile_grid: TileGrid,
+    #[serde(rename = "vacuum_after_build", default)]
+    pub vacuum_after_build: bool,
+    /// See [TileWriterConfig::durable_output]
+    #[serde(rename = "durable_output", default)]
+    pub durable_output: bool,
+    /// Exterior ring of [lon, lat] points, all features are clipped to it
+    #[serde(rename = "clip_polygon")]
+    pub clip_polygon: Option<Vec<[f64; 2]>>,
+    #[serde(rename = "simplification_preset", default)]
+    pub simplification_preset: SimplificationPreset,
+    /// Overrides of individual [SimplificationConfig] fields on top of the preset
+    #[serde(rename = "simplification", default)]
+    pub simplification: Value,
+    /// Node coordinates are spilled to a temporary db every time this many nodes are collected,
+    /// all nodes are kept in memory if not set
+    #[serde(rename = "node_spill_threshold")]
+    pub node_spill_threshold: Option<usize>,
+    /// OSM tags to extract, [TagMapping::default] if not set
+    #[serde(rename = "tag_mapping")]
+    pub tag_mapping: Option<TagMapping>,
     pub areas: Vec<Area>,
 }
 
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Invalid zoom range {0}..={1}, expected min <= max <= {2}")]
+    ZoomRange(u32, u32, u32),
+    #[error("Invalid tile grid, expected positive tiles count")]
+    TileGrid,
+    #[error("Invalid tile padding {0}, expected at least 1.0")]
+    TilePadding(f64),
+    #[error("Incremental output requires the bincode tile format, written MVT tiles can't be extended")]
+    IncrementalOutput,
+    #[error("Resume requires the bincode tile format, written MVT tiles can't be extended")]
+    Resume,
+    #[error("Invalid clip polygon, expected at least 3 points")]
+    ClipPolygon,
+    #[error("Invalid tag mapping entry for key {0}")]
+    TagMapping(String),
+    #[error("Invalid bounds of area {0}: {1}")]
+    Area(String, &'static str),
+    #[error("Invalid simplification config: {0}")]
+    Simplification(serde_json::Error),
+}
+
+impl ShashlikConfig {
+    pub fn zoom_range(&self) -> RangeInclusive<u32> {
+        self.min_zoom.unwrap_or(0)..=self.max_zoom.unwrap_or(self.tile_grid.max_zoom)
+    }
+
+    pub fn tile_writer_config(&self) -> TileWriterConfig {
+        TileWriterConfig {
+            zoom_range: self.zoom_range(),
+            emit_tile_bounds_debug: self.emit_tile_bounds_debug,
+            tile_format: self.tile_format,
+            tile_codec: self.tile_codec,
+            dedup_geometries: self.dedup_geometries,
+            tile_padding: self.tile_padding.unwrap_or(DEFAULT_TILE_PADDING),
+            db_path: self.tiles_db_path(),
+            vacuum_after_build: self.vacuum_after_build,
+            durable_output: self.durable_output,
+            tile_scheme: self.tile_scheme,
+            grid: self.tile_grid,
+            clip_polygon: self.clip_polygon.as_ref().map(|ring| {
+                Arc::new(Polygon::new(
+                    ring.iter().map(|&[x, y]| Coord { x, y }).collect(),
+                    vec![],
+                ))
+            }),
+        }
+    }
+
+    pub fn tiles_db_path(&self) -> PathBuf {
+        self.tiles_db_path
+            .as_ref()
+            .map_or_else(|| PathBuf::from(TilesSQLiteStore::DEFAULT_DB_PATH), PathBuf::from)
+    }
+
+    /// Dir of per area dbs, see [ShashlikConfig::per_area_output]
+    pub fn area_dbs_dir(&self) -> PathBuf {
+        self.tiles_db_path()
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default()
+    }
+
+    pub fn tag_mapping(&self) -> TagMapping {
+        self.tag_mapping.clone().unwrap_or_default()
+    }
+
+    pub fn simplification_config(&self) -> Result<SimplificationConfig, ConfigError> {
+        let mut config = serde_json::to_value(self.simplification_preset.config())
+            .map_err(ConfigError::Simplification)?;
+        Self::merge_overrides(&mut config, &self.simplification);
+        serde_json::from_value(config).map_err(ConfigError::Simplification)
+    }
+
+    fn merge_overrides(target: &mut Value, overrides: &Value) {
+        match (target, overrides) {
+            (Value::Object(target), Value::Object(overrides)) => {
+                for (key, value) in overrides {
+                    Self::merge_overrides(target.entry(key.clone()).or_insert(Value::Null), value);
+                }
+            }
+            (_, Value::Null) => {}
+            (target, overrides) => *target = overrides.clone(),
+        }
+    }
+
+    /// Checks the config can be extracted with `--resume` on top of [ShashlikConfig::validate]
+    pub fn validate_resume(&self) -> Result<(), ConfigError> {
+        if self.tile_format == TileFormat::Mvt {
+            return Err(ConfigError::Resume);
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.tile_grid.tiles_count <= 0 {
+            return Err(ConfigError::TileGrid);
+        }
+        let zoom_range = self.zoom_range();
+        if zoom_range.start() > zoom_range.end() || *zoom_range.end() > self.tile_grid.max_zoom {
+            return Err(ConfigError::ZoomRange(
+                *zoom_range.start(),
+                *zoom_range.end(),
+                self.tile_grid.max_zoom,
+            ));
+        }
+        if let Some(tile_padding) = self.tile_padding.filter(|padding| padding.is_nan() || *padding < 1.0) {
+            return Err(ConfigError::TilePadding(tile_padding));
+        }
+        if self.incremental_output && self.tile_format == TileFormat::Mvt {
+            return Err(ConfigError::IncrementalOutput);
+        }
+        if self.clip_polygon.as_ref().is_some_and(|ring| ring.len() < 3) {
+            return Err(ConfigError::ClipPolygon);
+        }
+        if let Some(key) = self.tag_mapping.as_ref().and_then(TagMapping::invalid_entry) {
+            return Err(ConfigError::TagMapping(key.to_string()));
+        }
+        if let Some((area, reason)) = self
+            .areas
+            .iter()
+            .filter(|area| area.enabled)
+            .find_map(|area| area.invalid_bounds().map(|reason| (area, reason)))
+        {
+            return Err(ConfigError::Area(area.name.clone(), reason));
+        }
+        self.simplification_config()?;
+        Ok(())
+    }
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Area {
     pub name: String,
     pub enabled: bool,
     pub path: String,
-    pub left: f64,
-    pub top: f64,
-    pub right: f64,
-    pub bottom: f64,
+    /// Bounds are either all set or all omitted, an area without bounds covers the whole file,
+    /// see [Area::resolve_boundary]
+    #[serde(default)]
+    pub left: Option<f64>,
+    #[serde(default)]
+    pub top: Option<f64>,
+    #[serde(default)]
+    pub right: Option<f64>,
+    #[serde(default)]
+    pub bottom: Option<f64>,
+}
+
+impl Area {
+    /// Tiles db file name of the area for [ShashlikConfig::per_area_output],
+    /// e.g. `san_francisco.db` for `San Francisco`
+    pub fn db_file_name(&self) -> String {
+        let name: String = self
+            .name
+            .trim()
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+            .collect();
+        format!("{}.db", if name.is_empty() { "area" } else { &name })
+    }
+
+    /// Explicit bounds of the area, `None` unless all of them are set
+    pub fn boundary(&self) -> Option<Rect> {
+        Some(Rect::new(
+            Coord {
+                x: self.left?,
+                y: self.top?,
+            },
+            Coord {
+                x: self.right?,
+                y: self.bottom?,
+            },
+        ))
+    }
+
+    /// Explicit bounds of the area, otherwise the bbox from the header of the OSM file
+    /// and the world boundary if the file doesn't declare one
+    pub fn resolve_boundary<T: Read + Seek>(&self, osm_file: T) -> Rect {
+        self.boundary().unwrap_or_else(|| {
+            OsmReader::new(osm_file, get_world_boundary())
+                .header_bbox()
+                .expect("Failed to read OSM header")
+                .unwrap_or_else(get_world_boundary)
+        })
+    }
+
+    /// Reason the bounds are malformed, `top` is the north edge
+    pub fn invalid_bounds(&self) -> Option<&'static str> {
+        let bounds = [self.left, self.top, self.right, self.bottom];
+        if bounds.iter().all(Option::is_none) {
+            return None;
+        }
+        let [Some(left), Some(top), Some(right), Some(bottom)] = bounds else {
+            return Some("left, top, right and bottom must be all set or all omitted");
+        };
+        let lon_range = -180.0..=180.0;
+        let lat_range = -90.0..=90.0;
+        if !lon_range.contains(&left) || !lon_range.contains(&right) {
+            Some("left and right must be within -180..=180")
+        } else if !lat_range.contains(&top) || !lat_range.contains(&bottom) {
+            Some("top and bottom must be within -90..=90")
+        } else if left >= right {
+            Some("left must be less than right")
+        } else if top <= bottom {
+            Some("top must be greater than bottom")
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::{Area, ConfigError, ShashlikConfig};
+    use crate::proto::{HeaderBBox, HeaderBlock};
+    use crate::reader::{test_pbf, test_pbf_with_header};
+    use crate::simplification::{SimplificationConfig, SimplificationPreset};
+    use geo::{coord, Rect};
+    use osm::map::get_world_boundary;
+    use serde_json::json;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_simplification_overrides() {
+        let config = ShashlikConfig::default();
+        assert_eq!(
+            config.simplification_config().unwrap(),
+            SimplificationConfig::default()
+        );
+
+        let config = ShashlikConfig {
+            simplification_preset: SimplificationPreset::Lightweight,
+            simplification: json!({"road_epsilon": 0.1, "forest": {"concavity": 5.0}}),
+            ..Default::default()
+        };
+        let simplification = config.simplification_config().unwrap();
+        let lightweight = SimplificationPreset::Lightweight.config();
+        assert_eq!(simplification.road_epsilon, 0.1);
+        assert_eq!(simplification.forest.concavity, 5.0);
+        assert_eq!(simplification.nature_epsilon, lightweight.nature_epsilon);
+        assert_eq!(simplification.forest.min_area, lightweight.forest.min_area);
+
+        let config = ShashlikConfig {
+            simplification: json!({"road_epsilon": "big"}),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_area_bounds() {
+        let london = Area {
+            name: "London".to_string(),
+            enabled: true,
+            path: "greater-london-latest.osm.pbf".to_string(),
+            left: Some(-0.2705),
+            top: Some(51.5775),
+            right: Some(0.0858),
+            bottom: Some(51.4232),
+        };
+        let config = |areas| ShashlikConfig {
+            areas,
+            ..Default::default()
+        };
+        assert!(config(vec![london.clone()]).validate().is_ok());
+
+        let swapped = Area {
+            name: "Swapped".to_string(),
+            top: london.bottom,
+            bottom: london.top,
+            ..london.clone()
+        };
+        let err = config(vec![london.clone(), swapped.clone()]).validate().unwrap_err();
+        assert!(matches!(err, ConfigError::Area(ref name, _) if name == "Swapped"));
+        assert_eq!(
+            err.to_string(),
+            "Invalid bounds of area Swapped: top must be greater than bottom"
+        );
+
+        let out_of_range = Area {
+            right: Some(181.0),
+            ..london.clone()
+        };
+        assert!(config(vec![out_of_range]).validate().is_err());
+
+        let partial = Area {
+            bottom: None,
+            ..london.clone()
+        };
+        assert!(config(vec![partial]).validate().is_err());
+        let whole_file = Area {
+            left: None,
+            top: None,
+            right: None,
+            bottom: None,
+            ..london.clone()
+        };
+        assert!(config(vec![whole_file]).validate().is_ok());
+
+        // disabled areas aren't extracted
+        let disabled = Area {
+            enabled: false,
+            ..swapped
+        };
+        assert!(config(vec![london, disabled]).validate().is_ok());
+    }
+
+    #[test]
+    fn test_area_bounds_from_header() {
+        let header = HeaderBlock {
+            bbox: Some(HeaderBBox {
+                left: -122_545_600_000,
+                right: -121_775_200_000,
+                top: 37_814_100_000,
+                bottom: 37_232_500_000,
+            }),
+            ..Default::default()
+        };
+        let whole_file: Area = serde_json::from_value(json!({
+            "name": "San Francisco",
+            "enabled": true,
+            "path": "norcal-latest.osm.pbf",
+        }))
+        .unwrap();
+        assert_eq!(whole_file.boundary(), None);
+
+        let pbf = test_pbf_with_header(Some(header), vec![]);
+        assert_eq!(
+            whole_file.resolve_boundary(Cursor::new(pbf.clone())),
+            Rect::new(
+                coord! {x: -122.5456, y: 37.2325},
+                coord! {x: -121.7752, y: 37.8141}
+            )
+        );
+        // a file without a bbox covers the world
+        let pbf_without_bbox = test_pbf_with_header(Some(HeaderBlock::default()), vec![]);
+        let world = get_world_boundary();
+        assert_eq!(whole_file.resolve_boundary(Cursor::new(pbf_without_bbox)), world);
+        assert_eq!(whole_file.resolve_boundary(Cursor::new(test_pbf(vec![]))), world);
+
+        // explicit bounds take precedence
+        let area = Area {
+            left: Some(-122.5),
+            top: Some(37.8),
+            right: Some(-122.3),
+            bottom: Some(37.7),
+            ..whole_file
+        };
+        assert_eq!(
+            area.resolve_boundary(Cursor::new(pbf)),
+            Rect::new(coord! {x: -122.5, y: 37.7}, coord! {x: -122.3, y: 37.8})
+        );
+    }
 }
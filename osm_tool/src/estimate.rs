@@ -0,0 +1,204 @@
+use crate::filter::TagFilter;
+use crate::reader::OsmReader;
+use crate::tag_mapping::TagMapping;
+use geo::Rect;
+use osm::tiles::TileGrid;
+use rustc_hash::FxHashSet;
+use std::fmt::{Display, Formatter};
+use std::io::{Read, Seek};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+
+/// Elements of an area the extract would process, counted without building any geometry
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AreaEstimate {
+    /// Nodes inside the area
+    pub nodes: usize,
+    /// Nodes matching the node tags
+    pub poi_nodes: usize,
+    /// Ways matching the way tags with at least one node inside the area
+    pub ways: usize,
+    /// Relations matching the relation tags, they aren't limited to the area
+    pub relations: usize,
+    /// Tiles covering the area at all zoom levels, an upper bound of the written tiles
+    pub max_tiles: u64,
+}
+
+/// Reads the OSM data the same way the extract does, only node ids are kept in memory
+pub fn estimate_area<T: Read + Seek>(
+    input: T,
+    boundary: Rect,
+    tag_mapping: &TagMapping,
+    grid: TileGrid,
+    zoom_range: RangeInclusive<u32>,
+    offsets_index_path: Option<PathBuf>,
+) -> AreaEstimate {
+    let mut reader = OsmReader::new(input, boundary);
+    if let Some(offsets_index_path) = offsets_index_path {
+        reader = reader.with_offsets_index(offsets_index_path);
+    }
+    reader.header().expect("Failed to read OSM header");
+    let relations = reader.count_relations(&TagMapping::filter_tags(&tag_mapping.relations));
+    let (node_blobs, way_blobs, _) = reader.data();
+
+    let mut node_ids = FxHashSet::default();
+    let mut poi_nodes = 0;
+    for data_blob in node_blobs {
+        let tag_filter = TagFilter::new(
+            &data_blob.string_table,
+            &TagMapping::filter_tags(&tag_mapping.nodes),
+        );
+        for node in &data_blob.nodes {
+            node_ids.insert(node.id);
+            if tag_filter.filter(&data_blob.string_table, &node.tags).is_some() {
+                poi_nodes += 1;
+            }
+        }
+    }
+
+    let ways = way_blobs
+        .iter()
+        .map(|data_blob| {
+            let tag_filter = TagFilter::new(
+                &data_blob.string_table,
+                &TagMapping::filter_tags(&tag_mapping.ways),
+            );
+            data_blob
+                .ways
+                .iter()
+                .filter(|way| {
+                    tag_filter.filter(&data_blob.string_table, &way.tags).is_some()
+                        && way.refs.iter().any(|id| node_ids.contains(id))
+                })
+                .count()
+        })
+        .sum();
+
+    let max_tiles = grid
+        .zoom_levels()
+        .filter(|zoom_level| zoom_range.contains(zoom_level))
+        .map(|zoom_level| {
+            let ranges = grid.tile_ranges(zoom_level as i32, &boundary);
+            (ranges.max_x - ranges.min_x + 1) as u64 * (ranges.max_y - ranges.min_y + 1) as u64
+        })
+        .sum();
+
+    AreaEstimate {
+        nodes: node_ids.len(),
+        poi_nodes,
+        ways,
+        relations,
+        max_tiles,
+    }
+}
+
+impl Display for AreaEstimate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} nodes ({} POIs), {} ways, {} relations, up to {} tiles",
+            self.nodes, self.poi_nodes, self.ways, self.relations, self.max_tiles
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::estimate::estimate_area;
+    use crate::node_store::NodeCoordStore;
+    use crate::pbf_processor::PbfProcessor;
+    use crate::proto::{DenseNodes, PrimitiveBlock, PrimitiveGroup, StringTable, Way};
+    use crate::reader::{test_pbf, OsmReader};
+    use crate::tag_mapping::TagMapping;
+    use geo::{coord, Rect};
+    use osm::tiles::TileGrid;
+    use std::io::Cursor;
+    use std::sync::{mpsc, Arc};
+
+    /// Nodes 1-3 are inside the area, node 4 is outside
+    fn fixture() -> Vec<u8> {
+        let string_table = StringTable {
+            s: ["", "highway", "primary", "building", "yes", "amenity", "toilets", "shop"]
+                .map(|s| s.as_bytes().to_vec())
+                .to_vec(),
+        };
+        let block = |group| PrimitiveBlock {
+            stringtable: string_table.clone(),
+            primitivegroup: vec![group],
+            granularity: None,
+            lat_offset: None,
+            lon_offset: None,
+            date_granularity: None,
+        };
+        let nodes = block(PrimitiveGroup {
+            dense: Some(DenseNodes {
+                id: vec![1, 1, 1, 1],
+                denseinfo: None,
+                lat: vec![10_000_000, 1_000, 1_000, 100_000_000],
+                lon: vec![10_000_000, 1_000, 1_000, 100_000_000],
+                keys_vals: vec![5, 6, 0, 0, 0, 0],
+            }),
+            ..Default::default()
+        });
+        let way = |id, tag: (u32, u32), refs: Vec<i64>| Way {
+            id,
+            keys: vec![tag.0],
+            vals: vec![tag.1],
+            info: None,
+            refs,
+            lat: vec![],
+            lon: vec![],
+        };
+        let ways = block(PrimitiveGroup {
+            ways: vec![
+                way(10, (1, 2), vec![1, 1]),
+                way(11, (3, 4), vec![1, 1, 1, -2]),
+                // outside of the area
+                way(12, (1, 2), vec![4]),
+                // not extracted tag
+                way(13, (7, 4), vec![1, 1]),
+            ],
+            ..Default::default()
+        });
+        test_pbf(vec![nodes, ways])
+    }
+
+    #[test]
+    fn test_estimate_ways() {
+        let boundary = Rect::new(coord! {x: 0.5, y: 0.5}, coord! {x: 2.0, y: 2.0});
+        let tag_mapping = TagMapping::default();
+        let estimate = estimate_area(
+            Cursor::new(fixture()),
+            boundary,
+            &tag_mapping,
+            TileGrid::default(),
+            0..=0,
+            None,
+        );
+
+        let (node_blobs, way_blobs, _) = OsmReader::new(Cursor::new(fixture()), boundary).data();
+        let mut nodes = NodeCoordStore::new(None);
+        let pois = node_blobs
+            .iter()
+            .flat_map(|data_blob| PbfProcessor::read_nodes(&tag_mapping, data_blob, &mut nodes))
+            .count();
+        let nodes = Arc::new(nodes);
+        let (tx, rx) = mpsc::channel();
+        for data_blob in way_blobs {
+            PbfProcessor::read_ways(tx.clone(), &tag_mapping, data_blob, &nodes);
+        }
+        drop(tx);
+        let extracted_ways = rx.into_iter().count();
+
+        assert_eq!(estimate.nodes, 3);
+        assert_eq!(estimate.poi_nodes, pois);
+        assert_eq!(estimate.ways, 2);
+        assert_eq!(estimate.ways, extracted_ways);
+        assert_eq!(estimate.relations, 0);
+        let ranges = TileGrid::default().tile_ranges(0, &boundary);
+        assert_eq!(
+            estimate.max_tiles,
+            (ranges.max_x - ranges.min_x + 1) as u64 * (ranges.max_y - ranges.min_y + 1) as u64
+        );
+    }
+}
@@ -0,0 +1,193 @@
+use error_stack::{Report, ResultExt};
+use geo::{coord, BoundingRect, Polygon, Rect};
+use osm::map::{MapGeomObject, MapGeomObjectKind, MapGeometry, MapPointObjectKind, NatureKind};
+use osm::source::tiles_sqlite_store::TilesSQLiteStore;
+use osm::tiles::{lat_lon_to_world, world_to_lat_lon, TileGrid, TileKey, TileStore};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum InspectTileError {
+    #[error("Failed to read tiles db")]
+    Read,
+    #[error("Tile is missing")]
+    MissingTile,
+}
+
+/// Features of the tile, `key` is in the row addressing of the db
+pub fn load_tile(
+    db: &Path,
+    key: &TileKey,
+) -> Result<Vec<(MapGeomObject, MapGeometry<f32>)>, Report<InspectTileError>> {
+    let store = TilesSQLiteStore::new(db);
+    if !store
+        .has_tile(key.tile_x, key.tile_y, key.zoom_level)
+        .change_context(InspectTileError::Read)?
+    {
+        return Err(Report::new(InspectTileError::MissingTile));
+    }
+    Ok(TileStore::new(store).load_geometries(key))
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct TileSummary {
+    pub feature_counts: BTreeMap<String, usize>,
+    pub coord_count: usize,
+    /// Lat/lon bounding box of all features
+    pub bounds: Option<Rect>,
+}
+
+impl TileSummary {
+    /// `geometries` are in the tile local world coordinates of `key` in the internal addressing of `grid`
+    pub fn new(grid: &TileGrid, key: &TileKey, geometries: &[(MapGeomObject, MapGeometry<f32>)]) -> Self {
+        let mut summary = TileSummary::default();
+        let mut local_bounds: Option<Rect<f32>> = None;
+        for (obj, geometry) in geometries {
+            *summary.feature_counts.entry(kind_name(&obj.kind)).or_default() += 1;
+            summary.coord_count += match geometry {
+                MapGeometry::Line(line) => line.0.len(),
+                MapGeometry::Poly(poly) => poly_coord_count(poly),
+                MapGeometry::Coord(_) => 1,
+                MapGeometry::MultiPoly(multi_poly) => multi_poly.iter().map(poly_coord_count).sum(),
+                MapGeometry::MultiLine(multi_line) => multi_line.iter().map(|line| line.0.len()).sum(),
+            };
+            if let Some(rect) = geometry.bounding_rect() {
+                local_bounds = Some(local_bounds.map_or(rect, |bounds| {
+                    Rect::new(
+                        coord! {x: bounds.min().x.min(rect.min().x), y: bounds.min().y.min(rect.min().y)},
+                        coord! {x: bounds.max().x.max(rect.max().x), y: bounds.max().y.max(rect.max().y)},
+                    )
+                }));
+            }
+        }
+        let origin = lat_lon_to_world(&grid.tile_boundary(key, 1.0).min());
+        summary.bounds = local_bounds.map(|bounds| {
+            let to_lat_lon = |coord: geo::Coord<f32>| {
+                world_to_lat_lon(&(coord! {x: coord.x as f64, y: coord.y as f64} + origin))
+            };
+            Rect::new(to_lat_lon(bounds.min()), to_lat_lon(bounds.max()))
+        });
+        summary
+    }
+}
+
+impl Display for TileSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Features: {}", self.feature_counts.values().sum::<usize>())?;
+        for (kind, count) in &self.feature_counts {
+            writeln!(f, "  {kind}: {count}")?;
+        }
+        writeln!(f, "Coordinates: {}", self.coord_count)?;
+        match self.bounds {
+            Some(bounds) => write!(
+                f,
+                "Bounds: {},{},{},{}",
+                bounds.min().x,
+                bounds.min().y,
+                bounds.max().x,
+                bounds.max().y
+            ),
+            None => write!(f, "Bounds: none"),
+        }
+    }
+}
+
+fn poly_coord_count(poly: &Polygon<f32>) -> usize {
+    poly.exterior().0.len() + poly.interiors().iter().map(|line| line.0.len()).sum::<usize>()
+}
+
+/// Kind with its subkind, e.g. `nature/water`
+pub fn kind_name(kind: &MapGeomObjectKind) -> String {
+    match kind {
+        MapGeomObjectKind::Nature(kind) => {
+            let kind = match kind {
+                NatureKind::Ground => "ground",
+                NatureKind::Park => "park",
+                NatureKind::Forest => "forest",
+                NatureKind::Water => "water",
+            };
+            format!("nature/{kind}")
+        }
+        MapGeomObjectKind::Building(_) => "building".to_string(),
+        MapGeomObjectKind::Way(info) => format!("way/{}", info.line_kind.as_str()),
+        MapGeomObjectKind::AdminLine(level) => format!("admin_line/{level}"),
+        MapGeomObjectKind::Poi(info) => {
+            let kind = match info.kind {
+                MapPointObjectKind::PopArea(_) => "pop_area",
+                MapPointObjectKind::TrafficLight => "traffic_light",
+                MapPointObjectKind::Toilet => "toilet",
+                MapPointObjectKind::Parking => "parking",
+                MapPointObjectKind::TrainStation(_) => "train_station",
+                MapPointObjectKind::Generic => "generic",
+            };
+            format!("poi/{kind}")
+        }
+        MapGeomObjectKind::TileBounds => "tile_bounds".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::inspect_tile::{load_tile, InspectTileError, TileSummary};
+    use geo::{coord, line_string, polygon};
+    use osm::map::{
+        MapGeomObject, MapGeomObjectKind, MapGeometry, MapGeometryCollection, NatureKind,
+    };
+    use osm::tile_writer::tile_writer::TileWriter;
+    use osm::tiles::codec::TileCodec;
+    use osm::tiles::{TileGrid, TileKey};
+    use rusqlite::Connection;
+    use std::fs;
+
+    #[test]
+    fn test_inspect_tile() {
+        let path = std::env::temp_dir().join(format!("osm_tool_inspect_tile_{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let object = |id, kind| MapGeomObject { id, kind };
+        let collection = MapGeometryCollection::<f32>(vec![
+            (
+                object(1, MapGeomObjectKind::Building(Default::default())),
+                MapGeometry::Poly(polygon![
+                    (x: 1.0, y: 1.0),
+                    (x: 2.0, y: 1.0),
+                    (x: 2.0, y: 2.0),
+                    (x: 1.0, y: 2.0),
+                ]),
+            ),
+            (
+                object(2, MapGeomObjectKind::Building(Default::default())),
+                MapGeometry::Coord(coord! {x: 3.0, y: 3.0}),
+            ),
+            (
+                object(3, MapGeomObjectKind::Nature(NatureKind::Water)),
+                MapGeometry::Line(line_string![(x: 0.5, y: 0.5), (x: 4.0, y: 1.0)]),
+            ),
+        ]);
+        let conn = Connection::open(&path).unwrap();
+        TileWriter::create_tables(&conn);
+        let data = TileCodec::Gzip
+            .compress(&bincode::serialize(&collection).unwrap())
+            .unwrap();
+        conn.execute("INSERT INTO tiles (x, y, z, data) VALUES (3, 5, 0, ?1)", [data])
+            .unwrap();
+        drop(conn);
+
+        let key = TileKey::new(3, 5, 0);
+        let geometries = load_tile(&path, &key).unwrap();
+        let summary = TileSummary::new(&TileGrid::default(), &key, &geometries);
+        assert_eq!(summary.feature_counts["building"], 2);
+        assert_eq!(summary.feature_counts["nature/water"], 1);
+        // closed polygon ring, a point and a line
+        assert_eq!(summary.coord_count, 5 + 1 + 2);
+        let bounds = summary.bounds.unwrap();
+        let tile = key.calc_tile_boundary(1.0);
+        assert!(bounds.min().x >= tile.min().x && bounds.max().x <= tile.max().x);
+        assert!(summary.to_string().starts_with("Features: 3\n"));
+
+        let missing = load_tile(&path, &TileKey::new(3, 6, 0)).unwrap_err();
+        assert!(matches!(missing.current_context(), InspectTileError::MissingTile));
+        fs::remove_file(path).unwrap();
+    }
+}
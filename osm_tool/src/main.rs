@@ -3,25 +3,41 @@ pub mod filter;
 pub mod proto;
 pub mod reader;
 pub mod tags;
+mod building_store;
 mod config;
 mod countries;
+mod estimate;
+mod area_extract;
 mod tile_processor;
 mod shape_processor;
 mod pbf_processor;
 mod way_store;
 mod polygon_store;
+mod inspect_tile;
+mod merge_tiles;
+mod node_store;
+mod selftest;
+mod simplification;
+mod stats;
+mod tile_container;
+mod tag_mapping;
 
 use clap::{Args, Parser, Subcommand};
-use geo::{Coord, CoordNum, Rect};
+use geo::{Coord, CoordNum};
 
 use crate::config::ShashlikConfig;
-use crate::pbf_processor::PbfProcessor;
+use crate::inspect_tile::TileSummary;
+use crate::merge_tiles::CollisionPolicy;
 use crate::shape_processor::ShapeProcessor;
 use crate::tile_processor::TileProcessor;
 use osm::map::get_world_boundary;
+use osm::source::tiles_sqlite_store::TilesSQLiteStore;
+use osm::tiles::{tile_geometries_to_geojson, TileKey};
 use rs_concaveman::location_trait::LocationTrait;
 use std::time::Instant;
 use std::fs::File;
+use std::path::Path;
+use std::fs;
 
 #[derive(Parser)]
 #[command(about = "OSM data manipulation tool")]
@@ -49,8 +65,61 @@ impl LocationTrait for LocationTraitCoord {
 struct ExtractArgs {
     /// Path to shashlik config json file.
     /// Example of json:
-    /// { land_path: "/Users/kirill/Downloads/japan-latest.osm.pbf", areas: [Area { name: "Tokyo", enabled: true, path: "/Users/kirill/Downloads/japan-latest.osm.pbf", left: 138.647, top: 36.532, right: 140.933, bottom: 34.574 }, Area { name: "San Francisco", enabled: true, path: "/Users/kirill/Downloads/norcal-latest.osm.pbf", left: -122.5456, top: 37.8141, right: -121.7752, bottom: 37.2325 }, Area { name: "London", enabled: true, path: "/Users/kirill/Downloads/greater-london-latest.osm.pbf", left: -0.2705, top: 51.5775, right: 0.0858, bottom: 51.4232 }] }
+    /// { areas: [Area { name: "Tokyo", enabled: true, path: "/Users/kirill/Downloads/japan-latest.osm.pbf", left: 138.647, top: 36.532, right: 140.933, bottom: 34.574 }, Area { name: "San Francisco", enabled: true, path: "/Users/kirill/Downloads/norcal-latest.osm.pbf", left: -122.5456, top: 37.8141, right: -121.7752, bottom: 37.2325 }, Area { name: "London", enabled: true, path: "/Users/kirill/Downloads/greater-london-latest.osm.pbf", left: -0.2705, top: 51.5775, right: 0.0858, bottom: 51.4232 }] }
+    /// Area path can be `-` to read OSM data from stdin.
+    /// Area bounds can be omitted to extract the whole file within the bbox of its header.
     shashlik_config_path: String,
+    /// Print counts of matching OSM elements and tiles per enabled area without writing anything
+    #[arg(long)]
+    estimate: bool,
+    /// Save the tiles db after every area and skip the areas completed by an interrupted run.
+    /// Ignored with per area output
+    #[arg(long)]
+    resume: bool,
+}
+
+#[derive(Args)]
+struct ExportContainerArgs {
+    /// Path to tiles db
+    tiles_db_path: String,
+    /// Container path for output, it isn't MBTiles and shouldn't use the .mbtiles extension
+    container_path: String,
+}
+
+#[derive(Args)]
+struct MergeTilesArgs {
+    /// Path to the merged tiles db for output
+    output_path: String,
+    /// Paths to tiles dbs to merge, later ones take precedence
+    #[arg(required = true)]
+    input_paths: Vec<String>,
+    /// How tiles present in several inputs are merged
+    #[arg(long, value_enum, default_value_t)]
+    collision: CollisionPolicy,
+}
+
+#[derive(Args)]
+struct InspectTileArgs {
+    /// Path to tiles db
+    tiles_db_path: String,
+    x: i32,
+    y: i32,
+    z: i32,
+    /// Print all features as GeoJSON in lat/lon
+    #[arg(long)]
+    geojson: bool,
+}
+
+#[derive(Args)]
+struct StatsArgs {
+    /// Path to tiles db
+    tiles_db_path: String,
+    /// Amount of the largest tiles to list
+    #[arg(long, default_value_t = 10)]
+    largest: usize,
+    /// Amount of tiles decoded to count features by kind
+    #[arg(long, default_value_t = 100)]
+    sample: usize,
 }
 
 #[derive(Args)]
@@ -65,10 +134,21 @@ struct RoadGraphArgs {
 enum OsmToolSubcommand {
     #[command(about = "Extract OSM spacial/vector data")]
     Extract(ExtractArgs),
+    #[command(
+        about = "Export tiles db to a non-standard sqlite container in the internal tile grid, not readable as MBTiles"
+    )]
+    ExportContainer(ExportContainerArgs),
+    #[command(about = "Merge tiles dbs into one")]
+    MergeTiles(MergeTilesArgs),
+    #[command(about = "Print a summary of a stored tile")]
+    InspectTile(InspectTileArgs),
+    #[command(about = "Print tile counts, sizes and feature kinds of a tiles db")]
+    Stats(StatsArgs),
+    #[command(about = "Build and read back a tiny synthetic tileset to verify the setup")]
+    Selftest,
 }
 
 const POLYGON_MERGE_ZOOM_LEVEL: u32 = 3;
-
 fn main() {
     let cmd = OsmToolCommand::parse();
 
@@ -77,45 +157,108 @@ fn main() {
             let shashlik_config: ShashlikConfig =
                 serde_json::from_reader(File::open(args.shashlik_config_path).unwrap()).expect("JSON was not well-formatted");
             println!("shashlik_config: {:?}", shashlik_config);
+            if let Err(err) = shashlik_config.validate() {
+                panic!("Invalid config: {err}");
+            }
+            // per area dbs are always written from scratch
+            let resume = args.resume && !shashlik_config.per_area_output;
+            if resume {
+                if let Err(err) = shashlik_config.validate_resume() {
+                    panic!("Invalid config: {err}");
+                }
+            }
+            let simplification_config = shashlik_config.simplification_config().unwrap();
+
+            if args.estimate {
+                let tag_mapping = shashlik_config.tag_mapping();
+                let mut stdin_buffer = None;
+                for area in shashlik_config.areas.iter().filter(|area| area.enabled) {
+                    let mut osm_file = area_extract::open_osm_file(&area.path, &mut stdin_buffer);
+                    let boundary = area.resolve_boundary(&mut osm_file);
+                    let estimate = estimate::estimate_area(
+                        osm_file,
+                        boundary,
+                        &tag_mapping,
+                        shashlik_config.tile_grid,
+                        shashlik_config.zoom_range(),
+                        area_extract::offsets_index_path(&area.path),
+                    );
+                    println!("{}: {}", area.name, estimate);
+                }
+                if let Some(stdin_buffer) = stdin_buffer {
+                    let _ = fs::remove_file(stdin_buffer);
+                }
+                return;
+            }
 
             let extract_ts = Instant::now();
 
-            let mut tile_processor = TileProcessor::new();
-            let shape_processor = ShapeProcessor {
-                world_boundary: get_world_boundary()
-            };
+            let mut tile_processor = TileProcessor::new(
+                shashlik_config.tile_writer_config(),
+                simplification_config,
+            )
+            .with_population_thresholds(shashlik_config.population_thresholds.clone())
+            .with_zoom_policy(shashlik_config.zoom_policy.clone());
+            let shape_processor = ShapeProcessor::new(
+                get_world_boundary(),
+                shashlik_config.planet_data_paths.clone(),
+            );
 
-            for area in shashlik_config.areas {
-                if !area.enabled {
-                    println!("Area {} disabled", area.name);
-                    continue;
-                }
-                let osm_file = File::open(area.path).expect("Could not open OSM file");
-                println!("Extracting OSM data for {}", area.name);
-                let boundary = Rect::new(
-                    Coord {
-                        x: area.left,
-                        y: area.top,
-                    },
-                    Coord {
-                        x: area.right,
-                        y: area.bottom,
-                    },
-                );
-                let mut pbf_processor = PbfProcessor::new();
-                pbf_processor.process_pbf(boundary, osm_file, &mut tile_processor,
-                                          shashlik_config.merge_polygons,
-                                          shashlik_config.preserve_road_topology);
-            }
+            area_extract::extract_areas(&shashlik_config, &mut tile_processor, resume);
 
             if shashlik_config.planet_data {
-                shape_processor.extract_planet_data(&mut tile_processor)
+                shape_processor
+                    .extract_planet_data(&mut tile_processor)
+                    .expect("Failed to extract planet data");
             }
 
-            tile_processor.save_to_disk();
+            if !shashlik_config.per_area_output {
+                tile_processor.save_to_disk();
+            } else if shashlik_config.planet_data {
+                tile_processor.save_to_path(&shashlik_config.tiles_db_path());
+            }
 
             println!("Total extract time: {:?}", extract_ts.elapsed());
         }
+        OsmToolSubcommand::ExportContainer(args) => {
+            let export_ts = Instant::now();
+            tile_container::export_tile_container(
+                args.tiles_db_path.as_ref(),
+                args.container_path.as_ref(),
+            )
+            .expect("Failed to export tile container");
+            println!("Total export time: {:?}", export_ts.elapsed());
+        }
+        OsmToolSubcommand::MergeTiles(args) => {
+            let merge_ts = Instant::now();
+            let inputs: Vec<&Path> = args.input_paths.iter().map(Path::new).collect();
+            merge_tiles::merge_tiles(args.output_path.as_ref(), &inputs, args.collision)
+                .expect("Failed to merge tiles dbs");
+            println!("Total merge time: {:?}", merge_ts.elapsed());
+        }
+        OsmToolSubcommand::InspectTile(args) => {
+            let key = TileKey::new(args.x, args.y, args.z);
+            let geometries = inspect_tile::load_tile(args.tiles_db_path.as_ref(), &key)
+                .expect("Failed to load tile");
+            // tile geometry is relative to the key in the internal tile grid
+            let store = TilesSQLiteStore::new(&args.tiles_db_path);
+            let grid = store.grid();
+            let grid_key = grid.flip_y(&key, store.scheme());
+            println!("{}", TileSummary::new(&grid, &grid_key, &geometries));
+            if args.geojson {
+                println!("{}", tile_geometries_to_geojson(&grid, &grid_key, &geometries));
+            }
+        }
+        OsmToolSubcommand::Stats(args) => {
+            let stats = stats::tiles_stats(args.tiles_db_path.as_ref(), args.largest, args.sample)
+                .expect("Failed to read tiles stats");
+            println!("{stats}");
+        }
+        OsmToolSubcommand::Selftest => {
+            if !selftest::run_selftest() {
+                std::process::exit(1);
+            }
+        }
     }
 }
 
@@ -0,0 +1,247 @@
+use clap::ValueEnum;
+use error_stack::{Report, ResultExt};
+use osm::source::tiles_sqlite_store::TilesSQLiteStore;
+use osm::tile_writer::tile_writer::{TileFormat, TileWriter, TileWriterConfig};
+use osm::tiles::codec::{decompress_tile, TileCodec, GEOMETRY_STORE_DEDUP};
+use osm::tiles::{decode_tile, TileKey, TileScheme};
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum MergeTilesError {
+    #[error("Failed to read tiles db")]
+    Read,
+    #[error("Failed to write merged tiles db")]
+    Write,
+    #[error("Input tiles dbs have different {0}")]
+    Mismatch(&'static str),
+    #[error("Tiles can be concatenated for bincode format only")]
+    Concatenate,
+}
+
+/// How a tile present in several inputs is merged
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum CollisionPolicy {
+    /// The tile of the later input replaces the earlier one
+    #[default]
+    LastWins,
+    /// Features of all inputs are stored in one tile
+    Concatenate,
+}
+
+/// Copies tiles of all inputs to a new db with inline geometries in the internal tile grid.
+/// Inputs must have the same tile format and grid, bincode tiles are re-encoded
+/// with the compression of the first input.
+pub fn merge_tiles(
+    output: &Path,
+    inputs: &[&Path],
+    policy: CollisionPolicy,
+) -> Result<(), Report<MergeTilesError>> {
+    let mut output_conn = Connection::open(output).change_context(MergeTilesError::Write)?;
+    TileWriter::create_tables(&output_conn);
+    let tx = output_conn
+        .transaction()
+        .change_context(MergeTilesError::Write)?;
+
+    let mut config: Option<TileWriterConfig> = None;
+    let mut coverage = Vec::new();
+    let mut covered_zooms: Option<RangeInclusive<u32>> = None;
+    for input in inputs {
+        let input_store = TilesSQLiteStore::open(input).change_context(MergeTilesError::Read)?;
+        coverage.extend(input_store.coverage().change_context(MergeTilesError::Read)?);
+        let zooms = input_store.covered_zooms();
+        if !zooms.is_empty() {
+            let (min_zoom, max_zoom) = (*zooms.start() as u32, *zooms.end() as u32);
+            covered_zooms = Some(covered_zooms.map_or(min_zoom..=max_zoom, |covered| {
+                min_zoom.min(*covered.start())..=max_zoom.max(*covered.end())
+            }));
+        }
+        let metadata = input_store.metadata().unwrap_or_default();
+        // dbs without metadata are always bincode tiles in the internal tiles grid
+        let tile_format = metadata
+            .get("format")
+            .and_then(|name| TileFormat::from_name(name))
+            .unwrap_or_default();
+        let grid = input_store.grid();
+        let tile_codec = metadata
+            .get("compression")
+            .and_then(|name| TileCodec::from_name(name))
+            .unwrap_or_default();
+        let config = config.get_or_insert_with(|| TileWriterConfig {
+            tile_format,
+            tile_codec,
+            grid,
+            ..TileWriterConfig::default()
+        });
+        if config.tile_format != tile_format {
+            return Err(Report::new(MergeTilesError::Mismatch("tile formats")));
+        }
+        if config.grid.tiles_count != grid.tiles_count {
+            return Err(Report::new(MergeTilesError::Mismatch("tile grids")));
+        }
+        if policy == CollisionPolicy::Concatenate && tile_format != TileFormat::Internal {
+            return Err(Report::new(MergeTilesError::Concatenate));
+        }
+        let is_dedup = metadata.get("geometry_store").map(String::as_str) == Some(GEOMETRY_STORE_DEDUP);
+        let recompress = tile_format == TileFormat::Internal && tile_codec != config.tile_codec;
+
+        let input_conn = Connection::open_with_flags(input, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .change_context(MergeTilesError::Read)?;
+        let mut select_stmt = input_conn
+            .prepare("SELECT x, y, z, data FROM tiles")
+            .change_context(MergeTilesError::Read)?;
+        let rows = select_stmt
+            .query_map((), |row| {
+                Ok((
+                    TileKey::new(row.get(0)?, row.get(1)?, row.get(2)?),
+                    row.get::<_, Vec<u8>>(3)?,
+                ))
+            })
+            .change_context(MergeTilesError::Read)?;
+        let mut existing_stmt = tx
+            .prepare("SELECT data FROM tiles WHERE x = ?1 AND y = ?2 AND z = ?3")
+            .change_context(MergeTilesError::Write)?;
+        let mut insert_stmt = tx
+            .prepare("INSERT OR REPLACE INTO tiles (x, y, z, data) VALUES (?1, ?2, ?3, ?4)")
+            .change_context(MergeTilesError::Write)?;
+        for row in rows {
+            let (key, mut data) = row.change_context(MergeTilesError::Read)?;
+            // tiles referencing deduplicated geometries are assembled by the store
+            if is_dedup {
+                data = input_store
+                    .get_tile(key.tile_x, key.tile_y, key.zoom_level)
+                    .change_context(MergeTilesError::Read)?;
+            }
+            if recompress {
+                data = config
+                    .tile_codec
+                    .compress(&decompress_tile(&data).change_context(MergeTilesError::Read)?)
+                    .change_context(MergeTilesError::Write)?;
+            }
+            let key = grid.flip_y(&key, input_store.scheme());
+            if policy == CollisionPolicy::Concatenate {
+                let existing: Option<Vec<u8>> = existing_stmt
+                    .query_row((key.tile_x, key.tile_y, key.zoom_level), |row| row.get(0))
+                    .optional()
+                    .change_context(MergeTilesError::Write)?;
+                if let Some(existing) = existing {
+                    data = concatenate(&existing, &data, config.tile_codec)?;
+                }
+            }
+            insert_stmt
+                .execute((key.tile_x, key.tile_y, key.zoom_level, &data))
+                .change_context(MergeTilesError::Write)?;
+        }
+    }
+
+    let keys = tx
+        .prepare("SELECT x, y, z FROM tiles")
+        .and_then(|mut stmt| {
+            stmt.query_map((), |row| Ok(TileKey::new(row.get(0)?, row.get(1)?, row.get(2)?)))?
+                .collect::<rusqlite::Result<Vec<_>>>()
+        })
+        .change_context(MergeTilesError::Write)?;
+    let config = config.unwrap_or_default();
+    TileWriter::write_metadata(
+        &tx,
+        keys.iter(),
+        &TileWriterConfig {
+            tile_scheme: TileScheme::default(),
+            ..config
+        },
+    );
+    TileWriter::write_coverage(&tx, &coverage, &covered_zooms.unwrap_or(RangeInclusive::new(1, 0)));
+    tx.commit().change_context(MergeTilesError::Write)
+}
+
+/// Features of both tiles in the order [TileWriter] writes them
+fn concatenate(
+    first: &[u8],
+    second: &[u8],
+    codec: TileCodec,
+) -> Result<Vec<u8>, Report<MergeTilesError>> {
+    let mut features = decode_tile(first).change_context(MergeTilesError::Read)?;
+    features
+        .0
+        .extend(decode_tile(second).change_context(MergeTilesError::Read)?.0);
+    features.0.sort_by(TileWriter::feature_cmp);
+    let data = bincode::serialize(&features).change_context(MergeTilesError::Write)?;
+    codec.compress(&data).change_context(MergeTilesError::Write)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::merge_tiles::{merge_tiles, CollisionPolicy};
+    use osm::map::{MapGeomObject, MapGeomObjectKind, MapGeometry, MapGeometryCollection};
+    use osm::source::tiles_sqlite_store::TilesSQLiteStore;
+    use osm::tile_writer::tile_writer::TileWriter;
+    use osm::tiles::codec::{gzip_stream, TileCodec};
+    use osm::tiles::decode_tile;
+    use rusqlite::Connection;
+    use std::fs;
+    use std::path::Path;
+
+    fn write_db(path: &Path, codec: TileCodec, tiles: &[((i32, i32, i32), i64)]) {
+        let _ = fs::remove_file(path);
+        let conn = Connection::open(path).unwrap();
+        TileWriter::create_tables(&conn);
+        conn.execute("INSERT INTO metadata (name, value) VALUES ('compression', ?1)", [codec.as_str()])
+            .unwrap();
+        for ((x, y, z), id) in tiles {
+            let collection = MapGeometryCollection::<f32>(vec![(
+                MapGeomObject {
+                    id: *id,
+                    kind: MapGeomObjectKind::Building(Default::default()),
+                },
+                MapGeometry::Coord(geo::coord! {x: 1.0, y: 2.0}),
+            )]);
+            let data = codec
+                .compress(&bincode::serialize(&collection).unwrap())
+                .unwrap();
+            conn.execute("INSERT INTO tiles (x, y, z, data) VALUES (?1, ?2, ?3, ?4)", (x, y, z, data))
+                .unwrap();
+        }
+    }
+
+    fn tile_ids(store: &TilesSQLiteStore, x: i32, y: i32, z: i32) -> Vec<i64> {
+        let collection = decode_tile(&store.get_tile(x, y, z).unwrap()).unwrap();
+        collection.0.iter().map(|(obj, _)| obj.id).collect()
+    }
+
+    #[test]
+    fn test_merge_tiles() {
+        let dir = std::env::temp_dir().join(format!("osm_tool_merge_tiles_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let city = dir.join("city.db");
+        let planet = dir.join("planet.db");
+        write_db(&city, TileCodec::Gzip, &[((3, 5, 0), 7), ((3, 6, 0), 2)]);
+        write_db(&planet, TileCodec::Zstd, &[((3, 5, 0), 3), ((0, 0, 10), 4)]);
+
+        let last_wins = dir.join("last_wins.db");
+        merge_tiles(&last_wins, &[&city, &planet], CollisionPolicy::LastWins).unwrap();
+        let store = TilesSQLiteStore::new(&last_wins);
+        assert_eq!(tile_ids(&store, 3, 5, 0), vec![3]);
+        assert_eq!(tile_ids(&store, 3, 6, 0), vec![2]);
+        assert_eq!(tile_ids(&store, 0, 0, 10), vec![4]);
+        let metadata = store.metadata().unwrap();
+        assert_eq!(metadata["minzoom"], "0");
+        assert_eq!(metadata["maxzoom"], "10");
+        // tiles of the zstd input are re-encoded with the codec of the first input
+        assert_eq!(metadata["compression"], "gzip");
+        assert!(gzip_stream(&store.get_tile(0, 0, 10).unwrap()).is_some());
+
+        let concatenated = dir.join("concatenated.db");
+        merge_tiles(&concatenated, &[&city, &planet], CollisionPolicy::Concatenate).unwrap();
+        let store = TilesSQLiteStore::new(&concatenated);
+        // features are sorted the same way the tile writer does it
+        assert_eq!(tile_ids(&store, 3, 5, 0), vec![3, 7]);
+        assert!(gzip_stream(&store.get_tile(3, 5, 0).unwrap()).is_some());
+        assert_eq!(tile_ids(&store, 3, 6, 0), vec![2]);
+        assert_eq!(store.count_tiles().unwrap(), 3);
+
+        drop(store);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
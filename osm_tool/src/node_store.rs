@@ -0,0 +1,187 @@
+use geo::Coord;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use rustc_hash::FxHashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+static SPILL_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Node coordinates by node id. Without a threshold all nodes are kept in memory,
+/// otherwise every time `spill_threshold` nodes are collected in memory they are moved
+/// to a temporary sqlite db, lookups consult both.
+pub struct NodeCoordStore {
+    memory: FxHashMap<i64, Coord>,
+    spill_threshold: Option<usize>,
+    spill: Option<NodeSpill>,
+}
+
+/// Nodes are written with `conn`, lookups take a connection of `readers` so concurrent
+/// lookups of different threads don't wait for each other, a new one is opened if all are busy
+struct NodeSpill {
+    path: PathBuf,
+    conn: Mutex<Connection>,
+    readers: Mutex<Vec<Connection>>,
+}
+
+impl NodeSpill {
+    fn new() -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "osm_tool_nodes_{}_{}.db",
+            std::process::id(),
+            SPILL_COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = std::fs::remove_file(&path);
+        let conn = Connection::open(&path).expect("Could not create node spill db");
+        conn.execute_batch(
+            "PRAGMA journal_mode = OFF;
+             PRAGMA synchronous = OFF;
+             CREATE TABLE nodes (id INTEGER PRIMARY KEY, x REAL NOT NULL, y REAL NOT NULL);",
+        )
+        .expect("Could not create node spill table");
+        NodeSpill {
+            path,
+            conn: Mutex::new(conn),
+            readers: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn insert_all(&mut self, nodes: impl Iterator<Item = (i64, Coord)>) {
+        let conn = self.conn.get_mut().expect("Expect lock");
+        let tx = conn.transaction().expect("Could not start node spill transaction");
+        {
+            let mut stmt = tx
+                .prepare_cached("INSERT OR REPLACE INTO nodes (id, x, y) VALUES (?1, ?2, ?3)")
+                .expect("Could not prepare node spill insert");
+            for (id, coord) in nodes {
+                stmt.execute(params![id, coord.x, coord.y])
+                    .expect("Could not spill node");
+            }
+        }
+        tx.commit().expect("Could not commit node spill");
+    }
+
+    fn get(&self, id: i64) -> Option<Coord> {
+        let reader = self.readers.lock().expect("Expect lock").pop();
+        let conn = reader.unwrap_or_else(|| {
+            Connection::open_with_flags(
+                &self.path,
+                OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            )
+            .expect("Could not open node spill db")
+        });
+        let coord = conn
+            .prepare_cached("SELECT x, y FROM nodes WHERE id = ?1")
+            .expect("Could not prepare node spill query")
+            .query_row(params![id], |row| Ok(Coord { x: row.get(0)?, y: row.get(1)? }))
+            .optional()
+            .expect("Could not read spilled node");
+        self.readers.lock().expect("Expect lock").push(conn);
+        coord
+    }
+}
+
+impl Drop for NodeSpill {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+impl NodeCoordStore {
+    pub fn new(spill_threshold: Option<usize>) -> Self {
+        NodeCoordStore {
+            memory: FxHashMap::default(),
+            spill_threshold,
+            spill: None,
+        }
+    }
+
+    pub fn insert(&mut self, id: i64, coord: Coord) {
+        self.memory.insert(id, coord);
+        if self
+            .spill_threshold
+            .is_some_and(|threshold| self.memory.len() >= threshold)
+        {
+            self.spill
+                .get_or_insert_with(NodeSpill::new)
+                .insert_all(self.memory.drain());
+        }
+    }
+
+    /// Moves nodes of `other` kept in memory into this store, spilled nodes of `other` aren't moved
+    pub fn append(&mut self, other: NodeCoordStore) {
+        for (id, coord) in other.memory {
+            self.insert(id, coord);
+        }
+    }
+
+    pub fn get(&self, id: i64) -> Option<Coord> {
+        self.memory
+            .get(&id)
+            .copied()
+            .or_else(|| self.spill.as_ref().and_then(|spill| spill.get(id)))
+    }
+
+    /// Nodes count including the spilled ones, a node inserted twice may be counted twice
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.memory.len()
+            + self.spill.as_ref().map_or(0, |spill| {
+                let conn = spill.conn.lock().expect("Expect lock");
+                conn.query_row("SELECT COUNT(*) FROM nodes", [], |row| row.get(0))
+                    .expect("Could not count spilled nodes")
+            })
+    }
+
+    #[cfg(test)]
+    fn is_spilled(&self) -> bool {
+        self.spill.is_some()
+    }
+}
+
+impl FromIterator<(i64, Coord)> for NodeCoordStore {
+    fn from_iter<T: IntoIterator<Item = (i64, Coord)>>(iter: T) -> Self {
+        NodeCoordStore {
+            memory: iter.into_iter().collect(),
+            spill_threshold: None,
+            spill: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::node_store::NodeCoordStore;
+    use geo::coord;
+
+    #[test]
+    fn test_spill() {
+        let mut memory = NodeCoordStore::new(None);
+        let mut spilled = NodeCoordStore::new(Some(3));
+        for id in 0..10 {
+            let coord = coord! {x: id as f64 * 0.5, y: -(id as f64)};
+            memory.insert(id, coord);
+            spilled.insert(id, coord);
+        }
+
+        assert!(!memory.is_spilled());
+        assert!(spilled.is_spilled());
+        assert_eq!(spilled.len(), 10);
+        for id in -1..11 {
+            assert_eq!(memory.get(id), spilled.get(id));
+        }
+        assert_eq!(spilled.get(7), Some(coord! {x: 3.5, y: -7.0}));
+
+        // concurrent lookups use their own connections
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for id in 0..10 {
+                        assert_eq!(memory.get(id), spilled.get(id));
+                    }
+                });
+            }
+        });
+        assert!(spilled.spill.as_ref().unwrap().readers.lock().unwrap().len() <= 4);
+    }
+}
@@ -1,53 +1,97 @@
 use crate::filter::TagFilter;
+use crate::node_store::NodeCoordStore;
 use crate::polygon_store::PolygonStore;
 use crate::reader::OsmBlobData;
+use crate::tag_mapping::{TagMapping, TagTarget};
 use crate::tile_processor::TileProcessor;
 use crate::way_store::{WayStore, WayStoreItem};
 use crate::{reader, POLYGON_MERGE_ZOOM_LEVEL};
-use geo::{Contains, Coord, HasDimensions, LineString, Polygon, Rect};
+use geo::{HasDimensions, Intersects, LineString, MultiLineString, MultiPolygon, Polygon, Rect, Relate, Scale};
 use itertools::Itertools;
 use osm::map::LineKind::Railway;
-use osm::map::NatureKind::Forest;
 use osm::map::{
-    HighwayKind, LayerKind, LineKind, MapGeomObject, MapGeomObjectKind, MapGeometry,
-    RailwayKind, WayInfo,
+    BuildingInfo, HighwayKind, LayerKind, LineKind, MapGeomObject, MapGeomObjectKind, MapGeometry,
+    RailwayKind, StationKind, WayInfo, COUNTRY_ADMIN_LEVEL,
 };
+use osm::tile_writer::sutherland_hodgman::{cohen_sutherland_clip, sutherland_hodgman_clip};
 use rustc_hash::FxHashMap;
 use std::fs::File;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
 use std::sync::mpsc::{channel, Sender};
 use std::sync::{mpsc, Arc};
 use std::time::Instant;
+use threadpool::ThreadPool;
+
+/// Feature with the zoom levels it's limited to
+type TileItem = (MapGeomObject, MapGeometry, RangeInclusive<u32>);
 
 pub struct PbfProcessor {
     way_store: WayStore,
     polygon_store: PolygonStore,
+    tag_mapping: Arc<TagMapping>,
+    node_spill_threshold: Option<usize>,
+    clip_to_bounds: bool,
 }
 
 impl PbfProcessor {
-    pub fn new() -> PbfProcessor {
+    pub fn new(tag_mapping: TagMapping) -> PbfProcessor {
         PbfProcessor {
             way_store: WayStore::new(),
             polygon_store: PolygonStore::new(),
+            tag_mapping: Arc::new(tag_mapping),
+            node_spill_threshold: None,
+            clip_to_bounds: false,
         }
     }
 
-    const POI_TAG: &'static [(&'static str, Option<&'static str>)] = &[
+    /// Node coordinates are moved to disk every time `threshold` nodes are collected in memory
+    pub fn with_node_spill_threshold(mut self, threshold: Option<usize>) -> Self {
+        self.node_spill_threshold = threshold;
+        self
+    }
+
+    /// Geometry is clipped to the area boundary instead of dropping nodes outside of it
+    pub fn with_clip_to_bounds(mut self, clip_to_bounds: bool) -> Self {
+        self.clip_to_bounds = clip_to_bounds;
+        self
+    }
+
+    /// Default tags, see [TagMapping::default]
+    pub const POI_TAG: &'static [(&'static str, Option<&'static str>)] = &[
         ("highway", Some("traffic_signals")),
         ("amenity", Some("toilets")),
         ("amenity", Some("parking")),
         ("railway", Some("station")),
     ];
-    const RELATION_TAG: &'static [(&'static str, Option<&'static str>)] = &[
+    /// Scale of the area boundary nodes are read from when geometry is clipped to the boundary,
+    /// so segments crossing the boundary keep their outer node. Segments reaching further
+    /// are still cut at their last node inside
+    const CLIP_READ_SCALE: f64 = 1.5;
+    /// Transport tags of stations, see [StationKind::from_tags]
+    const STATION_TAG: &'static [(&'static str, Option<&'static str>)] = &[
+        ("train", Some("yes")),
+        ("subway", Some("yes")),
+        ("light_rail", Some("yes")),
+        ("tram", Some("yes")),
+        ("station", None),
+    ];
+    pub const RELATION_TAG: &'static [(&'static str, Option<&'static str>)] = &[
         ("water", None),
         ("natural", Some("wood")),
         ("natural", Some("water")),
         ("natural", Some("bay")),
         ("landuse", Some("grass")),
         ("landuse", Some("forest")),
+        ("boundary", Some("administrative")),
     ];
 
-    const WAYS_TAG: &'static [(&'static str, Option<&'static str>)] = &[
+    pub const WAYS_TAG: &'static [(&'static str, Option<&'static str>)] = &[
         ("railway", Some("rail")),
+        ("railway", Some("subway")),
+        ("railway", Some("tram")),
+        ("railway", Some("light_rail")),
+        ("railway", Some("monorail")),
         ("highway", Some("motorway")),
         ("highway", Some("trunk")),
         ("highway", Some("primary")),
@@ -80,26 +124,38 @@ impl PbfProcessor {
         tile_processor: &mut TileProcessor,
         merge_polygons: bool,
         preserve_roads_topology: bool,
+        offsets_index_path: Option<PathBuf>,
     ) {
         let t_start = Instant::now();
         let mut blob_index = 0;
-        let mut reader = reader::OsmReader::new(osm_file, boundary);
-        let mut nodes: FxHashMap<i64, Coord> = FxHashMap::default();
+        let clip_bounds = self.clip_to_bounds.then_some(boundary);
+        let read_boundary = match clip_bounds {
+            Some(clip_bounds) => clip_bounds.scale(Self::CLIP_READ_SCALE),
+            None => boundary,
+        };
+        let mut reader = reader::OsmReader::new(osm_file, read_boundary);
+        if let Some(offsets_index_path) = offsets_index_path {
+            reader = reader.with_offsets_index(offsets_index_path);
+        }
+        let header = reader.header().expect("Failed to read OSM header");
+        if let Some(bbox) = header.and_then(|header| header.bbox) {
+            println!("OSM data bbox: {:?}", bbox);
+        }
+        let mut nodes = NodeCoordStore::new(self.node_spill_threshold);
         let mut ways: FxHashMap<i64, Vec<i64>> = FxHashMap::default();
 
         // TODO how to keep it inside Reader?
-        let used_ways_ids = reader.extract_ways_id_from_relations(Self::RELATION_TAG);
+        let used_ways_ids = reader
+            .extract_ways_id_from_relations(&TagMapping::filter_tags(&self.tag_mapping.relations));
         let (node_blobs, way_blobs, rels_blobs) = reader.data();
-        for data_blob in node_blobs {
-            blob_index += 1;
-            print!("\rProcessing blob: {}", blob_index);
-            Self::read_nodes(tile_processor, &data_blob, &mut nodes);
-        }
 
-        let tp = threadpool::ThreadPool::new(6);
+        let tp = ThreadPool::new(6);
+        let (tx, rx) = mpsc::channel();
+        blob_index += node_blobs.len();
+        print!("\rProcessing blob: {}", blob_index);
+        Self::read_nodes_async(&tp, tx.clone(), &self.tag_mapping, node_blobs, &mut nodes);
         let nodes = Arc::new(nodes);
 
-        let (tx, rx) = mpsc::channel();
         for data_blob in way_blobs {
             blob_index += 1;
             print!("\rProcessing blob: {}", blob_index);
@@ -109,9 +165,10 @@ impl PbfProcessor {
                 }
             }
             let nodes = Arc::clone(&nodes);
+            let tag_mapping = Arc::clone(&self.tag_mapping);
             let tx = tx.clone();
             tp.execute(move || {
-                Self::read_ways(tx, data_blob, &nodes);
+                Self::read_ways(tx, &tag_mapping, data_blob, &nodes);
             });
         }
         drop(tx);
@@ -119,7 +176,7 @@ impl PbfProcessor {
             if let Some(way_store_item) = way_store_item {
                 self.way_store.add_item(way_store_item);
             } else if let Some(tile_item) = tile_item {
-                self.handle_tile_item(tile_item, tile_processor);
+                self.handle_tile_item(tile_item, tile_processor, clip_bounds);
             }
         }
 
@@ -130,19 +187,25 @@ impl PbfProcessor {
             print!("\rProcessing blob: {}", blob_index);
             let nodes = Arc::clone(&nodes);
             let ways = Arc::clone(&ways);
+            let tag_mapping = Arc::clone(&self.tag_mapping);
             let tx = tx.clone();
             tp.execute(move || {
-                Self::read_relations(tx, &ways, data_blob, &nodes);
+                Self::read_relations(tx, &tag_mapping, &ways, data_blob, &nodes);
             });
         }
         drop(tx);
         for tile_item in rx {
-            self.handle_tile_item(tile_item, tile_processor);
+            self.handle_tile_item(tile_item, tile_processor, clip_bounds);
         }
 
         print!("\rBlobs processed: {:?}\n", t_start.elapsed());
 
-        self.process_ways_and_forest(tile_processor, merge_polygons, preserve_roads_topology);
+        self.process_ways_and_forest(
+            tile_processor,
+            merge_polygons,
+            preserve_roads_topology,
+            clip_bounds,
+        );
     }
 
     fn process_ways_and_forest(
@@ -150,53 +213,128 @@ impl PbfProcessor {
         tile_processor: &mut TileProcessor,
         merge_polygons: bool,
         preserve_roads_topology: bool,
+        clip_bounds: Option<Rect>,
     ) {
         let process_start_t = Instant::now();
         let (tx, rx) = channel::<(u32, MapGeomObject, MapGeometry)>();
-        self.polygon_store.process_forests_async(
+        let simplification = tile_processor.simplification;
+        self.polygon_store.process_polygons_async(
             tx.clone(),
             merge_polygons,
             POLYGON_MERGE_ZOOM_LEVEL,
+            tile_processor.grid.max_zoom,
+            |kind| simplification.merged_lod(kind),
+        );
+        self.way_store.process_ways_async(
+            tx,
+            preserve_roads_topology,
+            tile_processor.simplification,
+            tile_processor.grid.max_zoom,
         );
-        self.way_store
-            .process_ways_async(tx, preserve_roads_topology);
         for tile_data in rx {
             let (zoom, geom_obj, geom) = tile_data;
-            tile_processor
-                .tile_writer
-                .add_to_tiles(zoom, geom_obj, geom, true);
+            for geom in Self::clip(geom, clip_bounds) {
+                tile_processor
+                    .tile_writer
+                    .add_to_tiles(zoom, geom_obj.clone(), geom, true);
+            }
         }
+        tile_processor.add_merged_buildings();
 
         println!("Process finished: {:?}", process_start_t.elapsed());
     }
 
     fn handle_tile_item(
         &mut self,
-        tile_item: (MapGeomObject, MapGeometry),
+        tile_item: TileItem,
         tile_processor: &mut TileProcessor,
+        clip_bounds: Option<Rect>,
     ) {
-        let (map_geom_obj, geom_obj) = tile_item;
-        if map_geom_obj.kind == MapGeomObjectKind::Nature(Forest) {
-            match &geom_obj {
-                MapGeometry::Poly(ref poly) => {
-                    self.polygon_store
-                        .add_polygon(Polygon::new(poly.exterior().clone(), vec![]));
+        let (map_geom_obj, geom_obj, zooms) = tile_item;
+        for geom_obj in Self::clip(geom_obj, clip_bounds) {
+            if let MapGeomObjectKind::Nature(kind) = map_geom_obj.kind {
+                if tile_processor.simplification.merged_lod(kind).is_some() {
+                    match geom_obj {
+                        MapGeometry::Poly(ref poly) => self.polygon_store.add_polygon(kind, poly.clone()),
+                        MapGeometry::MultiPoly(ref multi_poly) => {
+                            for poly in multi_poly {
+                                self.polygon_store.add_polygon(kind, poly.clone());
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            tile_processor.add_to_tiles_in_zooms(map_geom_obj.clone(), geom_obj, zooms.clone());
+        }
+    }
+
+    /// Parts of the geometry inside the bounds, the geometry is kept as is without bounds
+    fn clip(geometry: MapGeometry, bounds: Option<Rect>) -> Vec<MapGeometry> {
+        let Some(bounds) = bounds else {
+            return vec![geometry];
+        };
+        let clip_poly = |poly: &Polygon| {
+            sutherland_hodgman_clip(poly.exterior(), &bounds).map(|exterior| {
+                let interiors = poly
+                    .interiors()
+                    .iter()
+                    .filter_map(|interior| sutherland_hodgman_clip(interior, &bounds))
+                    .collect();
+                Polygon::new(exterior, interiors)
+            })
+        };
+        match geometry {
+            MapGeometry::Line(line) => cohen_sutherland_clip(&line, &bounds)
+                .into_iter()
+                .map(MapGeometry::Line)
+                .collect(),
+            MapGeometry::Poly(poly) => clip_poly(&poly).map(MapGeometry::Poly).into_iter().collect(),
+            MapGeometry::MultiPoly(multi_poly) => {
+                let clipped = multi_poly.iter().filter_map(clip_poly).collect::<Vec<_>>();
+                if clipped.is_empty() {
+                    vec![]
+                } else {
+                    vec![MapGeometry::MultiPoly(MultiPolygon::new(clipped))]
+                }
+            }
+            MapGeometry::MultiLine(multi_line) => {
+                let clipped = multi_line
+                    .iter()
+                    .flat_map(|line| cohen_sutherland_clip(line, &bounds))
+                    .collect::<Vec<_>>();
+                if clipped.is_empty() {
+                    vec![]
+                } else {
+                    vec![MapGeometry::MultiLine(MultiLineString::new(clipped))]
+                }
+            }
+            MapGeometry::Coord(coord) => {
+                if bounds.intersects(&coord) {
+                    vec![MapGeometry::Coord(coord)]
+                } else {
+                    vec![]
                 }
-                _ => {}
             }
         }
-        tile_processor.add_to_tiles(map_geom_obj, geom_obj);
     }
 
     fn read_relations(
-        sender: Sender<(MapGeomObject, MapGeometry)>,
+        sender: Sender<TileItem>,
+        tag_mapping: &TagMapping,
         ways: &Arc<FxHashMap<i64, Vec<i64>>>,
         data_blob: OsmBlobData,
-        nodes: &Arc<FxHashMap<i64, Coord>>,
+        nodes: &Arc<NodeCoordStore>,
     ) {
-        let tag_filter = TagFilter::new(&data_blob.string_table, Self::RELATION_TAG);
+        let tag_filter = TagFilter::new(
+            &data_blob.string_table,
+            &TagMapping::filter_tags(&tag_mapping.relations),
+        );
+        let admin_level_tag_filter =
+            TagFilter::new(&data_blob.string_table, &[("admin_level", None)]);
         for relation in &data_blob.relations {
             if let Some((k, v)) = tag_filter.filter(&data_blob.string_table, &relation.tags) {
+                let entry = TagMapping::find(&tag_mapping.relations, k, v).expect("Filtered tag is mapped");
                 let mut all_outer_ways: Vec<Vec<i64>> = Vec::new();
                 let mut all_inner_ways: Vec<Vec<i64>> = Vec::new();
 
@@ -218,92 +356,189 @@ impl PbfProcessor {
                     continue;
                 }
 
-                let mut hm = FxHashMap::default();
-                for mut way in all_outer_ways {
-                    let mut key1 = way.first().cloned().unwrap();
-                    let mut key2 = way.last().cloned().unwrap();
-                    while hm.contains_key(&key1) || hm.contains_key(&key2) {
-                        if let Some(w) = hm.remove(&key1) {
-                            let mut nw = Vec::new();
-                            nw.extend(w);
-                            nw.extend(way);
-                            way = nw;
-                            key1 = *way.first().unwrap();
-                            key2 = *way.last().unwrap();
-                        } else if let Some(w) = hm.remove(&key2) {
-                            let mut nw = Vec::new();
-                            nw.extend(w);
-                            nw.extend(way.into_iter().rev());
-                            way = nw;
-                            key1 = *way.first().unwrap();
-                            key2 = *way.last().unwrap();
-                        }
-                    }
-
-                    hm.insert(key2, way);
-                }
-                let mut polygons: Vec<Polygon> = hm
-                    .values()
-                    .map(|way| {
-                        let coords = way
-                            .iter()
-                            .map(|id| nodes.get(id).unwrap().clone())
-                            .collect_vec();
-
-                        Polygon::new(LineString(coords), Vec::new()).into()
-                    })
-                    .collect_vec();
-
-                for line in all_inner_ways {
-                    if line.len() > 0 {
-                        if let Some(fc) = nodes.get(line.first().unwrap()) {
-                            for poly in &mut polygons {
-                                if poly.contains(fc) {
-                                    let coords = line
-                                        .iter()
-                                        .map(|id| nodes.get(id).unwrap().clone())
-                                        .collect_vec();
-                                    let ls = LineString(coords);
-                                    poly.interiors_push(ls);
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                }
-
-                for polygon in polygons {
+                if k == "boundary" && entry.target.is_none() {
+                    let level = admin_level_tag_filter
+                        .filter(&data_blob.string_table, &relation.tags)
+                        .and_then(|(_, level)| level.parse::<u8>().ok())
+                        .unwrap_or(COUNTRY_ADMIN_LEVEL);
+                    all_outer_ways.extend(all_inner_ways);
+                    let Some(geometry) = Self::relation_lines(all_outer_ways, nodes) else {
+                        continue;
+                    };
                     let map_geom_obj = MapGeomObject {
                         id: relation.id,
-                        kind: MapGeomObjectKind::from_tag(k, v, None, None, None, false),
+                        kind: MapGeomObjectKind::AdminLine(level),
                     };
                     sender
-                        .send((map_geom_obj, MapGeometry::Poly(polygon)))
+                        .send((map_geom_obj, geometry, entry.zoom_range()))
                         .unwrap();
+                    continue;
                 }
+
+                let polygons = Self::relation_polygons(all_outer_ways, all_inner_ways, nodes);
+                if polygons.is_empty() {
+                    continue;
+                }
+
+                let kind = match entry.target {
+                    Some(target) => target.kind(None, None, StationKind::Other),
+                    None => MapGeomObjectKind::from_tag(k, v, None, None, None, StationKind::Other),
+                };
+                let map_geom_obj = MapGeomObject {
+                    id: relation.id,
+                    kind,
+                };
+                sender
+                    .send((map_geom_obj, Self::relation_geometry(polygons), entry.zoom_range()))
+                    .unwrap();
             }
         }
     }
     
-    fn read_ways(
-        sender: Sender<(Option<WayStoreItem>, Option<(MapGeomObject, MapGeometry)>)>,
+    /// A single polygon of a relation stays a polygon, several are kept together as one multipolygon
+    fn relation_geometry(mut polygons: Vec<Polygon>) -> MapGeometry {
+        if polygons.len() == 1 {
+            MapGeometry::Poly(polygons.remove(0))
+        } else {
+            MapGeometry::MultiPoly(MultiPolygon::new(polygons))
+        }
+    }
+
+    /// Joins ways sharing endpoints into rings, see [PbfProcessor::join_ways].
+    /// Chains left unclosed are dropped.
+    fn assemble_rings(ways: Vec<Vec<i64>>) -> Vec<Vec<i64>> {
+        Self::join_ways(ways)
+            .into_iter()
+            .filter(|chain| chain[0] == chain[chain.len() - 1])
+            .collect()
+    }
+
+    /// Joins ways sharing endpoints into chains, a chain is closed as soon as its endpoints meet
+    /// and the next way starts a new one. Closed rings come first, then unclosed chains.
+    fn join_ways(ways: Vec<Vec<i64>>) -> Vec<Vec<i64>> {
+        let mut rings = Vec::new();
+        let mut open_chains: Vec<Vec<i64>> = Vec::new();
+        for mut chain in ways.into_iter().filter(|way| way.len() > 1) {
+            loop {
+                let (first, last) = (chain[0], chain[chain.len() - 1]);
+                if first == last {
+                    rings.push(chain);
+                    break;
+                }
+                let Some(index) = open_chains.iter().position(|other| {
+                    let (other_first, other_last) = (other[0], other[other.len() - 1]);
+                    other_first == first
+                        || other_first == last
+                        || other_last == first
+                        || other_last == last
+                }) else {
+                    open_chains.push(chain);
+                    break;
+                };
+                let mut other = open_chains.swap_remove(index);
+                if other[other.len() - 1] == last {
+                    chain.reverse();
+                } else if other[0] == last {
+                    std::mem::swap(&mut chain, &mut other);
+                } else if other[0] == first {
+                    other.reverse();
+                }
+                // other ends where chain starts
+                other.extend_from_slice(&chain[1..]);
+                chain = other;
+            }
+        }
+        rings.extend(open_chains);
+        rings
+    }
+
+    /// Boundary of an admin relation as joined member chains, closed or not. Unlike polygon rings
+    /// the chains are split at nodes outside the extract, so no segment crosses the missing part.
+    fn relation_lines(ways: Vec<Vec<i64>>, nodes: &NodeCoordStore) -> Option<MapGeometry> {
+        let mut lines = Self::join_ways(ways)
+            .iter()
+            .flat_map(|chain| {
+                chain
+                    .split(|id| nodes.get(*id).is_none())
+                    .filter(|part| part.len() > 1)
+                    .map(|part| LineString(part.iter().filter_map(|id| nodes.get(*id)).collect()))
+                    .collect_vec()
+            })
+            .collect_vec();
+        match lines.len() {
+            0 => None,
+            1 => Some(MapGeometry::Line(lines.remove(0))),
+            _ => Some(MapGeometry::MultiLine(MultiLineString::new(lines))),
+        }
+    }
+
+    /// Nodes outside the extract boundary are missing in `nodes`, they are skipped
+    /// the same way [crate::reader::OsmWay::as_line] does it and the rest of the ring is kept.
+    /// Rings left with less than 3 distinct points are dropped.
+    fn ring_line(ring: &[i64], nodes: &NodeCoordStore) -> Option<LineString> {
+        let mut line = LineString(ring.iter().filter_map(|id| nodes.get(*id)).collect());
+        line.close();
+        (line.0.len() >= 4).then_some(line)
+    }
+
+    /// Builds a polygon per outer ring, every inner ring is assigned to the first outer ring covering it
+    fn relation_polygons(
+        outer_ways: Vec<Vec<i64>>,
+        inner_ways: Vec<Vec<i64>>,
+        nodes: &NodeCoordStore,
+    ) -> Vec<Polygon> {
+        let mut polygons = Self::assemble_rings(outer_ways)
+            .iter()
+            .filter_map(|ring| Self::ring_line(ring, nodes))
+            .map(|exterior| Polygon::new(exterior, vec![]))
+            .collect_vec();
+        let exteriors = polygons
+            .iter()
+            .map(|polygon| Polygon::new(polygon.exterior().clone(), vec![]))
+            .collect_vec();
+
+        for inner in Self::assemble_rings(inner_ways)
+            .iter()
+            .filter_map(|ring| Self::ring_line(ring, nodes))
+        {
+            if let Some(index) = exteriors
+                .iter()
+                .position(|exterior| inner.relate(exterior).is_coveredby())
+            {
+                polygons[index].interiors_push(inner);
+            }
+        }
+        polygons
+    }
+
+    pub fn read_ways(
+        sender: Sender<(Option<WayStoreItem>, Option<TileItem>)>,
+        tag_mapping: &TagMapping,
         data_blob: OsmBlobData,
-        nodes: &Arc<FxHashMap<i64, Coord>>,
+        nodes: &Arc<NodeCoordStore>,
     ) {
-        let tag_filter = TagFilter::new(&data_blob.string_table, Self::WAYS_TAG);
+        let tag_filter = TagFilter::new(
+            &data_blob.string_table,
+            &TagMapping::filter_tags(&tag_mapping.ways),
+        );
         let road_tag_filter = TagFilter::new(
             &data_blob.string_table,
             &[("layer", None), ("tunnel", Some("yes")), ("bridge", None), ("name:en", None), ("name", None)],
         );
         let building_tag_filter = TagFilter::new(
             &data_blob.string_table,
-            &[("building:levels", None)],
+            &[("building:levels", None), ("height", None)],
+        );
+        let address_tag_filter = TagFilter::new(
+            &data_blob.string_table,
+            &[("addr:housenumber", None), ("addr:street", None)],
         );
 
         for way in &data_blob.ways {
             if let Some((k, v)) = tag_filter.filter(&data_blob.string_table, &way.tags) {
-                match k {
-                    "railway" | "highway" => {
+                let entry = TagMapping::find(&tag_mapping.ways, k, v).expect("Filtered tag is mapped");
+                match (k, entry.target) {
+                    ("railway" | "highway", None) => {
                         let path_data = way.as_line(&nodes);
 
                         let path = path_data.0;
@@ -352,9 +587,9 @@ impl PbfProcessor {
                         //     layer_kind = LayerKind::None
                         // }
 
-                        let line_kind = if v == "rail" {
+                        let line_kind = if k == "railway" {
                             Railway {
-                                kind: RailwayKind::Rail,
+                                kind: RailwayKind::from_descr(v).unwrap(),
                             }
                         } else {
                             LineKind::Highway {
@@ -382,15 +617,17 @@ impl PbfProcessor {
                             ))
                             .unwrap();
                     }
-                    _ => {
+                    (_, target) => {
                         let polygon = way.as_polygon(&nodes);
 
                         if polygon.is_empty() {
                             continue;
                         }
 
-                        let levels = if k == "building" {
+                        let is_building = target.map_or(k == "building", |target| target == TagTarget::Building);
+                        let building = if is_building {
                             let mut levels = 0;
+                            let mut height = None;
                             for (k, v) in
                                 building_tag_filter.filter_all(&data_blob.string_table, &way.tags)
                             {
@@ -398,21 +635,39 @@ impl PbfProcessor {
                                     "building:levels" => {
                                         levels = v.parse::<u16>().unwrap_or(0);
                                     }
+                                    "height" => {
+                                        height = BuildingInfo::parse_height(v);
+                                    }
+                                    _ => {}
+                                }
+                            }
+                            let mut housenumber = None;
+                            let mut street = None;
+                            for (k, v) in
+                                address_tag_filter.filter_all(&data_blob.string_table, &way.tags)
+                            {
+                                match k {
+                                    "addr:housenumber" => housenumber = Some(v.to_string()),
+                                    "addr:street" => street = Some(v.to_string()),
                                     _ => {}
                                 }
                             }
-                            Some(levels)
+                            Some(BuildingInfo::new(levels, height, housenumber, street))
                         } else {
                             None
                         };
 
-                        let map_geom_obj = MapGeomObject {
-                            id: way.id,
-                            kind: MapGeomObjectKind::from_tag(k, v, None, None, levels, false),
+                        let kind = match target {
+                            Some(target) => target.kind(None, building, StationKind::Other),
+                            None => MapGeomObjectKind::from_tag(k, v, None, None, building, StationKind::Other),
                         };
+                        let map_geom_obj = MapGeomObject { id: way.id, kind };
 
                         sender
-                            .send((None, Some((map_geom_obj, MapGeometry::Poly(polygon)))))
+                            .send((
+                                None,
+                                Some((map_geom_obj, MapGeometry::Poly(polygon), entry.zoom_range())),
+                            ))
                             .unwrap();
                     }
                 }
@@ -420,22 +675,53 @@ impl PbfProcessor {
         }
     }
 
-    fn read_nodes(
-        tile_processor: &mut TileProcessor,
-        data_blob: &OsmBlobData,
-        nodes: &mut FxHashMap<i64, Coord>,
+    /// Reads node blobs on the thread pool, nodes of every blob are merged into `nodes`
+    /// as soon as the blob is read and POIs are sent to `sender`
+    fn read_nodes_async(
+        tp: &ThreadPool,
+        sender: Sender<(Option<WayStoreItem>, Option<TileItem>)>,
+        tag_mapping: &Arc<TagMapping>,
+        node_blobs: Vec<OsmBlobData>,
+        nodes: &mut NodeCoordStore,
     ) {
-        let tag_filter = TagFilter::new(&data_blob.string_table, Self::POI_TAG);
+        let (nodes_tx, nodes_rx) = mpsc::channel();
+        for data_blob in node_blobs {
+            let tag_mapping = Arc::clone(tag_mapping);
+            let sender = sender.clone();
+            let nodes_tx = nodes_tx.clone();
+            tp.execute(move || {
+                let mut blob_nodes = NodeCoordStore::new(None);
+                for poi in Self::read_nodes(&tag_mapping, &data_blob, &mut blob_nodes) {
+                    sender.send((None, Some(poi))).unwrap();
+                }
+                nodes_tx.send(blob_nodes).unwrap();
+            });
+        }
+        drop(nodes_tx);
+        for blob_nodes in nodes_rx {
+            nodes.append(blob_nodes);
+        }
+    }
+
+    pub fn read_nodes(
+        tag_mapping: &TagMapping,
+        data_blob: &OsmBlobData,
+        nodes: &mut NodeCoordStore,
+    ) -> Vec<TileItem> {
+        let mut pois = Vec::new();
+        let tag_filter = TagFilter::new(
+            &data_blob.string_table,
+            &TagMapping::filter_tags(&tag_mapping.nodes),
+        );
         let name_en_tag_filter = TagFilter::new(
             &data_blob.string_table, &[("name:en", None), ("name", None)],
         );
-        let train_tag_filter = TagFilter::new(
-            &data_blob.string_table, &[("train", Some("yes"))],
-        );
+        let station_tag_filter = TagFilter::new(&data_blob.string_table, Self::STATION_TAG);
         for node in &data_blob.nodes {
             nodes.insert(node.id, node.coord);
 
             if let Some((k, v)) = tag_filter.filter(&data_blob.string_table, &node.tags) {
+                let entry = TagMapping::find(&tag_mapping.nodes, k, v).expect("Filtered tag is mapped");
                 let mut name_en: Option<String> = None;
                 let mut name: Option<String> = None;
                 for (k, v) in
@@ -452,14 +738,446 @@ impl PbfProcessor {
                     }
                 }
 
-                let is_train = train_tag_filter.filter(&data_blob.string_table, &node.tags).is_some();
-                let map_geom_obj = MapGeomObject {
-                    id: node.id,
-                    kind: MapGeomObjectKind::from_tag(k, v, None, name_en.or(name), None, is_train),
+                let station = StationKind::from_tags(
+                    station_tag_filter.filter_all(&data_blob.string_table, &node.tags),
+                );
+                let kind = match entry.target {
+                    Some(target) => target.kind(name_en.or(name), None, station),
+                    None => MapGeomObjectKind::from_tag(k, v, None, name_en.or(name), None, station),
                 };
+                let map_geom_obj = MapGeomObject { id: node.id, kind };
 
-                tile_processor.add_to_tiles(map_geom_obj, MapGeometry::Coord(node.coord));
+                pois.push((map_geom_obj, MapGeometry::Coord(node.coord), entry.zoom_range()));
             }
         }
+        pois
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::node_store::NodeCoordStore;
+    use crate::pbf_processor::PbfProcessor;
+    use crate::proto::{DenseNodes, PrimitiveBlock, PrimitiveGroup, StringTable, Way};
+    use crate::reader::{test_pbf, OsmBlobData, OsmNode, OsmWay};
+    use crate::simplification::SimplificationConfig;
+    use crate::tag_mapping::TagMapping;
+    use crate::tile_processor::TileProcessor;
+    use geo::{coord, line_string, Area, Rect};
+    use osm::map::{
+        BuildingInfo, MapGeomObjectKind, MapGeometry, MapPointInfo, MapPointObjectKind, StationKind,
+    };
+    use osm::source::tiles_sqlite_store::TilesSQLiteStore;
+    use osm::tile_writer::tile_writer::TileWriterConfig;
+    use osm::tiles::{lat_lon_to_world, world_to_lat_lon, TileKey, TileStore};
+    use std::fs::File;
+    use std::sync::{mpsc, Arc};
+    use threadpool::ThreadPool;
+
+    #[test]
+    fn test_relation_polygons() {
+        let nodes: NodeCoordStore = [
+            // first part
+            (1, (0.0, 0.0)),
+            (2, (4.0, 0.0)),
+            (3, (4.0, 4.0)),
+            (4, (0.0, 4.0)),
+            // second part
+            (5, (10.0, 0.0)),
+            (6, (14.0, 0.0)),
+            (7, (14.0, 4.0)),
+            (8, (10.0, 4.0)),
+            // hole in the second part
+            (9, (11.0, 1.0)),
+            (10, (13.0, 1.0)),
+            (11, (12.0, 3.0)),
+        ]
+        .into_iter()
+        .map(|(id, (x, y))| (id, coord! {x: x, y: y}))
+        .collect();
+
+        let outer_ways = vec![
+            vec![1, 2, 3],
+            vec![5, 6, 7],
+            // reversed way of the first part
+            vec![1, 4, 3],
+            vec![7, 8, 5],
+            // unclosed way with a missing node
+            vec![20, 21, 22],
+        ];
+        let inner_ways = vec![vec![9, 10, 11], vec![11, 9]];
+        let polygons = PbfProcessor::relation_polygons(outer_ways, inner_ways, &nodes);
+
+        assert_eq!(polygons.len(), 2);
+        let areas = polygons.iter().map(|polygon| polygon.unsigned_area()).collect::<Vec<_>>();
+        assert!(polygons.iter().all(|polygon| polygon.exterior().is_closed()));
+        assert_eq!(polygons.iter().map(|polygon| polygon.interiors().len()).sum::<usize>(), 1);
+        assert!(areas.contains(&16.0));
+        assert!(areas.contains(&14.0));
+
+        // nodes outside the extract are skipped, the rest of the ring is kept
+        let polygons =
+            PbfProcessor::relation_polygons(vec![vec![1, 2, 30, 3, 4, 1]], vec![vec![40, 41, 40]], &nodes);
+        assert_eq!(polygons.len(), 1);
+        assert_eq!(polygons[0].exterior().0.len(), 5);
+        assert!(polygons[0].interiors().is_empty());
+
+        // missing first node, the ring is closed again
+        let polygons = PbfProcessor::relation_polygons(vec![vec![30, 2, 3, 4, 30]], vec![], &nodes);
+        assert_eq!(polygons[0].exterior().0.len(), 4);
+        assert!(polygons[0].exterior().is_closed());
+
+        let polygons = PbfProcessor::relation_polygons(vec![vec![1, 2, 30, 1]], vec![], &nodes);
+        assert!(polygons.is_empty());
+    }
+
+    #[test]
+    fn test_relation_lines() {
+        let nodes: NodeCoordStore = [
+            (1, (0.0, 0.0)),
+            (2, (4.0, 0.0)),
+            (3, (4.0, 4.0)),
+            (4, (0.0, 4.0)),
+            (5, (10.0, 0.0)),
+            (6, (14.0, 0.0)),
+        ]
+        .into_iter()
+        .map(|(id, (x, y))| (id, coord! {x: x, y: y}))
+        .collect();
+
+        // an unclosed boundary is kept as a line
+        let geometry = PbfProcessor::relation_lines(vec![vec![1, 2], vec![3, 2]], &nodes).unwrap();
+        assert_eq!(
+            geometry,
+            MapGeometry::Line(line_string![(x: 0.0, y: 0.0), (x: 4.0, y: 0.0), (x: 4.0, y: 4.0)])
+        );
+
+        // a ring crossing the extract boundary is split instead of closed with a chord
+        let geometry =
+            PbfProcessor::relation_lines(vec![vec![1, 2, 30, 3, 4, 1], vec![5, 6]], &nodes).unwrap();
+        let MapGeometry::MultiLine(multi_line) = geometry else {
+            panic!("Expected a multiline, got {geometry:?}");
+        };
+        assert_eq!(multi_line.0.len(), 3);
+        assert!(multi_line.0.contains(&line_string![(x: 0.0, y: 0.0), (x: 4.0, y: 0.0)]));
+        assert!(multi_line
+            .0
+            .contains(&line_string![(x: 4.0, y: 4.0), (x: 0.0, y: 4.0), (x: 0.0, y: 0.0)]));
+
+        assert!(PbfProcessor::relation_lines(vec![vec![30, 31]], &nodes).is_none());
+    }
+
+    #[test]
+    fn test_relation_multipolygon() {
+        let nodes: NodeCoordStore = [
+            (1, (0.0, 0.0)),
+            (2, (4.0, 0.0)),
+            (3, (4.0, 4.0)),
+            (4, (10.0, 0.0)),
+            (5, (14.0, 0.0)),
+            (6, (14.0, 4.0)),
+        ]
+        .into_iter()
+        .map(|(id, (x, y))| (id, coord! {x: x, y: y}))
+        .collect();
+
+        let polygons =
+            PbfProcessor::relation_polygons(vec![vec![1, 2, 3, 1], vec![4, 5, 6, 4]], vec![], &nodes);
+        let geometry = PbfProcessor::relation_geometry(polygons);
+        let deserialized: MapGeometry = bincode::deserialize(&bincode::serialize(&geometry).unwrap()).unwrap();
+        let MapGeometry::MultiPoly(multi_poly) = &deserialized else {
+            panic!("Expected a multipolygon, got {deserialized:?}");
+        };
+        assert_eq!(multi_poly.0.len(), 2);
+        assert_eq!(deserialized, geometry);
+
+        let polygons = PbfProcessor::relation_polygons(vec![vec![1, 2, 3, 1]], vec![], &nodes);
+        assert!(matches!(PbfProcessor::relation_geometry(polygons), MapGeometry::Poly(_)));
+    }
+
+    #[test]
+    fn test_building_info() {
+        let string_table = ["", "building", "yes", "height", "30", "addr:housenumber", "10", "building:levels", "4"]
+            .map(str::to_string)
+            .to_vec();
+        let nodes: NodeCoordStore = [(1, (0.0, 0.0)), (2, (1.0, 0.0)), (3, (1.0, 1.0))]
+            .into_iter()
+            .map(|(id, (x, y))| (id, coord! {x: x, y: y}))
+            .collect();
+        let way = |id, tags: &[(u32, u32)]| OsmWay {
+            id,
+            tags: tags.iter().copied().collect(),
+            refs: vec![1, 2, 3, 1],
+        };
+        let data_blob = OsmBlobData {
+            string_table,
+            ways: vec![way(1, &[(1, 2), (3, 4), (5, 6)]), way(2, &[(1, 2), (7, 8)])],
+            nodes: vec![],
+            relations: vec![],
+        };
+
+        let (tx, rx) = mpsc::channel();
+        PbfProcessor::read_ways(tx, &TagMapping::default(), data_blob, &Arc::new(nodes));
+        let buildings = rx
+            .into_iter()
+            .filter_map(|(_, tile_item)| match tile_item?.0.kind {
+                MapGeomObjectKind::Building(info) => Some(info),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(buildings.len(), 2);
+        assert_eq!(buildings[0].height, 30.0);
+        assert_eq!(buildings[0].housenumber.as_deref(), Some("10"));
+        assert_eq!(buildings[0].street, None);
+        // height falls back to levels
+        assert_eq!(buildings[1].levels, 4);
+        assert_eq!(buildings[1].height, 12.0);
+
+        assert_eq!(BuildingInfo::parse_height("12 m"), Some(12.0));
+        assert_eq!(BuildingInfo::parse_height("12.5m"), Some(12.5));
+        assert_eq!(BuildingInfo::parse_height("tall"), None);
+    }
+
+    #[test]
+    fn test_tag_mapping() {
+        let string_table = ["", "amenity", "cafe", "name", "Cafe", "toilets"]
+            .map(str::to_string)
+            .to_vec();
+        let node = |id, tags: &[(u32, u32)]| OsmNode {
+            id,
+            coord: coord! {x: 139.7665, y: 35.6805},
+            tags: tags.iter().copied().collect(),
+        };
+        let data_blob = OsmBlobData {
+            string_table,
+            ways: vec![],
+            nodes: vec![node(1, &[(1, 2), (3, 4)]), node(2, &[(1, 5)])],
+            relations: vec![],
+        };
+
+        let mut nodes = NodeCoordStore::new(None);
+        let pois = PbfProcessor::read_nodes(&TagMapping::default(), &data_blob, &mut nodes);
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(pois.iter().map(|poi| poi.0.id).collect::<Vec<_>>(), vec![2]);
+
+        let tag_mapping: TagMapping = serde_json::from_str(
+            r#"{"nodes": [
+                {"key": "amenity", "value": "cafe", "target": "poi", "min_zoom": 0, "max_zoom": 2},
+                {"key": "amenity", "value": "toilets"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(tag_mapping.invalid_entry(), None);
+        let pois = PbfProcessor::read_nodes(&tag_mapping, &data_blob, &mut nodes);
+        assert_eq!(pois.len(), 2);
+        let (cafe, _, zooms) = &pois[0];
+        assert_eq!(cafe.id, 1);
+        assert_eq!(
+            cafe.kind,
+            MapGeomObjectKind::Poi(MapPointInfo {
+                text: "Cafe".to_string(),
+                kind: MapPointObjectKind::Generic,
+            })
+        );
+        assert_eq!(*zooms, 0..=2);
+
+        // cafe isn't known to the built-in classification
+        let tag_mapping: TagMapping =
+            serde_json::from_str(r#"{"nodes": [{"key": "amenity", "value": "cafe"}]}"#).unwrap();
+        assert_eq!(tag_mapping.invalid_entry(), Some("amenity"));
+
+        let tag_mapping: TagMapping = serde_json::from_str(
+            r#"{"nodes": [{"key": "amenity", "value": "cafe", "target": "poi", "min_zoom": 3, "max_zoom": 2}]}"#,
+        )
+        .unwrap();
+        assert_eq!(tag_mapping.invalid_entry(), Some("amenity"));
+
+        // roads are added to all zoom levels
+        let tag_mapping: TagMapping =
+            serde_json::from_str(r#"{"ways": [{"key": "highway", "value": "primary", "max_zoom": 2}]}"#).unwrap();
+        assert_eq!(tag_mapping.invalid_entry(), Some("highway"));
+    }
+
+    #[test]
+    fn test_station_kind() {
+        let string_table = ["", "railway", "station", "subway", "yes", "train", "tram", "light_rail"]
+            .map(str::to_string)
+            .to_vec();
+        let node = |id, tags: &[(u32, u32)]| OsmNode {
+            id,
+            coord: coord! {x: 139.7665, y: 35.6805},
+            tags: tags.iter().copied().collect(),
+        };
+        let data_blob = OsmBlobData {
+            string_table,
+            ways: vec![],
+            nodes: vec![
+                node(1, &[(1, 2), (3, 4)]),
+                node(2, &[(1, 2), (3, 4), (5, 4)]),
+                node(3, &[(1, 2), (2, 7)]),
+                node(4, &[(1, 2), (6, 4)]),
+                node(5, &[(1, 2)]),
+            ],
+            relations: vec![],
+        };
+
+        let mut nodes = NodeCoordStore::new(None);
+        let stations = PbfProcessor::read_nodes(&TagMapping::default(), &data_blob, &mut nodes)
+            .into_iter()
+            .map(|(map_geom_obj, _, _)| match map_geom_obj.kind {
+                MapGeomObjectKind::Poi(MapPointInfo {
+                    kind: MapPointObjectKind::TrainStation(station),
+                    ..
+                }) => (map_geom_obj.id, station),
+                kind => panic!("Unexpected kind {kind:?}"),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            stations,
+            vec![
+                (1, StationKind::Subway),
+                // interchange with heavy rail
+                (2, StationKind::Train),
+                (3, StationKind::LightRail),
+                (4, StationKind::Tram),
+                (5, StationKind::Other),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_clip_to_bounds() {
+        let string_table = StringTable {
+            s: ["", "highway", "primary"].map(|s| s.as_bytes().to_vec()).to_vec(),
+        };
+        let block = |group| PrimitiveBlock {
+            stringtable: string_table.clone(),
+            primitivegroup: vec![group],
+            granularity: None,
+            lat_offset: None,
+            lon_offset: None,
+            date_granularity: None,
+        };
+        // the second node is outside of the area
+        let nodes = block(PrimitiveGroup {
+            dense: Some(DenseNodes {
+                id: vec![1, 1],
+                denseinfo: None,
+                lat: vec![100_000, 0],
+                lon: vec![100_000, 140_000],
+                keys_vals: vec![0, 0],
+            }),
+            ..Default::default()
+        });
+        let ways = block(PrimitiveGroup {
+            ways: vec![Way {
+                id: 10,
+                keys: vec![1],
+                vals: vec![2],
+                info: None,
+                refs: vec![1, 1],
+                lat: vec![],
+                lon: vec![],
+            }],
+            ..Default::default()
+        });
+        let pbf_path = std::env::temp_dir().join(format!("osm_tool_clip_{}.osm.pbf", std::process::id()));
+        std::fs::write(&pbf_path, test_pbf(vec![nodes, ways])).unwrap();
+
+        let boundary = Rect::new(coord! {x: 0.0, y: 0.0}, coord! {x: 0.02, y: 0.02});
+        let mut tile_processor =
+            TileProcessor::new(TileWriterConfig::default(), SimplificationConfig::default());
+        PbfProcessor::new(TagMapping::default())
+            .with_clip_to_bounds(true)
+            .process_pbf(
+                boundary,
+                File::open(&pbf_path).unwrap(),
+                &mut tile_processor,
+                false,
+                false,
+                None,
+            );
+
+        let db_path = std::env::temp_dir().join(format!("osm_tool_clip_{}.db", std::process::id()));
+        tile_processor.save_to_path(&db_path);
+        let tile_store = TileStore::new(TilesSQLiteStore::new(&db_path));
+        let ranges = tile_processor.grid.tile_ranges(0, &boundary);
+        let xs = (ranges.min_x..=ranges.max_x)
+            .flat_map(|x| {
+                (ranges.min_y..=ranges.max_y).map(move |y| TileKey::new(x as i32, y as i32, 0))
+            })
+            .flat_map(|key| {
+                // tile geometry is relative to the tile origin
+                let origin = lat_lon_to_world(&key.calc_tile_boundary(1.0).min());
+                tile_store
+                    .load_geometries(&key)
+                    .into_iter()
+                    .flat_map(move |(_, geometry)| match geometry {
+                        MapGeometry::Line(line) => line
+                            .0
+                            .into_iter()
+                            .map(|coord| {
+                                let world = coord! {x: coord.x as f64, y: coord.y as f64} + origin;
+                                world_to_lat_lon(&world).x
+                            })
+                            .collect(),
+                        _ => vec![],
+                    })
+            })
+            .collect::<Vec<_>>();
+
+        assert!(!xs.is_empty());
+        let max_x = xs.iter().fold(f64::MIN, |max_x, x| max_x.max(*x));
+        assert!((max_x - 0.02).abs() < 1e-6, "line ends at {max_x}");
+
+        std::fs::remove_file(&pbf_path).unwrap();
+        std::fs::remove_file(&db_path).unwrap();
+    }
+
+    #[test]
+    fn test_read_nodes_async() {
+        let string_table = ["", "amenity", "toilets"].map(str::to_string).to_vec();
+        let node_blobs = (0..20)
+            .map(|blob| OsmBlobData {
+                string_table: string_table.clone(),
+                ways: vec![],
+                nodes: (0..100)
+                    .map(|index| {
+                        let id = blob * 100 + index;
+                        OsmNode {
+                            id,
+                            coord: coord! {x: id as f64 * 0.001, y: -(id as f64) * 0.001},
+                            tags: if id % 7 == 0 { [(1, 2)].into() } else { Default::default() },
+                        }
+                    })
+                    .collect(),
+                relations: vec![],
+            })
+            .collect::<Vec<_>>();
+        let tag_mapping = Arc::new(TagMapping::default());
+
+        let mut serial_nodes = NodeCoordStore::new(None);
+        let serial_pois = node_blobs
+            .iter()
+            .flat_map(|data_blob| {
+                PbfProcessor::read_nodes(&tag_mapping, data_blob, &mut serial_nodes)
+            })
+            .count();
+
+        let mut nodes = NodeCoordStore::new(None);
+        let (tx, rx) = mpsc::channel();
+        let tp = ThreadPool::new(4);
+        PbfProcessor::read_nodes_async(&tp, tx, &tag_mapping, node_blobs, &mut nodes);
+        let pois = rx.into_iter().filter(|(_, poi)| poi.is_some()).count();
+
+        assert_eq!(nodes.len(), 2000);
+        assert_eq!(nodes.len(), serial_nodes.len());
+        for id in -1..2001 {
+            assert_eq!(nodes.get(id), serial_nodes.get(id));
+        }
+        assert_eq!(pois, serial_pois);
+        assert_eq!(pois, 286);
     }
 }
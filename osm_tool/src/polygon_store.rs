@@ -1,58 +1,76 @@
+use crate::simplification::ForestLod;
 use crate::{LocationTraitCoord, POLYGON_MERGE_ZOOM_LEVEL};
 use geo::{
-    coord, Area, BooleanOps, Coord, CoordsIter, Intersects, LineString, Polygon, Scale, SimplifyVw,
+    coord, Area, BooleanOps, Coord, CoordsIter, Intersects, LineString, Polygon, Relate, Scale,
+    SimplifyVw,
 };
 use itertools::Itertools;
-use osm::map::{MapGeomObject, MapGeomObjectKind, MapGeometry, NatureKind, ZOOM_LEVELS};
+use osm::map::{MapGeomObject, MapGeomObjectKind, MapGeometry, NatureKind};
 use rstar::{RTree, RTreeObject};
+use rustc_hash::FxHashMap;
 use std::io;
 use std::io::Write;
 use std::sync::mpsc::Sender;
 
+/// Nature polygons merged for less detailed zoom levels, every kind is merged independently
 pub struct PolygonStore {
-    items: Vec<Polygon>,
+    items: FxHashMap<NatureKind, Vec<Polygon>>,
 }
 
 impl PolygonStore {
     pub fn new() -> Self {
         PolygonStore {
-            items: Vec::new(),
+            items: FxHashMap::default(),
         }
     }
     
-    pub fn add_polygon(&mut self, polygon: Polygon) {
-        self.items.push(polygon);
+    pub fn add_polygon(&mut self, kind: NatureKind, polygon: Polygon) {
+        self.items.entry(kind).or_default().push(polygon);
     }
 
-    pub fn process_forests_async(
+    /// Kinds without polygons or parameters in `lods` are skipped
+    pub fn process_polygons_async(
         &self,
         sender: Sender<(u32, MapGeomObject, MapGeometry)>,
         merge_enabled: bool,
         zoom_level: u32,
+        max_zoom: u32,
+        lods: impl Fn(NatureKind) -> Option<ForestLod>,
     ) {
-        let forest_polygons = self.items.clone();
-        std::thread::spawn(move || {
-            Self::process_forests(sender, merge_enabled, forest_polygons, zoom_level);
-        });
+        for (kind, polygons) in &self.items {
+            let Some(lod) = lods(*kind) else {
+                continue;
+            };
+            let kind = *kind;
+            let polygons = polygons.clone();
+            let sender = sender.clone();
+            std::thread::spawn(move || {
+                Self::process_polygons(sender, kind, merge_enabled, polygons, zoom_level, max_zoom, lod);
+            });
+        }
     }
 
-    fn process_forests(
+    fn process_polygons(
         sender: Sender<(u32, MapGeomObject, MapGeometry)>,
+        kind: NatureKind,
         merge_enabled: bool,
         forest_polygons: Vec<Polygon>,
         zoom_level: u32,
+        max_zoom: u32,
+        lod: ForestLod,
     ) {
         let total_polygon_nodes: i32 = forest_polygons
             .iter()
             .map(|item| item.coords_count() as i32)
             .sum();
         println!(
-            "Process forests for zoom level = {:?}, len = {:?}, nodes = {}",
+            "Process {:?} for zoom level = {:?}, len = {:?}, nodes = {}",
+            kind,
             zoom_level,
             forest_polygons.len(),
             total_polygon_nodes
         );
-        let zoom_level = zoom_level.max(POLYGON_MERGE_ZOOM_LEVEL).min(ZOOM_LEVELS);
+        let zoom_level = zoom_level.max(POLYGON_MERGE_ZOOM_LEVEL).min(max_zoom);
         let zlf = zoom_level as f64;
 
         let forest_polygons = if merge_enabled {
@@ -60,7 +78,7 @@ impl PolygonStore {
             let forest_polygons = merged_polygons
                 .0
                 .into_iter()
-                .filter(|item| item.unsigned_area() >= 0.00000005 * (zlf - 2.0) * (zlf - 2.0))
+                .filter(|item| item.unsigned_area() >= lod.merged_min_area * (zlf - 2.0) * (zlf - 2.0))
                 .collect_vec();
 
             let total_polygon_nodes: i32 = forest_polygons
@@ -85,18 +103,21 @@ impl PolygonStore {
                     .collect_vec();
 
                 let mut coords_for_concavehull = Vec::new();
+                // holes of aggregated polygons, e.g. islands of lakes, are kept in the hull
+                let mut interiors = Vec::new();
 
                 for geom_poly in drained {
                     if geom_poly.unsigned_area()
-                        > 0.000005 * (zlf - 2.0) * (zlf - 2.0) * (zlf - 2.0)
+                        > lod.aggregation_max_area * (zlf - 2.0) * (zlf - 2.0) * (zlf - 2.0)
                     {
                         rtree.insert(geom_poly);
                     } else {
-                        let scale_koef = 1.01 + 0.03 * (zlf - 2.0);
+                        let scale_koef = lod.aggregation_scale + lod.aggregation_scale_step * (zlf - 2.0);
                         let test_poly1 = poly.scale(scale_koef);
                         let test_poly2 = geom_poly.scale(scale_koef);
                         if test_poly1.intersects(&test_poly2) {
                             coords_for_concavehull.extend(geom_poly.coords_iter().collect_vec());
+                            interiors.extend(geom_poly.into_inner().1);
                         } else {
                             rtree.insert(geom_poly);
                         }
@@ -111,7 +132,7 @@ impl PolygonStore {
                         .iter()
                         .map(|coord| LocationTraitCoord { coord: *coord })
                         .collect_vec();
-                    let concave_hull = rs_concaveman::concaveman(coords_vec.as_slice(), None, None)
+                    let concave_hull = rs_concaveman::concaveman(coords_vec.as_slice(), Some(lod.concavity), None)
                         .iter()
                         .map(|item| Coord {
                             x: item.0,
@@ -119,7 +140,13 @@ impl PolygonStore {
                         })
                         .collect_vec();
 
-                    Polygon::new(LineString(concave_hull), vec![])
+                    let hull = Polygon::new(LineString(concave_hull), vec![]);
+                    interiors.extend(poly.interiors().iter().cloned());
+                    let interiors = interiors
+                        .into_iter()
+                        .filter(|interior| interior.relate(&hull).is_coveredby())
+                        .collect_vec();
+                    Polygon::new(hull.into_inner().0, interiors)
                 } else {
                     poly
                 };
@@ -134,16 +161,16 @@ impl PolygonStore {
             forest_polygons
         };
 
-        let all_geom = forest_polygons
-            .iter()
-            .filter_map(|poly| {
-                if poly.unsigned_area() < 0.000003 * (zlf - 2.0) * (zlf - 2.0) {
-                    None
-                } else {
-                    Some(poly.simplify_vw(0.0000003 * (zlf - 2.0) * (zlf - 2.0)))
-                }
-            })
-            .collect_vec();
+        let all_geom = Self::parallel_map(&forest_polygons, Self::threads(), |poly| {
+            if poly.unsigned_area() < lod.min_area * (zlf - 2.0) * (zlf - 2.0) {
+                None
+            } else {
+                Some(lod.algo.simplify(poly, lod.epsilon * (zlf - 2.0) * (zlf - 2.0)))
+            }
+        })
+        .into_iter()
+        .flatten()
+        .collect_vec();
 
         all_geom.iter().for_each(|geom| {
             sender
@@ -151,15 +178,15 @@ impl PolygonStore {
                     zoom_level,
                     MapGeomObject {
                         id: -2, // TODO figure out what to do with merged IDs
-                        kind: MapGeomObjectKind::Nature(NatureKind::Forest),
+                        kind: MapGeomObjectKind::Nature(kind),
                     },
                     MapGeometry::Poly(geom.clone()),
                 ))
                 .unwrap();
         });
 
-        if zoom_level + 1 < ZOOM_LEVELS {
-            Self::process_forests(sender, merge_enabled, all_geom, zoom_level + 1);
+        if zoom_level < max_zoom {
+            Self::process_polygons(sender, kind, merge_enabled, all_geom, zoom_level + 1, max_zoom, lod);
         }
     }
 
@@ -180,7 +207,35 @@ impl PolygonStore {
         densified_exterior
     }
 
+    fn threads() -> usize {
+        std::thread::available_parallelism().map_or(1, |threads| threads.get())
+    }
+
+    /// Maps items in chunks on up to `threads` scoped threads, the order of items is kept
+    fn parallel_map<T: Sync, R: Send>(items: &[T], threads: usize, f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+        if threads <= 1 || items.len() <= 1 {
+            return items.iter().map(f).collect();
+        }
+        let chunk_size = items.len().div_ceil(threads);
+        let f = &f;
+        std::thread::scope(|scope| {
+            items
+                .chunks(chunk_size)
+                .map(|chunk| scope.spawn(move || chunk.iter().map(f).collect_vec()))
+                .collect_vec()
+                .into_iter()
+                .flat_map(|handle| handle.join().expect("Merge thread panicked"))
+                .collect()
+        })
+    }
+
     fn merge_polygons(polygons: Vec<Polygon>) -> geo::MultiPolygon {
+        Self::merge_polygons_with_threads(polygons, Self::threads())
+    }
+
+    /// Unions of the same tree level are independent and run in parallel, the tree is
+    /// the same for any `threads` so the result doesn't depend on it
+    fn merge_polygons_with_threads(polygons: Vec<Polygon>, threads: usize) -> geo::MultiPolygon {
         let mut polygons = polygons
             .into_iter()
             .map(|item| geo::MultiPolygon::new(vec![item]))
@@ -198,18 +253,15 @@ impl PolygonStore {
         loop {
             half_step = step;
             step *= 2;
-            let mut i = 0;
-
-            loop {
-                let p1 = polygons.get(i).unwrap();
-                let p2 = polygons.get(i + half_step).unwrap();
-
-                polygons[i] = p1.union(p2);
-
-                i += step;
-                if i + half_step >= polygons.len() {
-                    break;
-                }
+            let pairs = (0..polygons.len())
+                .step_by(step)
+                .take_while(|i| i + half_step < polygons.len())
+                .collect_vec();
+            let unions = Self::parallel_map(&pairs, threads, |&i| {
+                polygons[i].union(&polygons[i + half_step])
+            });
+            for (i, union) in pairs.into_iter().zip(unions) {
+                polygons[i] = union;
             }
 
             if step >= polygons.len() {
@@ -224,3 +276,117 @@ impl PolygonStore {
         polygons.first().unwrap().simplify_vw(0.00000001)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::polygon_store::PolygonStore;
+    use crate::simplification::ForestLod;
+    use crate::POLYGON_MERGE_ZOOM_LEVEL;
+    use geo::{polygon, Area, BoundingRect, Polygon};
+    use osm::map::{MapGeomObjectKind, MapGeometry, NatureKind};
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_parallel_merge() {
+        // overlapping squares in rows, every row merges into a single polygon
+        let polygons = (0..5)
+            .flat_map(|row| {
+                (0..7).map(move |col| {
+                    let (x, y) = (col as f64 * 0.9, row as f64 * 2.0);
+                    polygon![(x: x, y: y), (x: x + 1.0, y: y), (x: x + 1.0, y: y + 1.0), (x: x, y: y + 1.0)]
+                })
+            })
+            .collect::<Vec<Polygon>>();
+
+        let serial = PolygonStore::merge_polygons_with_threads(polygons.clone(), 1);
+        let parallel = PolygonStore::merge_polygons_with_threads(polygons, 4);
+        assert_eq!(serial.0.len(), 5);
+        assert_eq!(parallel.0.len(), serial.0.len());
+        assert!((parallel.unsigned_area() - serial.unsigned_area()).abs() < 1e-9);
+        assert!((serial.unsigned_area() - 5.0 * 6.4).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_merge_water() {
+        let mut polygon_store = PolygonStore::new();
+        polygon_store.add_polygon(
+            NatureKind::Water,
+            polygon![(x: 0.0, y: 0.0), (x: 0.01, y: 0.0), (x: 0.01, y: 0.01), (x: 0.0, y: 0.01)],
+        );
+        polygon_store.add_polygon(
+            NatureKind::Water,
+            polygon![(x: 0.01, y: 0.0), (x: 0.02, y: 0.0), (x: 0.02, y: 0.01), (x: 0.01, y: 0.01)],
+        );
+        polygon_store.add_polygon(
+            NatureKind::Park,
+            polygon![(x: 1.0, y: 1.0), (x: 1.01, y: 1.0), (x: 1.01, y: 1.01), (x: 1.0, y: 1.01)],
+        );
+
+        let (tx, rx) = channel();
+        let lod = ForestLod {
+            merged_min_area: 0.0,
+            min_area: 0.0,
+            ..ForestLod::default()
+        };
+        polygon_store.process_polygons_async(
+            tx,
+            true,
+            POLYGON_MERGE_ZOOM_LEVEL,
+            POLYGON_MERGE_ZOOM_LEVEL,
+            |kind| (kind == NatureKind::Water).then_some(lod),
+        );
+        let items = rx.into_iter().collect::<Vec<_>>();
+
+        // parks aren't merged, both water polygons become one
+        assert_eq!(items.len(), 1);
+        let (zoom, map_geom_obj, geom) = &items[0];
+        assert_eq!(*zoom, POLYGON_MERGE_ZOOM_LEVEL);
+        assert_eq!(map_geom_obj.kind, MapGeomObjectKind::Nature(NatureKind::Water));
+        let MapGeometry::Poly(poly) = geom else {
+            panic!("Expect polygon");
+        };
+        let rect = poly.bounding_rect().unwrap();
+        assert!((rect.width() - 0.02).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_aggregation_keeps_interiors() {
+        let mut polygon_store = PolygonStore::new();
+        // lake with an island and a pond next to it, both are aggregated into one hull
+        polygon_store.add_polygon(
+            NatureKind::Water,
+            polygon!(
+                exterior: [(x: 0.0, y: 0.0), (x: 0.01, y: 0.0), (x: 0.01, y: 0.01), (x: 0.0, y: 0.01)],
+                interiors: [[(x: 0.004, y: 0.004), (x: 0.006, y: 0.004), (x: 0.006, y: 0.006), (x: 0.004, y: 0.006)]],
+            ),
+        );
+        polygon_store.add_polygon(
+            NatureKind::Water,
+            polygon![(x: 0.0105, y: 0.0), (x: 0.0125, y: 0.0), (x: 0.0125, y: 0.002), (x: 0.0105, y: 0.002)],
+        );
+
+        let (tx, rx) = channel();
+        let lod = ForestLod {
+            merged_min_area: 0.0,
+            min_area: 0.0,
+            aggregation_max_area: 1.0,
+            aggregation_scale: 1.2,
+            ..ForestLod::default()
+        };
+        polygon_store.process_polygons_async(
+            tx,
+            true,
+            POLYGON_MERGE_ZOOM_LEVEL,
+            POLYGON_MERGE_ZOOM_LEVEL,
+            |_| Some(lod),
+        );
+        let items = rx.into_iter().collect::<Vec<_>>();
+
+        assert_eq!(items.len(), 1);
+        let MapGeometry::Poly(poly) = &items[0].2 else {
+            panic!("Expect polygon");
+        };
+        assert!(poly.bounding_rect().unwrap().width() > 0.012);
+        assert_eq!(poly.interiors().len(), 1);
+    }
+}
@@ -1,18 +1,32 @@
 use crate::filter;
-use crate::proto::{Blob, BlobHeader, PrimitiveBlock, Relation};
+use crate::node_store::NodeCoordStore;
+use crate::proto::{Blob, BlobHeader, HeaderBlock, PrimitiveBlock, Relation};
 use crate::tags::IntoTagIterator;
 use error_stack::{Report, ResultExt};
 use geo::{Coord, Intersects, LineString, Polygon, Rect};
 use itertools::izip;
 use prost::Message;
-use rustc_hash::{FxHashMap, FxHashSet};
+use rustc_hash::{FxHashSet, FxHasher};
+use flate2::read::MultiGzDecoder;
+use std::fs::File;
+use std::hash::Hasher;
 use std::io::{Seek, SeekFrom};
 use std::iter::Iterator;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::{
     collections::HashMap,
+    fs,
     io::{ErrorKind, Read},
 };
 
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+static GUNZIP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Length of the input start hashed to tell apart inputs of the same length
+const FINGERPRINT_LEN: u64 = 64 * 1024;
+
 /// Packed delta value decoder
 struct Delta<I> {
     acu: Option<i64>,
@@ -62,6 +76,8 @@ pub enum OsmBlobReaderError {
     Decode,
     #[error("Unsupported blob type {0}")]
     UnsupportedType(String),
+    #[error("Unsupported required feature {0}")]
+    UnsupportedFeature(String),
 }
 
 #[derive(Debug, Clone)]
@@ -79,19 +95,18 @@ pub struct OsmNode {
 }
 
 impl OsmWay {
-    pub fn as_line(&self, nodes: &FxHashMap<i64, Coord>) -> (LineString, i64, i64) {
+    pub fn as_line(&self, nodes: &NodeCoordStore) -> (LineString, i64, i64) {
         let mut f_node: i64 = -1;
         let mut l_node: i64 = -1;
         (
             self.refs
                 .iter()
                 .filter_map(|i| {
-                    nodes.get(i).map(|v| {
+                    nodes.get(*i).inspect(|_| {
                         if f_node == -1 {
                             f_node = *i;
                         }
                         l_node = *i;
-                        v.clone()
                     })
                 })
                 .collect(),
@@ -100,7 +115,7 @@ impl OsmWay {
         )
     }
 
-    pub fn as_polygon(&self, nodes: &FxHashMap<i64, Coord>) -> Polygon {
+    pub fn as_polygon(&self, nodes: &NodeCoordStore) -> Polygon {
         Polygon::new(self.as_line(nodes).0, vec![])
     }
 }
@@ -157,7 +172,69 @@ pub struct OsmBlobHeader {
 
 #[derive(Debug)]
 pub struct OsmHeaderBlock {
-    // Nothing of interest for now
+    /// Bounding box of the data in lon/lat
+    pub bbox: Option<Rect>,
+    pub required_features: Vec<String>,
+}
+
+impl OsmHeaderBlock {
+    /// Required features the reader understands, a file requiring anything else can't be read correctly
+    pub const SUPPORTED_FEATURES: &'static [&'static str] = &["OsmSchema-V0.6", "DenseNodes"];
+
+    pub fn new(header_block: HeaderBlock) -> Self {
+        let bbox = header_block.bbox.map(|bbox| {
+            Rect::new(
+                Coord {
+                    x: bbox.left as f64 / 1_000_000_000.0,
+                    y: bbox.top as f64 / 1_000_000_000.0,
+                },
+                Coord {
+                    x: bbox.right as f64 / 1_000_000_000.0,
+                    y: bbox.bottom as f64 / 1_000_000_000.0,
+                },
+            )
+        });
+        Self {
+            bbox,
+            required_features: header_block.required_features,
+        }
+    }
+
+    pub fn unsupported_feature(&self) -> Option<&str> {
+        self.required_features
+            .iter()
+            .map(String::as_str)
+            .find(|feature| !Self::SUPPORTED_FEATURES.contains(feature))
+    }
+}
+
+/// Opens the OSM file at `path`. A gzip compressed file is decompressed into a temporary file
+/// first since reading needs Seek, the temporary file is removed as soon as it's opened
+/// so it's only kept on disk until the returned file is closed
+pub fn open_decompressed(path: &Path) -> Result<File, Report<OsmBlobReaderError>> {
+    let mut file = File::open(path).change_context(OsmBlobReaderError::Read)?;
+    let mut magic = [0; 2];
+    let is_gzip = file.read_exact(&mut magic).is_ok() && magic == GZIP_MAGIC;
+    file.seek(SeekFrom::Start(0)).change_context(OsmBlobReaderError::Read)?;
+    if !is_gzip {
+        return Ok(file);
+    }
+
+    let buffer_path = std::env::temp_dir().join(format!(
+        "osm_tool_gunzip_{}_{}.osm.pbf",
+        std::process::id(),
+        GUNZIP_COUNTER.fetch_add(1, Ordering::SeqCst)
+    ));
+    let mut buffer_file = File::create(&buffer_path).change_context(OsmBlobReaderError::Read)?;
+    let size = std::io::copy(&mut MultiGzDecoder::new(file), &mut buffer_file)
+        .change_context(OsmBlobReaderError::Decode)
+        .attach_printable_lazy(|| format!("Failed to decompress {:?}", path));
+    let buffer_file = size.and_then(|size| {
+        println!("Decompressed {} bytes of {:?} into {:?}", size, path, buffer_path);
+        File::open(&buffer_path).change_context(OsmBlobReaderError::Read)
+    });
+    let _ = fs::remove_file(&buffer_path);
+    buffer_file
 }
 
 #[derive(Debug)]
@@ -172,6 +249,8 @@ pub struct OsmReader<T> {
     header_buffer: Vec<u8>,
     blob_buffer: Vec<u8>,
     boundry: Rect,
+    block_offsets: Option<Vec<u64>>,
+    offsets_index_path: Option<PathBuf>,
 }
 
 impl<T: Read + Seek> OsmReader<T> {
@@ -182,44 +261,141 @@ impl<T: Read + Seek> OsmReader<T> {
             header_buffer: Vec::new(),
             blob_buffer: Vec::new(),
             boundry,
+            block_offsets: None,
+            offsets_index_path: None,
         }
     }
 
+    /// Keeps blob offsets in the file at `path`, so later runs over the same input
+    /// skip scanning all blob headers
+    pub fn with_offsets_index(mut self, path: PathBuf) -> Self {
+        self.offsets_index_path = Some(path);
+        self
+    }
+
+    /// Offsets of all blobs in the input, scanned once and cached for all later passes
+    fn block_offsets(&mut self) -> Vec<u64> {
+        if let Some(block_offsets) = &self.block_offsets {
+            return block_offsets.clone();
+        }
+        let input_len = self.input.seek(SeekFrom::End(0)).unwrap();
+        let fingerprint = self.input_fingerprint();
+        let block_offsets = match self
+            .offsets_index_path
+            .as_deref()
+            .and_then(|path| Self::read_offsets_index(path, input_len, fingerprint))
+        {
+            Some(block_offsets) => {
+                println!("Loaded {} blob offsets from index", block_offsets.len());
+                block_offsets
+            }
+            None => {
+                let block_offsets = self.scan_block_offsets();
+                if let Some(path) = &self.offsets_index_path {
+                    if let Err(err) =
+                        Self::write_offsets_index(path, input_len, fingerprint, &block_offsets)
+                    {
+                        println!("Failed to write blob offsets index {:?}: {}", path, err);
+                    }
+                }
+                block_offsets
+            }
+        };
+        self.block_offsets = Some(block_offsets.clone());
+        block_offsets
+    }
+
+    fn scan_block_offsets(&mut self) -> Vec<u64> {
+        let mut block_offsets = Vec::new();
+        let mut offset = 0u64;
+        self.input.seek(SeekFrom::Start(0)).unwrap();
+        while let Ok(Some(osm_blob_header)) = self.parse_blob_header() {
+            block_offsets.push(offset);
+            offset += osm_blob_header.total_size as u64;
+            // skip the blob itself
+            self.input.seek(SeekFrom::Start(offset)).unwrap();
+        }
+        block_offsets
+    }
+
+    /// Hash of the input start including the first blob header, a rewritten input of the same
+    /// length has different blob sizes there
+    fn input_fingerprint(&mut self) -> u64 {
+        let mut start = Vec::new();
+        self.input.seek(SeekFrom::Start(0)).unwrap();
+        (&mut self.input)
+            .take(FINGERPRINT_LEN)
+            .read_to_end(&mut start)
+            .unwrap();
+        let mut hasher = FxHasher::default();
+        hasher.write(&start);
+        hasher.finish()
+    }
+
+    /// The index is the input length and fingerprint followed by blob offsets,
+    /// all little endian u64
+    fn read_offsets_index(path: &Path, input_len: u64, fingerprint: u64) -> Option<Vec<u64>> {
+        let bytes = fs::read(path).ok()?;
+        let mut values = bytes
+            .chunks_exact(size_of::<u64>())
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()));
+        // the input was changed since the index was written
+        if values.next()? != input_len || values.next()? != fingerprint {
+            return None;
+        }
+        Some(values.collect())
+    }
+
+    fn write_offsets_index(
+        path: &Path,
+        input_len: u64,
+        fingerprint: u64,
+        block_offsets: &[u64],
+    ) -> std::io::Result<()> {
+        let bytes = [input_len, fingerprint]
+            .into_iter()
+            .chain(block_offsets.iter().copied())
+            .flat_map(u64::to_le_bytes)
+            .collect::<Vec<_>>();
+        fs::write(path, bytes)
+    }
+
     /// Pre extract way id which part of Relations. It reduces the memory consumption and speed up the process since
     /// there will be fewer ways in cache in general, we cache only what we need for Relations.
     pub fn extract_ways_id_from_relations(
         &mut self,
         relation_tags: &[(&str, Option<&str>)],
     ) -> FxHashSet<i64> {
-        let mut blocks_sizes = Vec::new();
-        let mut total_size = 0u64;
-        loop {
-            let osm_blob_header = match self.parse_blob_header() {
-                Ok(data) => match data {
-                    None => break,
-                    Some(data) => data,
-                },
-                Err(_) => break,
-            };
-            let block_size = osm_blob_header.total_size as u64;
-            total_size += block_size;
-            blocks_sizes.push(block_size);
-            // skip the blob itself
-            self.input
-                .seek(SeekFrom::Current(
-                    osm_blob_header.blob_header.datasize as i64,
-                ))
-                .unwrap();
-        }
-
         let mut ways_ids = FxHashSet::default();
+        self.for_each_relation(relation_tags, |rel| {
+            rel.ways.iter().for_each(|way| {
+                let way_id = way.0;
+                ways_ids.insert(way_id);
+            })
+        });
+        println!("Extracted ways from all relations: {}", ways_ids.len());
+        ways_ids
+    }
+
+    /// Amount of relations matching `relation_tags`
+    pub fn count_relations(&mut self, relation_tags: &[(&str, Option<&str>)]) -> usize {
+        let mut count = 0;
+        self.for_each_relation(relation_tags, |_| count += 1);
+        count
+    }
+
+    /// Walks the relation blobs only, the input is at the beginning afterwards
+    fn for_each_relation(
+        &mut self,
+        relation_tags: &[(&str, Option<&str>)],
+        mut f: impl FnMut(&OsmRelation),
+    ) {
+        let block_offsets = self.block_offsets();
+
         // the PBF order is Nodes/DenseNodes - Ways - Relations.
         // we just start from the end until there are no relations
-        let mut last_block_index = blocks_sizes.len() as i32 - 1;
-        while last_block_index >= 0 {
-            total_size -= blocks_sizes[last_block_index as usize];
-            last_block_index -= 1;
-            self.input.seek(SeekFrom::Start(total_size as u64)).unwrap();
+        for offset in block_offsets.into_iter().rev() {
+            self.input.seek(SeekFrom::Start(offset)).unwrap();
             let blob = self.parse_blob().unwrap().ok().unwrap();
             if let OsmBlob::Data(data) = blob {
                 if !data.nodes.is_empty() || !data.ways.is_empty() {
@@ -233,10 +409,7 @@ impl<T: Read + Seek> OsmReader<T> {
                     //  but Relation parsing takes relatively small amount of time so it's not a
                     //  priority now
                     if let Some((_, _)) = tag_filter.filter(&data.string_table, &rel.tags) {
-                        rel.ways.iter().for_each(|way| {
-                            let way_id = way.0;
-                            ways_ids.insert(way_id);
-                        })
+                        f(&rel);
                     }
                 }
             } else {
@@ -245,8 +418,39 @@ impl<T: Read + Seek> OsmReader<T> {
         }
         // seek to the beginning so Iterator would start from the beginning
         self.input.seek(SeekFrom::Start(0)).unwrap();
-        println!("Extracted ways from all relations: {}", ways_ids.len());
-        ways_ids
+    }
+
+    /// Header block of the input if the first blob is a header, the input is at the beginning
+    /// afterwards. Fails if the header requires a feature the reader doesn't support
+    pub fn header(&mut self) -> Result<Option<OsmHeaderBlock>, Report<OsmBlobReaderError>> {
+        self.input
+            .seek(SeekFrom::Start(0))
+            .change_context(OsmBlobReaderError::Read)?;
+        let header = match self.read_typed_blob() {
+            Some(Ok((blob_header, blob))) if blob_header.r#type == "OSMHeader" => {
+                let data = blob.extract().change_context(OsmBlobReaderError::Decode)?;
+                let header_block =
+                    HeaderBlock::decode(data).change_context(OsmBlobReaderError::Decode)?;
+                Some(OsmHeaderBlock::new(header_block))
+            }
+            Some(Err(err)) => return Err(err),
+            _ => None,
+        };
+        self.input
+            .seek(SeekFrom::Start(0))
+            .change_context(OsmBlobReaderError::Read)?;
+
+        if let Some(feature) = header.as_ref().and_then(OsmHeaderBlock::unsupported_feature) {
+            return Err(Report::new(OsmBlobReaderError::UnsupportedFeature(
+                feature.to_string(),
+            )));
+        }
+        Ok(header)
+    }
+
+    /// Bounding box declared in the header, `None` if there is no header or it has no bbox
+    pub fn header_bbox(&mut self) -> Result<Option<Rect>, Report<OsmBlobReaderError>> {
+        Ok(self.header()?.and_then(|header| header.bbox))
     }
 
     fn parse_blob_header(&mut self) -> Result<Option<OsmBlobHeader>, Report<OsmBlobReaderError>> {
@@ -291,6 +495,13 @@ impl<T: Read + Seek> OsmReader<T> {
     }
 
     pub fn read_blob(&mut self) -> Option<Result<Blob, Report<OsmBlobReaderError>>> {
+        self.read_typed_blob().map(|blob| blob.map(|(_, blob)| blob))
+    }
+
+    /// Next blob along with its header, the header type tells how the blob is decoded
+    fn read_typed_blob(
+        &mut self,
+    ) -> Option<Result<(BlobHeader, Blob), Report<OsmBlobReaderError>>> {
         let blob_header = match self.parse_blob_header() {
             Ok(header) => header,
             Err(err) => return Some(Err(err).change_context(OsmBlobReaderError::Decode)),
@@ -311,7 +522,7 @@ impl<T: Read + Seek> OsmReader<T> {
             Ok(blob) => blob,
             Err(err) => return Some(Err(err).change_context(OsmBlobReaderError::Decode)),
         };
-        Some(Ok(blob))
+        Some(Ok((blob_header, blob)))
     }
 
     fn blob_to_osm_blob_data(blob: Blob, boundary: Rect) -> Option<OsmBlobData> {
@@ -407,7 +618,11 @@ impl<T: Read + Seek> OsmReader<T> {
         let tp = threadpool::ThreadPool::new(6);
 
         let boundary = self.boundry;
-        while let Some(blob) = self.read_blob() {
+        for offset in self.block_offsets() {
+            self.input.seek(SeekFrom::Start(offset)).unwrap();
+            let Some(blob) = self.read_blob() else {
+                break;
+            };
             let blob = blob.expect("Failed to read blob");
             let sender = tx.clone();
             tp.execute(move || {
@@ -444,3 +659,202 @@ impl<T: Read + Seek> Iterator for OsmReader<T> {
         self.parse_blob()
     }
 }
+
+/// PBF of zlib compressed data blobs for tests, the header blob is omitted
+#[cfg(test)]
+pub fn test_pbf(blocks: Vec<PrimitiveBlock>) -> Vec<u8> {
+    test_pbf_with_header(None, blocks)
+}
+
+/// Same as [test_pbf] starting with the header blob
+#[cfg(test)]
+pub fn test_pbf_with_header(header: Option<HeaderBlock>, blocks: Vec<PrimitiveBlock>) -> Vec<u8> {
+    use crate::proto::blob;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    let mut pbf = Vec::new();
+    let mut write_blob = |blob_type: &str, message: Vec<u8>| {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&message).unwrap();
+        let blob = Blob {
+            raw_size: None,
+            data: Some(blob::Data::ZlibData(encoder.finish().unwrap())),
+        }
+        .encode_to_vec();
+        let header = BlobHeader {
+            r#type: blob_type.to_string(),
+            indexdata: None,
+            datasize: blob.len() as i32,
+        }
+        .encode_to_vec();
+        pbf.extend((header.len() as i32).to_be_bytes());
+        pbf.extend(header);
+        pbf.extend(blob);
+    };
+    if let Some(header) = header {
+        write_blob("OSMHeader", header.encode_to_vec());
+    }
+    for block in blocks {
+        write_blob("OSMData", block.encode_to_vec());
+    }
+    pbf
+}
+
+#[cfg(test)]
+mod test {
+    use crate::proto::{
+        DenseNodes, HeaderBBox, HeaderBlock, PrimitiveBlock, PrimitiveGroup, StringTable,
+    };
+    use crate::reader::{
+        open_decompressed, test_pbf, test_pbf_with_header, OsmBlobReaderError, OsmReader,
+    };
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use geo::{coord, Rect};
+    use std::fs::File;
+    use std::io::{Cursor, Read, Write};
+
+    #[test]
+    fn test_open_gzip() {
+        let block = PrimitiveBlock {
+            stringtable: StringTable { s: vec![vec![]] },
+            primitivegroup: vec![PrimitiveGroup {
+                dense: Some(DenseNodes {
+                    id: vec![1, 1, 1],
+                    denseinfo: None,
+                    lat: vec![5_000_000, 1_000, 1_000],
+                    lon: vec![5_000_000, 1_000, 1_000],
+                    keys_vals: vec![0, 0, 0],
+                }),
+                ..Default::default()
+            }],
+            granularity: None,
+            lat_offset: None,
+            lon_offset: None,
+            date_granularity: None,
+        };
+        let pbf = test_pbf(vec![block]);
+        let path =
+            std::env::temp_dir().join(format!("osm_tool_gzip_{}.osm.pbf.gz", std::process::id()));
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&pbf).unwrap();
+        std::fs::write(&path, encoder.finish().unwrap()).unwrap();
+
+        let boundary = Rect::new(coord! {x: 0.0, y: 0.0}, coord! {x: 1.0, y: 1.0});
+        let (node_blobs, _, _) = OsmReader::new(open_decompressed(&path).unwrap(), boundary).data();
+        assert_eq!(node_blobs.len(), 1);
+        let ids = node_blobs[0].nodes.iter().map(|node| node.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2, 3]);
+
+        // uncompressed files are read as is
+        std::fs::write(&path, &pbf).unwrap();
+        let mut file = open_decompressed(&path).unwrap();
+        let mut content = Vec::new();
+        file.read_to_end(&mut content).unwrap();
+        assert_eq!(content, pbf);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_header_required_features() {
+        let header = |required_features: &[&str]| HeaderBlock {
+            bbox: Some(HeaderBBox {
+                left: 139_000_000_000,
+                right: 140_500_000_000,
+                top: 36_000_000_000,
+                bottom: 35_000_000_000,
+            }),
+            required_features: required_features.iter().map(|feature| feature.to_string()).collect(),
+            optional_features: vec![],
+            writingprogram: None,
+            source: None,
+            osmosis_replication_timestamp: None,
+            osmosis_replication_sequence_number: None,
+            osmosis_replication_base_url: None,
+        };
+        let block = PrimitiveBlock {
+            stringtable: StringTable { s: vec![vec![]] },
+            primitivegroup: vec![PrimitiveGroup::default()],
+            granularity: None,
+            lat_offset: None,
+            lon_offset: None,
+            date_granularity: None,
+        };
+        let boundary = Rect::new(coord! {x: 0.0, y: 0.0}, coord! {x: 1.0, y: 1.0});
+        let reader = |pbf| OsmReader::new(Cursor::new(pbf), boundary);
+
+        let pbf = test_pbf_with_header(
+            Some(header(&["OsmSchema-V0.6", "DenseNodes"])),
+            vec![block.clone()],
+        );
+        let header_block = reader(pbf).header().unwrap().unwrap();
+        assert_eq!(
+            header_block.bbox,
+            Some(Rect::new(coord! {x: 139.0, y: 35.0}, coord! {x: 140.5, y: 36.0}))
+        );
+
+        let pbf = test_pbf_with_header(
+            Some(header(&["OsmSchema-V0.6", "HistoricalInformation"])),
+            vec![block.clone()],
+        );
+        let err = reader(pbf).header().unwrap_err();
+        assert!(matches!(
+            err.current_context(),
+            OsmBlobReaderError::UnsupportedFeature(feature) if feature == "HistoricalInformation"
+        ));
+
+        assert!(reader(test_pbf(vec![block])).header().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_offsets_index_rebuilt() {
+        let block = |ids: Vec<i64>| PrimitiveBlock {
+            stringtable: StringTable { s: vec![vec![]] },
+            primitivegroup: vec![PrimitiveGroup {
+                dense: Some(DenseNodes {
+                    lat: vec![1_000; ids.len()],
+                    lon: vec![1_000; ids.len()],
+                    keys_vals: vec![0; ids.len()],
+                    id: ids,
+                    denseinfo: None,
+                }),
+                ..Default::default()
+            }],
+            granularity: None,
+            lat_offset: None,
+            lon_offset: None,
+            date_granularity: None,
+        };
+        let (small, large) = (block(vec![1]), block(vec![1, 1, 1, 1, 1]));
+        let pbf = test_pbf(vec![small.clone(), large.clone()]);
+        // same length, but the blobs are swapped
+        let rewritten_pbf = test_pbf(vec![large, small]);
+        assert_eq!(pbf.len(), rewritten_pbf.len());
+
+        let boundary = Rect::new(coord! {x: 0.0, y: 0.0}, coord! {x: 1.0, y: 1.0});
+        let path = std::env::temp_dir().join(format!("osm_tool_offsets_{}.osm.pbf", std::process::id()));
+        let index_path = path.with_extension("offsets");
+        let _ = std::fs::remove_file(&index_path);
+        let indexed_offsets = |pbf: &[u8]| {
+            std::fs::write(&path, pbf).unwrap();
+            OsmReader::new(File::open(&path).unwrap(), boundary)
+                .with_offsets_index(index_path.clone())
+                .block_offsets()
+        };
+        let scanned_offsets =
+            |pbf: Vec<u8>| OsmReader::new(Cursor::new(pbf), boundary).block_offsets();
+
+        assert_eq!(indexed_offsets(&pbf), scanned_offsets(pbf.clone()));
+        let rewritten_offsets = scanned_offsets(rewritten_pbf.clone());
+        assert_ne!(rewritten_offsets, scanned_offsets(pbf));
+        assert_eq!(indexed_offsets(&rewritten_pbf), rewritten_offsets);
+        // the rebuilt index is reused as is
+        assert_eq!(indexed_offsets(&rewritten_pbf), rewritten_offsets);
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&index_path).unwrap();
+    }
+}
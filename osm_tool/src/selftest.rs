@@ -0,0 +1,168 @@
+use crate::simplification::SimplificationConfig;
+use crate::tile_processor::TileProcessor;
+use crate::LocationTraitCoord;
+use geo::{coord, polygon, BoundingRect, Coord, LineString, Polygon};
+use osm::map::{
+    BuildingInfo, MapGeomObject, MapGeomObjectKind, MapGeometry, MapPointInfo, MapPointObjectKind, NatureKind,
+};
+use osm::source::tiles_sqlite_store::TilesSQLiteStore;
+use osm::tile_writer::tile_writer::TileWriterConfig;
+use osm::tiles::{calc_tile_ranges, TileKey, TileStore, TILES_COUNT};
+use std::fs;
+
+fn stage<T>(name: &str, f: impl FnOnce() -> Result<T, String>) -> Option<T> {
+    match f() {
+        Ok(result) => {
+            println!("[PASS] {}", name);
+            Some(result)
+        }
+        Err(err) => {
+            println!("[FAIL] {}: {}", name, err);
+            None
+        }
+    }
+}
+
+/// A building, a POI and a forest around Tokyo station, the forest is a concave hull
+/// so the concaveman C++ build gets checked as well
+fn fixture() -> Result<Vec<(MapGeomObject, MapGeometry)>, String> {
+    let building = polygon![
+        (x: 139.7660, y: 35.6800),
+        (x: 139.7670, y: 35.6800),
+        (x: 139.7670, y: 35.6810),
+        (x: 139.7660, y: 35.6810),
+    ];
+    let forest_points = [
+        (139.70, 35.70),
+        (139.72, 35.71),
+        (139.74, 35.70),
+        (139.73, 35.72),
+        (139.74, 35.74),
+        (139.72, 35.73),
+        (139.70, 35.74),
+        (139.71, 35.72),
+    ]
+    .iter()
+    .map(|&(x, y)| LocationTraitCoord {
+        coord: coord! {x: x, y: y},
+    })
+    .collect::<Vec<_>>();
+    let forest_hull = rs_concaveman::concaveman(forest_points.as_slice(), None, None)
+        .into_iter()
+        .map(|(x, y)| Coord { x, y })
+        .collect::<Vec<_>>();
+    if forest_hull.len() < 3 {
+        return Err(format!("Concave hull has {} points", forest_hull.len()));
+    }
+
+    Ok(vec![
+        (
+            MapGeomObject {
+                id: 1,
+                kind: MapGeomObjectKind::Building(BuildingInfo::new(3, None, None, None)),
+            },
+            MapGeometry::Poly(building),
+        ),
+        (
+            MapGeomObject {
+                id: 2,
+                kind: MapGeomObjectKind::Poi(MapPointInfo {
+                    text: "".to_string(),
+                    kind: MapPointObjectKind::Toilet,
+                }),
+            },
+            MapGeometry::Coord(coord! {x: 139.7665, y: 35.6805}),
+        ),
+        (
+            MapGeomObject {
+                id: 3,
+                kind: MapGeomObjectKind::Nature(NatureKind::Forest),
+            },
+            MapGeometry::Poly(Polygon::new(LineString(forest_hull), vec![])),
+        ),
+    ])
+}
+
+fn run_stages() -> bool {
+    let Some(features) = stage("read", fixture) else {
+        return false;
+    };
+    let building_key = match features[0].1.bounding_rect() {
+        Some(rect) => {
+            let ranges = calc_tile_ranges(TILES_COUNT, 0, &rect);
+            TileKey::new(ranges.min_x as i32, ranges.min_y as i32, 0)
+        }
+        None => return false,
+    };
+
+    let Some(mut tile_processor) = stage("process", || {
+        let mut tile_processor =
+            TileProcessor::new(TileWriterConfig::default(), SimplificationConfig::default());
+        for (map_geom_obj, geom) in features {
+            tile_processor.add_to_tiles(map_geom_obj, geom);
+        }
+        Ok(tile_processor)
+    }) else {
+        return false;
+    };
+
+    let db_path = tile_processor.tile_writer.config().db_path.clone();
+    let written = stage("write", || {
+        tile_processor.save_to_disk();
+        if db_path.exists() {
+            Ok(())
+        } else {
+            Err(format!("{:?} was not created", db_path))
+        }
+    });
+    if written.is_none() {
+        return false;
+    }
+
+    let Some(tile_store) = stage("read-back", || {
+        let store = TilesSQLiteStore::new(&db_path);
+        store
+            .get_tile(building_key.tile_x, building_key.tile_y, building_key.zoom_level)
+            .map_err(|err| format!("{:?}", err))?;
+        Ok(TileStore::new(store))
+    }) else {
+        return false;
+    };
+
+    stage("decode", || {
+        let geometries = tile_store.load_geometries(&building_key);
+        let kinds = geometries
+            .iter()
+            .map(|(map_geom_obj, _)| map_geom_obj.kind.clone())
+            .collect::<Vec<_>>();
+        let has_building = kinds
+            .iter()
+            .any(|kind| matches!(kind, MapGeomObjectKind::Building(info) if info.levels == 3));
+        let has_poi = kinds
+            .iter()
+            .any(|kind| matches!(kind, MapGeomObjectKind::Poi(..)));
+        if has_building && has_poi {
+            Ok(())
+        } else {
+            Err(format!("Unexpected tile content {:?}", kinds))
+        }
+    })
+    .is_some()
+}
+
+/// Builds a tiny synthetic tileset in a temporary directory and reads it back.
+/// `TileWriter` always writes to `dbs` in the working directory, so the working directory
+/// is switched for the time of the test.
+pub fn run_selftest() -> bool {
+    let work_dir = std::env::temp_dir().join(format!("osm_tool_selftest_{}", std::process::id()));
+    let prev_dir = std::env::current_dir().expect("Could not get working dir");
+    fs::create_dir_all(&work_dir).expect("Could not create selftest dir");
+    std::env::set_current_dir(&work_dir).expect("Could not switch to selftest dir");
+
+    let passed = run_stages();
+
+    std::env::set_current_dir(prev_dir).expect("Could not switch back to working dir");
+    let _ = fs::remove_dir_all(work_dir);
+    println!("Selftest {}", if passed { "PASS" } else { "FAIL" });
+    passed
+}
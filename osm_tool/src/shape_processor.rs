@@ -5,43 +5,118 @@ use osm::map::MapGeomObjectKind::{AdminLine, Poi};
 use osm::map::NatureKind::Ground;
 use osm::map::{
     MapGeomObject, MapGeomObjectKind, MapGeometry, MapPointInfo, MapPointObjectKind, PopAreaInfo,
+    COUNTRY_ADMIN_LEVEL,
 };
+use error_stack::{Report, ResultExt};
+use serde::{Deserialize, Serialize};
 use shapefile::dbase::FieldValue;
 use shapefile::dbase::FieldValue::Character;
 use std::fs::File;
+use std::path::Path;
 use std::sync::mpsc::{channel, Sender};
+use thiserror::Error;
 use threadpool::ThreadPool;
 
+type PlanetItem = Result<(MapGeomObject, MapGeometry), Report<ShapeProcessorError>>;
+
+#[derive(Debug, Error)]
+pub enum ShapeProcessorError {
+    #[error("Planet data file {0} doesn't exist")]
+    MissingFile(String),
+    #[error("Failed to read planet data file {0}")]
+    Read(String),
+}
+
+/// Planet data files, relative paths are resolved against the working directory
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlanetDataPaths {
+    pub land_shapes: String,
+    /// Can be downloaded from https://www.naturalearthdata.com/http//www.naturalearthdata.com/download/50m/cultural/ne_50m_populated_places.zip
+    pub populated_places: String,
+    pub admin_boundaries: String,
+    pub countries: String,
+}
+
+impl Default for PlanetDataPaths {
+    fn default() -> Self {
+        PlanetDataPaths {
+            land_shapes: "./land_shapes/land_polygons.shp".to_string(),
+            populated_places: "./ne_50m_populated_places/ne_50m_populated_places.shp".to_string(),
+            admin_boundaries:
+                "./ne_50m_admin_0_boundary_lines_land/ne_50m_admin_0_boundary_lines_land.shp"
+                    .to_string(),
+            countries: "temp_countries.json".to_string(),
+        }
+    }
+}
+
+impl PlanetDataPaths {
+    fn check(&self) -> Result<(), Report<ShapeProcessorError>> {
+        for path in [
+            &self.land_shapes,
+            &self.populated_places,
+            &self.admin_boundaries,
+            &self.countries,
+        ] {
+            if !Path::new(path).is_file() {
+                return Err(ShapeProcessorError::MissingFile(path.clone()).into());
+            }
+        }
+        Ok(())
+    }
+}
+
 pub struct ShapeProcessor {
     pub(crate) world_boundary: Rect,
+    pub(crate) paths: PlanetDataPaths,
 }
 impl ShapeProcessor {
-    pub fn extract_planet_data(&self, tile_processor: &mut TileProcessor) {
+    pub fn new(world_boundary: Rect, paths: PlanetDataPaths) -> Self {
+        ShapeProcessor {
+            world_boundary,
+            paths,
+        }
+    }
+
+    /// Fails before touching the tiles if any of the planet data files is missing
+    pub fn extract_planet_data(
+        &self,
+        tile_processor: &mut TileProcessor,
+    ) -> Result<(), Report<ShapeProcessorError>> {
+        self.paths.check()?;
         let thread_pool = ThreadPool::new(2);
-        let (tx, rx) = channel::<(MapGeomObject, MapGeometry)>();
-        Self::extract_countries_and_cities(&thread_pool, tx.clone());
-        Self::extract_land_shapes(&thread_pool, tx.clone(), self.world_boundary);
-        Self::extract_admin_boundaries(&thread_pool, tx, self.world_boundary);
+        let (tx, rx) = channel::<PlanetItem>();
+        Self::extract_countries_and_cities(&thread_pool, tx.clone(), self.paths.clone());
+        Self::extract_land_shapes(&thread_pool, tx.clone(), self.world_boundary, self.paths.land_shapes.clone());
+        Self::extract_admin_boundaries(&thread_pool, tx, self.world_boundary, self.paths.admin_boundaries.clone());
 
         tile_processor.prepare_for_planet_data();
 
         for item in rx {
-            let (map_geom_obj, geom) = item;
+            let (map_geom_obj, geom) = item?;
             tile_processor.add_to_tiles(map_geom_obj, geom);
         }
+        Ok(())
     }
 
     fn extract_land_shapes(
         thread_pool: &ThreadPool,
-        sender: Sender<(MapGeomObject, MapGeometry)>,
+        sender: Sender<PlanetItem>,
         world_boundary: Rect,
+        path: String,
     ) {
         println!("Extract land shapes");
         thread_pool.execute(move || {
-            let shapes = shapefile::read_shapes_as::<_, shapefile::Polygon>(
-                "./land_shapes/land_polygons.shp",
-            )
-            .unwrap();
+            let shapes = match shapefile::read_shapes_as::<_, shapefile::Polygon>(&path)
+                .change_context(ShapeProcessorError::Read(path.clone()))
+            {
+                Ok(shapes) => shapes,
+                Err(err) => {
+                    let _ = sender.send(Err(err));
+                    return;
+                }
+            };
             let mut shapes_amount = 0;
             shapes
                 .into_iter()
@@ -61,13 +136,13 @@ impl ShapeProcessor {
                 .for_each(|item| {
                     shapes_amount += 1;
                     sender
-                        .send((
+                        .send(Ok((
                             MapGeomObject {
                                 id: -1,
                                 kind: MapGeomObjectKind::Nature(Ground),
                             },
                             MapGeometry::Poly(item),
-                        ))
+                        )))
                         .unwrap();
                 });
             print!("\rLand shapes extracted, count: {}\n", shapes_amount);
@@ -76,13 +151,24 @@ impl ShapeProcessor {
 
     fn extract_countries_and_cities(
         thread_pool: &ThreadPool,
-        sender: Sender<(MapGeomObject, MapGeometry)>,
+        sender: Sender<PlanetItem>,
+        paths: PlanetDataPaths,
     ) {
         thread_pool.execute(move || {
             // TODO Find shapefile for that
-            let temp_countries: TempCountries =
-                serde_json::from_reader(File::open("temp_countries.json").unwrap())
-                    .expect("JSON was not well-formatted");
+            let temp_countries = File::open(&paths.countries)
+                .change_context(ShapeProcessorError::Read(paths.countries.clone()))
+                .and_then(|file| {
+                    serde_json::from_reader::<_, TempCountries>(file)
+                        .change_context(ShapeProcessorError::Read(paths.countries.clone()))
+                });
+            let temp_countries = match temp_countries {
+                Ok(temp_countries) => temp_countries,
+                Err(err) => {
+                    let _ = sender.send(Err(err));
+                    return;
+                }
+            };
             temp_countries.ref_country_codes.iter().for_each(|country| {
                 let coord = coord! {x: country.longitude, y: country.latitude};
 
@@ -96,10 +182,9 @@ impl ShapeProcessor {
                         }),
                     }),
                 };
-                sender.send((map_geom_obj, MapGeometry::Coord(coord))).unwrap();
+                sender.send(Ok((map_geom_obj, MapGeometry::Coord(coord)))).unwrap();
             });
-            // can be downloaded from https://www.naturalearthdata.com/http//www.naturalearthdata.com/download/50m/cultural/ne_50m_populated_places.zip
-            match shapefile::read("./ne_50m_populated_places/ne_50m_populated_places.shp") {
+            match shapefile::read(&paths.populated_places) {
                 Ok(cities) => {
                     for (_, record) in &cities {
                         let name = match record.get("NAME").unwrap() {
@@ -130,12 +215,14 @@ impl ShapeProcessor {
                                     }),
                                 }),
                             };
-                            sender.send((map_geom_obj, MapGeometry::Coord(coord))).unwrap();
+                            sender.send(Ok((map_geom_obj, MapGeometry::Coord(coord)))).unwrap();
                         }
                     }
                 }
-                Err(_) => {
-                    println!("Can't read cities shapefile");
+                Err(err) => {
+                    let _ = sender.send(Err(Report::new(err)
+                        .change_context(ShapeProcessorError::Read(paths.populated_places))));
+                    return;
                 }
             }
             println!("Countries and cities extracted");
@@ -144,15 +231,14 @@ impl ShapeProcessor {
 
     fn extract_admin_boundaries(
         thread_pool: &ThreadPool,
-        sender: Sender<(MapGeomObject, MapGeometry)>,
+        sender: Sender<PlanetItem>,
         world_boundary: Rect,
+        path: String,
     ) {
         println!("Extract admin boundaries");
         thread_pool.execute(move || {
             let mut shapes_amount = 0;
-            match shapefile::read_shapes_as::<_, shapefile::Polyline>(
-                "./ne_50m_admin_0_boundary_lines_land/ne_50m_admin_0_boundary_lines_land.shp",
-            ) {
+            match shapefile::read_shapes_as::<_, shapefile::Polyline>(&path) {
                 Ok(shapes) => {
                     shapes
                         .into_iter()
@@ -166,21 +252,70 @@ impl ShapeProcessor {
                         .for_each(|item| {
                             shapes_amount += 1;
                             sender
-                                .send((
+                                .send(Ok((
                                     MapGeomObject {
                                         id: -1,
-                                        kind: AdminLine,
+                                        // admin 0 lines are country borders
+                                        kind: AdminLine(COUNTRY_ADMIN_LEVEL),
                                     },
                                     MapGeometry::Line(item),
-                                ))
+                                )))
                                 .unwrap();
                         });
                 }
-                Err(e) => {
-                    println!("Error extracting admin lines {:?}", e);
+                Err(err) => {
+                    let _ = sender.send(Err(Report::new(err)
+                        .change_context(ShapeProcessorError::Read(path))));
+                    return;
                 }
             }
             print!("\rAdmin lines extracted, count: {}\n", shapes_amount);
         });
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::shape_processor::{PlanetDataPaths, ShapeProcessor, ShapeProcessorError};
+    use crate::simplification::SimplificationConfig;
+    use crate::tile_processor::TileProcessor;
+    use osm::map::get_world_boundary;
+    use osm::tile_writer::tile_writer::TileWriterConfig;
+    use std::fs;
+
+    #[test]
+    fn test_planet_data_paths() {
+        let dir = std::env::temp_dir().join(format!("osm_tool_planet_data_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().to_string();
+        for name in ["land.shp", "places.shp", "admin.shp"] {
+            fs::write(path(name), b"").unwrap();
+        }
+        let paths = PlanetDataPaths {
+            land_shapes: path("land.shp"),
+            populated_places: path("places.shp"),
+            admin_boundaries: path("admin.shp"),
+            countries: path("countries.json"),
+        };
+        let shape_processor = ShapeProcessor::new(get_world_boundary(), paths);
+        let mut tile_processor =
+            TileProcessor::new(TileWriterConfig::default(), SimplificationConfig::default());
+
+        let err = shape_processor
+            .extract_planet_data(&mut tile_processor)
+            .unwrap_err();
+        assert!(matches!(
+            err.current_context(),
+            ShapeProcessorError::MissingFile(missing) if *missing == path("countries.json")
+        ));
+
+        // the files exist now, but aren't valid
+        fs::write(path("countries.json"), b"{}").unwrap();
+        let err = shape_processor
+            .extract_planet_data(&mut tile_processor)
+            .unwrap_err();
+        assert!(matches!(err.current_context(), ShapeProcessorError::Read(..)));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
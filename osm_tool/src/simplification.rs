@@ -0,0 +1,252 @@
+use geo::{CoordsIter, LineString, Simplify, SimplifyVw};
+use osm::map::NatureKind;
+use serde::{Deserialize, Serialize};
+
+/// Named bundles of generalization parameters.
+/// Individual values can be overridden with the `simplification` config section.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SimplificationPreset {
+    Detailed,
+    #[default]
+    Balanced,
+    Lightweight,
+}
+
+impl SimplificationPreset {
+    pub fn config(self) -> SimplificationConfig {
+        let balanced = SimplificationConfig::default();
+        match self {
+            Self::Detailed => SimplificationConfig {
+                forest: ForestLod {
+                    concavity: 1.5,
+                    ..balanced.forest.scaled(0.5)
+                },
+                water: balanced.water.map(|lod| lod.scaled(0.5)),
+                park: balanced.park.map(|lod| lod.scaled(0.5)),
+                building: balanced.building.scaled(0.5),
+                ..balanced.scaled(0.5)
+            },
+            Self::Balanced => balanced,
+            Self::Lightweight => SimplificationConfig {
+                forest: ForestLod {
+                    concavity: 3.0,
+                    ..balanced.forest.scaled(2.0)
+                },
+                water: balanced.water.map(|lod| lod.scaled(2.0)),
+                park: balanced.park.map(|lod| lod.scaled(2.0)),
+                building: balanced.building.scaled(2.0),
+                ..balanced.scaled(2.0)
+            },
+        }
+    }
+}
+
+/// Douglas-Peucker epsilon is a distance, while Visvalingam epsilon is an area,
+/// the same epsilon can't be reused when switching the algorithm
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SimplifyAlgo {
+    #[default]
+    Dp,
+    Vw,
+}
+
+impl SimplifyAlgo {
+    /// Geometries with less than 3 points are returned as is
+    pub fn simplify<G>(self, geom: &G, epsilon: f64) -> G
+    where
+        G: Simplify<f64> + SimplifyVw<f64> + CoordsIter<Scalar = f64> + Clone,
+    {
+        if geom.coords_count() < 3 {
+            return geom.clone();
+        }
+        match self {
+            SimplifyAlgo::Dp => geom.simplify(epsilon),
+            SimplifyAlgo::Vw => geom.simplify_vw(epsilon),
+        }
+    }
+}
+
+/// Simplification epsilons and min areas, all of them are coefficients multiplied by
+/// the squared zoom level unless stated otherwise
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SimplificationConfig {
+    pub road_epsilon: f64,
+    pub road_algo: SimplifyAlgo,
+    /// Multiplied by the zoom level
+    pub nature_line_epsilon: f64,
+    pub nature_epsilon: f64,
+    pub nature_min_area: f64,
+    /// Algorithm of nature lines and polygons except ground
+    pub nature_algo: SimplifyAlgo,
+    pub ground_epsilon: f64,
+    pub ground_algo: SimplifyAlgo,
+    pub ground_min_area: f64,
+    /// Replaces `ground_min_area` starting with zoom level 6
+    pub ground_overview_min_area: f64,
+    pub forest: ForestLod,
+    /// Water polygons aren't merged if not set
+    pub water: Option<ForestLod>,
+    /// Parks aren't merged if not set
+    pub park: Option<ForestLod>,
+    pub building: BuildingLod,
+}
+
+/// Parameters of merged forests and other nature polygons for zoom levels starting with `POLYGON_MERGE_ZOOM_LEVEL`,
+/// coefficients are multiplied by the squared (zoom level - 2)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ForestLod {
+    pub merged_min_area: f64,
+    /// Multiplied by the cubed (zoom level - 2), bigger polygons are never aggregated
+    pub aggregation_max_area: f64,
+    pub aggregation_scale: f64,
+    /// Multiplied by (zoom level - 2) and added to `aggregation_scale`
+    pub aggregation_scale_step: f64,
+    /// Concave hull concavity of aggregated polygons, bigger values give more convex hulls
+    pub concavity: f64,
+    pub min_area: f64,
+    pub epsilon: f64,
+    pub algo: SimplifyAlgo,
+}
+
+/// Building footprints of less detailed zoom levels, coefficients are multiplied by the squared zoom level,
+/// so the most detailed zoom level keeps the footprints as is
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BuildingLod {
+    pub epsilon: f64,
+    pub algo: SimplifyAlgo,
+    /// Smaller footprints are dropped, merged footprints are checked after merging
+    pub min_area: f64,
+    /// Smaller footprints touching each other are merged into one building, buildings aren't merged if not set
+    pub merge_max_area: Option<f64>,
+}
+
+impl Default for SimplificationConfig {
+    fn default() -> Self {
+        SimplificationConfig {
+            road_epsilon: 0.000008,
+            road_algo: SimplifyAlgo::Dp,
+            nature_line_epsilon: 0.001,
+            nature_epsilon: 0.00003,
+            nature_min_area: 0.0000003,
+            nature_algo: SimplifyAlgo::Dp,
+            ground_epsilon: 0.00006,
+            ground_algo: SimplifyAlgo::Dp,
+            ground_min_area: 0.000005,
+            ground_overview_min_area: 0.0001,
+            forest: ForestLod::default(),
+            water: None,
+            park: None,
+            building: BuildingLod::default(),
+        }
+    }
+}
+
+impl SimplificationConfig {
+    fn scaled(self, factor: f64) -> Self {
+        SimplificationConfig {
+            road_epsilon: self.road_epsilon * factor,
+            nature_line_epsilon: self.nature_line_epsilon * factor,
+            nature_epsilon: self.nature_epsilon * factor,
+            nature_min_area: self.nature_min_area * factor,
+            ground_epsilon: self.ground_epsilon * factor,
+            ground_min_area: self.ground_min_area * factor,
+            ground_overview_min_area: self.ground_overview_min_area * factor,
+            forest: self.forest,
+            water: self.water,
+            park: self.park,
+            building: self.building,
+            ..self
+        }
+    }
+
+    pub fn simplify_road(&self, line: &LineString, epsilon_scale: f64) -> LineString {
+        self.road_algo.simplify(line, self.road_epsilon * epsilon_scale)
+    }
+
+    /// Parameters of the nature kind if its polygons are merged starting with `POLYGON_MERGE_ZOOM_LEVEL`
+    pub fn merged_lod(&self, kind: NatureKind) -> Option<ForestLod> {
+        match kind {
+            NatureKind::Forest => Some(self.forest),
+            NatureKind::Water => self.water,
+            NatureKind::Park => self.park,
+            NatureKind::Ground => None,
+        }
+    }
+}
+
+impl Default for ForestLod {
+    fn default() -> Self {
+        ForestLod {
+            merged_min_area: 0.00000005,
+            aggregation_max_area: 0.000005,
+            aggregation_scale: 1.01,
+            aggregation_scale_step: 0.03,
+            concavity: 2.0,
+            min_area: 0.000003,
+            epsilon: 0.0000003,
+            algo: SimplifyAlgo::Vw,
+        }
+    }
+}
+
+impl ForestLod {
+    fn scaled(self, factor: f64) -> Self {
+        ForestLod {
+            merged_min_area: self.merged_min_area * factor,
+            aggregation_max_area: self.aggregation_max_area * factor,
+            min_area: self.min_area * factor,
+            epsilon: self.epsilon * factor,
+            ..self
+        }
+    }
+}
+
+impl Default for BuildingLod {
+    fn default() -> Self {
+        BuildingLod {
+            epsilon: 0.000005,
+            algo: SimplifyAlgo::Dp,
+            min_area: 0.000000002,
+            merge_max_area: None,
+        }
+    }
+}
+
+impl BuildingLod {
+    fn scaled(self, factor: f64) -> Self {
+        BuildingLod {
+            epsilon: self.epsilon * factor,
+            min_area: self.min_area * factor,
+            merge_max_area: self.merge_max_area.map(|area| area * factor),
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::simplification::SimplifyAlgo;
+    use geo::{line_string, LineString};
+
+    #[test]
+    fn test_simplify_algo() {
+        // narrow spike, far from the line but with a tiny area
+        let line: LineString = line_string![
+            (x: 0.0, y: 0.0),
+            (x: 1.0, y: 0.0),
+            (x: 1.01, y: 0.5),
+            (x: 1.02, y: 0.0),
+            (x: 3.0, y: 0.0),
+        ];
+        let dp = SimplifyAlgo::Dp.simplify(&line, 0.3);
+        let vw = SimplifyAlgo::Vw.simplify(&line, 0.3);
+        assert_ne!(dp, vw);
+        assert!(dp.0.iter().any(|coord| coord.y == 0.5));
+        assert!(vw.0.iter().all(|coord| coord.y == 0.0));
+
+        let short: LineString = line_string![(x: 0.0, y: 0.0), (x: 1.0, y: 1.0)];
+        assert_eq!(SimplifyAlgo::Vw.simplify(&short, 100.0), short);
+    }
+}
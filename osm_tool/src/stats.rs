@@ -0,0 +1,206 @@
+use crate::inspect_tile::kind_name;
+use error_stack::{Report, ResultExt};
+use osm::source::tiles_sqlite_store::TilesSQLiteStore;
+use osm::tile_writer::tile_writer::TileFormat;
+use osm::tiles::{TileKey, TileStore};
+use rusqlite::{Connection, OpenFlags};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum StatsError {
+    #[error("Failed to read tiles db")]
+    Read,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ZoomStats {
+    pub zoom_level: i32,
+    pub tile_count: u64,
+    pub total_bytes: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct TilesStats {
+    /// Ordered by zoom level
+    pub zooms: Vec<ZoomStats>,
+    /// Biggest stored blobs in bytes, biggest first
+    pub largest: Vec<(TileKey, u64)>,
+    /// Features by kind in the sampled tiles
+    pub feature_counts: BTreeMap<String, usize>,
+    pub sampled_tiles: usize,
+}
+
+/// Sizes are aggregated by sqlite, features are counted in about `sample_size` tiles
+/// spread over the db. MVT tiles aren't sampled.
+pub fn tiles_stats(
+    db: &Path,
+    largest_count: usize,
+    sample_size: usize,
+) -> Result<TilesStats, Report<StatsError>> {
+    let conn = Connection::open_with_flags(db, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .change_context(StatsError::Read)?;
+    let zooms = conn
+        .prepare("SELECT z, count(*), sum(length(data)) FROM tiles GROUP BY z ORDER BY z")
+        .and_then(|mut stmt| {
+            stmt.query_map((), |row| {
+                Ok(ZoomStats {
+                    zoom_level: row.get(0)?,
+                    tile_count: row.get(1)?,
+                    total_bytes: row.get::<_, Option<u64>>(2)?.unwrap_or(0),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()
+        })
+        .change_context(StatsError::Read)?;
+    let largest = conn
+        .prepare("SELECT x, y, z, length(data) FROM tiles ORDER BY length(data) DESC LIMIT ?1")
+        .and_then(|mut stmt| {
+            stmt.query_map([largest_count as i64], |row| {
+                Ok((
+                    TileKey::new(row.get(0)?, row.get(1)?, row.get(2)?),
+                    row.get::<_, Option<u64>>(3)?.unwrap_or(0),
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()
+        })
+        .change_context(StatsError::Read)?;
+
+    let mut stats = TilesStats {
+        zooms,
+        largest,
+        ..TilesStats::default()
+    };
+    let store = TilesSQLiteStore::new(db);
+    let is_mvt = store
+        .metadata()
+        .unwrap_or_default()
+        .get("format")
+        .and_then(|name| TileFormat::from_name(name))
+        == Some(TileFormat::Mvt);
+    if is_mvt || sample_size == 0 {
+        return Ok(stats);
+    }
+    let total_tiles: u64 = stats.zooms.iter().map(|zoom| zoom.tile_count).sum();
+    let step = (total_tiles / sample_size as u64).max(1);
+    let sample_keys = conn
+        .prepare("SELECT x, y, z FROM tiles WHERE rowid % ?1 = 0 LIMIT ?2")
+        .and_then(|mut stmt| {
+            stmt.query_map((step as i64, sample_size as i64), |row| {
+                Ok(TileKey::new(row.get(0)?, row.get(1)?, row.get(2)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()
+        })
+        .change_context(StatsError::Read)?;
+    let tile_store = TileStore::new(store);
+    for key in &sample_keys {
+        for (obj, _) in tile_store.load_geometries(key) {
+            *stats.feature_counts.entry(kind_name(&obj.kind)).or_default() += 1;
+        }
+    }
+    stats.sampled_tiles = sample_keys.len();
+    Ok(stats)
+}
+
+impl Display for TilesStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "zoom  tiles  total bytes  avg bytes")?;
+        for zoom in &self.zooms {
+            writeln!(
+                f,
+                "{:>4}  {:>5}  {:>11}  {:>9}",
+                zoom.zoom_level,
+                zoom.tile_count,
+                zoom.total_bytes,
+                zoom.total_bytes / zoom.tile_count.max(1)
+            )?;
+        }
+        let tile_count: u64 = self.zooms.iter().map(|zoom| zoom.tile_count).sum();
+        let total_bytes: u64 = self.zooms.iter().map(|zoom| zoom.total_bytes).sum();
+        writeln!(
+            f,
+            "Total: {} tiles, {} bytes, {} bytes on average",
+            tile_count,
+            total_bytes,
+            total_bytes / tile_count.max(1)
+        )?;
+        writeln!(f, "Largest tiles:")?;
+        for (key, size) in &self.largest {
+            writeln!(f, "  {} {} bytes", key.as_string_key(), size)?;
+        }
+        write!(f, "Features in {} sampled tiles:", self.sampled_tiles)?;
+        for (kind, count) in &self.feature_counts {
+            write!(f, "\n  {kind}: {count}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::stats::{tiles_stats, ZoomStats};
+    use osm::map::{MapGeomObject, MapGeomObjectKind, MapGeometry, MapGeometryCollection};
+    use osm::tile_writer::tile_writer::TileWriter;
+    use osm::tiles::codec::TileCodec;
+    use osm::tiles::TileKey;
+    use rusqlite::Connection;
+    use std::fs;
+
+    #[test]
+    fn test_tiles_stats() {
+        let path = std::env::temp_dir().join(format!("osm_tool_stats_{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let conn = Connection::open(&path).unwrap();
+        TileWriter::create_tables(&conn);
+        let tile = |features: usize| {
+            let collection = MapGeometryCollection::<f32>(
+                (0..features)
+                    .map(|id| {
+                        (
+                            MapGeomObject {
+                                id: id as i64,
+                                kind: MapGeomObjectKind::Building(Default::default()),
+                            },
+                            MapGeometry::Coord(geo::coord! {x: id as f32, y: 0.0}),
+                        )
+                    })
+                    .collect(),
+            );
+            TileCodec::Gzip
+                .compress(&bincode::serialize(&collection).unwrap())
+                .unwrap()
+        };
+        let tiles = [(0, 0, 0, 1), (0, 1, 0, 2), (1, 1, 0, 1), (0, 0, 3, 40)];
+        let mut sizes = Vec::new();
+        for (x, y, z, features) in tiles {
+            let data = tile(features);
+            sizes.push(data.len() as u64);
+            conn.execute("INSERT INTO tiles (x, y, z, data) VALUES (?1, ?2, ?3, ?4)", (x, y, z, data))
+                .unwrap();
+        }
+        drop(conn);
+
+        let stats = tiles_stats(&path, 1, 10).unwrap();
+        assert_eq!(
+            stats.zooms,
+            vec![
+                ZoomStats {
+                    zoom_level: 0,
+                    tile_count: 3,
+                    total_bytes: sizes[..3].iter().sum(),
+                },
+                ZoomStats {
+                    zoom_level: 3,
+                    tile_count: 1,
+                    total_bytes: sizes[3],
+                },
+            ]
+        );
+        assert_eq!(stats.largest, vec![(TileKey::new(0, 0, 3), sizes[3])]);
+        assert_eq!(stats.sampled_tiles, 4);
+        assert_eq!(stats.feature_counts["building"], 44);
+        fs::remove_file(path).unwrap();
+    }
+}
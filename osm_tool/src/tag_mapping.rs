@@ -0,0 +1,175 @@
+use crate::pbf_processor::PbfProcessor;
+use osm::map::{
+    BuildingInfo, MapGeomObjectKind, MapPointInfo, MapPointObjectKind, NatureKind, StationKind,
+};
+use serde::{Deserialize, Serialize};
+use std::ops::RangeInclusive;
+
+/// OSM tags extracted per element type and the kinds they are mapped to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TagMapping {
+    #[serde(default)]
+    pub nodes: Vec<TagMappingEntry>,
+    #[serde(default)]
+    pub ways: Vec<TagMappingEntry>,
+    #[serde(default)]
+    pub relations: Vec<TagMappingEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TagMappingEntry {
+    pub key: String,
+    /// Any value of the key matches if not set
+    #[serde(default)]
+    pub value: Option<String>,
+    /// Built-in [MapGeomObjectKind::from_tag] classification is used if not set
+    #[serde(default)]
+    pub target: Option<TagTarget>,
+    /// Zoom range of the extracted elements, not supported for highway and railway lines
+    #[serde(default)]
+    pub min_zoom: Option<u32>,
+    #[serde(default)]
+    pub max_zoom: Option<u32>,
+}
+
+/// Kind a tag is mapped to. Highway and railway lines need way info
+/// and are always classified by the built-in rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TagTarget {
+    TrafficLight,
+    Toilet,
+    Parking,
+    TrainStation,
+    /// POI without a dedicated kind, labeled with its name
+    Poi,
+    Ground,
+    Park,
+    Forest,
+    Water,
+    Building,
+}
+
+impl TagTarget {
+    pub fn is_poi(&self) -> bool {
+        matches!(
+            self,
+            TagTarget::TrafficLight
+                | TagTarget::Toilet
+                | TagTarget::Parking
+                | TagTarget::TrainStation
+                | TagTarget::Poi
+        )
+    }
+
+    pub fn kind(&self, name: Option<String>, building: Option<BuildingInfo>, station: StationKind) -> MapGeomObjectKind {
+        let poi = |kind, text: Option<String>| {
+            MapGeomObjectKind::Poi(MapPointInfo {
+                text: text.unwrap_or_default(),
+                kind,
+            })
+        };
+        match self {
+            TagTarget::TrafficLight => poi(MapPointObjectKind::TrafficLight, None),
+            TagTarget::Toilet => poi(MapPointObjectKind::Toilet, None),
+            TagTarget::Parking => poi(MapPointObjectKind::Parking, None),
+            TagTarget::TrainStation => poi(MapPointObjectKind::TrainStation(station), name),
+            TagTarget::Poi => poi(MapPointObjectKind::Generic, name),
+            TagTarget::Ground => MapGeomObjectKind::Nature(NatureKind::Ground),
+            TagTarget::Park => MapGeomObjectKind::Nature(NatureKind::Park),
+            TagTarget::Forest => MapGeomObjectKind::Nature(NatureKind::Forest),
+            TagTarget::Water => MapGeomObjectKind::Nature(NatureKind::Water),
+            TagTarget::Building => MapGeomObjectKind::Building(building.unwrap_or_default()),
+        }
+    }
+}
+
+impl TagMappingEntry {
+    fn from_tag((key, value): &(&str, Option<&str>)) -> Self {
+        TagMappingEntry {
+            key: key.to_string(),
+            value: value.map(str::to_string),
+            target: None,
+            min_zoom: None,
+            max_zoom: None,
+        }
+    }
+
+    pub fn zoom_range(&self) -> RangeInclusive<u32> {
+        self.min_zoom.unwrap_or(0)..=self.max_zoom.unwrap_or(u32::MAX)
+    }
+
+    /// Highway and railway ways are roads in the way store, they are added to all zoom levels
+    fn is_road(&self) -> bool {
+        self.target.is_none() && matches!(self.key.as_str(), "highway" | "railway")
+    }
+
+    fn matches(&self, key: &str, value: &str) -> bool {
+        self.key == key && self.value.as_ref().is_none_or(|v| v == value)
+    }
+}
+
+impl Default for TagMapping {
+    fn default() -> Self {
+        let entries = |tags: &[(&str, Option<&str>)]| {
+            tags.iter().map(TagMappingEntry::from_tag).collect()
+        };
+        TagMapping {
+            nodes: entries(PbfProcessor::POI_TAG),
+            ways: entries(PbfProcessor::WAYS_TAG),
+            relations: entries(PbfProcessor::RELATION_TAG),
+        }
+    }
+}
+
+impl TagMapping {
+    /// Tags in the form [crate::filter::TagFilter] is built from
+    pub fn filter_tags(entries: &[TagMappingEntry]) -> Vec<(&str, Option<&str>)> {
+        entries
+            .iter()
+            .map(|entry| (entry.key.as_str(), entry.value.as_deref()))
+            .collect()
+    }
+
+    /// Entry matching the tag, an entry with exact value has priority over a key-only one
+    pub fn find<'a>(entries: &'a [TagMappingEntry], key: &str, value: &str) -> Option<&'a TagMappingEntry> {
+        entries
+            .iter()
+            .filter(|entry| entry.matches(key, value))
+            .min_by_key(|entry| entry.value.is_none())
+    }
+
+    /// Returns the key of the first entry with a target unsupported for its element type
+    /// or an invalid zoom range, entries without a target must be known to the built-in classification
+    pub fn invalid_entry(&self) -> Option<&str> {
+        let defaults = TagMapping::default();
+        let is_valid = |entry: &TagMappingEntry, defaults: &[TagMappingEntry], poi: bool| {
+            let zoom_range = entry.zoom_range();
+            if zoom_range.start() > zoom_range.end() {
+                return false;
+            }
+            if !poi && entry.is_road() && (entry.min_zoom.is_some() || entry.max_zoom.is_some()) {
+                return false;
+            }
+            match entry.target {
+                Some(target) => target.is_poi() == poi,
+                None => defaults
+                    .iter()
+                    .any(|default| default.key == entry.key && default.value == entry.value),
+            }
+        };
+        let nodes = self
+            .nodes
+            .iter()
+            .filter(|entry| !is_valid(entry, &defaults.nodes, true));
+        let ways = self
+            .ways
+            .iter()
+            .filter(|entry| !is_valid(entry, &defaults.ways, false));
+        let relations = self
+            .relations
+            .iter()
+            .filter(|entry| !is_valid(entry, &defaults.relations, false));
+        nodes.chain(ways).chain(relations).map(|entry| entry.key.as_str()).next()
+    }
+}
@@ -0,0 +1,247 @@
+use error_stack::{Report, ResultExt};
+use flate2::read::GzDecoder;
+use geo::{coord, Rect};
+use osm::source::tiles_sqlite_store::TilesSQLiteStore;
+use osm::tile_writer::mvt;
+use osm::tiles::codec::GEOMETRY_STORE_DEDUP;
+use osm::tiles::{world_bounds_metadata, TileGrid, TileKey, TileScheme};
+use prost::Message;
+use rusqlite::{Connection, OpenFlags};
+use std::io::Read;
+use std::path::Path;
+use thiserror::Error;
+
+/// Value of `scheme` in the exported metadata. Tiles are in the internal grid splitting
+/// the world boundary linearly in lat/lon, they aren't Web Mercator tiles
+pub const CONTAINER_SCHEME: &str = "shashlik-linear";
+
+#[derive(Debug, Error)]
+pub enum ContainerExportError {
+    #[error("Failed to read tiles db")]
+    Read,
+    #[error("Failed to write tile container")]
+    Write,
+}
+
+/// Internal zoom levels are inverted and zoom 0 is the most detailed one, while container zoom
+/// is the power of two of tiles per axis. Internal zoom levels collapsing to a single tile
+/// share container zoom 0, only the most detailed of them is exported.
+fn container_zoom(grid: &TileGrid, zoom_level: i32) -> u32 {
+    grid.tiles_count_for_zoom(zoom_level).ilog2()
+}
+
+fn read_metadata_value(conn: &Connection, name: &str) -> Option<String> {
+    conn.query_row("SELECT value FROM metadata WHERE name = ?1", [name], |row| {
+        row.get::<_, String>(0)
+    })
+    .ok()
+}
+
+fn tile_format(data: &[u8]) -> &'static str {
+    let mut decompressed_data = Vec::new();
+    let is_mvt = GzDecoder::new(data)
+        .read_to_end(&mut decompressed_data)
+        .is_ok()
+        && mvt::Tile::decode(decompressed_data.as_slice()).is_ok_and(|tile| {
+            !tile.layers.is_empty() && tile.layers.iter().all(|layer| layer.version == 2)
+        });
+    if is_mvt {
+        "pbf"
+    } else {
+        "application/x-shashlik-tile"
+    }
+}
+
+/// Copies tiles db rows to a non-standard container borrowing the MBTiles 1.3 table layout.
+/// It isn't an MBTiles file: the grid splits the world boundary linearly in lat/lon instead of
+/// Web Mercator and bincode tiles aren't a registered format, so MBTiles viewers and servers
+/// would misplace or reject the tiles. The grid is marked with [CONTAINER_SCHEME] and the
+/// `world_bounds` it's built for. Rows of the internal tile grid start from the south edge of
+/// the world boundary, so they are in TMS row order once converted from the db [TileScheme].
+pub fn export_tile_container(input: &Path, output: &Path) -> Result<(), Report<ContainerExportError>> {
+    let input_conn = Connection::open_with_flags(input, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .change_context(ContainerExportError::Read)?;
+    // dbs without metadata are always in the internal tiles grid
+    let scheme = read_metadata_value(&input_conn, "scheme")
+        .and_then(|name| TileScheme::from_name(&name))
+        .unwrap_or_default();
+    let mut grid = TileGrid::default();
+    if let Some(tiles_count) =
+        read_metadata_value(&input_conn, "tiles_count").and_then(|value| value.parse().ok())
+    {
+        grid.tiles_count = tiles_count;
+    }
+    // tiles referencing deduplicated geometries are assembled by the store
+    let dedup_store = (read_metadata_value(&input_conn, "geometry_store").as_deref()
+        == Some(GEOMETRY_STORE_DEDUP))
+    .then(|| TilesSQLiteStore::new(input));
+    let mut output_conn = Connection::open(output).change_context(ContainerExportError::Write)?;
+    output_conn
+        .execute_batch(
+            "DROP TABLE IF EXISTS tiles;
+             DROP TABLE IF EXISTS metadata;
+             CREATE TABLE tiles (
+                 zoom_level INTEGER NOT NULL,
+                 tile_column INTEGER NOT NULL,
+                 tile_row INTEGER NOT NULL,
+                 tile_data BLOB
+             );
+             CREATE UNIQUE INDEX tile_index ON tiles (zoom_level, tile_column, tile_row);
+             CREATE TABLE metadata (name TEXT, value TEXT);",
+        )
+        .change_context(ContainerExportError::Write)?;
+
+    let tx = output_conn
+        .transaction()
+        .change_context(ContainerExportError::Write)?;
+    let mut bounds: Option<Rect> = None;
+    let mut zoom_range: Option<(u32, u32)> = None;
+    let mut format = None;
+    {
+        let mut select_stmt = input_conn
+            .prepare("SELECT x, y, z, data FROM tiles ORDER BY z, x, y")
+            .change_context(ContainerExportError::Read)?;
+        let mut insert_stmt = tx
+            .prepare(
+                "INSERT OR IGNORE INTO tiles (zoom_level, tile_column, tile_row, tile_data)
+                 VALUES (?1, ?2, ?3, ?4)",
+            )
+            .change_context(ContainerExportError::Write)?;
+        let rows = select_stmt
+            .query_map((), |row| {
+                Ok((
+                    TileKey::new(row.get(0)?, row.get(1)?, row.get(2)?),
+                    row.get::<_, Vec<u8>>(3)?,
+                ))
+            })
+            .change_context(ContainerExportError::Read)?;
+        for row in rows {
+            let (key, mut data) = row.change_context(ContainerExportError::Read)?;
+            if let Some(store) = &dedup_store {
+                data = store
+                    .get_tile(key.tile_x, key.tile_y, key.zoom_level)
+                    .change_context(ContainerExportError::Read)?;
+            }
+            let key = grid.flip_y(&key, scheme);
+            let zoom = container_zoom(&grid, key.zoom_level);
+            let inserted = insert_stmt
+                .execute((zoom, key.tile_x, key.tile_y, &data))
+                .change_context(ContainerExportError::Write)?;
+            if inserted == 0 {
+                continue;
+            }
+
+            let tile_rect = grid.tile_boundary(&key, 1.0);
+            bounds = Some(bounds.map_or(tile_rect, |bounds| {
+                Rect::new(
+                    coord! {x: bounds.min().x.min(tile_rect.min().x), y: bounds.min().y.min(tile_rect.min().y)},
+                    coord! {x: bounds.max().x.max(tile_rect.max().x), y: bounds.max().y.max(tile_rect.max().y)},
+                )
+            }));
+            zoom_range = Some(zoom_range.map_or((zoom, zoom), |(min, max)| {
+                (min.min(zoom), max.max(zoom))
+            }));
+            format.get_or_insert_with(|| tile_format(&data));
+        }
+    }
+
+    let name = output
+        .file_stem()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut metadata = vec![
+        ("name", name),
+        ("format", format.unwrap_or("pbf").to_string()),
+        ("scheme", CONTAINER_SCHEME.to_string()),
+        ("world_bounds", world_bounds_metadata()),
+        (
+            "description",
+            "Non-standard container of a linear lat/lon grid of world_bounds, not MBTiles".to_string(),
+        ),
+    ];
+    if let Some(bounds) = bounds {
+        metadata.push((
+            "bounds",
+            format!(
+                "{},{},{},{}",
+                bounds.min().x,
+                bounds.min().y,
+                bounds.max().x,
+                bounds.max().y
+            ),
+        ));
+    }
+    if let Some((min_zoom, max_zoom)) = zoom_range {
+        metadata.push(("minzoom", min_zoom.to_string()));
+        metadata.push(("maxzoom", max_zoom.to_string()));
+    }
+    for (name, value) in metadata {
+        tx.execute("INSERT INTO metadata (name, value) VALUES (?1, ?2)", (name, value))
+            .change_context(ContainerExportError::Write)?;
+    }
+    tx.commit().change_context(ContainerExportError::Write)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tile_container::{export_tile_container, CONTAINER_SCHEME};
+    use osm::tiles::{world_bounds_metadata, TileKey};
+    use rusqlite::Connection;
+    use std::collections::HashMap;
+    use std::fs;
+
+    #[test]
+    fn test_export_tile_container() {
+        let dir = std::env::temp_dir().join(format!("osm_tool_tile_container_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("tiles.db");
+        let output = dir.join("world.shtiles");
+        let _ = fs::remove_file(&input);
+
+        let conn = Connection::open(&input).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE tiles (x INTEGER NOT NULL, y INTEGER NOT NULL, z INTEGER NOT NULL, data BLOB);
+             INSERT INTO tiles VALUES (3, 5, 10, x'00');
+             INSERT INTO tiles VALUES (3, 6, 10, x'01');
+             INSERT INTO tiles VALUES (0, 0, 16, x'02');
+             INSERT INTO tiles VALUES (0, 0, 17, x'03');",
+        )
+        .unwrap();
+        drop(conn);
+
+        export_tile_container(&input, &output).unwrap();
+
+        let conn = Connection::open(&output).unwrap();
+        let metadata: HashMap<String, String> = conn
+            .prepare("SELECT name, value FROM metadata")
+            .unwrap()
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect();
+        assert_eq!(metadata["name"], "world");
+        assert_eq!(metadata["minzoom"], "0");
+        assert_eq!(metadata["maxzoom"], "5");
+        assert_eq!(metadata["scheme"], CONTAINER_SCHEME);
+        assert_eq!(metadata["world_bounds"], world_bounds_metadata());
+        let world = TileKey::new(0, 0, 16).calc_tile_boundary(1.0);
+        assert_eq!(
+            metadata["bounds"],
+            format!("{},{},{},{}", world.min().x, world.min().y, world.max().x, world.max().y)
+        );
+
+        let tiles: Vec<(u32, i32, i32, Vec<u8>)> = conn
+            .prepare("SELECT zoom_level, tile_column, tile_row, tile_data FROM tiles ORDER BY zoom_level, tile_row")
+            .unwrap()
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect();
+        assert_eq!(
+            tiles,
+            vec![(0, 0, 0, vec![2]), (5, 3, 5, vec![0]), (5, 3, 6, vec![1])]
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
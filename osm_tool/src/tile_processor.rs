@@ -1,18 +1,222 @@
+use crate::building_store::BuildingStore;
+use crate::simplification::{BuildingLod, SimplificationConfig};
 use crate::POLYGON_MERGE_ZOOM_LEVEL;
-use geo::{Area, Polygon, Simplify};
-use osm::map::MapGeomObjectKind::AdminLine;
+use geo::{Area, LineString, MultiLineString, MultiPolygon, Polygon};
 use osm::map::NatureKind::Ground;
-use osm::map::{MapGeomObject, MapGeomObjectKind, MapGeometry, MapPointObjectKind, NatureKind, ZOOM_LEVELS};
-use osm::tile_writer::tile_writer::TileWriter;
+use osm::map::{
+    MapGeomObject, MapGeomObjectKind, MapGeometry, MapPointObjectKind, PopAreaInfo, StationKind,
+    COUNTRY_ADMIN_LEVEL,
+};
+use osm::tile_writer::tile_writer::{TileWriter, TileWriterConfig};
+use osm::tiles::TileGrid;
+use serde::{Deserialize, Serialize};
+use std::ops::RangeInclusive;
+use std::path::Path;
 
 pub struct TileProcessor {
     pub tile_writer: TileWriter,
+    pub simplification: SimplificationConfig,
+    pub grid: TileGrid,
+    pub population_thresholds: Vec<PopulationThreshold>,
+    pub zoom_policy: ZoomPolicy,
+    /// Small buildings waiting to be merged, see [TileProcessor::add_merged_buildings]
+    building_store: BuildingStore,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ZoomRange {
+    pub min_zoom: u32,
+    pub max_zoom: u32,
+}
+
+impl ZoomRange {
+    pub const fn new(min_zoom: u32, max_zoom: u32) -> Self {
+        ZoomRange { min_zoom, max_zoom }
+    }
+
+    pub fn range(&self) -> RangeInclusive<u32> {
+        self.min_zoom..=self.max_zoom
+    }
+}
+
+/// Zoom levels every feature kind is written to, zoom levels are inverted: 0 is the most detailed.
+/// Nature polygons merged by PolygonStore are still limited to zoom levels below `POLYGON_MERGE_ZOOM_LEVEL`,
+/// admin lines are limited by their admin level.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ZoomPolicy {
+    #[serde(rename = "buildings")]
+    pub buildings: ZoomRange,
+    #[serde(rename = "traffic_light")]
+    pub traffic_light: ZoomRange,
+    /// Train stations
+    #[serde(rename = "train_station")]
+    pub train_station: ZoomRange,
+    /// Subway stations, they are dense in city centers
+    #[serde(rename = "subway")]
+    pub subway: ZoomRange,
+    /// Tram, light rail and other non-train stations
+    #[serde(rename = "station")]
+    pub station: ZoomRange,
+    /// All other POIs
+    #[serde(rename = "poi")]
+    pub poi: ZoomRange,
+    #[serde(rename = "city")]
+    pub city: ZoomRange,
+    #[serde(rename = "country")]
+    pub country: ZoomRange,
+    #[serde(rename = "nature")]
+    pub nature: ZoomRange,
+}
+
+impl Default for ZoomPolicy {
+    fn default() -> Self {
+        ZoomPolicy {
+            buildings: ZoomRange::new(0, 1),
+            traffic_light: ZoomRange::new(0, 0),
+            train_station: ZoomRange::new(0, 4),
+            subway: ZoomRange::new(0, 1),
+            station: ZoomRange::new(0, 2),
+            poi: ZoomRange::new(0, 1),
+            city: ZoomRange::new(5, 12),
+            country: ZoomRange::new(13, u32::MAX),
+            nature: ZoomRange::new(0, u32::MAX),
+        }
+    }
+}
+
+impl ZoomPolicy {
+    /// Zoom levels of the kind, kinds without a policy are not limited
+    pub fn zoom_range(&self, kind: &MapGeomObjectKind) -> RangeInclusive<u32> {
+        let range = match kind {
+            MapGeomObjectKind::Building(..) => self.buildings,
+            MapGeomObjectKind::Nature(..) => self.nature,
+            MapGeomObjectKind::Poi(info) => match info.kind {
+                MapPointObjectKind::TrafficLight => self.traffic_light,
+                MapPointObjectKind::TrainStation(StationKind::Train) => self.train_station,
+                MapPointObjectKind::TrainStation(StationKind::Subway) => self.subway,
+                MapPointObjectKind::TrainStation(_) => self.station,
+                MapPointObjectKind::PopArea(info) => match self.pop_area_range(info.level) {
+                    Some(range) => range,
+                    None => return 0..=u32::MAX,
+                },
+                _ => self.poi,
+            },
+            _ => return 0..=u32::MAX,
+        };
+        range.range()
+    }
+
+    /// Populated places of other levels are not shown
+    pub fn pop_area_range(&self, level: i32) -> Option<ZoomRange> {
+        match level {
+            0 => Some(self.city),
+            1 => Some(self.country),
+            _ => None,
+        }
+    }
+}
+
+/// Populated places smaller than `min_population` are hidden from `zoom` upwards,
+/// until the next threshold. Places with unknown population count as 0.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PopulationThreshold {
+    pub zoom: u32,
+    pub min_population: u32,
 }
 
 impl TileProcessor {
-    pub fn new() -> Self {
+    pub fn new(
+        tile_writer_config: TileWriterConfig,
+        simplification: SimplificationConfig,
+    ) -> Self {
         TileProcessor {
-            tile_writer: TileWriter::default(),
+            grid: tile_writer_config.grid,
+            tile_writer: TileWriter::with_config(tile_writer_config),
+            simplification,
+            population_thresholds: Vec::new(),
+            zoom_policy: ZoomPolicy::default(),
+            building_store: BuildingStore::new(),
+        }
+    }
+
+    pub fn with_zoom_policy(mut self, zoom_policy: ZoomPolicy) -> Self {
+        self.zoom_policy = zoom_policy;
+        self
+    }
+
+    pub fn with_population_thresholds(mut self, population_thresholds: Vec<PopulationThreshold>) -> Self {
+        self.population_thresholds = population_thresholds;
+        self
+    }
+
+    /// Country borders are kept at every zoom level, state and lower level borders
+    /// only at more detailed ones
+    fn admin_line_max_zoom(level: u8) -> u32 {
+        match level {
+            0..=COUNTRY_ADMIN_LEVEL => u32::MAX,
+            3..=4 => 8,
+            _ => 4,
+        }
+    }
+
+    fn simplify_nature_line(&self, line: &LineString, zoom_level: u32) -> LineString {
+        self.simplification
+            .nature_algo
+            .simplify(line, self.simplification.nature_line_epsilon * zoom_level as f64)
+    }
+
+    /// Polygon simplified for the zoom level, `None` if it's too small to be visible
+    fn simplify_nature_poly(&self, kind: &MapGeomObjectKind, poly: &Polygon, zoom_level: u32) -> Option<Polygon> {
+        let zlf = zoom_level as f64;
+        let (epsilon, algo) = if *kind == MapGeomObjectKind::Nature(Ground) {
+            (self.simplification.ground_epsilon, self.simplification.ground_algo)
+        } else {
+            (self.simplification.nature_epsilon, self.simplification.nature_algo)
+        };
+        let area = if *kind == MapGeomObjectKind::Nature(Ground) {
+            if zoom_level >= 6 {
+                self.simplification.ground_overview_min_area
+            } else {
+                self.simplification.ground_min_area
+            }
+        } else {
+            self.simplification.nature_min_area
+        };
+
+        let simplified_exterior = algo.simplify(poly.exterior(), epsilon * zlf * zlf);
+        let interiors = if zoom_level < 2 {
+            poly.interiors().iter().map(|line| algo.simplify(line, epsilon * zlf * zlf)).collect()
+        } else {
+            Vec::new()
+        };
+        let np = Polygon::new(simplified_exterior, interiors);
+        // TODO Consider to calculate area for exterior only
+        if np.unsigned_area() < area * zlf * zlf {
+            None
+        } else {
+            Some(np)
+        }
+    }
+
+    fn is_pop_area_visible(&self, info: &PopAreaInfo, zoom_level: u32) -> bool {
+        let Some(range) = self.zoom_policy.pop_area_range(info.level) else {
+            return false;
+        };
+        if !range.range().contains(&zoom_level) {
+            return false;
+        }
+        match info.level {
+            0 => {
+                let min_population = self
+                    .population_thresholds
+                    .iter()
+                    .filter(|threshold| threshold.zoom <= zoom_level)
+                    .max_by_key(|threshold| threshold.zoom)
+                    .map_or(0, |threshold| threshold.min_population);
+                info.population >= min_population
+            }
+            _ => true,
         }
     }
 
@@ -20,91 +224,157 @@ impl TileProcessor {
         &mut self,
         map_geom_object: MapGeomObject,
         map_geometry: MapGeometry,
+    ) {
+        self.add_to_tiles_in_zooms(map_geom_object, map_geometry, 0..=u32::MAX);
+    }
+
+    /// Same as [TileProcessor::add_to_tiles], the zoom levels of the kind are limited to `zooms`
+    pub fn add_to_tiles_in_zooms(
+        &mut self,
+        map_geom_object: MapGeomObject,
+        map_geometry: MapGeometry,
+        zooms: RangeInclusive<u32>,
     ) {
         match map_geom_object.kind {
-            MapGeomObjectKind::Poi(..) => self.add_to_poi(map_geom_object, map_geometry),
-            MapGeomObjectKind::Nature(..) => self.add_to_nature(map_geom_object, map_geometry),
-            MapGeomObjectKind::AdminLine => self.add_to_nature(map_geom_object, map_geometry),
-            MapGeomObjectKind::Building(..) => self.add_to_buildings(map_geom_object, map_geometry),
+            MapGeomObjectKind::Poi(..) => self.add_to_poi(map_geom_object, map_geometry, zooms),
+            MapGeomObjectKind::Nature(..) => self.add_to_nature(map_geom_object, map_geometry, zooms),
+            MapGeomObjectKind::AdminLine(..) => self.add_to_nature(map_geom_object, map_geometry, zooms),
+            MapGeomObjectKind::Building(..) => self.add_to_buildings(map_geom_object, map_geometry, zooms),
             _ => {}
         }
     }
 
-    fn add_to_buildings(&mut self, map_geom_obj: MapGeomObject, geom: MapGeometry) {
-        for zoom_level in 0..=1 {
-            self.tile_writer.add_to_tiles(zoom_level, map_geom_obj.clone(), geom.clone(), true);
+    fn add_to_buildings(&mut self, map_geom_obj: MapGeomObject, geom: MapGeometry, zooms: RangeInclusive<u32>) {
+        let policy_zooms = self.zoom_policy.zoom_range(&map_geom_obj.kind);
+        for zoom_level in self
+            .grid
+            .zoom_levels()
+            .filter(|zoom_level| zooms.contains(zoom_level) && policy_zooms.contains(zoom_level))
+        {
+            let (MapGeometry::Poly(poly), MapGeomObjectKind::Building(info)) = (&geom, &map_geom_obj.kind) else {
+                self.tile_writer.add_to_tiles(zoom_level, map_geom_obj.clone(), geom.clone(), true);
+                continue;
+            };
+            let lod = self.simplification.building;
+            let zlf = zoom_level as f64;
+            if lod
+                .merge_max_area
+                .is_some_and(|max_area| poly.unsigned_area() < max_area * zlf * zlf)
+            {
+                self.building_store
+                    .add_building(zoom_level, map_geom_obj.id, poly.clone(), info.clone());
+                continue;
+            }
+            if let Some(poly) = Self::simplify_building(poly, zoom_level, lod) {
+                self.tile_writer.add_to_tiles(zoom_level, map_geom_obj.clone(), MapGeometry::Poly(poly), true);
+            }
+        }
+    }
+
+    /// `None` if the building is too small for the zoom level
+    fn simplify_building(poly: &Polygon, zoom_level: u32, lod: BuildingLod) -> Option<Polygon> {
+        let zlf = zoom_level as f64;
+        let poly = lod.algo.simplify(poly, lod.epsilon * zlf * zlf);
+        (poly.unsigned_area() >= lod.min_area * zlf * zlf).then_some(poly)
+    }
+
+    /// Adds the small buildings collected so far merged with the touching ones,
+    /// must be called before the tiles are saved
+    pub fn add_merged_buildings(&mut self) {
+        let lod = self.simplification.building;
+        for (zoom_level, id, poly, info) in self.building_store.drain_merged() {
+            if let Some(poly) = Self::simplify_building(&poly, zoom_level, lod) {
+                self.tile_writer.add_to_tiles(
+                    zoom_level,
+                    MapGeomObject {
+                        id,
+                        kind: MapGeomObjectKind::Building(info),
+                    },
+                    MapGeometry::Poly(poly),
+                    true,
+                );
+            }
         }
     }
 
     // TODO Refactor to separate planet data from tiles data
-    fn add_to_nature(&mut self, map_geom_obj: MapGeomObject, geom: MapGeometry) {
-        let can_create_new_tiles = map_geom_obj.kind != AdminLine
+    fn add_to_nature(&mut self, map_geom_obj: MapGeomObject, geom: MapGeometry, zooms: RangeInclusive<u32>) {
+        let can_create_new_tiles = !matches!(map_geom_obj.kind, MapGeomObjectKind::AdminLine(..))
             && map_geom_obj.kind != MapGeomObjectKind::Nature(Ground);
 
         // it's faster to simplify geometry that already simplified for previous zoom level
+        let policy_zooms = self.zoom_policy.zoom_range(&map_geom_obj.kind);
         let mut temp_geom = geom;
-        for zoom_level in 0..ZOOM_LEVELS {
+        for zoom_level in self.grid.zoom_levels() {
+            if zoom_level > *policy_zooms.end() {
+                break;
+            }
+            if let MapGeomObjectKind::AdminLine(level) = map_geom_obj.kind {
+                if zoom_level > Self::admin_line_max_zoom(level) {
+                    break;
+                }
+            }
+            // merged polygons are added by PolygonStore
             if zoom_level >= POLYGON_MERGE_ZOOM_LEVEL
-                && map_geom_obj.kind == MapGeomObjectKind::Nature(NatureKind::Forest)
+                && matches!(map_geom_obj.kind, MapGeomObjectKind::Nature(kind)
+                    if self.simplification.merged_lod(kind).is_some())
             {
                 break;
             }
             
-            let zlf = zoom_level as f64;
             if let Some(geom) = match &temp_geom {
                 MapGeometry::Line(ref line) => {
-                    Some(MapGeometry::Line(line.simplify(0.001 * zlf)))
+                    Some(MapGeometry::Line(self.simplify_nature_line(line, zoom_level)))
                 }
+                MapGeometry::MultiLine(ref multi_line) => Some(MapGeometry::MultiLine(MultiLineString::new(
+                    multi_line
+                        .iter()
+                        .map(|line| self.simplify_nature_line(line, zoom_level))
+                        .collect(),
+                ))),
                 MapGeometry::Poly(ref poly) => {
-                    let epsilon =
-                        if map_geom_obj.kind == MapGeomObjectKind::Nature(Ground) {
-                            0.00006
-                        } else {
-                            0.00003
-                        };
-                    let area = if map_geom_obj.kind == MapGeomObjectKind::Nature(Ground)
-                    {
-                        if zoom_level >= 6 { 0.0001 } else { 0.000005 }
-                    } else {
-                        0.0000003
-                    };
-
-                    let simplified_exterior = poly.exterior().simplify(epsilon * zlf * zlf);
-                    let interiors = if zoom_level < 2 {
-                        poly.interiors().into_iter().map(|line| line.simplify(epsilon * zlf * zlf)).collect()
-                    } else {
-                        Vec::new()
-                    };
-                    let np = Polygon::new(simplified_exterior, interiors);
-                    // TODO Consider to calculate area for exterior only
-                    if np.unsigned_area() < area * zlf * zlf {
+                    let Some(np) = self.simplify_nature_poly(&map_geom_obj.kind, poly, zoom_level) else {
                         // return immediately since all other zoom levels won't have data
                         return;
-                    } else {
-                        Some(MapGeometry::Poly(np))
+                    };
+                    Some(MapGeometry::Poly(np))
+                }
+                MapGeometry::MultiPoly(ref multi_poly) => {
+                    let polygons = multi_poly
+                        .iter()
+                        .filter_map(|poly| self.simplify_nature_poly(&map_geom_obj.kind, poly, zoom_level))
+                        .collect::<Vec<_>>();
+                    if polygons.is_empty() {
+                        return;
                     }
+                    Some(MapGeometry::MultiPoly(MultiPolygon::new(polygons)))
                 }
                 _ => None,
             } {
-                self.tile_writer.add_to_tiles(
-                    zoom_level,
-                    map_geom_obj.clone(),
-                    geom.clone(),
-                    can_create_new_tiles,
-                );
+                if zooms.contains(&zoom_level) && policy_zooms.contains(&zoom_level) {
+                    self.tile_writer.add_to_tiles(
+                        zoom_level,
+                        map_geom_obj.clone(),
+                        geom.clone(),
+                        can_create_new_tiles,
+                    );
+                }
                 temp_geom = geom;
             };
         }
     }
     
-    fn add_to_poi(&mut self, map_geom_obj: MapGeomObject, geom: MapGeometry) {
-        for zoom_level in 0..ZOOM_LEVELS {
+    fn add_to_poi(&mut self, map_geom_obj: MapGeomObject, geom: MapGeometry, zooms: RangeInclusive<u32>) {
+        let policy_zooms = self.zoom_policy.zoom_range(&map_geom_obj.kind);
+        for zoom_level in self
+            .grid
+            .zoom_levels()
+            .filter(|zoom_level| zooms.contains(zoom_level) && policy_zooms.contains(zoom_level))
+        {
             match map_geom_obj.kind {
                 MapGeomObjectKind::Poi(ref obj) => match obj.kind {
                     MapPointObjectKind::PopArea(info) => {
-                        if info.level == 0 && zoom_level >= 5 && zoom_level <= 12
-                            || info.level == 1 && zoom_level > 12
-                        {
+                        if self.is_pop_area_visible(&info, zoom_level) {
                             self.tile_writer.add_to_tiles(
                                 zoom_level,
                                 map_geom_obj.clone(),
@@ -114,21 +384,12 @@ impl TileProcessor {
                         }
                     }
                     _ => {
-                        let condition = match obj.kind {
-                            MapPointObjectKind::TrafficLight => zoom_level == 0,
-                            MapPointObjectKind::TrainStation(is_train) => {
-                                zoom_level <= if is_train { 4 } else { 2 }
-                            },
-                            _ => zoom_level <= 1
-                        };
-                        if condition {
-                            self.tile_writer.add_to_tiles(
-                                zoom_level,
-                                map_geom_obj.clone(),
-                                geom.clone(),
-                                true,
-                            );
-                        }
+                        self.tile_writer.add_to_tiles(
+                            zoom_level,
+                            map_geom_obj.clone(),
+                            geom.clone(),
+                            true,
+                        );
                     }
                 },
                 _ => {}
@@ -143,4 +404,234 @@ impl TileProcessor {
     pub fn save_to_disk(&mut self) {
         self.tile_writer.save_to_file();
     }
+
+    /// Adds tiles collected so far to the tiles db, see [TileWriter::write_collected_tiles]
+    pub fn write_collected_tiles(&mut self) {
+        self.tile_writer.write_collected_tiles();
+    }
+
+    /// Adds tiles collected so far to the tiles db and records the area as completed,
+    /// see [TileWriter::save_checkpoint]
+    pub fn save_checkpoint(&mut self, area: &str) {
+        self.tile_writer.save_checkpoint(area);
+    }
+
+    /// Saves tiles collected so far to `path` and starts collecting tiles from scratch
+    pub fn save_to_path(&mut self, path: &Path) {
+        self.tile_writer.save_to_path(path);
+        self.tile_writer = TileWriter::with_config(self.tile_writer.config().clone());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::Area;
+    use crate::simplification::SimplificationConfig;
+    use crate::tile_processor::{PopulationThreshold, TileProcessor, ZoomPolicy, ZoomRange};
+    use geo::{polygon, LineString};
+    use osm::map::{BuildingInfo, MapGeomObject, MapGeomObjectKind, MapGeometry, PopAreaInfo};
+    use osm::source::tiles_sqlite_store::TilesSQLiteStore;
+    use osm::tile_writer::tile_writer::TileWriterConfig;
+    use osm::tiles::{TileKey, TileStore};
+    use rusqlite::Connection;
+
+    #[test]
+    fn test_population_thresholds() {
+        let thresholds = [(5, 0), (8, 50_000), (10, 500_000)]
+            .map(|(zoom, min_population)| PopulationThreshold {
+                zoom,
+                min_population,
+            })
+            .to_vec();
+        let tile_processor =
+            TileProcessor::new(TileWriterConfig::default(), SimplificationConfig::default())
+                .with_population_thresholds(thresholds);
+        let visible_zooms = |population| {
+            let info = PopAreaInfo {
+                level: 0,
+                population,
+            };
+            (0..=17)
+                .filter(|zoom| tile_processor.is_pop_area_visible(&info, *zoom))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(visible_zooms(50_000), (5..=9).collect::<Vec<_>>());
+        assert_eq!(visible_zooms(49_999), (5..=7).collect::<Vec<_>>());
+        assert_eq!(visible_zooms(0), (5..=7).collect::<Vec<_>>());
+        assert_eq!(visible_zooms(1_000_000), (5..=12).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_simplification_epsilon() {
+        let line = LineString::from(
+            (0..100)
+                .map(|i| (i as f64 * 0.001, ((i * 37) % 11) as f64 * 0.001))
+                .collect::<Vec<_>>(),
+        );
+        let coords_count = |simplification| {
+            TileProcessor::new(TileWriterConfig::default(), simplification)
+                .simplify_nature_line(&line, 4)
+                .0
+                .len()
+        };
+        let default_count = coords_count(SimplificationConfig::default());
+        let coarse_count = coords_count(SimplificationConfig {
+            nature_line_epsilon: 0.005,
+            ..SimplificationConfig::default()
+        });
+        assert!(default_count < line.0.len());
+        assert!(coarse_count < default_count);
+    }
+
+    #[test]
+    fn test_admin_line_zooms() {
+        let country = TileProcessor::admin_line_max_zoom(2);
+        let state = TileProcessor::admin_line_max_zoom(4);
+        // zoom levels are inverted, the country border is retained at less detailed zooms
+        assert!(country > state);
+        assert!(state > TileProcessor::admin_line_max_zoom(8));
+    }
+
+    #[test]
+    fn test_zoom_policy() {
+        let building_zooms = |zoom_policy| {
+            let mut tile_processor =
+                TileProcessor::new(TileWriterConfig::default(), SimplificationConfig::default())
+                    .with_zoom_policy(zoom_policy);
+            let building = MapGeomObject {
+                kind: MapGeomObjectKind::Building(BuildingInfo::default()),
+                id: 1,
+            };
+            let poly = polygon![
+                (x: 10.0, y: 10.0),
+                (x: 10.0002, y: 10.0),
+                (x: 10.0002, y: 10.0002),
+                (x: 10.0, y: 10.0002),
+            ];
+            tile_processor.add_to_tiles(building, MapGeometry::Poly(poly));
+            tile_processor.add_merged_buildings();
+            tile_processor.tile_writer.flush_to_collections(false);
+            let mut zooms = tile_processor
+                .tile_writer
+                .tile_keys()
+                .map(|key| key.zoom_level)
+                .collect::<Vec<_>>();
+            zooms.sort();
+            zooms.dedup();
+            zooms
+        };
+
+        assert_eq!(building_zooms(ZoomPolicy::default()), vec![0, 1]);
+        let zooms = building_zooms(ZoomPolicy {
+            buildings: ZoomRange::new(0, 3),
+            ..ZoomPolicy::default()
+        });
+        assert!(zooms.contains(&2));
+        assert_eq!(zooms, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_merge_small_buildings() {
+        let mut simplification = SimplificationConfig::default();
+        assert_eq!(simplification.building.merge_max_area, None);
+        simplification.building.merge_max_area = Some(0.00000005);
+        let mut tile_processor = TileProcessor::new(TileWriterConfig::default(), simplification);
+        for (id, x, levels) in [(2, 10.0, 2), (1, 10.0001, 4)] {
+            let poly = polygon![
+                (x: x, y: 10.0),
+                (x: x + 0.0001, y: 10.0),
+                (x: x + 0.0001, y: 10.0001),
+                (x: x, y: 10.0001),
+            ];
+            tile_processor.add_to_tiles(
+                MapGeomObject {
+                    kind: MapGeomObjectKind::Building(BuildingInfo::new(levels, None, None, None)),
+                    id,
+                },
+                MapGeometry::Poly(poly),
+            );
+        }
+        tile_processor.add_merged_buildings();
+
+        let path = std::env::temp_dir().join(format!("osm_tool_buildings_{}.db", std::process::id()));
+        tile_processor.save_to_path(&path);
+        let tile_store = TileStore::new(TilesSQLiteStore::new(&path));
+        let buildings = |zoom_level| {
+            let key = tile_processor
+                .grid
+                .tile_ranges(zoom_level, &geo::Rect::new((10.0, 10.0), (10.0, 10.0)));
+            tile_store.load_geometries(&TileKey::new(key.min_x as i32, key.min_y as i32, zoom_level))
+        };
+
+        // the most detailed zoom level keeps both footprints
+        assert_eq!(buildings(0).len(), 2);
+        let merged = buildings(1);
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].0.id, 1);
+        let MapGeomObjectKind::Building(info) = &merged[0].0.kind else {
+            panic!("Expect building");
+        };
+        assert_eq!(info.levels, 4);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_save_per_area() {
+        let dir = std::env::temp_dir().join(format!("osm_tool_per_area_{}", std::process::id()));
+        let area = |name: &str| Area {
+            name: name.to_string(),
+            ..Area::default()
+        };
+        let tokyo_path = dir.join(area("Tokyo").db_file_name());
+        let sf_path = dir.join(area("San Francisco").db_file_name());
+        assert!(sf_path.ends_with("san_francisco.db"));
+
+        let building = |id, x: f64, y: f64| {
+            (
+                MapGeomObject {
+                    kind: MapGeomObjectKind::Building(BuildingInfo::default()),
+                    id,
+                },
+                MapGeometry::Poly(polygon![
+                    (x: x, y: y),
+                    (x: x + 0.0002, y: y),
+                    (x: x + 0.0002, y: y + 0.0002),
+                    (x: x, y: y + 0.0002),
+                ]),
+            )
+        };
+        let mut tile_processor =
+            TileProcessor::new(TileWriterConfig::default(), SimplificationConfig::default());
+        let (obj, geom) = building(1, 139.7660, 35.6800);
+        tile_processor.add_to_tiles(obj, geom);
+        tile_processor.save_to_path(&tokyo_path);
+        let (obj, geom) = building(2, -122.4190, 37.7750);
+        tile_processor.add_to_tiles(obj, geom);
+        tile_processor.save_to_path(&sf_path);
+
+        let tile_ids = |path| {
+            let store = TileStore::new(TilesSQLiteStore::new(path));
+            let keys = Connection::open(path)
+                .unwrap()
+                .prepare("SELECT x, y, z FROM tiles")
+                .unwrap()
+                .query_map((), |row| Ok(TileKey::new(row.get(0)?, row.get(1)?, row.get(2)?)))
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert!(!keys.is_empty());
+            let mut ids = keys
+                .iter()
+                .flat_map(|key| store.load_geometries(key))
+                .map(|(obj, _)| obj.id)
+                .collect::<Vec<_>>();
+            ids.dedup();
+            ids
+        };
+        assert_eq!(tile_ids(&tokyo_path), vec![1]);
+        assert_eq!(tile_ids(&sf_path), vec![2]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
@@ -1,8 +1,9 @@
 use geo::line_measures::LengthMeasurable;
-use geo::{Coord, Euclidean, LineString, Simplify};
+use crate::simplification::SimplificationConfig;
+use geo::{Coord, Euclidean, LineString};
 use itertools::Itertools;
 use osm::map::LineKind::{Highway, Railway};
-use osm::map::{HighwayKind, LineKind, MapGeomObject, MapGeomObjectKind, MapGeometry, RailwayKind, WayInfo, ZOOM_LEVELS};
+use osm::map::{HighwayKind, LineKind, MapGeomObject, MapGeomObjectKind, MapGeometry, RailwayKind, WayInfo};
 use rustc_hash::{FxHashMap, FxHashSet};
 use std::collections::{HashMap, HashSet};
 use std::sync::mpsc::Sender;
@@ -51,10 +52,12 @@ impl WayStore {
         &self,
         sender: Sender<(u32, MapGeomObject, MapGeometry)>,
         preserve_topology: bool,
+        simplification: SimplificationConfig,
+        max_zoom: u32,
     ) {
         let items = self.items.clone();
         std::thread::spawn(move || {
-            Self::process_ways(sender, preserve_topology, items);
+            Self::process_ways(sender, preserve_topology, items, simplification, max_zoom);
         });
     }
 
@@ -62,6 +65,8 @@ impl WayStore {
         sender: Sender<(u32, MapGeomObject, MapGeometry)>,
         preserve_topology: bool,
         items: Vec<WayStoreItem>,
+        simplification: SimplificationConfig,
+        max_zoom: u32,
     ) {
         println!("Process ways");
         let merged_ways = Self::merge_ways(items, &[
@@ -69,19 +74,21 @@ impl WayStore {
         ]);
 
         if preserve_topology {
-            Self::process_with_preserve_topology(sender, merged_ways);
+            Self::process_with_preserve_topology(sender, merged_ways, simplification, max_zoom);
         } else {
-            Self::process_without_preserve_topology(sender, merged_ways);
+            Self::process_without_preserve_topology(sender, merged_ways, simplification, max_zoom);
         }
     }
 
     fn process_without_preserve_topology(
         sender: Sender<(u32, MapGeomObject, MapGeometry)>,
         data: Vec<(MapGeomObject, LineString)>,
+        simplification: SimplificationConfig,
+        max_zoom: u32,
     ) {
         for (map_geom_obj, line) in data {
             let mut temp_line = line;
-            for zoom_level in 0..ZOOM_LEVELS {
+            for zoom_level in 0..=max_zoom {
                 let included = match &map_geom_obj.kind {
                     MapGeomObjectKind::Way(info) => {
                         if zoom_level == 0 {
@@ -91,12 +98,8 @@ impl WayStore {
                                 != (Highway {
                                     kind: HighwayKind::Footway,
                                 })
-                        } else if info.line_kind
-                            == (Railway {
-                                kind: RailwayKind::Rail,
-                            })
-                        {
-                            zoom_level < 4
+                        } else if let Railway { kind } = info.line_kind {
+                            zoom_level < Self::railway_max_zoom_level(kind)
                         } else if zoom_level >= 13 {
                             false
                         } else {
@@ -112,11 +115,7 @@ impl WayStore {
                 };
                 if included {
                     let zlf = zoom_level as f64;
-                    let line = if temp_line.0.len() > 2 {
-                        temp_line.simplify(0.000008 * zlf * zlf)
-                    } else {
-                        temp_line.clone()
-                    };
+                    let line = simplification.simplify_road(&temp_line, zlf * zlf);
 
                     temp_line = line.clone();
                     sender
@@ -132,6 +131,8 @@ impl WayStore {
     fn process_with_preserve_topology(
         sender: Sender<(u32, MapGeomObject, MapGeometry)>,
         data: Vec<(MapGeomObject, LineString)>,
+        simplification: SimplificationConfig,
+        max_zoom: u32,
     ) {
         let mut seen = FxHashSet::default();
 
@@ -158,7 +159,7 @@ impl WayStore {
             });
         }
 
-        for zoom_level in 0..ZOOM_LEVELS {
+        for zoom_level in 0..=max_zoom {
             let filtered = data
                 .iter()
                 .filter_map(|(map_geom_obj, line)| {
@@ -175,12 +176,8 @@ impl WayStore {
                                         != (Highway {
                                             kind: HighwayKind::Footway,
                                         })
-                                } else if info.line_kind
-                                    == (Railway {
-                                        kind: RailwayKind::Rail,
-                                    })
-                                {
-                                    zoom_level < 4
+                                } else if let Railway { kind } = info.line_kind {
+                                    zoom_level < Self::railway_max_zoom_level(kind)
                                 } else if zoom_level >= 13 {
                                     false
                                 } else {
@@ -225,11 +222,7 @@ impl WayStore {
 
             for (map_geom_obj, line) in filtered {
                 if zoom_level == 0 || !preserve_topology {
-                    let line = if line.0.len() > 2 {
-                        line.simplify(0.000008 * zlf * zlf)
-                    } else {
-                        line.clone()
-                    };
+                    let line = simplification.simplify_road(line, zlf * zlf);
                     way_nodes_for_level += line.0.len() as u32;
 
                     let geom = MapGeometry::Line(line);
@@ -270,11 +263,7 @@ impl WayStore {
                             } else {
                                 prev_index = index;
                                 let line = LineString(temp.clone());
-                                let line = if line.0.len() > 2 {
-                                    line.simplify(0.000008 * zlf * zlf)
-                                } else {
-                                    line.clone()
-                                };
+                                let line = simplification.simplify_road(&line, zlf * zlf);
 
                                 intersections += 1;
                                 way_nodes_for_level += line.0.len() as u32;
@@ -393,6 +382,16 @@ impl WayStore {
             .collect_vec()
     }
 
+    // urban transit lines are only interesting on detailed zoom levels,
+    // while the main railway network is kept a bit longer
+    fn railway_max_zoom_level(kind: RailwayKind) -> u32 {
+        match kind {
+            RailwayKind::Rail => 4,
+            RailwayKind::Subway | RailwayKind::LightRail | RailwayKind::Monorail => 3,
+            RailwayKind::Tram => 2,
+        }
+    }
+
     fn create_coord_id(coord: &Coord) -> CoordInt {
         CoordInt {
             x: (coord.x * 1000000000000.0) as i64,
@@ -1,25 +1,57 @@
-use error_stack::{FutureExt, Report, ResultExt};
+use error_stack::{Report, ResultExt};
 use poem::error::ResponseError;
-use poem::http::StatusCode;
+use poem::http::{HeaderMap, StatusCode, header};
 use poem::{
-    EndpointExt, Result, Route, Server, get, handler,
+    Endpoint, EndpointExt, IntoResponse, Response, Result, Route, Server, get, handler,
     listener::TcpListener,
-    middleware::AddData,
-    web::{Data, Path},
+    middleware::{AddData, Cors},
+    web::{Data, Json, Path},
 };
-use serde::Deserialize;
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fmt::{Debug, Display, Formatter};
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
 use poem::endpoint::StaticFileEndpoint;
 use thiserror::Error;
-use tokio::task::spawn_blocking;
-use osm::source::tiles_sqlite_store::TilesSQLiteStore;
-use osm::source::TileSource;
+use osm::source::tiles_sqlite_store::{TilesSQLiteStore, TilesSQLiteStoreError};
+use osm::source::async_source::AsyncTileSource;
+use osm::source::{TileSource, TileSourceFetchError};
+use osm::tiles::codec::{decompress_tile, gzip_stream};
+use osm::tiles::{TileGrid, TileKey, TileScheme};
+
+const DEFAULT_BIND_ADDRESS: &str = "0.0.0.0:3000";
+const DEFAULT_STYLES_PATH: &str = "styles_v0.json";
+
+#[derive(Parser)]
+#[command(about = "Serves tiles from a tiles db")]
+struct ServerArgs {
+    /// Address to listen on
+    #[arg(long, default_value = DEFAULT_BIND_ADDRESS)]
+    bind: String,
+    /// Path to tiles db
+    #[arg(long, default_value = TilesSQLiteStore::DEFAULT_DB_PATH)]
+    db: PathBuf,
+    /// Path to the styles json served at `/styles_v0.json`
+    #[arg(long, default_value = DEFAULT_STYLES_PATH)]
+    styles: PathBuf,
+}
+
+impl ServerArgs {
+    /// Tiles db at the `db` path with a connection per available core
+    fn tile_store(&self) -> Result<TilesSQLiteStore, Report<TilesSQLiteStoreError>> {
+        let pool_size = std::thread::available_parallelism().map_or(1, |count| count.get());
+        TilesSQLiteStore::open_pooled(&self.db, pool_size)
+    }
+}
 
 #[derive(Error, Debug, Clone)]
 enum TileServerError {
     #[error("Internal")]
     Internal,
+    #[error("MissingData")]
+    MissingData,
 }
 
 trait DetachReport<T, E> {
@@ -43,7 +75,10 @@ impl Display for ReportResponseError<TileServerError> {
 
 impl ResponseError for ReportResponseError<TileServerError> {
     fn status(&self) -> StatusCode {
-        StatusCode::INTERNAL_SERVER_ERROR
+        match self.0.current_context() {
+            TileServerError::MissingData => StatusCode::NOT_FOUND,
+            TileServerError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
     }
 }
 
@@ -57,30 +92,247 @@ struct TileParam {
 #[derive(Clone)]
 struct AppState {
     tile_source: Arc<dyn TileSource>,
+    /// Row addressing of requested tiles
+    request_scheme: TileScheme,
+    /// Row addressing of stored tiles
+    source_scheme: TileScheme,
+    /// Tile grid of the tiles db, the row flip depends on its tiles count
+    grid: TileGrid,
+    /// `max-age` of tile responses in seconds
+    cache_max_age: u64,
+    /// Metadata table of the tiles db
+    metadata: HashMap<String, String>,
+    /// Tiles don't change for a db build, so they are counted once
+    tile_count: OnceLock<Option<u64>>,
+}
+
+impl AppState {
+    const DEFAULT_CACHE_MAX_AGE: u64 = 86400;
+
+    async fn tile_count(self: &Arc<Self>) -> Result<Option<u64>, Report<TileServerError>> {
+        if let Some(tile_count) = self.tile_count.get() {
+            return Ok(*tile_count);
+        }
+        let state = self.clone();
+        let tile_count = tokio::task::spawn_blocking(move || state.tile_source.tile_count())
+            .await
+            .change_context(TileServerError::Internal)?
+            .change_context(TileServerError::Internal)?;
+        Ok(*self.tile_count.get_or_init(|| tile_count))
+    }
+}
+
+/// Whether the client accepts gzip content encoding, a request without `Accept-Encoding` accepts any
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    let Some(accept_encoding) = headers.get(header::ACCEPT_ENCODING) else {
+        return true;
+    };
+    accept_encoding
+        .to_str()
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|encoding| {
+            let mut parts = encoding.split(';').map(str::trim);
+            let name = parts.next()?;
+            let disabled = parts.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            (!disabled).then_some(name)
+        })
+        .any(|name| name.eq_ignore_ascii_case("gzip") || name == "*")
+}
+
+/// FNV-1a, stable across builds unlike the std hasher
+fn etag(body: &[u8]) -> String {
+    let hash = body.iter().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("\"{hash:016x}\"")
+}
+
+fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|value| value.trim().trim_start_matches("W/"))
+        .any(|value| value == etag || value == "*")
+}
+
+/// Gzip tiles are sent as stored with `Content-Encoding: gzip`,
+/// other tiles and clients not accepting gzip get decompressed data.
+/// Tiles don't change for a db build so they are cached and validated by the body hash.
+fn tile_response(
+    blob: &[u8],
+    headers: &HeaderMap,
+    cache_max_age: u64,
+) -> Result<Response, Report<TileServerError>> {
+    let (body, content_encoding) = match gzip_stream(blob) {
+        Some(stream) if accepts_gzip(headers) => (stream.to_vec(), Some("gzip")),
+        _ => (
+            decompress_tile(blob).change_context(TileServerError::Internal)?,
+            None,
+        ),
+    };
+    let etag = etag(&body);
+    let mut response = Response::builder()
+        .header(header::CACHE_CONTROL, format!("public, max-age={cache_max_age}"))
+        .header(header::VARY, "Accept-Encoding")
+        .header(header::ETAG, &etag);
+    if etag_matches(headers, &etag) {
+        return Ok(response.status(StatusCode::NOT_MODIFIED).finish());
+    }
+    if let Some(content_encoding) = content_encoding {
+        response = response.header(header::CONTENT_ENCODING, content_encoding);
+    }
+    Ok(response.content_type("application/octet-stream").body(body))
+}
+
+/// Host of tile URLs for requests without a `Host` header
+const DEFAULT_HOST: &str = "localhost:3000";
+
+/// TileJSON 3.0 document, zoom levels are the internal ones: 0 is the most detailed
+#[derive(Serialize, Deserialize, Debug)]
+struct TileJson {
+    tilejson: String,
+    tiles: Vec<String>,
+    scheme: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    minzoom: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    maxzoom: Option<i32>,
+    /// West, south, east, north
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bounds: Option<[f64; 4]>,
+}
+
+impl TileJson {
+    fn new(state: &AppState, host: &str) -> Self {
+        let zoom = |name: &str| state.metadata.get(name).and_then(|zoom| zoom.parse().ok());
+        let zooms = state.tile_source.list_zooms();
+        let bounds = state.metadata.get("bounds").and_then(|bounds| {
+            let values = bounds
+                .split(',')
+                .map(|value| value.trim().parse::<f64>().ok())
+                .collect::<Option<Vec<_>>>()?;
+            let [min_x, min_y, max_x, max_y] = values[..] else {
+                return None;
+            };
+            Some([min_x.min(max_x), min_y.min(max_y), min_x.max(max_x), min_y.max(max_y)])
+        });
+        TileJson {
+            tilejson: "3.0.0".to_string(),
+            tiles: vec![format!("http://{host}/tile/{{x}}/{{y}}/{{z}}")],
+            scheme: state.request_scheme.as_str().to_string(),
+            minzoom: zoom("minzoom").or_else(|| zooms.iter().min().copied()),
+            maxzoom: zoom("maxzoom").or_else(|| zooms.iter().max().copied()),
+            bounds,
+        }
+    }
+}
+
+#[handler]
+async fn get_tile_json(headers: &HeaderMap, state: Data<&Arc<AppState>>) -> Json<TileJson> {
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .unwrap_or(DEFAULT_HOST);
+    Json(TileJson::new(&state, host))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Health {
+    status: String,
+    tile_count: Option<u64>,
+}
+
+/// 200 when the tiles table can be queried, 503 otherwise
+#[handler]
+async fn get_health(state: Data<&Arc<AppState>>) -> Response {
+    match state.tile_count().await {
+        Ok(tile_count) => Json(Health {
+            status: "ok".to_string(),
+            tile_count,
+        })
+        .into_response(),
+        Err(err) => {
+            println!("Health check failed: {err:?}");
+            Json(Health {
+                status: "unavailable".to_string(),
+                tile_count: None,
+            })
+            .with_status(StatusCode::SERVICE_UNAVAILABLE)
+            .into_response()
+        }
+    }
 }
 
 #[handler]
 async fn get_state(
     Path(TileParam { x, y, z }): Path<TileParam>,
+    headers: &HeaderMap,
     state: Data<&Arc<AppState>>,
-) -> Result<Vec<u8>> {
+) -> Result<Response> {
     println!("getting tile {}/{}/{}", x, y, z);
     let state = state.clone();
-    let db_res = spawn_blocking(move || {
-        state
-            .tile_source
-            .fetch(x, y, z)
-            .change_context(TileServerError::Internal)
-            .detach_report()
-    })
-    .change_context(TileServerError::Internal)
-    .await
-    .detach_report()??;
-    Ok(db_res)
+    let TileKey {
+        tile_x: x,
+        tile_y: y,
+        zoom_level: z,
+    } = state.grid.flip_y(
+        &state.grid.flip_y(&TileKey::new(x, y, z), state.request_scheme),
+        state.source_scheme,
+    );
+    let db_res = state
+        .tile_source
+        .fetch_async(x, y, z)
+        .await
+        .map_err(|err| {
+            let context = match err.current_context() {
+                TileSourceFetchError::MissingData => TileServerError::MissingData,
+                TileSourceFetchError::Internal => TileServerError::Internal,
+            };
+            err.change_context(context)
+        })
+        .detach_report()?;
+    Ok(tile_response(&db_res, headers, state.cache_max_age).detach_report()?)
+}
+
+/// `*` allows any origin, otherwise a comma separated list of allowed origins
+fn cors(allowed_origins: &str) -> Cors {
+    if allowed_origins.trim() == "*" {
+        Cors::new()
+    } else {
+        Cors::new().allow_origins(
+            allowed_origins
+                .split(',')
+                .map(str::trim)
+                .filter(|origin| !origin.is_empty()),
+        )
+    }
+}
+
+fn app(
+    state: Arc<AppState>,
+    cors: Cors,
+    styles_path: impl Into<PathBuf>,
+) -> impl Endpoint<Output = Response> {
+    Route::new()
+        .at("/tile/:x/:y/:z", get(get_state))
+        .at("/tiles.json", get(get_tile_json))
+        .at("/healthz", get(get_health))
+        .at("/styles_v0.json", StaticFileEndpoint::new(styles_path))
+        .with(AddData::new(state))
+        .with(cors)
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Report<TileServerError>> {
+    let args = ServerArgs::parse();
     println!("RUN TILES SQLITE");
     if std::env::var_os("RUST_LOG").is_none() {
         unsafe {
@@ -89,18 +341,377 @@ async fn main() -> Result<(), Report<TileServerError>> {
     }
     tracing_subscriber::fmt::init();
 
+    let request_scheme = std::env::var("TILE_SCHEME")
+        .ok()
+        .and_then(|name| TileScheme::from_name(&name))
+        .unwrap_or_default();
+    let cors_origin = std::env::var("CORS_ORIGIN").unwrap_or_else(|_| "*".to_string());
+    let cache_max_age = std::env::var("CACHE_MAX_AGE")
+        .ok()
+        .and_then(|max_age| max_age.parse().ok())
+        .unwrap_or(AppState::DEFAULT_CACHE_MAX_AGE);
+    let tile_store = args
+        .tile_store()
+        .change_context(TileServerError::Internal)?;
     let state = Arc::new(AppState {
-        tile_source: Arc::new(TilesSQLiteStore::new_default_db()),
+        source_scheme: tile_store.scheme(),
+        grid: tile_store.grid(),
+        metadata: tile_store.metadata().unwrap_or_default(),
+        tile_source: Arc::new(tile_store),
+        request_scheme,
+        cache_max_age,
+        tile_count: OnceLock::new(),
     });
 
-    let app = Route::new()
-        .at("/tile/:x/:y/:z", get(get_state))
-        .at("/styles_v0.json", StaticFileEndpoint::new("styles_v0.json"))
-        .with(AddData::new(state));
+    let app = app(state, cors(&cors_origin), args.styles.clone());
 
-    Server::new(TcpListener::bind("0.0.0.0:3000"))
+    Server::new(TcpListener::bind(&args.bind))
         .name("add-data")
         .run(app)
         .await
         .change_context(TileServerError::Internal)
 }
+
+#[cfg(test)]
+mod test {
+    use crate::{AppState, DEFAULT_STYLES_PATH, Health, ServerArgs, TileJson, app, cors};
+    use error_stack::Report;
+    use osm::source::tiles_sqlite_store::TilesSQLiteStore;
+    use osm::source::{TileSource, TileSourceFetchError};
+    use osm::tiles::{TileGrid, TileScheme};
+    use osm::tiles::codec::{split_header, TileCodec};
+    use rusqlite::Connection;
+    use poem::http::{Method, StatusCode, header};
+    use poem::{Endpoint, Request, Response};
+    use clap::Parser;
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use std::sync::{Arc, OnceLock};
+
+    const TILE_DATA: &[u8] = &[1, 2, 3];
+
+    /// Serves the same tile at every key but zoom level 6, where it fails
+    struct StaticSource(TileCodec);
+
+    impl TileSource for StaticSource {
+        fn fetch(&self, _x: i32, _y: i32, z: i32) -> Result<Vec<u8>, Report<TileSourceFetchError>> {
+            match z {
+                6 => Err(Report::new(TileSourceFetchError::Internal)),
+                _ => Ok(self.0.compress(TILE_DATA).unwrap()),
+            }
+        }
+    }
+
+    fn state() -> Arc<AppState> {
+        state_with_codec(TileCodec::Gzip)
+    }
+
+    fn state_with_codec(codec: TileCodec) -> Arc<AppState> {
+        Arc::new(AppState {
+            tile_source: Arc::new(StaticSource(codec)),
+            request_scheme: TileScheme::default(),
+            source_scheme: TileScheme::default(),
+            grid: TileGrid::default(),
+            cache_max_age: AppState::DEFAULT_CACHE_MAX_AGE,
+            tile_count: OnceLock::new(),
+            metadata: HashMap::new(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_cors() {
+        let endpoint = app(state(), cors("*"), DEFAULT_STYLES_PATH);
+        let response = endpoint
+            .call(
+                Request::builder()
+                    .uri("/tile/1/2/3".parse().unwrap())
+                    .header(header::ORIGIN, "https://viewer.example.com")
+                    .finish(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        let preflight = endpoint
+            .call(
+                Request::builder()
+                    .method(Method::OPTIONS)
+                    .uri("/tile/1/2/3".parse().unwrap())
+                    .header(header::ORIGIN, "https://viewer.example.com")
+                    .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+                    .finish(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(preflight.status(), StatusCode::OK);
+        assert!(preflight.headers().contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        let restricted = app(state(), cors("https://maps.example.com"), DEFAULT_STYLES_PATH);
+        let rejected = restricted
+            .call(
+                Request::builder()
+                    .uri("/tile/1/2/3".parse().unwrap())
+                    .header(header::ORIGIN, "https://viewer.example.com")
+                    .finish(),
+            )
+            .await;
+        assert!(rejected.is_err());
+    }
+
+    async fn get_tile(state: Arc<AppState>, accept_encoding: Option<&str>) -> Response {
+        let mut request = Request::builder().uri("/tile/1/2/3".parse().unwrap());
+        if let Some(accept_encoding) = accept_encoding {
+            request = request.header(header::ACCEPT_ENCODING, accept_encoding);
+        }
+        let response = app(state, cors("*"), DEFAULT_STYLES_PATH).call(request.finish()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/octet-stream"
+        );
+        response
+    }
+
+    #[tokio::test]
+    async fn test_content_encoding() {
+        let stored = TileCodec::Gzip.compress(TILE_DATA).unwrap();
+
+        let gzip = get_tile(state(), Some("gzip, deflate")).await;
+        assert_eq!(gzip.headers().get(header::CONTENT_ENCODING).unwrap(), "gzip");
+        // the stored blob without the header and the codec tag
+        assert_eq!(gzip.into_body().into_vec().await.unwrap(), split_header(&stored).1[1..]);
+
+        let any = get_tile(state(), None).await;
+        assert_eq!(any.headers().get(header::CONTENT_ENCODING).unwrap(), "gzip");
+
+        for accept_encoding in ["identity", "gzip;q=0"] {
+            let identity = get_tile(state(), Some(accept_encoding)).await;
+            assert!(!identity.headers().contains_key(header::CONTENT_ENCODING));
+            assert_eq!(identity.into_body().into_vec().await.unwrap(), TILE_DATA);
+        }
+
+        let zstd = get_tile(state_with_codec(TileCodec::Zstd), Some("gzip")).await;
+        assert!(!zstd.headers().contains_key(header::CONTENT_ENCODING));
+        assert_eq!(zstd.into_body().into_vec().await.unwrap(), TILE_DATA);
+    }
+
+    async fn status(state: Arc<AppState>, z: i32) -> StatusCode {
+        let request = Request::builder()
+            .uri(format!("/tile/3/5/{z}").parse().unwrap())
+            .finish();
+        match app(state, cors("*"), DEFAULT_STYLES_PATH).call(request).await {
+            Ok(response) => response.status(),
+            Err(err) => err.status(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_missing_tile() {
+        let path = std::env::temp_dir().join(format!("tiles_server_missing_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let conn = Connection::open(&path).unwrap();
+        conn.execute(
+            "CREATE TABLE tiles (x INTEGER NOT NULL, y INTEGER NOT NULL, z INTEGER NOT NULL, data BLOB)",
+            [],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO tiles VALUES (3, 5, 10, ?1)",
+            [TileCodec::Gzip.compress(TILE_DATA).unwrap()],
+        )
+        .unwrap();
+        drop(conn);
+
+        let sqlite_state = Arc::new(AppState {
+            tile_source: Arc::new(TilesSQLiteStore::new(&path)),
+            request_scheme: TileScheme::default(),
+            source_scheme: TileScheme::default(),
+            grid: TileGrid::default(),
+            cache_max_age: AppState::DEFAULT_CACHE_MAX_AGE,
+            tile_count: OnceLock::new(),
+            metadata: HashMap::new(),
+        });
+        assert_eq!(status(sqlite_state.clone(), 10).await, StatusCode::OK);
+        assert_eq!(status(sqlite_state.clone(), 11).await, StatusCode::NOT_FOUND);
+        assert_eq!(status(state(), 6).await, StatusCode::INTERNAL_SERVER_ERROR);
+
+        drop(sqlite_state);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_etag() {
+        let endpoint = app(state(), cors("*"), DEFAULT_STYLES_PATH);
+        let response = endpoint
+            .call(Request::builder().uri("/tile/1/2/3".parse().unwrap()).finish())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CACHE_CONTROL).unwrap(),
+            "public, max-age=86400"
+        );
+        let etag = response.headers().get(header::ETAG).unwrap().clone();
+
+        let cached = endpoint
+            .call(
+                Request::builder()
+                    .uri("/tile/1/2/3".parse().unwrap())
+                    .header(header::IF_NONE_MATCH, etag.clone())
+                    .finish(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(cached.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(cached.headers().get(header::ETAG), Some(&etag));
+        assert!(cached.into_body().into_vec().await.unwrap().is_empty());
+
+        let changed = endpoint
+            .call(
+                Request::builder()
+                    .uri("/tile/1/2/3".parse().unwrap())
+                    .header(header::IF_NONE_MATCH, "\"0000000000000000\"")
+                    .finish(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(changed.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_tile_json() {
+        let path = std::env::temp_dir().join(format!("tiles_server_tile_json_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE tiles (x INTEGER NOT NULL, y INTEGER NOT NULL, z INTEGER NOT NULL, data BLOB);
+             CREATE TABLE metadata (name TEXT NOT NULL, value TEXT NOT NULL);
+             INSERT INTO tiles VALUES (3, 5, 2, x'00'), (1, 1, 6, x'00');
+             INSERT INTO metadata VALUES ('minzoom', '2'), ('bounds', '10,50.5,11,49.5');",
+        )
+        .unwrap();
+        drop(conn);
+
+        let tile_store = TilesSQLiteStore::new(&path);
+        let state = Arc::new(AppState {
+            metadata: tile_store.metadata().unwrap(),
+            tile_source: Arc::new(tile_store),
+            request_scheme: TileScheme::Xyz,
+            source_scheme: TileScheme::default(),
+            grid: TileGrid::default(),
+            cache_max_age: AppState::DEFAULT_CACHE_MAX_AGE,
+            tile_count: OnceLock::new(),
+        });
+        let response = app(state.clone(), cors("*"), DEFAULT_STYLES_PATH)
+            .call(
+                Request::builder()
+                    .uri("/tiles.json".parse().unwrap())
+                    .header(header::HOST, "tiles.example.com:8080")
+                    .finish(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().into_vec().await.unwrap();
+        let tile_json: TileJson = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(tile_json.tilejson, "3.0.0");
+        assert_eq!(tile_json.tiles, vec!["http://tiles.example.com:8080/tile/{x}/{y}/{z}"]);
+        assert_eq!(tile_json.scheme, "xyz");
+        assert_eq!(tile_json.minzoom, Some(2));
+        // not in the metadata, taken from stored tiles
+        assert_eq!(tile_json.maxzoom, Some(6));
+        assert_eq!(tile_json.bounds, Some([10.0, 49.5, 11.0, 50.5]));
+
+        drop(state);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_server_args() {
+        let defaults = ServerArgs::try_parse_from(["tiles-server-sqlite"]).unwrap();
+        assert_eq!(defaults.bind, "0.0.0.0:3000");
+        assert_eq!(defaults.db, PathBuf::from(TilesSQLiteStore::DEFAULT_DB_PATH));
+        assert_eq!(defaults.styles, PathBuf::from("styles_v0.json"));
+
+        let path = std::env::temp_dir().join(format!("tiles_server_args_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE tiles (x INTEGER NOT NULL, y INTEGER NOT NULL, z INTEGER NOT NULL, data BLOB);
+             INSERT INTO tiles VALUES (3, 5, 10, x'0102');",
+        )
+        .unwrap();
+        drop(conn);
+
+        let args = ServerArgs::try_parse_from([
+            "tiles-server-sqlite",
+            "--bind",
+            "127.0.0.1:8080",
+            "--db",
+            path.to_str().unwrap(),
+        ])
+        .unwrap();
+        assert_eq!(args.bind, "127.0.0.1:8080");
+        let tile_store = args.tile_store().unwrap();
+        assert_eq!(tile_store.get_tile(3, 5, 10).unwrap(), vec![1, 2]);
+
+        drop(tile_store);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_health() {
+        let health = |db: &str| {
+            let path = std::env::temp_dir().join(format!("tiles_server_{db}_{}.db", std::process::id()));
+            async move {
+                let tile_store = TilesSQLiteStore::new(&path);
+                let state = Arc::new(AppState {
+                    metadata: HashMap::new(),
+                    tile_source: Arc::new(tile_store),
+                    request_scheme: TileScheme::default(),
+                    source_scheme: TileScheme::default(),
+                    grid: TileGrid::default(),
+                    cache_max_age: AppState::DEFAULT_CACHE_MAX_AGE,
+                    tile_count: OnceLock::new(),
+                });
+                let response = app(state.clone(), cors("*"), DEFAULT_STYLES_PATH)
+                    .call(Request::builder().uri("/healthz".parse().unwrap()).finish())
+                    .await
+                    .unwrap();
+                let status = response.status();
+                let body = response.into_body().into_vec().await.unwrap();
+                drop(state);
+                std::fs::remove_file(path).unwrap();
+                (status, serde_json::from_slice::<Health>(&body).unwrap())
+            }
+        };
+
+        let valid_path = std::env::temp_dir().join(format!("tiles_server_valid_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&valid_path);
+        let conn = Connection::open(&valid_path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE tiles (x INTEGER NOT NULL, y INTEGER NOT NULL, z INTEGER NOT NULL, data BLOB);
+             INSERT INTO tiles VALUES (3, 5, 10, x'00'), (3, 6, 10, x'00');",
+        )
+        .unwrap();
+        drop(conn);
+        let (status, valid) = health("valid").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(valid.status, "ok");
+        assert_eq!(valid.tile_count, Some(2));
+
+        // a db without the tiles table
+        let invalid_path = std::env::temp_dir().join(format!("tiles_server_invalid_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&invalid_path);
+        Connection::open(&invalid_path)
+            .unwrap()
+            .execute_batch("CREATE TABLE other (id INTEGER);")
+            .unwrap();
+        let (status, invalid) = health("invalid").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(invalid.status, "unavailable");
+        assert_eq!(invalid.tile_count, None);
+    }
+}